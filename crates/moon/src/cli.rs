@@ -34,6 +34,7 @@ pub mod new;
 mod pre_build;
 pub mod query;
 pub mod run;
+pub mod server;
 pub mod shell_completion;
 pub mod test;
 pub mod tool;
@@ -61,6 +62,7 @@ use mooncake::pkg::{
 pub use new::*;
 pub use query::*;
 pub use run::*;
+pub use server::*;
 pub use shell_completion::*;
 pub use test::*;
 pub use tool::*;
@@ -75,8 +77,12 @@ use moonutil::{
         BuildPackageFlags, DiagnosticLevel, LinkCoreFlags, MOON_MOD_JSON, MOONBITLANG_CORE,
         MooncOpt, OutputFormat, SurfaceTarget, TargetBackend, read_module_desc_file_in_dir,
     },
+    compiler_flags::CC,
+    module_features::resolve_features,
     mooncakes::{LoginSubcommand, PackageSubcommand, PublishSubcommand, RegisterSubcommand},
+    profile::{BaseProfile, resolve_profile},
 };
+use std::collections::HashMap;
 use std::path::Path;
 
 #[derive(Debug, clap::Parser)]
@@ -109,6 +115,7 @@ pub enum MoonBuildSubcommands {
     Doc(DocSubcommand),
     Info(InfoSubcommand),
     Bench(BenchSubcommand),
+    Server(ServerSubcommand),
 
     // Dependencies
     Add(AddSubcommand),
@@ -160,6 +167,11 @@ pub struct BuildFlags {
     #[clap(long, conflicts_with = "debug")]
     pub release: bool,
 
+    /// Build with a named profile from `moon.mod.json`, or the built-in
+    /// `debug` or `release` profile
+    #[clap(long, value_name = "NAME", conflicts_with_all = ["debug", "release"])]
+    pub build_profile: Option<String>,
+
     /// Enable stripping debug information
     #[clap(long, conflicts_with = "no_strip")]
     pub strip: bool,
@@ -175,6 +187,15 @@ pub struct BuildFlags {
     #[clap(skip)]
     pub target_backend: Option<TargetBackend>,
 
+    /// Cross-compile the native backend for the given target triple, e.g.
+    /// `aarch64-unknown-linux-gnu`
+    #[clap(long, value_name = "TRIPLE")]
+    pub triple: Option<String>,
+
+    /// Enable features declared in `moon.mod.json`, separated by commas
+    #[clap(long, value_delimiter = ',', value_name = "FEATURES")]
+    pub features: Vec<String>,
+
     /// [Deprecated] Handle the selected targets sequentially
     ///
     /// This flag is deprecated, because all targets are handled sequentially
@@ -225,6 +246,18 @@ pub struct BuildFlags {
     pub render_no_loc: DiagnosticLevel,
 }
 
+/// Flags that tweak the behavior of `--watch`
+#[derive(Debug, clap::Parser, Clone, Default)]
+pub struct WatchFlags {
+    /// Do not clear the screen before rerunning in watch mode
+    #[clap(long, requires = "watch")]
+    pub no_clear: bool,
+
+    /// Command to execute after each successful run in watch mode
+    #[clap(long, requires = "watch", value_name = "COMMAND")]
+    pub exec: Option<String>,
+}
+
 impl Default for BuildFlags {
     #[allow(deprecated)]
     fn default() -> Self {
@@ -233,10 +266,13 @@ impl Default for BuildFlags {
             no_std: false,
             debug: false,
             release: false,
+            build_profile: None,
             strip: false,
             no_strip: false,
             target: None,
             target_backend: None,
+            triple: None,
+            features: vec![],
             serial: false,
             enable_coverage: false,
             sort_input: false,
@@ -279,8 +315,8 @@ pub fn get_compiler_flags(src_dir: &Path, build_flags: &BuildFlags) -> anyhow::R
         bail!("could not find `{}`", MOON_MOD_JSON);
     }
     let moon_mod = read_module_desc_file_in_dir(src_dir)?;
-    let extra_build_opt = moon_mod.compile_flags.unwrap_or_default();
-    let extra_link_opt = moon_mod.link_flags.unwrap_or_default();
+    let mut extra_build_opt = moon_mod.compile_flags.unwrap_or_default();
+    let mut extra_link_opt = moon_mod.link_flags.unwrap_or_default();
 
     let output_format = if build_flags.output_wat {
         OutputFormat::Wat
@@ -296,6 +332,11 @@ pub fn get_compiler_flags(src_dir: &Path, build_flags: &BuildFlags) -> anyhow::R
     if target_backend == TargetBackend::Js && output_format == OutputFormat::Wat {
         bail!("--output-wat is not supported for --target js");
     }
+    let cross_cc = build_flags
+        .triple
+        .as_deref()
+        .map(|triple| cross_compiler(target_backend, triple))
+        .transpose()?;
 
     let output_format = match target_backend {
         TargetBackend::Js => OutputFormat::Js,
@@ -304,13 +345,30 @@ pub fn get_compiler_flags(src_dir: &Path, build_flags: &BuildFlags) -> anyhow::R
         _ => output_format,
     };
 
-    let debug_flag = build_flags.debug;
+    let profile = build_flags
+        .build_profile
+        .as_deref()
+        .map(|name| resolve_profile(moon_mod.profiles.as_ref(), name))
+        .transpose()?;
+    if let Some(profile) = &profile {
+        extra_build_opt.extend(profile.config.compile_flags.iter().flatten().cloned());
+        extra_link_opt.extend(profile.config.link_flags.iter().flatten().cloned());
+    }
+
+    let debug_flag = match &profile {
+        Some(profile) => profile.config.inherits == BaseProfile::Debug,
+        None => build_flags.debug,
+    };
+    let strip_flag = match &profile {
+        Some(profile) => profile.config.strip(),
+        None => build_flags.strip(),
+    };
     let enable_coverage = build_flags.enable_coverage;
     let source_map = debug_flag && target_backend.supports_source_map();
 
     let build_opt = BuildPackageFlags {
         debug_flag,
-        strip_flag: build_flags.strip(),
+        strip_flag,
         source_map,
         enable_coverage,
         deny_warn: false,
@@ -327,6 +385,12 @@ pub fn get_compiler_flags(src_dir: &Path, build_flags: &BuildFlags) -> anyhow::R
         target_backend,
     };
 
+    let features = resolve_features(
+        &moon_mod.name,
+        moon_mod.features.as_ref(),
+        &build_flags.features,
+    )?;
+
     let nostd = !build_flags.std() || moon_mod.name == MOONBITLANG_CORE;
     let render =
         !build_flags.no_render || std::env::var("MOON_NO_RENDER").unwrap_or_default() == "1";
@@ -339,9 +403,26 @@ pub fn get_compiler_flags(src_dir: &Path, build_flags: &BuildFlags) -> anyhow::R
         nostd,
         render,
         single_file: false,
+        target_triple: build_flags.triple.clone(),
+        cross_cc,
+        profile,
+        features: HashMap::from([(moon_mod.name.clone(), features)]),
     })
 }
 
+/// Check that `--triple` can be used for `target_backend`, and find the C
+/// compiler for it.
+pub fn cross_compiler(target_backend: TargetBackend, triple: &str) -> anyhow::Result<CC> {
+    if target_backend != TargetBackend::Native {
+        bail!(
+            "`--triple` is only supported for the native backend, not {}",
+            target_backend.to_flag()
+        );
+    }
+    moonutil::build_script::TargetInfo::from_triple(target_backend, triple)?;
+    CC::for_target(triple)
+}
+
 #[test]
 fn gen_docs_for_moon_help_page() {
     let markdown: String = clap_markdown::help_markdown::<MoonBuildSubcommands>();
@@ -17,7 +17,11 @@
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
 use anyhow::Context;
-use moonutil::{common::lower_surface_targets, dirs::PackageDirs, mooncakes::sync::AutoSyncFlags};
+use moonutil::{
+    common::{ProfileFlags, lower_surface_targets},
+    dirs::PackageDirs,
+    mooncakes::sync::AutoSyncFlags,
+};
 use std::path::Path;
 use tracing::{Level, instrument};
 
@@ -51,6 +55,9 @@ pub struct BenchSubcommand {
     /// Run the benchmarks in a target backend sequentially
     #[clap(long)]
     pub no_parallelize: bool,
+
+    #[clap(flatten)]
+    pub profile_flags: ProfileFlags,
 }
 
 #[instrument(skip_all)]
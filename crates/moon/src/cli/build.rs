@@ -19,11 +19,13 @@
 use anyhow::Context;
 use anyhow::anyhow;
 use colored::Colorize;
+use moonbuild::compdb::write_compile_commands;
 use moonbuild::dry_run;
 use moonbuild::entry;
 use moonbuild_rupes_recta::intent::UserIntent;
 use mooncake::pkg::sync::auto_sync;
 use moonutil::common::BuildOpt;
+use moonutil::common::ExplainFormat;
 use moonutil::common::FileLock;
 use moonutil::common::MoonbuildOpt;
 use moonutil::common::PrePostBuild;
@@ -47,7 +49,7 @@ use crate::rr_build::preconfig_compile;
 use crate::watch::watching;
 
 use super::pre_build::scan_with_x_build;
-use super::{BuildFlags, UniversalFlags};
+use super::{BuildFlags, UniversalFlags, WatchFlags};
 
 /// Build the current package
 #[derive(Debug, clap::Parser, Clone)]
@@ -62,6 +64,24 @@ pub struct BuildSubcommand {
     #[clap(long, short)]
     pub watch: bool,
 
+    #[clap(flatten)]
+    pub watch_flags: WatchFlags,
+
+    /// Record the time taken by each build step and write a timing report to
+    /// `target/timings`
+    #[clap(long)]
+    pub timings: bool,
+
+    /// Print why each build step is rerun, as text or as JSON lines
+    #[clap(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "human"
+    )]
+    pub explain: Option<ExplainFormat>,
+
     #[clap(long, hide = true)]
     pub install_path: Option<PathBuf>,
 
@@ -108,7 +128,7 @@ fn run_build_internal(
     source_dir: &Path,
     target_dir: &Path,
 ) -> anyhow::Result<i32> {
-    let f = || {
+    let f = |_: Option<&[PathBuf]>| {
         if cli.unstable_feature.rupes_recta {
             run_build_rr(cli, cmd, source_dir, target_dir)
         } else {
@@ -117,9 +137,9 @@ fn run_build_internal(
     };
 
     if cmd.watch {
-        watching(f, source_dir, target_dir, target_dir)
+        watching(f, &cmd.watch_flags, source_dir, target_dir, target_dir)
     } else {
-        f()
+        f(None)
     }
 }
 
@@ -138,7 +158,7 @@ fn run_build_rr(
         OptLevel::Release,
         RunMode::Build,
     );
-    let (_build_meta, build_graph) = rr_build::plan_build(
+    let (build_meta, build_graph) = rr_build::plan_build(
         preconfig,
         &cli.unstable_feature,
         source_dir,
@@ -149,7 +169,7 @@ fn run_build_rr(
     if cli.dry_run {
         rr_build::print_dry_run(
             &build_graph,
-            _build_meta.artifacts.values(),
+            build_meta.artifacts.values(),
             source_dir,
             target_dir,
         );
@@ -157,11 +177,15 @@ fn run_build_rr(
     } else {
         let _lock = FileLock::lock(target_dir)?;
 
-        let result = rr_build::execute_build(
-            &BuildConfig::from_flags(&cmd.build_flags, &cli.unstable_feature),
-            build_graph,
-            target_dir,
-        )?;
+        if build_meta.target_backend.is_native() {
+            write_compile_commands(&build_graph, target_dir)?;
+        }
+
+        let mut cfg = BuildConfig::from_flags(&cmd.build_flags, &cli.unstable_feature);
+        cfg.timings = cmd.timings;
+        cfg.explain = cmd.explain.map(|format| (format, source_dir.to_path_buf()));
+        let result = rr_build::execute_build(&cfg, build_graph, target_dir)?;
+        result.write_timings(target_dir, build_meta.target_backend, cli.quiet)?;
         result.print_info(cli.quiet, "building")?;
         Ok(result.return_code_for_success())
     }
@@ -212,6 +236,8 @@ fn run_build_legacy(
         build_opt: Some(BuildOpt {
             install_path: cmd.install_path.clone(),
             filter_package: cmd.package.clone(),
+            timings: cmd.timings,
+            explain: cmd.explain,
         }),
         fmt_opt: None,
         args: vec![],
@@ -39,7 +39,7 @@ use crate::rr_build::{self, BuildConfig, CalcUserIntentOutput, preconfig_compile
 use crate::watch::watching;
 
 use super::pre_build::scan_with_x_build;
-use super::{BuildFlags, get_compiler_flags};
+use super::{BuildFlags, WatchFlags, get_compiler_flags};
 
 /// Check the current package, but don't build object files
 #[derive(Debug, clap::Parser, Clone)]
@@ -58,6 +58,9 @@ pub struct CheckSubcommand {
     #[clap(long, short)]
     pub watch: bool,
 
+    #[clap(flatten)]
+    pub watch_flags: WatchFlags,
+
     /// The package(and it's deps) to check
     #[clap(long, short)]
     pub package_path: Option<PathBuf>,
@@ -206,6 +209,10 @@ fn run_check_for_single_file(cli: &UniversalFlags, cmd: &CheckSubcommand) -> any
         nostd: false,
         render: !cmd.build_flags.no_render,
         single_file: true,
+        target_triple: None,
+        cross_cc: None,
+        profile: None,
+        features: Default::default(),
     };
     let module =
         get_module_for_single_file(single_file_path, &moonc_opt, &moonbuild_opt, mbt_md_header)?;
@@ -224,7 +231,7 @@ fn run_check_normal_internal(
     source_dir: &Path,
     target_dir: &Path,
 ) -> anyhow::Result<i32> {
-    let f = || {
+    let f = |_: Option<&[PathBuf]>| {
         if cli.unstable_feature.rupes_recta {
             run_check_normal_internal_rr(cli, cmd, source_dir, target_dir)
         } else {
@@ -240,9 +247,9 @@ fn run_check_normal_internal(
                 actual_target.display()
             )
         })?;
-        watching(f, source_dir, &actual_target, target_dir)
+        watching(f, &cmd.watch_flags, source_dir, &actual_target, target_dir)
     } else {
-        f()
+        f(None)
     }
 }
 
@@ -16,13 +16,16 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
+use std::path::{Path, PathBuf};
+
 use anyhow::bail;
+use moonbuild::doc_export::DocFormat;
 use moonbuild::dry_run::print_commands;
 use moonbuild_rupes_recta::intent::UserIntent;
 use mooncake::pkg::sync::auto_sync;
 use moonutil::common::{
     CargoPathExt, DiagnosticLevel, FileLock, MOONBITLANG_CORE, MoonbuildOpt, MooncOpt,
-    PrePostBuild, RunMode, read_module_desc_file_in_dir,
+    PrePostBuild, RunMode, TargetBackend, read_module_desc_file_in_dir,
 };
 use moonutil::dirs::{PackageDirs, mk_arch_mode_dir};
 use moonutil::mooncakes::RegistryConfig;
@@ -32,8 +35,13 @@ use tracing::instrument;
 use super::UniversalFlags;
 use super::pre_build::scan_with_x_build;
 
-use crate::cli::BuildFlags;
+use crate::cli::{BuildFlags, WatchFlags};
 use crate::rr_build::{self, BuildConfig, preconfig_compile};
+use crate::watch::watching;
+
+/// Where the markdown pages the search index is built from are generated,
+/// relative to the target dir.
+const DOC_PAGES_DIR: &str = "doc_pages";
 
 /// Generate documentation
 #[derive(Debug, clap::Parser)]
@@ -50,31 +58,188 @@ pub struct DocSubcommand {
     #[clap(long, short, default_value = "3000", requires("serve"))]
     pub port: u16,
 
+    /// Regenerate the documentation and reload the browser when files change
+    #[clap(long, short, requires("serve"))]
+    pub watch: bool,
+
+    #[clap(flatten)]
+    pub watch_flags: WatchFlags,
+
+    /// Export the documentation as markdown pages or a JSON model instead of generating the web UI
+    #[clap(long, requires("out"), conflicts_with("serve"))]
+    pub format: Option<DocFormat>,
+
+    /// The directory to write the exported documentation to
+    #[clap(long, requires("format"))]
+    pub out: Option<PathBuf>,
+
     #[clap(flatten)]
     pub auto_sync_flags: AutoSyncFlags,
 }
 
 #[instrument(skip_all)]
 pub fn run_doc(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result<i32> {
+    let PackageDirs {
+        source_dir,
+        target_dir,
+    } = cli.source_tgt_dir.try_into_package_dirs()?;
+
+    if let Some(format) = cmd.format {
+        let out_dir = cmd.out.as_deref().unwrap();
+        return run_doc_export(&cli, &cmd, format, out_dir, &source_dir, &target_dir);
+    }
+
+    let mod_desc = read_module_desc_file_in_dir(&source_dir)?;
+    let mod_name = mod_desc.name.clone();
+    let static_dir = target_dir.join("doc");
+
+    let generate = || {
+        let code = generate_doc(&cli, &cmd, &source_dir, &target_dir)?;
+        if code == 0 && cmd.serve {
+            generate_search_index(&cli, &mod_desc.name, &source_dir, &target_dir, &static_dir)?;
+        }
+        Ok(code)
+    };
+
+    if !cmd.serve || cli.dry_run {
+        return generate();
+    }
+
+    if !cmd.watch {
+        let code = generate()?;
+        if code != 0 {
+            return Ok(code);
+        }
+        moonbuild::doc_http::start_server(
+            static_dir.clone(),
+            &mod_name,
+            cmd.bind.clone(),
+            cmd.port,
+            None,
+        )?;
+        return Ok(0);
+    }
+
+    // Serve in the background, and regenerate the documentation on changes.
+    // Connected browsers are told to reload after each successful generation.
+    let reload = moonbuild::doc_http::ReloadHandle::new();
+    {
+        let reload = reload.clone();
+        let static_dir = static_dir.clone();
+        let bind = cmd.bind.clone();
+        let port = cmd.port;
+        std::thread::spawn(move || {
+            if let Err(e) =
+                moonbuild::doc_http::start_server(static_dir, &mod_name, bind, port, Some(reload))
+            {
+                eprintln!("failed to start the doc server: {e:?}");
+                std::process::exit(-1);
+            }
+        });
+    }
+    watching(
+        |_| {
+            let code = generate()?;
+            if code == 0 {
+                reload.reload();
+            }
+            Ok(code)
+        },
+        &cmd.watch_flags,
+        &source_dir,
+        &target_dir,
+        &target_dir,
+    )
+}
+
+/// Export the documentation from the markdown pages generated by `moondoc`.
+#[instrument(skip_all)]
+fn run_doc_export(
+    cli: &UniversalFlags,
+    cmd: &DocSubcommand,
+    format: DocFormat,
+    out_dir: &Path,
+    source_dir: &Path,
+    target_dir: &Path,
+) -> anyhow::Result<i32> {
+    // `--format` conflicts with `--serve`, so `moondoc` writes markdown pages
+    let code = generate_doc(cli, cmd, source_dir, target_dir)?;
+    if code != 0 || cli.dry_run {
+        return Ok(code);
+    }
+
+    let mod_desc = read_module_desc_file_in_dir(source_dir)?;
+    let docs = moonbuild::doc_export::collect_module_docs(&mod_desc.name, &target_dir.join("doc"))?;
+    moonbuild::doc_export::write_module_docs(&docs, format, out_dir)?;
+    Ok(0)
+}
+
+/// Build the search index served by `moon doc --serve` into `static_dir`.
+///
+/// In serve mode `moondoc` writes the web UI instead of markdown pages, so it
+/// runs once more without `-serve-mode` to produce the pages the index is
+/// read from.
+fn generate_search_index(
+    cli: &UniversalFlags,
+    mod_name: &str,
+    source_dir: &Path,
+    target_dir: &Path,
+    static_dir: &Path,
+) -> anyhow::Result<()> {
+    let pages_dir = target_dir.join(DOC_PAGES_DIR);
+    let args = [
+        source_dir.display().to_string(),
+        "-o".to_string(),
+        pages_dir.display().to_string(),
+        "-std-path".to_string(),
+        moonutil::moon_dir::core_bundle(TargetBackend::default())
+            .display()
+            .to_string(),
+        "-packages-json".to_string(),
+        target_dir.join("packages.json").display().to_string(),
+    ];
+    if cli.dry_run {
+        println!("moondoc {}", args.join(" "));
+        return Ok(());
+    }
+
+    if pages_dir.exists() {
+        pages_dir.rm_rf();
+    }
+    let output = std::process::Command::new("moondoc").args(&args).output()?;
+    if !output.status.success() {
+        eprintln!("{}", String::from_utf8_lossy(&output.stderr));
+        bail!("failed to generate the search index");
+    }
+    moonbuild::doc_export::write_search_index(mod_name, &pages_dir, static_dir)
+}
+
+fn generate_doc(
+    cli: &UniversalFlags,
+    cmd: &DocSubcommand,
+    source_dir: &Path,
+    target_dir: &Path,
+) -> anyhow::Result<i32> {
     if cli.unstable_feature.rupes_recta {
-        run_doc_rr(cli, cmd)
+        generate_doc_rr(cli, cmd, source_dir, target_dir)
     } else {
-        run_doc_legacy(cli, cmd)
+        generate_doc_legacy(cli, cmd, source_dir, target_dir)
     }
 }
 
 #[instrument(skip_all)]
-pub fn run_doc_rr(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result<i32> {
-    let dir = cli.source_tgt_dir.try_into_package_dirs()?;
-    let source_dir = dir.source_dir;
-    let target_dir = dir.target_dir;
-
+fn generate_doc_rr(
+    cli: &UniversalFlags,
+    cmd: &DocSubcommand,
+    source_dir: &Path,
+    target_dir: &Path,
+) -> anyhow::Result<i32> {
     // FIXME: This is copied from `moon check`'s code
     let mut preconfig = preconfig_compile(
         &cmd.auto_sync_flags,
-        &cli,
+        cli,
         &BuildFlags::default(),
-        &target_dir,
+        target_dir,
         moonutil::cond_expr::OptLevel::Release,
         RunMode::Check,
     );
@@ -83,8 +248,8 @@ pub fn run_doc_rr(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result<i32
     let (_build_meta, build_graph) = rr_build::plan_build(
         preconfig,
         &cli.unstable_feature,
-        &source_dir,
-        &target_dir,
+        source_dir,
+        target_dir,
         // Docs are global
         Box::new(|_, _| Ok(vec![UserIntent::Docs].into())),
     )?;
@@ -94,28 +259,25 @@ pub fn run_doc_rr(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result<i32
         rr_build::print_dry_run(
             &build_graph,
             _build_meta.artifacts.values(),
-            &source_dir,
-            &target_dir,
+            source_dir,
+            target_dir,
         );
         return Ok(0);
     }
 
     // Generate metadata for `moondoc`
-    let _lock = FileLock::lock(&target_dir)?;
-    rr_build::generate_metadata(&source_dir, &target_dir, &_build_meta)?;
+    let _lock = FileLock::lock(target_dir)?;
+    rr_build::generate_metadata(source_dir, target_dir, &_build_meta)?;
 
     // Execute the build
     let cfg = BuildConfig::from_flags(&BuildFlags::default(), &cli.unstable_feature);
-    let result = rr_build::execute_build(&cfg, build_graph, &target_dir)?;
+    let result = rr_build::execute_build(&cfg, build_graph, target_dir)?;
     result.print_info(cli.quiet, "checking")?;
 
     if !result.successful() {
         return Ok(result.return_code_for_success());
     }
 
-    // Release lock before serving (no writes beyond this point)
-    drop(_lock);
-    // Serve
     if cmd.serve {
         let static_dir = target_dir.join("doc");
         if !static_dir.exists() {
@@ -124,26 +286,18 @@ pub fn run_doc_rr(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result<i32
                 static_dir.display()
             );
         }
-        let mid = _build_meta.resolve_output.local_modules()[0];
-        let full_name = _build_meta
-            .resolve_output
-            .module_rel
-            .mod_name_from_id(mid)
-            .name()
-            .to_string();
-        moonbuild::doc_http::start_server(static_dir, &full_name, cmd.bind, cmd.port)?;
     }
 
     Ok(0)
 }
 
 #[instrument(skip_all)]
-pub fn run_doc_legacy(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result<i32> {
-    let PackageDirs {
-        source_dir,
-        target_dir,
-    } = cli.source_tgt_dir.try_into_package_dirs()?;
-
+fn generate_doc_legacy(
+    cli: &UniversalFlags,
+    cmd: &DocSubcommand,
+    source_dir: &Path,
+    target_dir: &Path,
+) -> anyhow::Result<i32> {
     let static_dir = target_dir.join("doc");
     if !static_dir.exists() {
         std::fs::create_dir_all(&static_dir)?;
@@ -154,10 +308,8 @@ pub fn run_doc_legacy(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result
         static_dir.rm_rf();
     }
     let serve = cmd.serve;
-    let bind = cmd.bind;
-    let port = cmd.port;
 
-    let mod_desc = read_module_desc_file_in_dir(&source_dir)?;
+    let mod_desc = read_module_desc_file_in_dir(source_dir)?;
 
     let mut moonc_opt = MooncOpt::default();
     if mod_desc.name == MOONBITLANG_CORE {
@@ -165,7 +317,7 @@ pub fn run_doc_legacy(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result
     }
 
     let (resolved_env, dir_sync_result) = auto_sync(
-        &source_dir,
+        source_dir,
         &cmd.auto_sync_flags,
         &RegistryConfig::load(),
         cli.quiet,
@@ -173,9 +325,9 @@ pub fn run_doc_legacy(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result
 
     let run_mode = RunMode::Check;
     let raw_target_dir = target_dir.to_path_buf();
-    let target_dir = mk_arch_mode_dir(&source_dir, &target_dir, &moonc_opt, run_mode)?;
+    let target_dir = mk_arch_mode_dir(source_dir, target_dir, &moonc_opt, run_mode)?;
     let moonbuild_opt = MoonbuildOpt {
-        source_dir: source_dir.clone(),
+        source_dir: source_dir.to_path_buf(),
         raw_target_dir,
         target_dir,
         sort_input: true,
@@ -234,9 +386,5 @@ pub fn run_doc_legacy(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result
         eprintln!("{}", String::from_utf8_lossy(&output.stderr));
         bail!("failed to generate documentation");
     }
-
-    if serve {
-        moonbuild::doc_http::start_server(static_dir, &mod_desc.name, bind, port)?;
-    }
     Ok(0)
 }
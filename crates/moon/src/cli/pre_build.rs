@@ -44,7 +44,13 @@ pub fn scan_with_x_build(
         moonc_opt,
         moonbuild_opt,
     )?;
-    run_prebuild_config(dir_sync_result, resolved_env, &mut module)?;
+    run_prebuild_config(
+        dir_sync_result,
+        resolved_env,
+        &mut module,
+        moonc_opt.link_opt.target_backend,
+        moonc_opt.target_triple.as_deref(),
+    )?;
     match build_type {
         PrePostBuild::PreBuild => {
             if !module.contain_pre_build() {
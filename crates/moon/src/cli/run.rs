@@ -28,7 +28,9 @@ use moonutil::common::FileLock;
 use moonutil::common::MOON_PKG_JSON;
 use moonutil::common::MOONBITLANG_CORE;
 use moonutil::common::PrePostBuild;
+use moonutil::common::ProfileFlags;
 use moonutil::common::RunMode;
+use moonutil::common::SandboxFlags;
 use moonutil::common::SurfaceTarget;
 use moonutil::common::TargetBackend;
 use moonutil::common::TestArtifacts;
@@ -43,6 +45,7 @@ use moonutil::mooncakes::DirSyncResult;
 use moonutil::mooncakes::RegistryConfig;
 use moonutil::mooncakes::result::ResolvedEnv;
 use moonutil::mooncakes::sync::AutoSyncFlags;
+use moonutil::package::JsRuntime;
 use n2::trace;
 use tracing::{Level, instrument};
 
@@ -73,10 +76,36 @@ pub struct RunSubcommand {
     /// Only build, do not run the code
     #[clap(long)]
     pub build_only: bool,
+
+    #[clap(flatten)]
+    pub sandbox_flags: SandboxFlags,
+
+    /// Wait for a DevTools debugger to attach to `moonrun` and pause before
+    /// the program starts (wasm and wasm-gc backends only). Implies `--debug`
+    #[clap(long, conflicts_with_all = ["release", "build_only", "profile"])]
+    pub debugger: bool,
+
+    #[clap(flatten)]
+    pub profile_flags: ProfileFlags,
+}
+
+impl RunSubcommand {
+    fn moonrun_args(&self) -> Vec<String> {
+        let mut args = self.sandbox_flags.to_moonrun_args();
+        if self.debugger {
+            args.push(crate::run::MOONRUN_DEBUGGER_ARG.to_string());
+        }
+        args
+    }
 }
 
 #[instrument(skip_all)]
-pub fn run_run(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Result<i32> {
+pub fn run_run(cli: &UniversalFlags, mut cmd: RunSubcommand) -> anyhow::Result<i32> {
+    if cmd.debugger {
+        // Source maps are only emitted in debug builds
+        cmd.build_flags.debug = true;
+    }
+
     if let Some(surface_targets) = &cmd.build_flags.target {
         for st in surface_targets.iter() {
             if *st == SurfaceTarget::All {
@@ -102,6 +131,12 @@ pub fn run_run(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Result<i32>
 
 #[instrument(level = Level::DEBUG, skip_all)]
 fn run_single_mbt_file(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Result<i32> {
+    if cmd.build_flags.triple.is_some() {
+        bail!("`--triple` is not supported for single files");
+    }
+    if !cmd.build_flags.features.is_empty() {
+        bail!("`--features` is not supported for single files");
+    }
     let current_dir = std::env::current_dir()?;
     let mbt_file_path = dunce::canonicalize(current_dir.join(cmd.package_or_mbt_file))?;
     let mbt_file_parent_path = mbt_file_path.parent().unwrap();
@@ -302,10 +337,21 @@ fn run_single_mbt_file(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Resu
         return Ok(0);
     }
 
+    if cmd.debugger {
+        crate::run::check_debugger_backend(target_backend)?;
+    }
+    if cmd.profile_flags.profile {
+        crate::run::check_profile_backend(target_backend)?;
+    }
+    let mut moonrun_args = cmd.moonrun_args();
+    moonrun_args.extend(cmd.profile_flags.to_moonrun_args(&output_wasm_or_js_path));
     trace::scope("run", || match target_backend {
-        TargetBackend::Wasm | TargetBackend::WasmGC => {
-            moonbuild::build::run_wat(&output_wasm_or_js_path, &cmd.args, cli.verbose)
-        }
+        TargetBackend::Wasm | TargetBackend::WasmGC => moonbuild::build::run_wat(
+            &output_wasm_or_js_path,
+            &moonrun_args,
+            &cmd.args,
+            cli.verbose,
+        ),
         TargetBackend::Js => {
             moonbuild::build::run_js(&output_wasm_or_js_path, &cmd.args, cli.verbose)
         }
@@ -357,6 +403,12 @@ fn run_run_rr(cli: &UniversalFlags, cmd: RunSubcommand) -> Result<i32, anyhow::E
         &target_dir,
         Box::new(|r, m| calc_user_intent(&input_path, r, m)),
     )?;
+    if cmd.debugger {
+        crate::run::check_debugger_backend(build_meta.target_backend)?;
+    }
+    if cmd.profile_flags.profile {
+        crate::run::check_profile_backend(build_meta.target_backend)?;
+    }
     if cli.dry_run {
         // Print build commands
         rr_build::print_dry_run(
@@ -366,7 +418,12 @@ fn run_run_rr(cli: &UniversalFlags, cmd: RunSubcommand) -> Result<i32, anyhow::E
             &target_dir,
         );
 
-        let run_cmd = get_run_cmd(&build_meta, &cmd.args)?;
+        let run_cmd = get_run_cmd(
+            &build_meta,
+            &cmd.moonrun_args(),
+            &cmd.profile_flags,
+            &cmd.args,
+        )?;
         rr_build::dry_print_command(run_cmd.command.as_std());
 
         Ok(0)
@@ -383,7 +440,12 @@ fn run_run_rr(cli: &UniversalFlags, cmd: RunSubcommand) -> Result<i32, anyhow::E
             return Ok(build_result.return_code_for_success());
         }
 
-        let cmd = get_run_cmd(&build_meta, &cmd.args)?;
+        let cmd = get_run_cmd(
+            &build_meta,
+            &cmd.moonrun_args(),
+            &cmd.profile_flags,
+            &cmd.args,
+        )?;
 
         // FIXME: Simplify this part
         let res = default_rt()
@@ -402,6 +464,8 @@ fn run_run_rr(cli: &UniversalFlags, cmd: RunSubcommand) -> Result<i32, anyhow::E
 #[instrument(level = Level::DEBUG, skip_all)]
 fn get_run_cmd(
     build_meta: &rr_build::BuildMeta,
+    moonrun_args: &[String],
+    profile: &ProfileFlags,
     argv: &[String],
 ) -> Result<CommandGuard, anyhow::Error> {
     let (_, artifact) = build_meta
@@ -412,7 +476,19 @@ fn get_run_cmd(
         .artifacts
         .first()
         .expect("Expected exactly one executable as the output of the build node");
-    let mut cmd = crate::run::command_for(build_meta.target_backend, executable, None)?;
+    let mut cmd = match build_meta.runner() {
+        Some(runner) => {
+            crate::run::command_for_runner(runner, build_meta.target_backend, executable, None)?
+        }
+        None => crate::run::command_for(
+            build_meta.target_backend,
+            executable,
+            None,
+            moonrun_args,
+            profile,
+            JsRuntime::Node,
+        )?,
+    };
     cmd.command.args(argv);
     Ok(cmd)
 }
@@ -657,12 +733,20 @@ fn run_run_internal_legacy(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::
     if trace_flag {
         trace::open("trace.json").context("failed to open `trace.json`")?;
     }
+    if cmd.debugger {
+        crate::run::check_debugger_backend(moonc_opt.link_opt.target_backend)?;
+    }
+    if cmd.profile_flags.profile {
+        crate::run::check_profile_backend(moonc_opt.link_opt.target_backend)?;
+    }
     let result = entry::run_run(
         &package_path,
         &moonc_opt,
         &moonbuild_opt,
         &module,
         cmd.build_only,
+        &cmd.moonrun_args(),
+        &cmd.profile_flags,
     );
     if trace_flag {
         trace::close();
@@ -0,0 +1,48 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+use moonutil::cli::UniversalFlags;
+use moonutil::dirs::PackageDirs;
+
+use crate::server;
+
+/// Start a build server that keeps the resolved project in memory between
+/// builds
+///
+/// This is an unstable feature, only available on Unix-like systems. Pass `-Z
+/// build_server` to `moon build`, `moon check` and `moon test` to forward them
+/// to the server running for the project.
+#[derive(Debug, clap::Parser, Clone)]
+pub struct ServerSubcommand {
+    /// Stop the build server running for the current project
+    #[clap(long)]
+    pub stop: bool,
+}
+
+pub fn run_build_server(cli: &UniversalFlags, cmd: &ServerSubcommand) -> anyhow::Result<i32> {
+    let PackageDirs {
+        source_dir,
+        target_dir,
+    } = cli.source_tgt_dir.try_into_package_dirs()?;
+
+    if cmd.stop {
+        server::stop_server(&target_dir)
+    } else {
+        server::run_server(&source_dir, &target_dir)
+    }
+}
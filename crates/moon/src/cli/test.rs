@@ -28,22 +28,25 @@ use moonbuild_rupes_recta::intent::UserIntent;
 use moonbuild_rupes_recta::model::BuildPlanNode;
 use moonbuild_rupes_recta::model::BuildTarget;
 use moonbuild_rupes_recta::model::PackageId;
+use moonbuild_rupes_recta::model::TargetKind;
 use mooncake::pkg::sync::auto_sync;
 use mooncake::pkg::sync::auto_sync_for_single_mbt_md;
 use moonutil::common::PrePostBuild;
 use moonutil::common::{BLACKBOX_TEST_DRIVER, DOT_MBT_DOT_MD, SINGLE_FILE_TEST_PACKAGE};
 use moonutil::common::{
     FileLock, GeneratedTestDriver, MOONBITLANG_CORE, MbtMdHeader, MoonbuildOpt, MooncOpt,
-    OutputFormat, RunMode, TargetBackend, TestOpt, lower_surface_targets,
-    parse_front_matter_config,
+    OutputFormat, ProfileFlags, RunMode, SandboxFlags, TargetBackend, TestOpt,
+    lower_surface_targets, parse_front_matter_config,
 };
+use moonutil::compiler_flags::{Sanitizer, validate_sanitizers};
 use moonutil::cond_expr::CompileCondition;
 use moonutil::cond_expr::OptLevel;
 use moonutil::dirs::mk_arch_mode_dir;
+use moonutil::limits;
 use moonutil::module::ModuleDB;
 use moonutil::mooncakes::RegistryConfig;
 use moonutil::mooncakes::sync::AutoSyncFlags;
-use moonutil::package::Package;
+use moonutil::package::{Package, TestLimits};
 use moonutil::path::PathComponent;
 use n2::trace;
 use smallvec::SmallVec;
@@ -60,9 +63,10 @@ use crate::rr_build::{BuildConfig, CalcUserIntentOutput};
 use crate::run::TestFilter;
 use crate::run::TestIndex;
 use crate::run::perform_promotion;
+use crate::watch::{find_owning_package, propagate_to_dependents, watching};
 
 use super::BenchSubcommand;
-use super::{BuildFlags, UniversalFlags};
+use super::{BuildFlags, UniversalFlags, WatchFlags};
 
 /// Print test summary statistics in the legacy format
 fn print_test_summary(total: usize, passed: usize, quiet: bool, backend_hint: Option<&str>) {
@@ -145,6 +149,54 @@ pub struct TestSubcommand {
     #[clap(long = "doc")]
     pub doc_test: bool,
 
+    /// Monitor the file system and rerun tests of the packages affected by
+    /// the changes
+    #[clap(long, short)]
+    pub watch: bool,
+
+    #[clap(flatten)]
+    pub watch_flags: WatchFlags,
+
+    #[clap(flatten)]
+    pub sandbox_flags: SandboxFlags,
+
+    /// Run the selected test with `moonrun` waiting for a DevTools debugger to
+    /// attach, paused before the test starts (wasm and wasm-gc backends only)
+    #[clap(
+        long,
+        requires = "index",
+        conflicts_with_all = ["release", "build_only", "watch", "update"]
+    )]
+    pub debugger: bool,
+
+    /// Fail a WebAssembly test executable whose memory use exceeds this size,
+    /// e.g. `512M`. Overrides `test-limits` in moon.pkg.json
+    #[clap(
+        long,
+        value_name = "SIZE",
+        value_parser = |s: &str| limits::parse_size(s).map(|_| s.to_string())
+    )]
+    pub max_heap: Option<String>,
+
+    /// Fail a WebAssembly test executable running longer than this, e.g.
+    /// `30s`. Overrides `test-limits` in moon.pkg.json
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = |s: &str| limits::parse_duration(s).map(|_| s.to_string())
+    )]
+    pub max_wall_time: Option<String>,
+
+    /// Build the native and llvm test executables, including C stubs, with
+    /// the given comma-separated sanitizers (gcc and clang only)
+    #[clap(long, value_enum, value_delimiter = ',', value_name = "SANITIZERS")]
+    pub sanitize: Vec<Sanitizer>,
+
+    /// Run each native and llvm test executable through this command, e.g.
+    /// `valgrind --error-exitcode=1`
+    #[clap(long, value_name = "COMMAND", conflicts_with = "debugger")]
+    pub runner: Option<String>,
+
     /// Run test in single file or directory. If in a project, runs only this
     /// package (if matches a package path) or file (if matches a file in
     /// package); otherwise, runs in a temporary project.
@@ -152,8 +204,38 @@ pub struct TestSubcommand {
     pub single_file: Option<PathBuf>,
 }
 
+impl TestSubcommand {
+    fn moonrun_args(&self) -> Vec<String> {
+        let mut args = self.sandbox_flags.to_moonrun_args();
+        if self.debugger {
+            args.push(crate::run::MOONRUN_DEBUGGER_ARG.to_string());
+        }
+        args
+    }
+
+    fn test_limits(&self) -> TestLimits {
+        TestLimits {
+            max_heap: self.max_heap.clone(),
+            max_wall_time: self.max_wall_time.clone(),
+        }
+    }
+}
+
+/// Split the `--runner` command into the program and its arguments
+fn parse_runner(runner: Option<&str>) -> anyhow::Result<Vec<String>> {
+    let Some(runner) = runner else {
+        return Ok(vec![]);
+    };
+    match shlex::split(runner) {
+        Some(args) if !args.is_empty() => Ok(args),
+        _ => bail!("invalid `--runner` command: `{runner}`"),
+    }
+}
+
 #[instrument(skip_all)]
 pub fn run_test(cli: UniversalFlags, cmd: TestSubcommand) -> anyhow::Result<i32> {
+    validate_sanitizers(&cmd.sanitize)?;
+
     // Check if we're running within a project
     let dirs = match cli.source_tgt_dir.try_into_package_dirs() {
         Ok(dirs) => dirs,
@@ -211,17 +293,27 @@ fn run_test_internal(
     target_dir: &Path,
     display_backend_hint: Option<()>,
 ) -> anyhow::Result<i32> {
-    run_test_or_bench_internal(
-        cli,
-        cmd.into(),
-        source_dir,
-        target_dir,
-        display_backend_hint,
-    )
+    let f = |changed_files: Option<&[PathBuf]>| {
+        let mut test_cmd = TestLikeSubcommand::from(cmd);
+        test_cmd.changed_files = changed_files;
+        run_test_or_bench_internal(cli, test_cmd, source_dir, target_dir, display_backend_hint)
+    };
+
+    if cmd.watch {
+        watching(f, &cmd.watch_flags, source_dir, target_dir, target_dir)
+    } else {
+        f(None)
+    }
 }
 
 #[instrument(level = Level::DEBUG, skip_all)]
 fn run_test_in_single_file(cli: &UniversalFlags, cmd: &TestSubcommand) -> anyhow::Result<i32> {
+    if cmd.build_flags.triple.is_some() {
+        bail!("`--triple` is not supported for single files");
+    }
+    if !cmd.build_flags.features.is_empty() {
+        bail!("`--features` is not supported for single files");
+    }
     let single_file_path = &dunce::canonicalize(cmd.single_file.as_ref().unwrap()).unwrap();
     let source_dir = single_file_path.parent().unwrap().to_path_buf();
     let raw_target_dir = source_dir.join("target");
@@ -243,6 +335,9 @@ fn run_test_in_single_file(cli: &UniversalFlags, cmd: &TestSubcommand) -> anyhow
     };
 
     let debug_flag = !cmd.build_flags.release;
+    if cmd.debugger {
+        crate::run::check_debugger_backend(target_backend)?;
+    }
 
     let target_dir = raw_target_dir
         .join(target_backend.to_dir_name())
@@ -262,6 +357,12 @@ fn run_test_in_single_file(cli: &UniversalFlags, cmd: &TestSubcommand) -> anyhow
             test_failure_json: false,
             display_backend_hint: None,
             patch_file: None,
+            moonrun_args: cmd.moonrun_args(),
+            test_limits: cmd.test_limits(),
+            profile: ProfileFlags::default(),
+            sanitizers: cmd.sanitize.clone(),
+            runner: parse_runner(cmd.runner.as_deref())?,
+            backend_runner: None,
         }),
         check_opt: None,
         build_opt: None,
@@ -307,6 +408,10 @@ fn run_test_in_single_file(cli: &UniversalFlags, cmd: &TestSubcommand) -> anyhow
         nostd: false,
         render: !cmd.build_flags.no_render,
         single_file: true,
+        target_triple: None,
+        cross_cc: None,
+        profile: None,
+        features: HashMap::new(),
     };
     let module =
         get_module_for_single_file(single_file_path, &moonc_opt, &moonbuild_opt, mbt_md_header)?;
@@ -396,6 +501,8 @@ pub fn get_module_for_single_file(
             link_flags: None,
             link_libs: vec![],
             link_search_paths: vec![],
+            test_limits: None,
+            js_test_runtime: None,
         }
     };
 
@@ -469,6 +576,23 @@ pub(crate) struct TestLikeSubcommand<'a> {
     pub no_parallelize: bool,
     pub test_failure_json: bool,
     pub patch_file: &'a Option<PathBuf>,
+    /// The files changed since the last run in watch mode.
+    ///
+    /// If present and no other package filter is given, only the packages
+    /// containing these files and their dependents are tested.
+    pub changed_files: Option<&'a [PathBuf]>,
+    /// Extra arguments passed to `moonrun` when running the tests
+    pub moonrun_args: Vec<String>,
+    /// Resource limits overriding those of each package
+    pub test_limits: TestLimits,
+    /// Whether the test is run under the debugger
+    pub debugger: bool,
+    /// Whether to record CPU profiles of the executables run
+    pub profile: ProfileFlags,
+    /// Sanitizers to build the native and llvm test executables with
+    pub sanitizers: &'a [Sanitizer],
+    /// Command to run the native and llvm test executables through
+    pub runner: Option<&'a str>,
 }
 
 impl<'a> From<&'a TestSubcommand> for TestLikeSubcommand<'a> {
@@ -488,6 +612,13 @@ impl<'a> From<&'a TestSubcommand> for TestLikeSubcommand<'a> {
             no_parallelize: cmd.no_parallelize,
             test_failure_json: cmd.test_failure_json,
             patch_file: &cmd.patch_file,
+            changed_files: None,
+            moonrun_args: cmd.moonrun_args(),
+            test_limits: cmd.test_limits(),
+            debugger: cmd.debugger,
+            profile: ProfileFlags::default(),
+            sanitizers: &cmd.sanitize,
+            runner: cmd.runner.as_deref(),
         }
     }
 }
@@ -508,6 +639,13 @@ impl<'a> From<&'a BenchSubcommand> for TestLikeSubcommand<'a> {
             no_parallelize: cmd.no_parallelize,
             test_failure_json: false,
             patch_file: &None,
+            changed_files: None,
+            moonrun_args: vec![],
+            test_limits: TestLimits::default(),
+            debugger: false,
+            profile: cmd.profile_flags.clone(),
+            sanitizers: &[],
+            runner: None,
         }
     }
 }
@@ -558,7 +696,7 @@ fn run_test_rr(
     } else {
         OptLevel::Debug
     };
-    let preconfig = preconfig_compile(
+    let mut preconfig = preconfig_compile(
         cmd.auto_sync_flags,
         cli,
         cmd.build_flags,
@@ -566,6 +704,8 @@ fn run_test_rr(
         default_opt_level,
         RunMode::Test,
     );
+    preconfig.sanitizers = cmd.sanitizers.to_vec();
+    let runner = parse_runner(cmd.runner)?;
 
     let mut filter = TestFilter::default();
     let (build_meta, build_graph) = rr_build::plan_build(
@@ -577,6 +717,12 @@ fn run_test_rr(
             calc_user_intent(resolved, main_modules, cmd, &mut filter)
         }),
     )?;
+    if cmd.debugger {
+        crate::run::check_debugger_backend(build_meta.target_backend)?;
+    }
+    if cmd.profile.profile {
+        crate::run::check_profile_backend(build_meta.target_backend)?;
+    }
 
     if cli.dry_run {
         rr_build::print_dry_run(
@@ -601,7 +747,16 @@ fn run_test_rr(
             return Ok(result.return_code_for_success());
         }
 
-        let mut test_result = crate::run::run_tests(&build_meta, target_dir, &filter)?;
+        let mut test_result = crate::run::run_tests(
+            &build_meta,
+            target_dir,
+            &filter,
+            &cmd.moonrun_args,
+            &cmd.test_limits,
+            &cmd.profile,
+            &runner,
+            !cmd.sanitizers.is_empty(),
+        )?;
 
         let backend_hint = display_backend_hint
             .and(cmd.build_flags.target_backend)
@@ -678,8 +833,16 @@ fn run_test_rr(
                 let rerun_filter = TestFilter {
                     filter: Some(rerun_filter),
                 };
-                let new_test_result =
-                    crate::run::run_tests(&build_meta, target_dir, &rerun_filter)?;
+                let new_test_result = crate::run::run_tests(
+                    &build_meta,
+                    target_dir,
+                    &rerun_filter,
+                    &cmd.moonrun_args,
+                    &cmd.test_limits,
+                    &cmd.profile,
+                    &runner,
+                    !cmd.sanitizers.is_empty(),
+                )?;
 
                 // Merge test results
                 test_result.merge(&new_test_result);
@@ -909,8 +1072,15 @@ fn calc_user_intent(
             out_filter,
         )?
     } else {
-        // No filter: emit one intent per package (Test/Bench)
-        let intents: Vec<_> = affected_packages.map(UserIntent::Test).collect();
+        // No filter: emit one intent per package (Test/Bench), limited to the
+        // ones affected by the changes in watch mode
+        let changed = cmd.changed_files.and_then(|files| {
+            packages_affected_by_changes(resolve_output, affected_packages.clone(), files)
+        });
+        let intents: Vec<_> = affected_packages
+            .filter(|p| changed.as_ref().is_none_or(|c| c.contains(p)))
+            .map(UserIntent::Test)
+            .collect();
         return Ok(intents.into());
     };
 
@@ -928,6 +1098,39 @@ fn calc_user_intent(
     Ok((intents, directive).into())
 }
 
+/// Calculate the packages affected by the files changed in watch mode, i.e.
+/// the packages containing these files and all packages depending on them.
+///
+/// Returns `None` if any of the files is not inside one of `packages`, in
+/// which case everything should be rerun.
+fn packages_affected_by_changes(
+    resolve_output: &moonbuild_rupes_recta::ResolveOutput,
+    packages: impl Iterator<Item = PackageId> + Clone,
+    changed_files: &[PathBuf],
+) -> Option<HashSet<PackageId>> {
+    let mut affected = HashSet::new();
+    for file in changed_files {
+        let roots = packages.clone().map(|id| {
+            let pkg = resolve_output.pkg_dirs.get_package(id);
+            (id, pkg.root_path.as_path())
+        });
+        affected.insert(find_owning_package(file, roots)?);
+    }
+
+    // Propagate to all dependents. Only source targets can be imported.
+    let dep_graph = &resolve_output.pkg_rel.dep_graph;
+    propagate_to_dependents(&mut affected, |pkg| {
+        [TargetKind::Source, TargetKind::SubPackage]
+            .into_iter()
+            .flat_map(|kind| {
+                dep_graph.neighbors_directed(pkg.build_target(kind), petgraph::Incoming)
+            })
+            .map(|dependent| dependent.package)
+            .collect::<Vec<_>>()
+    });
+    Some(affected)
+}
+
 #[instrument(skip_all)]
 pub(crate) fn run_test_or_bench_internal_legacy(
     cli: &UniversalFlags,
@@ -962,16 +1165,25 @@ pub(crate) fn run_test_or_bench_internal_legacy(
         ..build_flags.clone()
     };
     let mut moonc_opt = super::get_compiler_flags(source_dir, &compiler_flags)?;
-    moonc_opt.build_opt.debug_flag = !build_flags.release;
     moonc_opt.build_opt.enable_value_tracing = build_flags.enable_value_tracing;
-    moonc_opt.build_opt.strip_flag = if build_flags.strip {
-        true
-    } else if build_flags.no_strip {
-        false
-    } else {
-        build_flags.release
-    };
-    moonc_opt.link_opt.debug_flag = !build_flags.release;
+    // A build profile already decides these
+    if build_flags.build_profile.is_none() {
+        moonc_opt.build_opt.debug_flag = !build_flags.release;
+        moonc_opt.build_opt.strip_flag = if build_flags.strip {
+            true
+        } else if build_flags.no_strip {
+            false
+        } else {
+            build_flags.release
+        };
+        moonc_opt.link_opt.debug_flag = !build_flags.release;
+    }
+    if cmd.debugger {
+        crate::run::check_debugger_backend(moonc_opt.link_opt.target_backend)?;
+    }
+    if cmd.profile.profile {
+        crate::run::check_profile_backend(moonc_opt.link_opt.target_backend)?;
+    }
 
     // TODO: remove this once LLVM backend is well supported
     if moonc_opt.build_opt.target_backend == TargetBackend::LLVM {
@@ -1012,6 +1224,10 @@ pub(crate) fn run_test_or_bench_internal_legacy(
     };
     let filter_index = *cmd.index;
     let filter_doc_index = *cmd.doc_index;
+    let runner = parse_runner(cmd.runner)?;
+    let backend_runner = moonutil::common::read_module_desc_file_in_dir(source_dir)?
+        .runner(moonc_opt.link_opt.target_backend)
+        .map(str::to_owned);
 
     let test_opt = if run_mode == RunMode::Bench {
         Some(TestOpt {
@@ -1023,6 +1239,12 @@ pub(crate) fn run_test_or_bench_internal_legacy(
             test_failure_json: false,
             display_backend_hint,
             patch_file: None,
+            moonrun_args: cmd.moonrun_args.clone(),
+            test_limits: cmd.test_limits.clone(),
+            profile: cmd.profile.clone(),
+            sanitizers: cmd.sanitizers.to_vec(),
+            runner,
+            backend_runner,
         })
     } else {
         Some(TestOpt {
@@ -1034,6 +1256,12 @@ pub(crate) fn run_test_or_bench_internal_legacy(
             test_failure_json: cmd.test_failure_json,
             display_backend_hint,
             patch_file: patch_file.clone(),
+            moonrun_args: cmd.moonrun_args.clone(),
+            test_limits: cmd.test_limits.clone(),
+            profile: cmd.profile.clone(),
+            sanitizers: cmd.sanitizers.to_vec(),
+            runner,
+            backend_runner,
         })
     };
     let moonbuild_opt = MoonbuildOpt {
@@ -1197,13 +1425,35 @@ pub(crate) fn run_test_or_bench_internal_legacy(
                 final_set.contains(&pkg.full_name())
             }));
         (package_filter, moonbuild_opt)
+    } else if let Some(affected) = cmd
+        .changed_files
+        .and_then(|files| packages_affected_by_changes_legacy(&module, files))
+    {
+        let moonbuild_opt = MoonbuildOpt {
+            test_opt: Some(TestOpt {
+                filter_package: Some(affected.clone()),
+                ..moonbuild_opt.test_opt.unwrap()
+            }),
+            ..moonbuild_opt
+        };
+
+        let package_filter: Option<Box<dyn for<'a> Fn(&'a _) -> _>> =
+            Some(Box::new(move |pkg: &Package| {
+                affected.contains(&pkg.full_name())
+            }));
+        (package_filter, moonbuild_opt)
     } else {
         (None, moonbuild_opt)
     };
 
     let mut use_tcc_run = moonc_opt.build_opt.debug_flag
         && moonbuild_opt.run_mode == RunMode::Test
-        && moonc_opt.build_opt.target_backend == TargetBackend::Native;
+        && moonc_opt.build_opt.target_backend == TargetBackend::Native
+        // tcc can neither sanitize nor be run through a runner
+        && cmd.sanitizers.is_empty()
+        && cmd.runner.is_none()
+        // nor cross-compile
+        && moonc_opt.target_triple.is_none();
 
     for (_, pkg) in module.get_filtered_packages_mut(package_filter) {
         // do a pre-check to ensure that enabling fast cc mode (using tcc for debug testing)
@@ -1296,6 +1546,42 @@ pub(crate) fn run_test_or_bench_internal_legacy(
     res
 }
 
+/// The legacy counterpart of [`packages_affected_by_changes`], returning the
+/// full names of the affected packages.
+fn packages_affected_by_changes_legacy(
+    module: &ModuleDB,
+    changed_files: &[PathBuf],
+) -> Option<HashSet<String>> {
+    let local_packages = module
+        .get_all_packages()
+        .values()
+        .filter(|pkg| !pkg.is_third_party);
+
+    let mut affected = HashSet::new();
+    for file in changed_files {
+        let roots = local_packages
+            .clone()
+            .map(|pkg| (pkg.full_name(), pkg.root_path.as_path()));
+        affected.insert(find_owning_package(file, roots)?);
+    }
+
+    // Propagate to all dependents
+    propagate_to_dependents(&mut affected, |name| {
+        local_packages
+            .clone()
+            .filter(|pkg| {
+                pkg.imports
+                    .iter()
+                    .chain(&pkg.wbtest_imports)
+                    .chain(&pkg.test_imports)
+                    .any(|import| import.path.make_full_path() == *name)
+            })
+            .map(|pkg| pkg.full_name())
+            .collect::<Vec<_>>()
+    });
+    Some(affected)
+}
+
 #[instrument(level = Level::DEBUG, skip_all)]
 fn do_run_test(
     moonc_opt: MooncOpt,
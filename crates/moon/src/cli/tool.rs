@@ -16,9 +16,13 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
+pub mod build_cache;
+pub mod c_header;
 pub mod embed;
 pub mod format_and_diff;
 
+use build_cache::*;
+use c_header::*;
 use embed::*;
 use format_and_diff::*;
 
@@ -32,11 +36,15 @@ pub struct ToolSubcommand {
 pub enum ToolSubcommands {
     FormatAndDiff(FormatAndDiffSubcommand),
     Embed(Embed),
+    CHeader(CHeader),
+    BuildCache(BuildCache),
 }
 
 pub fn run_tool(cmd: ToolSubcommand) -> anyhow::Result<i32> {
     match cmd.subcommand {
         ToolSubcommands::FormatAndDiff(subcmd) => run_format_and_diff(subcmd),
         ToolSubcommands::Embed(subcmd) => run_embed(subcmd),
+        ToolSubcommands::CHeader(subcmd) => run_c_header(subcmd),
+        ToolSubcommands::BuildCache(subcmd) => run_build_cache(subcmd),
     }
 }
@@ -0,0 +1,52 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+use std::path::PathBuf;
+
+use moonbuild::build_cache::CachedStep;
+
+/// Run a build step through the shared build cache
+#[derive(Debug, clap::Parser)]
+pub struct BuildCache {
+    /// Version of moonc the step is run with
+    #[clap(long)]
+    moonc_version: String,
+    /// Directory whose location does not affect the cache key
+    #[clap(long = "root")]
+    roots: Vec<PathBuf>,
+    /// Input file not mentioned on the command line
+    #[clap(long = "input")]
+    inputs: Vec<PathBuf>,
+    /// Output file of the step
+    #[clap(long = "output")]
+    outputs: Vec<PathBuf>,
+    /// The command of the step
+    #[clap(last = true, required = true)]
+    command: Vec<String>,
+}
+
+pub fn run_build_cache(cmd: BuildCache) -> anyhow::Result<i32> {
+    CachedStep {
+        moonc_version: cmd.moonc_version,
+        roots: cmd.roots,
+        inputs: cmd.inputs,
+        outputs: cmd.outputs,
+        command: cmd.command,
+    }
+    .run()
+}
@@ -0,0 +1,234 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+
+/// Generate a C header declaring the exported functions of a native library
+/// built by moon, for C code linking against it.
+///
+/// The signatures are taken from the interface of the package emitted by the
+/// compiler, and mapped to C types the way the native backend passes them.
+#[derive(Debug, clap::Parser)]
+pub struct CHeader {
+    /// The `.mi` file of the package, emitted by `moonc build-package`
+    #[clap(long)]
+    mi: PathBuf,
+    #[clap(long, short)]
+    output: PathBuf,
+    /// The exported functions, in the format of `exports` in `moon.pkg.json`
+    #[clap(long, value_delimiter = ',')]
+    exports: Vec<String>,
+}
+
+pub fn run_c_header(cmd: CHeader) -> anyhow::Result<i32> {
+    let interface = read_interface(&cmd.mi, &cmd.output.with_extension("h.mbti"))?;
+    let stem = cmd
+        .output
+        .file_stem()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let guard = format!(
+        "MOONBIT_{}_H",
+        stem.to_uppercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    );
+
+    // The runtime and package initializers must be run before calling exports
+    let mut decls = vec!["void moonbit_init(void);".to_string()];
+    for export in &cmd.exports {
+        // `name:alias` exports the function `name` under `alias`, as in
+        // `-exported_functions` of `moonc link-core`
+        let (name, alias) = export.split_once(':').unwrap_or((export, export));
+        let decl = declaration(&interface, name, alias).with_context(|| {
+            format!(
+                "failed to declare exported function `{name}` of `{}`",
+                cmd.mi.display()
+            )
+        })?;
+        decls.push(decl);
+    }
+
+    let content = format!(
+        r#"// Generated by `moon tool c-header`, do not edit.
+#ifndef {guard}
+#define {guard}
+
+#include "moonbit.h"
+
+#ifdef __cplusplus
+extern "C" {{
+#endif
+
+{}
+
+#ifdef __cplusplus
+}}
+#endif
+
+#endif // {guard}
+"#,
+        decls.join("\n")
+    );
+    std::fs::write(&cmd.output, content).context("write output file")?;
+    Ok(0)
+}
+
+/// Render the interface in `mi` as text with `mooninfo`, through `mbti`.
+fn read_interface(mi: &Path, mbti: &Path) -> anyhow::Result<String> {
+    let args = [
+        "-format=text".to_string(),
+        mi.display().to_string(),
+        format!("-o={}", mbti.display()),
+    ];
+    let out = std::process::Command::new("mooninfo")
+        .args(&args)
+        .output()
+        .context("failed to run `mooninfo`")?;
+    if !out.status.success() {
+        eprintln!("{}", String::from_utf8_lossy(&out.stderr));
+        bail!("failed to run `mooninfo {}`", args.join(" "));
+    }
+    std::fs::read_to_string(mbti).with_context(|| format!("failed to read `{}`", mbti.display()))
+}
+
+/// Declare the function `name` of `interface` as the C function `alias`.
+fn declaration(interface: &str, name: &str, alias: &str) -> anyhow::Result<String> {
+    let prefix = format!("fn {name}(");
+    let Some(signature) = interface.lines().find_map(|line| {
+        line.trim_start()
+            .trim_start_matches("pub ")
+            .strip_prefix(&prefix)
+    }) else {
+        bail!("no such function in the package interface");
+    };
+    let Some((params, ret)) = signature.rsplit_once(") -> ") else {
+        bail!("unexpected signature `{signature}`");
+    };
+    let ret = ret.trim();
+    if ret.contains(" raise") || ret.starts_with("raise") {
+        bail!("functions that raise errors cannot be exported");
+    }
+    let params = split_params(params)
+        .into_iter()
+        .map(|param| {
+            // Labelled parameters are written `label : Type`
+            let ty = param.rsplit_once(" : ").map_or(param, |(_, ty)| ty);
+            c_type(ty.trim())
+        })
+        .collect::<Vec<_>>();
+    let params = if params.is_empty() {
+        "void".to_string()
+    } else {
+        params.join(", ")
+    };
+    Ok(format!("{} {alias}({params});", c_type(ret)))
+}
+
+/// Split a parameter list at the commas outside of type arguments.
+fn split_params(params: &str) -> Vec<&str> {
+    let mut result = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in params.char_indices() {
+        match c {
+            '[' | '(' => depth += 1,
+            ']' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                result.push(params[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = params[start..].trim();
+    if !last.is_empty() {
+        result.push(last);
+    }
+    result
+}
+
+/// The C type of a MoonBit type in the native backend. Types without a C
+/// counterpart are passed as opaque pointers.
+fn c_type(ty: &str) -> String {
+    match ty {
+        "Unit" => "void",
+        "Bool" | "Int" | "Char" => "int32_t",
+        "UInt" => "uint32_t",
+        "Int16" => "int16_t",
+        "UInt16" => "uint16_t",
+        "Int64" => "int64_t",
+        "UInt64" => "uint64_t",
+        "Byte" => "uint8_t",
+        "Float" => "float",
+        "Double" => "double",
+        "String" => "moonbit_string_t",
+        "Bytes" | "FixedArray[Byte]" => "moonbit_bytes_t",
+        _ => {
+            return match ty
+                .strip_prefix("FixedArray[")
+                .and_then(|elem| elem.strip_suffix(']'))
+            {
+                Some(elem) => format!("{}*", c_type(elem)),
+                None => "void*".to_string(),
+            };
+        }
+    }
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::declaration;
+
+    #[test]
+    fn test_declaration() {
+        let interface = r#"// Generated using `moon info`, DON'T EDIT IT
+package "username/lib"
+
+// Values
+fn add(Int, Int) -> Int
+
+fn fill(FixedArray[Double], value~ : Double) -> Unit
+
+fn greet(String) -> Bytes
+
+fn parse(String) -> Int raise
+
+// Types and methods
+pub struct Point {
+  x : Int
+}
+"#;
+        assert_eq!(
+            declaration(interface, "add", "add").unwrap(),
+            "int32_t add(int32_t, int32_t);"
+        );
+        assert_eq!(
+            declaration(interface, "fill", "fill_doubles").unwrap(),
+            "void fill_doubles(double*, double);"
+        );
+        assert_eq!(
+            declaration(interface, "greet", "greet").unwrap(),
+            "moonbit_bytes_t greet(moonbit_string_t);"
+        );
+        assert!(declaration(interface, "parse", "parse").is_err());
+        assert!(declaration(interface, "sub", "sub").is_err());
+    }
+}
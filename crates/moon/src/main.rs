@@ -27,9 +27,11 @@ mod cli;
 mod panic;
 pub mod rr_build;
 mod run;
+mod server;
 mod watch;
 
 use colored::*;
+use moonutil::cli::UniversalFlags;
 use tracing_subscriber::{Layer, layer::SubscriberExt, util::SubscriberInitExt};
 
 /// Initialize logging and tracing-related functionality.
@@ -90,8 +92,36 @@ pub fn main() {
 
     let _trace_guard = init_tracing();
 
+    let res = try_forward_to_server(&flags, &cli.subcommand)
+        .unwrap_or_else(|| run_subcommand(flags, cli.subcommand));
+
+    drop(_trace_guard);
+
+    match res {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("{}: {:?}", "error".red().bold(), e);
+            std::process::exit(-1);
+        }
+    }
+}
+
+/// Forward the command to the `moon server` of the project, if `-Z
+/// build_server` is enabled and a server is running.
+fn try_forward_to_server(
+    flags: &UniversalFlags,
+    subcommand: &MoonBuildSubcommands,
+) -> Option<anyhow::Result<i32>> {
+    if !flags.unstable_feature.build_server || !server::is_forwardable(subcommand) {
+        return None;
+    }
+    let dirs = flags.source_tgt_dir.try_into_package_dirs().ok()?;
+    server::forward_to_server(&dirs.target_dir)
+}
+
+fn run_subcommand(flags: UniversalFlags, subcommand: MoonBuildSubcommands) -> anyhow::Result<i32> {
     use MoonBuildSubcommands::*;
-    let res = match cli.subcommand {
+    match subcommand {
         Add(a) => cli::add_cli(flags, a),
         Bench(b) => cli::run_bench(flags, b),
         Build(b) => cli::run_build(&flags, &b),
@@ -113,6 +143,7 @@ pub fn main() {
         Register(r) => cli::mooncake_adapter::register_cli(flags, r),
         Remove(r) => cli::remove_cli(flags, r),
         Run(r) => cli::run_run(&flags, r),
+        Server(s) => cli::run_build_server(&flags, &s),
         Test(t) => cli::run_test(flags, t),
         Tree(t) => cli::tree_cli(flags, t),
         Update(u) => cli::update_cli(flags, u),
@@ -121,15 +152,5 @@ pub fn main() {
         Version(v) => cli::run_version(&flags, v),
         Tool(v) => cli::run_tool(v),
         External(args) => cli::run_external(args),
-    };
-
-    drop(_trace_guard);
-
-    match res {
-        Ok(code) => std::process::exit(code),
-        Err(e) => {
-            eprintln!("{}: {:?}", "error".red().bold(), e);
-            std::process::exit(-1);
-        }
     }
 }
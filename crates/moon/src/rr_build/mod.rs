@@ -28,38 +28,51 @@
 //!   two parts: [``]
 
 use std::{
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
 use anyhow::Context;
 use indexmap::IndexMap;
+use moonbuild::build_cache::{build_cache_enabled, wrap_cacheable_builds};
 use moonbuild::entry::{
     N2RunStats, ResultCatcher, create_progress_console, render_and_catch_callback,
 };
+use moonbuild::explain::{CommandLog, CommandLogProgress, ExplainProgress};
+use moonbuild::timings::{BuildTimings, TimingProgress};
 use moonbuild_rupes_recta::{
-    CompileConfig, ResolveConfig, ResolveOutput,
+    CompileConfig, ResolveOutput,
     build_plan::InputDirective,
+    cond_comp::CfgContext,
     intent::UserIntent,
-    model::{Artifacts, BuildPlanNode, PackageId, TargetKind},
+    model::{Artifacts, BuildPlanNode, OperatingSystem, PackageId, TargetKind},
     prebuild::run_prebuild_config,
 };
 use moonutil::{
+    build_script::BuildInfo,
     cli::UniversalFlags,
     common::{
-        BLACKBOX_TEST_PATCH, DiagnosticLevel, MOONBITLANG_CORE, RunMode, TargetBackend,
-        WHITEBOX_TEST_PATCH,
+        BLACKBOX_TEST_PATCH, DiagnosticLevel, ExplainFormat, MOONBITLANG_CORE, RunMode,
+        TargetBackend, WHITEBOX_TEST_PATCH,
     },
+    compiler_flags::Sanitizer,
     cond_expr::OptLevel,
     features::FeatureGate,
+    module_features::resolve_features,
     mooncakes::{ModuleId, sync::AutoSyncFlags},
+    profile::{Profile, resolve_profile},
 };
 use tracing::{Level, instrument};
 
 use crate::cli::BuildFlags;
 
 mod dry_run;
+mod resolve_cache;
 pub use dry_run::{dry_print_command, print_dry_run, print_dry_run_all};
+pub use resolve_cache::{
+    affects_resolve, enable_resolve_cache, invalidate_resolve_cache, resolve_cached,
+};
 
 /// The function that calculates the user intent for the build process.
 ///
@@ -135,7 +148,7 @@ pub fn build_patch_directive_for_package(
 /// The build graph is kept separate to allow execute_build to take ownership of it.
 pub struct BuildMeta {
     /// The result of the resolve step, containing package metadata
-    pub resolve_output: ResolveOutput,
+    pub resolve_output: Arc<ResolveOutput>,
 
     /// The list of artifacts that will be produced
     pub artifacts: IndexMap<BuildPlanNode, Artifacts>,
@@ -145,6 +158,22 @@ pub struct BuildMeta {
 
     /// The main optimization level used in this compile process
     pub opt_level: OptLevel,
+
+    /// The target platform and module features used for conditional
+    /// compilation
+    pub cfg: CfgContext,
+}
+
+impl BuildMeta {
+    /// The command in `runners` of the main module for the target backend,
+    /// replacing the default runtime when running executables.
+    pub fn runner(&self) -> Option<&str> {
+        let main_module = *self.resolve_output.local_modules().first()?;
+        self.resolve_output
+            .module_rel
+            .module_info(main_module)
+            .runner(self.target_backend)
+    }
 }
 
 /// Represents the result of the build process
@@ -203,8 +232,15 @@ pub struct CompilePreConfig {
     pub deny_warn: bool,
     /// Whether to not emit alias when running `mooninfo`
     pub info_no_alias: bool,
+    /// Sanitizers to build native executables and C stubs with
+    pub sanitizers: Vec<Sanitizer>,
+    target_triple: Option<String>,
     warn_list: Option<String>,
     alert_list: Option<String>,
+    /// The name of the build profile, resolved against `moon.mod.json`
+    profile: Option<String>,
+    /// The features of the main module selected with `--features`
+    features: Vec<String>,
 }
 
 impl CompilePreConfig {
@@ -212,19 +248,32 @@ impl CompilePreConfig {
         self,
         preferred_backend: Option<TargetBackend>,
         is_core: bool,
-    ) -> CompileConfig {
+        profile: Option<Profile>,
+        features: HashMap<ModuleId, HashSet<String>>,
+    ) -> anyhow::Result<CompileConfig> {
         let std = self.use_std && !is_core;
         let target_backend = self
             .target_backend
             .or(preferred_backend)
             .unwrap_or_default();
-
-        CompileConfig {
+        let (opt_level, debug_symbols) = match &profile {
+            Some(profile) => (profile.config.inherits.opt_level(), !profile.config.strip()),
+            None => (self.opt_level, self.debug_symbols),
+        };
+        let cross_cc = self
+            .target_triple
+            .as_deref()
+            .map(|triple| crate::cli::cross_compiler(target_backend, triple))
+            .transpose()?;
+        let target = BuildInfo::new(target_backend, self.target_triple.as_deref())?.target;
+
+        Ok(CompileConfig {
             target_dir: self.target_dir,
             target_backend,
-            opt_level: self.opt_level,
+            opt_level,
             action: self.action,
-            debug_symbols: self.debug_symbols,
+            debug_symbols,
+            profile,
             stdlib_path: if std {
                 Some(moonutil::moon_dir::core())
             } else {
@@ -239,7 +288,11 @@ impl CompilePreConfig {
             warn_list: self.warn_list,
             alert_list: self.alert_list,
             info_no_alias: self.info_no_alias,
-        }
+            sanitizers: self.sanitizers,
+            target_triple: self.target_triple,
+            cross_cc,
+            cfg: CfgContext::new(&target, features),
+        })
     }
 }
 
@@ -276,9 +329,13 @@ pub fn preconfig_compile(
         moonc_output_json: !build_flags.no_render && !cli.dry_run,
         docs_serve: false,
         info_no_alias: false,
+        sanitizers: vec![],
+        target_triple: build_flags.triple.clone(),
         deny_warn: build_flags.deny_warn,
         warn_list: build_flags.warn_list.clone(),
         alert_list: build_flags.alert_list.clone(),
+        profile: build_flags.build_profile.clone(),
+        features: build_flags.features.clone(),
     }
 }
 
@@ -299,8 +356,7 @@ pub fn plan_build<'a>(
     target_dir: &'a Path,
     calc_user_intent: Box<CalcUserIntentFn<'a>>,
 ) -> anyhow::Result<(BuildMeta, n2::graph::Graph)> {
-    let cfg = ResolveConfig::new_with_load_defaults(preconfig.frozen);
-    let resolve_output = moonbuild_rupes_recta::resolve(&cfg, source_dir)?;
+    let resolve_output = resolve_cached(preconfig.frozen, source_dir)?;
 
     // A couple of debug things:
     if unstable_features.rr_export_module_graph {
@@ -335,10 +391,34 @@ pub fn plan_build<'a>(
     // Ultimately we want to determine this from config instead of special cases.
     let is_core = main_module.name == MOONBITLANG_CORE;
 
-    // Run prebuild config if any
-    let prebuild_config = run_prebuild_config(&resolve_output)?;
+    let profile = preconfig
+        .profile
+        .as_deref()
+        .map(|name| resolve_profile(main_module.profiles.as_ref(), name))
+        .transpose()?;
+    let main_features = resolve_features(
+        &main_module.name,
+        main_module.features.as_ref(),
+        &preconfig.features,
+    )?;
+    // Dependencies use the features their dependents enabled during resolution
+    let mut features = resolve_output
+        .module_rel
+        .all_modules_and_id()
+        .filter_map(|(id, _)| Some((id, resolve_output.module_rel.features(id)?.clone())))
+        .collect::<HashMap<_, _>>();
+    features.insert(main_module_id, main_features);
+    let cx = preconfig.into_compile_config(preferred_backend, is_core, profile, features)?;
+    if let Some(triple) = &cx.target_triple {
+        OperatingSystem::from_triple(triple).map_err(anyhow::Error::msg)?;
+    }
 
-    let cx = preconfig.into_compile_config(preferred_backend, is_core);
+    // Run prebuild config if any
+    let prebuild_config = run_prebuild_config(
+        &resolve_output,
+        cx.target_backend,
+        cx.target_triple.as_deref(),
+    )?;
     // Expand user intents to concrete BuildPlanNode inputs
     let mut input_nodes: Vec<BuildPlanNode> = Vec::new();
     for i in &intent.intents {
@@ -363,14 +443,23 @@ pub fn plan_build<'a>(
         )?;
     }
 
+    let mut build_graph = compile_output.build_graph;
+    if build_cache_enabled() {
+        wrap_cacheable_builds(&mut build_graph, &[source_dir, target_dir], |build| {
+            let name = build.location.filename.display().to_string();
+            name.ends_with("@BuildCore") || name.ends_with("@LinkCore")
+        })?;
+    }
+
     let build_meta = BuildMeta {
         resolve_output,
         artifacts: compile_output.artifacts,
         target_backend: cx.target_backend,
         opt_level: cx.opt_level,
+        cfg: cx.cfg,
     };
 
-    Ok((build_meta, compile_output.build_graph))
+    Ok((build_meta, build_graph))
 }
 
 /// Generate metadata file `packages.json` in the target directory.
@@ -387,6 +476,7 @@ pub fn generate_metadata(
         target_dir,
         build_meta.opt_level,
         build_meta.target_backend,
+        &build_meta.cfg,
     );
     std::fs::write(
         &metadata_file,
@@ -416,6 +506,13 @@ pub struct BuildConfig {
 
     /// The patch file to use
     pub patch_file: Option<PathBuf>,
+
+    /// Record the time taken by each build step
+    pub timings: bool,
+
+    /// Print why each build step is rerun in the given format, with paths
+    /// relative to the given source directory
+    pub explain: Option<(ExplainFormat, PathBuf)>,
 }
 
 impl BuildConfig {
@@ -428,6 +525,8 @@ impl BuildConfig {
             explain_errors: false,
             n2_explain: unstable_features.rr_n2_explain,
             patch_file: None,
+            timings: false,
+            explain: None,
         }
     }
 }
@@ -442,6 +541,8 @@ impl Default for BuildConfig {
             explain_errors: false,
             n2_explain: false,
             patch_file: None,
+            timings: false,
+            explain: None,
         }
     }
 }
@@ -514,6 +615,28 @@ pub fn execute_build_partial(
         target_dir.into(),
     );
     let mut prog_console = create_progress_console(Some(Box::new(callback)), false);
+    let commands_path = target_dir.join("moon.rupes-recta.commands");
+    if let Some((format, source_dir)) = &cfg.explain {
+        prog_console = Box::new(ExplainProgress::new(
+            prog_console,
+            &build_graph,
+            *format,
+            source_dir,
+            CommandLog::load(&commands_path),
+        ));
+    }
+    let commands = Arc::new(Mutex::new(CommandLog::load(&commands_path)));
+    prog_console = Box::new(CommandLogProgress::new(
+        prog_console,
+        &build_graph,
+        Arc::clone(&commands),
+    ));
+    let timings = cfg
+        .timings
+        .then(|| Arc::new(Mutex::new(BuildTimings::default())));
+    if let Some(timings) = &timings {
+        prog_console = Box::new(TimingProgress::new(prog_console, Arc::clone(timings)));
+    }
     let mut work = n2::work::Work::new(
         build_graph,
         hashes,
@@ -532,12 +655,14 @@ pub fn execute_build_partial(
 
     // The actual execution done by the n2 executor
     let res = work.run().context("Failed to run n2 graph")?;
+    commands.lock().unwrap().save()?;
 
     let result_catcher = result_catcher.lock().unwrap();
     let stats = N2RunStats {
         n_tasks_executed: res,
         n_errors: result_catcher.n_errors,
         n_warnings: result_catcher.n_warnings,
+        timings: timings.map(|it| it.lock().unwrap().clone()),
     };
 
     Ok(stats)
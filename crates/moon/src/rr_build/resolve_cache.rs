@@ -0,0 +1,183 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! An in-memory cache of [`ResolveOutput`] used by `moon server`.
+//!
+//! Resolving reads every `moon.mod.json` and `moon.pkg.json`, discovers all
+//! packages and solves the package graph, which dominates incremental builds in
+//! large projects. A long-running build server enables this cache, so that
+//! consecutive builds reuse the same resolve output as long as the project
+//! layout is unchanged.
+//!
+//! The cache does not look at the file system itself. Whoever enables it
+//! watches the project and calls [`invalidate_resolve_cache`] for every
+//! change that [`affects_resolve`], before looking up the cache again.
+//!
+//! When the cache is not enabled (the normal CLI case), [`resolve_cached`]
+//! always resolves from scratch.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use moonbuild_rupes_recta::{ResolveConfig, ResolveOutput, resolve::ResolveError};
+use moonutil::common::{MOON_MOD_JSON, MOON_PKG_JSON};
+use notify::{EventKind, event::ModifyKind};
+use tracing::debug;
+
+struct CachedResolve {
+    source_dir: PathBuf,
+    frozen: bool,
+    generation: u64,
+    output: Arc<ResolveOutput>,
+}
+
+static CACHE_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Bumped on every change that may affect the resolve output. An atomic
+/// rather than the cache lock, so a watcher thread never holds a lock the
+/// server needs.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+static CACHE: Mutex<Option<CachedResolve>> = Mutex::new(None);
+
+/// Enable caching resolve outputs for the rest of the process lifetime.
+pub fn enable_resolve_cache() {
+    CACHE_ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Mark the cached resolve output as stale.
+pub fn invalidate_resolve_cache() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Whether a file system event in a project may change its resolve output.
+///
+/// Adding, removing or renaming a file may change the discovered packages
+/// and their source files, while other changes only matter for module and
+/// package configuration files. Changes in `target_dir` and `.git` never
+/// matter.
+pub fn affects_resolve(event: &notify::Event, target_dir: &Path) -> bool {
+    if event.need_rescan() {
+        return true;
+    }
+    let mut paths = event.paths.iter().filter(|path| {
+        !path.starts_with(target_dir) && !path.components().any(|c| c.as_os_str() == ".git")
+    });
+    match event.kind {
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_)) => {
+            paths.next().is_some()
+        }
+        EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_)) => false,
+        _ => paths.any(|path| {
+            path.file_name()
+                .is_some_and(|name| name == MOON_MOD_JSON || name == MOON_PKG_JSON)
+        }),
+    }
+}
+
+/// Resolve the project at `source_dir`, reusing the cached output if caching
+/// is enabled and the project has not changed since it was cached.
+pub fn resolve_cached(frozen: bool, source_dir: &Path) -> Result<Arc<ResolveOutput>, ResolveError> {
+    let resolve = || {
+        let cfg = ResolveConfig::new_with_load_defaults(frozen);
+        moonbuild_rupes_recta::resolve(&cfg, source_dir).map(Arc::new)
+    };
+
+    if !CACHE_ENABLED.load(Ordering::SeqCst) {
+        return resolve();
+    }
+
+    // Taken before resolving, so changes made while resolving invalidate the
+    // cache on the next lookup
+    let generation = GENERATION.load(Ordering::SeqCst);
+    let mut cache = CACHE.lock().unwrap();
+    if let Some(cached) = cache.as_ref()
+        && cached.source_dir == source_dir
+        && cached.frozen == frozen
+        && cached.generation == generation
+    {
+        debug!("Reusing cached resolve output for {}", source_dir.display());
+        return Ok(Arc::clone(&cached.output));
+    }
+
+    let output = resolve()?;
+    *cache = Some(CachedResolve {
+        source_dir: source_dir.to_owned(),
+        frozen,
+        generation,
+        output: Arc::clone(&output),
+    });
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, DataChange, Flag, MetadataKind, RenameMode};
+
+    #[test]
+    fn test_affects_resolve() {
+        let root = Path::new("/m");
+        let target_dir = root.join("target");
+        let event =
+            |kind: EventKind, path: &str| notify::Event::new(kind).add_path(root.join(path));
+        let data = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        let created = EventKind::Create(CreateKind::File);
+
+        assert!(affects_resolve(
+            &event(data, "lib/moon.pkg.json"),
+            &target_dir
+        ));
+        assert!(affects_resolve(&event(data, "moon.mod.json"), &target_dir));
+        assert!(affects_resolve(&event(created, "lib/new.mbt"), &target_dir));
+        assert!(affects_resolve(
+            &event(EventKind::Modify(ModifyKind::Name(RenameMode::Any)), "lib"),
+            &target_dir
+        ));
+        assert!(affects_resolve(
+            &notify::Event::new(EventKind::Other).set_flag(Flag::Rescan),
+            &target_dir
+        ));
+
+        // Editing sources does not change the package graph
+        assert!(!affects_resolve(&event(data, "lib/lib.mbt"), &target_dir));
+        assert!(!affects_resolve(
+            &event(EventKind::Access(AccessKind::Any), "lib/moon.pkg.json"),
+            &target_dir
+        ));
+        assert!(!affects_resolve(
+            &event(
+                EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)),
+                "lib/moon.pkg.json"
+            ),
+            &target_dir
+        ));
+        // Neither do build outputs or git
+        assert!(!affects_resolve(
+            &event(created, "target/wasm-gc/release/build/lib/lib.core"),
+            &target_dir
+        ));
+        assert!(!affects_resolve(
+            &event(created, ".git/index.lock"),
+            &target_dir
+        ));
+    }
+}
@@ -22,6 +22,7 @@ use std::process::{ExitStatus, Stdio};
 
 use anyhow::Context;
 use moonbuild::section_capture::{SectionCapture, handle_stdout_async};
+use tokio::io::AsyncReadExt;
 use tokio::process::Command;
 
 /// Run a command under the governing of `moon run`.
@@ -36,8 +37,27 @@ use tokio::process::Command;
 pub async fn run<'a>(
     captures: &mut [&mut SectionCapture<'a>],
     stdin: bool,
-    mut cmd: Command,
+    cmd: Command,
 ) -> anyhow::Result<ExitStatus> {
+    let (status, _) = run_impl(captures, stdin, cmd, false).await?;
+    Ok(status)
+}
+
+/// Like [`run`], but collects the `stderr` output of the command instead of
+/// forwarding it, e.g. to attach a sanitizer report to the failing test.
+pub async fn run_collecting_stderr<'a>(
+    captures: &mut [&mut SectionCapture<'a>],
+    cmd: Command,
+) -> anyhow::Result<(ExitStatus, Vec<u8>)> {
+    run_impl(captures, false, cmd, true).await
+}
+
+async fn run_impl<'a>(
+    captures: &mut [&mut SectionCapture<'a>],
+    stdin: bool,
+    mut cmd: Command,
+    collect_stderr: bool,
+) -> anyhow::Result<(ExitStatus, Vec<u8>)> {
     if stdin {
         cmd.stdin(Stdio::inherit());
     } else {
@@ -53,10 +73,19 @@ pub async fn run<'a>(
 
     let stderr_pipe_task = child.stderr.take().map(|mut stderr| {
         tokio::spawn(async move {
-            let mut proc_stderr = tokio::io::stderr();
-            tokio::io::copy(&mut stderr, &mut proc_stderr)
-                .await
-                .context("Failed to pipe stderr to child process")
+            let mut collected = vec![];
+            if collect_stderr {
+                stderr
+                    .read_to_end(&mut collected)
+                    .await
+                    .context("Failed to read stderr of child process")?;
+            } else {
+                let mut proc_stderr = tokio::io::stderr();
+                tokio::io::copy(&mut stderr, &mut proc_stderr)
+                    .await
+                    .context("Failed to pipe stderr to child process")?;
+            }
+            anyhow::Ok(collected)
         })
     });
 
@@ -82,10 +111,12 @@ pub async fn run<'a>(
         .await
         .context("Failed to wait for child process")?;
 
+    let mut stderr = vec![];
     if let Some(task) = stderr_pipe_task {
-        task.await
+        stderr = task
+            .await
             .expect("Failed to pipe stderr to child process")?;
     }
 
-    Ok(status)
+    Ok((status, stderr))
 }
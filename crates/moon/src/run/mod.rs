@@ -21,10 +21,14 @@
 mod child;
 mod runtest;
 mod runtime;
+mod worker;
 
 pub use child::run;
 pub use runtest::{TestFilter, TestIndex, perform_promotion, run_tests};
-pub use runtime::{CommandGuard, command_for};
+pub use runtime::{
+    CommandGuard, MOONRUN_DEBUGGER_ARG, check_debugger_backend, check_profile_backend, command_for,
+    command_for_runner,
+};
 
 pub fn default_rt() -> std::io::Result<tokio::runtime::Runtime> {
     tokio::runtime::Builder::new_current_thread()
@@ -60,7 +60,7 @@
 mod filter;
 mod promotion;
 
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{collections::HashMap, io::Write, path::Path, sync::Arc};
 
 use anyhow::Context;
 use indexmap::IndexMap;
@@ -78,12 +78,18 @@ use moonbuild::{
 use moonbuild_rupes_recta::model::{BuildPlanNode, BuildTarget};
 use moonutil::common::{
     MOON_COVERAGE_DELIMITER_BEGIN, MOON_COVERAGE_DELIMITER_END, MOON_TEST_DELIMITER_BEGIN,
-    MOON_TEST_DELIMITER_END, MbtTestInfo, MooncGenTestInfo,
+    MOON_TEST_DELIMITER_END, MOONRUN_LIMIT_DELIMITER_BEGIN, MOONRUN_LIMIT_DELIMITER_END,
+    MbtTestInfo, MooncGenTestInfo, ProfileFlags, TargetBackend,
 };
+use moonutil::package::{MoonPkg, TestLimits};
 use tokio::runtime::Runtime;
 
 use crate::{rr_build::BuildMeta, run::default_rt};
 
+use super::child::run_collecting_stderr;
+use super::runtime::{CommandGuard, RuntimeExecutableCache, wrap_with_runner};
+use super::worker::{self, PersistentWorker};
+
 pub use filter::TestFilter;
 pub use promotion::perform_promotion;
 
@@ -131,22 +137,64 @@ impl TestIndex {
 }
 
 /// Run the tests compiled in this session. Does **not** print or update
-/// snapshots.
+/// snapshots. `moonrun_args` are passed to `moonrun` for WASM tests, along
+/// with `test_limits` or the limits set by each package, and the arguments to
+/// record a CPU profile of each executable if `profile` asks for one.
+///
+/// WASM tests are run one at a time by a persistent `moonrun` worker unless
+/// they are debugged or profiled, see the `worker` module.
+///
+/// If the main module sets a `runners` command for the target backend, every
+/// test executable is run through it instead, and crashes are reported as
+/// below.
+///
+/// Native and LLVM tests are run through `runner` if it is not empty. If they
+/// are run that way or built with sanitizers, the `stderr` of an executable
+/// that exits abnormally is attached to the test it crashed in.
 ///
 /// An external driver should check the results for reruns. See [module-level
 /// docs](crate::run::runtest) for more information about the workflow.
+#[allow(clippy::too_many_arguments)]
 pub fn run_tests(
     build_meta: &BuildMeta,
     target_dir: &Path,
     filter: &TestFilter,
+    moonrun_args: &[String],
+    test_limits: &TestLimits,
+    profile: &ProfileFlags,
+    runner: &[String],
+    sanitized: bool,
 ) -> anyhow::Result<ReplaceableTestResults> {
     // Gathering artifacts
     let executables = gather_tests(build_meta);
 
     let rt = default_rt().context("Failed to create runtime")?;
+    let module_runner = build_meta.runner();
+    let mut worker = (matches!(
+        build_meta.target_backend,
+        TargetBackend::Wasm | TargetBackend::WasmGC
+    ) && module_runner.is_none()
+        && worker::supports(moonrun_args, profile))
+    .then(|| PersistentWorker::new(RuntimeExecutableCache::default().moonrun().to_path_buf()));
+    let report_crashes = module_runner.is_some()
+        || (matches!(
+            build_meta.target_backend,
+            TargetBackend::Native | TargetBackend::LLVM
+        ) && (sanitized || !runner.is_empty()));
     let mut stats = ReplaceableTestResults::default();
     for r in executables {
-        let res = run_one_test_executable(build_meta, &rt, target_dir, &r, filter)?;
+        let res = run_one_test_executable(
+            build_meta,
+            &rt,
+            worker.as_mut(),
+            target_dir,
+            &r,
+            filter,
+            moonrun_args,
+            test_limits,
+            profile,
+            report_crashes.then_some(runner),
+        )?;
         stats.merge_with_target(r.target, res);
     }
 
@@ -290,12 +338,18 @@ fn gather_tests(build_meta: &BuildMeta) -> Vec<TestExecutableToRun<'_>> {
     results
 }
 
+#[allow(clippy::too_many_arguments)]
 fn run_one_test_executable(
     build_meta: &BuildMeta,
     rt: &Runtime, // FIXME: parallel execution
+    worker: Option<&mut PersistentWorker>,
     target_dir: &Path,
     test: &TestExecutableToRun,
     filter: &TestFilter,
+    moonrun_args: &[String],
+    test_limits: &TestLimits,
+    profile: &ProfileFlags,
+    crash_runner: Option<&[String]>,
 ) -> Result<TargetTestResult, anyhow::Error> {
     let (included, file_filt) = filter.check_package(test.target);
     if !included {
@@ -317,21 +371,78 @@ fn run_one_test_executable(
 
     filter::apply_filter(file_filt, &meta, &mut test_args.file_and_index);
 
-    let cmd =
-        crate::run::command_for(build_meta.target_backend, test.executable, Some(&test_args))?;
+    let pkg = &build_meta
+        .resolve_output
+        .pkg_dirs
+        .get_package(test.target.package)
+        .raw;
+    let pkg_limits = pkg.test_limits.as_ref();
+    let mut moonrun_args = moonrun_args.to_vec();
+    moonrun_args.extend(test_limits.or(pkg_limits).to_moonrun_args());
+
     let mut cov_cap = mk_coverage_capture();
     let mut test_cap = make_test_capture();
-
-    rt.block_on(crate::run::run(
-        &mut [&mut cov_cap, &mut test_cap],
-        false,
-        cmd.command,
-    ))
+    let mut limit_cap = make_limit_capture();
+    let mut crash_report = None;
+
+    if let Some(worker) = worker {
+        rt.block_on(worker.run(
+            &moonrun_args,
+            test.executable,
+            &test_args,
+            &mut [&mut cov_cap, &mut test_cap, &mut limit_cap],
+        ))
+        .map(|_| ())
+    } else if let Some(runner) = crash_runner {
+        let cmd = test_command(
+            build_meta,
+            test.executable,
+            &test_args,
+            &moonrun_args,
+            profile,
+            pkg,
+        )?;
+        let cmd = wrap_with_runner(cmd, runner);
+        rt.block_on(run_collecting_stderr(
+            &mut [&mut cov_cap, &mut test_cap, &mut limit_cap],
+            cmd.command,
+        ))
+        .map(|(status, stderr)| {
+            if status.success() {
+                let _ = std::io::stderr().write_all(&stderr);
+            } else {
+                crash_report = Some(format!(
+                    "{RUNTIME_ERROR}: test executable {status}\n{}",
+                    String::from_utf8_lossy(&stderr).trim_end()
+                ));
+            }
+        })
+    } else {
+        let cmd = test_command(
+            build_meta,
+            test.executable,
+            &test_args,
+            &moonrun_args,
+            profile,
+            pkg,
+        )?;
+        rt.block_on(crate::run::run(
+            &mut [&mut cov_cap, &mut test_cap, &mut limit_cap],
+            false,
+            cmd.command,
+        ))
+        .map(|_| ())
+    }
     .with_context(|| format!("Failed to run test for {fqn} {:?}", test.target.kind))?;
 
+    // `moonrun` reports the limits it enforces, whatever its exit code
+    if let Some(report) = limit_cap.finish() {
+        crash_report = Some(format!("{RUNTIME_ERROR}: {}", report.trim_end()));
+    }
+
     handle_finished_coverage(target_dir, cov_cap)?;
 
-    parse_test_results(meta, test_cap).with_context(|| {
+    parse_test_results(meta, test_cap, &test_args, crash_report).with_context(|| {
         format!(
             "Failed to parse test results for {fqn} {:?}",
             test.target.kind
@@ -339,6 +450,34 @@ fn run_one_test_executable(
     })
 }
 
+/// The command running a test executable, through the `runners` command of
+/// the module if there is one for the target backend.
+fn test_command(
+    build_meta: &BuildMeta,
+    executable: &Path,
+    test_args: &TestArgs,
+    moonrun_args: &[String],
+    profile: &ProfileFlags,
+    pkg: &MoonPkg,
+) -> anyhow::Result<CommandGuard> {
+    match build_meta.runner() {
+        Some(runner) => crate::run::command_for_runner(
+            runner,
+            build_meta.target_backend,
+            executable,
+            Some(test_args),
+        ),
+        None => crate::run::command_for(
+            build_meta.target_backend,
+            executable,
+            Some(test_args),
+            moonrun_args,
+            profile,
+            pkg.js_test_runtime.unwrap_or_default(),
+        ),
+    }
+}
+
 fn mk_coverage_capture() -> SectionCapture<'static> {
     SectionCapture::new(
         MOON_COVERAGE_DELIMITER_BEGIN,
@@ -351,6 +490,14 @@ fn make_test_capture() -> SectionCapture<'static> {
     SectionCapture::new(MOON_TEST_DELIMITER_BEGIN, MOON_TEST_DELIMITER_END, false)
 }
 
+fn make_limit_capture() -> SectionCapture<'static> {
+    SectionCapture::new(
+        MOONRUN_LIMIT_DELIMITER_BEGIN,
+        MOONRUN_LIMIT_DELIMITER_END,
+        false,
+    )
+}
+
 fn handle_finished_coverage(target_dir: &Path, cap: SectionCapture) -> anyhow::Result<()> {
     if let Some(coverage_output) = cap.finish() {
         let time = chrono::Local::now().timestamp_micros();
@@ -370,9 +517,16 @@ fn handle_finished_coverage(target_dir: &Path, cap: SectionCapture) -> anyhow::R
 fn parse_test_results(
     meta: MooncGenTestInfo,
     cap: SectionCapture,
+    test_args: &TestArgs,
+    crash_report: Option<String>,
 ) -> anyhow::Result<TargetTestResult> {
-    let Some(s) = cap.finish() else {
-        return Ok(TargetTestResult::default());
+    let s = if crash_report.is_some() {
+        cap.finish_partial().unwrap_or_default()
+    } else {
+        let Some(s) = cap.finish() else {
+            return Ok(TargetTestResult::default());
+        };
+        s
     };
 
     // Create a map to repopulate test names
@@ -441,9 +595,67 @@ fn parse_test_results(
         res.add(&stat.filename, index, case_result);
     }
 
+    if let Some(report) = crash_report {
+        attach_crash_report(&mut res, &mut test_name_map, test_args, report);
+    }
+
     Ok(res)
 }
 
+/// Blame the report of a test executable that exited abnormally on the first
+/// requested test without a result, as that is where it crashed. If every
+/// test finished, e.g. when `valgrind` finds leaks at exit, the last test run
+/// is blamed instead.
+fn attach_crash_report(
+    res: &mut TargetTestResult,
+    unfinished: &mut HashMap<String, HashMap<u32, MbtTestInfo>>,
+    test_args: &TestArgs,
+    report: String,
+) {
+    let requested = test_args.file_and_index.iter().flat_map(|(file, ranges)| {
+        ranges
+            .iter()
+            .flat_map(move |range| range.clone().map(move |index| (file, index)))
+    });
+    let mut last_finished = None;
+    for (file, index) in requested {
+        if let Some(meta) = unfinished
+            .get_mut(file.as_str())
+            .and_then(|m| m.remove(&index))
+        {
+            let raw = TestStatistics {
+                package: test_args.package.clone(),
+                filename: file.clone(),
+                index: index.to_string(),
+                test_name: meta.name.clone().unwrap_or_else(|| index.to_string()),
+                message: report,
+            };
+            let case_result = TestCaseResult {
+                kind: TestResultKind::RuntimeError,
+                raw: Arc::new(raw),
+                meta,
+            };
+            res.add(file, index, case_result);
+            return;
+        }
+        if res.map.get(file).is_some_and(|m| m.contains_key(&index)) {
+            last_finished = Some((file, index));
+        }
+    }
+
+    let Some((file, index)) = last_finished else {
+        eprintln!("{report}");
+        return;
+    };
+    let case = &mut res.map[file][&index];
+    let raw = TestStatistics {
+        message: report,
+        ..(*case.raw).clone()
+    };
+    case.kind = TestResultKind::RuntimeError;
+    case.raw = Arc::new(raw);
+}
+
 fn parse_one_test_result(
     result: &TestStatistics,
     test_name: &str,
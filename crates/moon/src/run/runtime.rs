@@ -24,7 +24,8 @@ use std::{
 };
 
 use moonbuild::entry::TestArgs;
-use moonutil::common::TargetBackend;
+use moonutil::common::{ProfileFlags, TargetBackend};
+use moonutil::package::JsRuntime;
 use tempfile::TempDir;
 use tokio::process::Command;
 
@@ -90,6 +91,15 @@ impl From<Command> for CommandGuard {
 /// file in WASM or WASM-GC backends, a `.js` file in JS backend, or a native
 /// executable in Native or LLVM backends.
 ///
+/// `moonrun_args` are passed to `moonrun` before the executable, e.g. to
+/// restrict its permissions. They are ignored by other runtimes.
+///
+/// If `profile` asks for it, `moonrun` writes a CPU profile of the executable
+/// next to it. See [`check_profile_backend`].
+///
+/// JS tests are run by `js_runtime`. Other JS programs are always run by
+/// Node.js, since `moonrun` only supports the CommonJS output.
+///
 /// ### Note
 ///
 /// Currently there's no support for using `tcc` to execute the target program.
@@ -97,9 +107,48 @@ pub fn command_for(
     backend: TargetBackend,
     mbt_executable: &Path,
     test: Option<&TestArgs>,
+    moonrun_args: &[String],
+    profile: &ProfileFlags,
+    js_runtime: JsRuntime,
 ) -> anyhow::Result<CommandGuard> {
     let cache = RuntimeExecutableCache::default();
-    command_for_cached(&cache, backend, mbt_executable, test)
+    command_for_cached(
+        &cache,
+        backend,
+        mbt_executable,
+        test,
+        moonrun_args,
+        profile,
+        js_runtime,
+    )
+}
+
+/// The `moonrun` argument that makes it wait for a DevTools debugger and
+/// pause before the program starts.
+pub const MOONRUN_DEBUGGER_ARG: &str = "--inspect-brk";
+
+/// `--debugger` relies on the inspector of `moonrun`, so only the WASM
+/// backends can be debugged.
+pub fn check_debugger_backend(backend: TargetBackend) -> anyhow::Result<()> {
+    match backend {
+        TargetBackend::Wasm | TargetBackend::WasmGC => Ok(()),
+        _ => anyhow::bail!(
+            "`--debugger` is only supported for the wasm and wasm-gc backends, not {}",
+            backend.to_flag()
+        ),
+    }
+}
+
+/// CPU profiles are recorded by `moonrun`, so only the WASM backends can be
+/// profiled.
+pub fn check_profile_backend(backend: TargetBackend) -> anyhow::Result<()> {
+    match backend {
+        TargetBackend::Wasm | TargetBackend::WasmGC => Ok(()),
+        _ => anyhow::bail!(
+            "`--profile` is only supported for the wasm and wasm-gc backends, not {}",
+            backend.to_flag()
+        ),
+    }
 }
 
 pub fn command_for_cached(
@@ -107,10 +156,15 @@ pub fn command_for_cached(
     backend: TargetBackend,
     mbt_executable: &Path,
     test: Option<&TestArgs>,
+    moonrun_args: &[String],
+    profile: &ProfileFlags,
+    js_runtime: JsRuntime,
 ) -> anyhow::Result<CommandGuard> {
     match backend {
         TargetBackend::Wasm | TargetBackend::WasmGC => {
             let mut cmd = Command::new(cache.moonrun());
+            cmd.args(moonrun_args);
+            cmd.args(profile.to_moonrun_args(mbt_executable));
             if let Some(t) = test {
                 cmd.arg("--test-args");
                 cmd.arg(serde_json::to_string(t).unwrap());
@@ -122,8 +176,18 @@ pub fn command_for_cached(
         TargetBackend::Js => {
             if let Some(t) = test {
                 let (dir, driver) = create_js_driver(mbt_executable, t)?;
-                let mut cmd = Command::new(cache.node());
-                cmd.arg("--enable-source-maps");
+                let mut cmd = match js_runtime {
+                    JsRuntime::Moonrun => {
+                        let mut cmd = Command::new(cache.moonrun());
+                        cmd.args(moonrun_args);
+                        cmd
+                    }
+                    JsRuntime::Node => {
+                        let mut cmd = Command::new(cache.node());
+                        cmd.arg("--enable-source-maps");
+                        cmd
+                    }
+                };
                 cmd.arg(driver);
                 cmd.arg(serde_json::to_string(t).expect("Failed to serialize test args"));
                 Ok(CommandGuard {
@@ -146,6 +210,61 @@ pub fn command_for_cached(
     }
 }
 
+/// Returns a command to run the given MoonBit executable through `template`,
+/// a `runners` command from `moon.mod.json`. See
+/// [`moonbuild::build::runner_command`] for how the artifact is passed.
+///
+/// For tests, the artifact of the JS backend is the generated test driver.
+/// The test args follow the artifact, in the form the default runtime would
+/// use, and are also passed as JSON in
+/// [`MOON_TEST_ARGS_ENV`](moonbuild::build::MOON_TEST_ARGS_ENV).
+pub fn command_for_runner(
+    template: &str,
+    backend: TargetBackend,
+    mbt_executable: &Path,
+    test: Option<&TestArgs>,
+) -> anyhow::Result<CommandGuard> {
+    let (temp_file, artifact) = match (backend, test) {
+        (TargetBackend::Js, Some(t)) => {
+            let (dir, driver) = create_js_driver(mbt_executable, t)?;
+            (Some(dir), driver)
+        }
+        _ => (None, mbt_executable.to_path_buf()),
+    };
+    let parts = moonbuild::build::runner_command(template, &artifact)?;
+    let mut cmd = Command::new(&parts[0]);
+    cmd.args(&parts[1..]);
+    if let Some(t) = test {
+        cmd.env(
+            moonbuild::build::MOON_TEST_ARGS_ENV,
+            serde_json::to_string(t).expect("Failed to serialize test args"),
+        );
+        cmd.arg(t.to_runner_arg(backend));
+    }
+    Ok(CommandGuard {
+        _temp_file: temp_file,
+        command: cmd,
+    })
+}
+
+/// Prefix the command with `runner`, e.g. `valgrind`, keeping the arguments
+/// passed to the program. An empty `runner` leaves the command untouched.
+pub fn wrap_with_runner(mut guard: CommandGuard, runner: &[String]) -> CommandGuard {
+    let Some((program, args)) = runner.split_first() else {
+        return guard;
+    };
+    let inner = guard.command.as_std();
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    cmd.arg(inner.get_program());
+    cmd.args(inner.get_args());
+    if let Some(dir) = inner.get_current_dir() {
+        cmd.current_dir(dir);
+    }
+    guard.command = cmd;
+    guard
+}
+
 fn create_js_driver(js_path: &Path, test_args: &TestArgs) -> anyhow::Result<(TempDir, PathBuf)> {
     let js_driver_text = include_str!(concat!(
         env!("CARGO_MANIFEST_DIR"),
@@ -0,0 +1,187 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! A persistent `moonrun --worker` process, so that the test executables of
+//! the WASM backends, which are run one after another, don't pay for the
+//! start-up of V8 one by one.
+//!
+//! This is not a pool: test executables are run sequentially by
+//! [`run_tests`](super::runtest::run_tests), and `-j` has no effect on them.
+//! Each run is sent to the single worker started with its `moonrun`
+//! arguments and awaited before the next one. Limits set per package make
+//! for distinct arguments, so there may be more than one process, but never
+//! more than one run at a time.
+//!
+//! See the `worker` module of `moonrun` for the protocol.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::Context;
+use moonbuild::entry::TestArgs;
+use moonbuild::section_capture::{SectionCapture, handle_stdout_async};
+use moonutil::common::ProfileFlags;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use super::MOONRUN_DEBUGGER_ARG;
+
+/// Written by `moonrun` after the output of each run.
+const END_MARKER: [u8; 4] = [0xFF, 0xFE, 0xFD, 0xFC];
+
+#[derive(serde::Serialize)]
+struct Request<'a> {
+    path: &'a Path,
+    test_args: &'a TestArgs,
+}
+
+#[derive(serde::Deserialize)]
+struct Response {
+    exit_code: i32,
+}
+
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+/// The persistent worker of `moonrun`. A process is kept for each distinct
+/// set of arguments, since they apply to the whole process.
+pub struct PersistentWorker {
+    moonrun: PathBuf,
+    processes: HashMap<Vec<String>, Worker>,
+}
+
+/// Whether tests run with these options can be run by a worker. Debugging
+/// and profiling are set up per process.
+pub fn supports(moonrun_args: &[String], profile: &ProfileFlags) -> bool {
+    !profile.profile && !moonrun_args.iter().any(|a| a == MOONRUN_DEBUGGER_ARG)
+}
+
+impl PersistentWorker {
+    pub fn new(moonrun: PathBuf) -> Self {
+        PersistentWorker {
+            moonrun,
+            processes: HashMap::new(),
+        }
+    }
+
+    /// Run a WASM test executable in the worker started with `moonrun_args`,
+    /// feeding its output to `captures`, and wait for it to finish. Returns the exit code of the run,
+    /// or `None` if the worker was killed by a signal.
+    ///
+    /// A worker exiting in the middle of a run, e.g. when the heap limit is
+    /// exceeded, is replaced by a new one on the next run.
+    pub async fn run<'a>(
+        &mut self,
+        moonrun_args: &[String],
+        executable: &Path,
+        test_args: &TestArgs,
+        captures: &mut [&mut SectionCapture<'a>],
+    ) -> anyhow::Result<Option<i32>> {
+        let worker = match self.processes.entry(moonrun_args.to_vec()) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(spawn(&self.moonrun, moonrun_args)?),
+        };
+
+        let mut request = serde_json::to_string(&Request {
+            path: executable,
+            test_args,
+        })?;
+        request.push('\n');
+        // A worker that exited after its last run can't take the request,
+        // which is handled like exiting during the run
+        let sent = match worker.stdin.write_all(request.as_bytes()).await {
+            Ok(()) => worker.stdin.flush().await,
+            Err(e) => Err(e),
+        };
+
+        let mut output = vec![];
+        let finished = sent.is_ok() && read_run_output(&mut worker.stdout, &mut output).await?;
+        let exit_code = if finished {
+            let mut line = String::new();
+            worker.stdout.read_line(&mut line).await?;
+            let response: Response = serde_json::from_str(&line)
+                .with_context(|| format!("Invalid response from moonrun worker: {line:?}"))?;
+            Some(response.exit_code)
+        } else {
+            // The worker is gone, so its exit status is the result of the run
+            let mut worker = self
+                .processes
+                .remove(moonrun_args)
+                .expect("the worker should be running");
+            let status = worker
+                .child
+                .wait()
+                .await
+                .context("Failed to wait for moonrun worker")?;
+            status.code()
+        };
+
+        handle_stdout_async(&output[..], captures).await?;
+        Ok(exit_code)
+    }
+}
+
+fn spawn(moonrun: &Path, moonrun_args: &[String]) -> anyhow::Result<Worker> {
+    let mut cmd = Command::new(moonrun);
+    cmd.args(moonrun_args);
+    cmd.arg("--worker");
+    cmd.stdin(Stdio::piped());
+    cmd.stdout(Stdio::piped());
+    // Piped for the same reason as in `run::run`
+    cmd.stderr(Stdio::piped());
+    cmd.kill_on_drop(true);
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to spawn command {:?}", cmd))?;
+
+    let mut stderr = child.stderr.take().expect("stderr should be piped");
+    tokio::spawn(async move {
+        let _ = tokio::io::copy(&mut stderr, &mut tokio::io::stderr()).await;
+    });
+    Ok(Worker {
+        stdin: child.stdin.take().expect("stdin should be piped"),
+        stdout: BufReader::new(child.stdout.take().expect("stdout should be piped")),
+        child,
+    })
+}
+
+/// Read the output of a run up to the end marker. Returns `false` if the
+/// worker exited before finishing the run.
+async fn read_run_output(
+    stdout: &mut BufReader<ChildStdout>,
+    output: &mut Vec<u8>,
+) -> anyhow::Result<bool> {
+    loop {
+        let n = stdout
+            .read_until(END_MARKER[3], output)
+            .await
+            .context("Failed to read from moonrun worker")?;
+        if n == 0 {
+            return Ok(false);
+        }
+        if output.ends_with(&END_MARKER) {
+            output.truncate(output.len() - END_MARKER.len());
+            return Ok(true);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! `moon server`: a long-running build server for a single project.
+//!
+//! The server keeps the result of the resolve step (see
+//! [`crate::rr_build::resolve_cached`]) warm between builds. It watches the
+//! project with the same file watcher as `--watch`, and drops the cached
+//! output whenever a change may affect package discovery or the package
+//! graph.
+//!
+//! Only the resolve output is kept. The build graph depends on the flags of
+//! each request and is lowered again every time, and n2 loads its state from
+//! the database in the target directory on every build, as in a local run.
+//!
+//! # Protocol
+//!
+//! The server listens on a Unix domain socket at `<target-dir>/moon.server.sock`.
+//! Each connection carries exactly one request:
+//!
+//! 1. The client sends a [`ServerRequest`] serialized as a single line of JSON,
+//!    passing its stdin, stdout and stderr file descriptors along with it.
+//! 2. For [`ServerRequest::Run`], the server forks a child that executes the
+//!    command in the client's working directory and environment, with the
+//!    client's standard streams, so the output behaves as if the command was
+//!    run locally. The server process itself never changes them.
+//! 3. The server then sends a NUL byte, followed by the exit code in decimal
+//!    and a newline, and closes the connection.
+//!
+//! Requests are handled one at a time, since they build into the same target
+//! directory.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cli::MoonBuildSubcommands;
+
+/// The name of the socket file inside the target directory.
+const SOCKET_FILE: &str = "moon.server.sock";
+
+/// The byte separating the command output from the exit code.
+const EXIT_CODE_SEPARATOR: u8 = 0;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ServerRequest {
+    /// Run a `moon` command with the given arguments in the given directory
+    /// and environment.
+    Run {
+        cwd: PathBuf,
+        args: Vec<String>,
+        env: Vec<(String, String)>,
+    },
+    /// Ask the server to shut down.
+    Stop,
+}
+
+pub fn socket_path(target_dir: &Path) -> PathBuf {
+    target_dir.join(SOCKET_FILE)
+}
+
+/// Whether a command can be served by the build server.
+///
+/// Only one-shot build, check and test commands are forwarded. Watch mode
+/// keeps running forever and would block the server.
+pub fn is_forwardable(cmd: &MoonBuildSubcommands) -> bool {
+    match cmd {
+        MoonBuildSubcommands::Build(b) => !b.watch,
+        MoonBuildSubcommands::Check(c) => !c.watch && c.single_file.is_none(),
+        MoonBuildSubcommands::Test(t) => !t.watch,
+        _ => false,
+    }
+}
+
+#[cfg(unix)]
+mod unix;
+#[cfg(unix)]
+pub use unix::{forward_to_server, run_server, stop_server};
+
+#[cfg(not(unix))]
+pub fn run_server(_source_dir: &Path, _target_dir: &Path) -> anyhow::Result<i32> {
+    anyhow::bail!("`moon server` is only supported on Unix-like systems for now")
+}
+
+#[cfg(not(unix))]
+pub fn stop_server(_target_dir: &Path) -> anyhow::Result<i32> {
+    anyhow::bail!("`moon server` is only supported on Unix-like systems for now")
+}
+
+#[cfg(not(unix))]
+pub fn forward_to_server(_target_dir: &Path) -> Option<anyhow::Result<i32>> {
+    None
+}
@@ -0,0 +1,551 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Unix domain socket implementation of the build server and its client.
+
+use std::io::{Read, Write};
+use std::ops::ControlFlow;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use clap::Parser;
+use colored::Colorize;
+use moonutil::dirs::{PackageDirs, SourceTargetDirs};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{debug, info, warn};
+
+use super::{EXIT_CODE_SEPARATOR, ServerRequest, is_forwardable, socket_path};
+use crate::cli::{self, MoonBuildCli, MoonBuildSubcommands};
+use crate::rr_build::{
+    affects_resolve, enable_resolve_cache, invalidate_resolve_cache, resolve_cached,
+};
+use crate::watch::watch_recursive;
+
+/// Prefix of the cookie files written by [`ProjectWatcher::sync`].
+const COOKIE_PREFIX: &str = "moon.server.cookie.";
+
+/// How long to wait for the watcher to catch up before giving up on the
+/// cached resolve output.
+const COOKIE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Run the build server for the project in the foreground until it is stopped.
+pub fn run_server(source_dir: &Path, target_dir: &Path) -> anyhow::Result<i32> {
+    std::fs::create_dir_all(target_dir).with_context(|| {
+        format!(
+            "Failed to create target directory: '{}'",
+            target_dir.display()
+        )
+    })?;
+
+    let socket = socket_path(target_dir);
+    if socket.exists() {
+        if UnixStream::connect(&socket).is_ok() {
+            bail!(
+                "a build server is already running at `{}`",
+                socket.display()
+            );
+        }
+        // A stale socket left by a server that did not exit cleanly
+        std::fs::remove_file(&socket)
+            .with_context(|| format!("Failed to remove stale socket `{}`", socket.display()))?;
+    }
+    let listener = UnixListener::bind(&socket)
+        .with_context(|| format!("Failed to listen on `{}`", socket.display()))?;
+
+    enable_resolve_cache();
+    let mut project = ProjectWatcher::new(source_dir, target_dir)?;
+
+    println!("Build server listening on `{}`", socket.display());
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        match handle_connection(stream, &mut project) {
+            Ok(ControlFlow::Break(())) => break,
+            Ok(ControlFlow::Continue(())) => {}
+            Err(e) => warn!("Failed to handle request: {:?}", e),
+        }
+    }
+
+    let _ = std::fs::remove_file(&socket);
+    println!("Build server stopped");
+    Ok(0)
+}
+
+/// Ask the build server of the project to shut down.
+pub fn stop_server(target_dir: &Path) -> anyhow::Result<i32> {
+    let socket = socket_path(target_dir);
+    let stream = UnixStream::connect(&socket)
+        .with_context(|| format!("No build server is running at `{}`", socket.display()))?;
+    send_request(stream, &ServerRequest::Stop)
+}
+
+/// Forward the current command line to the build server of the project, if
+/// one is running.
+///
+/// Returns `None` if there is no server to forward to, in which case the
+/// command should be run locally.
+pub fn forward_to_server(target_dir: &Path) -> Option<anyhow::Result<i32>> {
+    let stream = UnixStream::connect(socket_path(target_dir)).ok()?;
+    let request = std::env::current_dir()
+        .context("Failed to get the current directory")
+        .map(|cwd| ServerRequest::Run {
+            cwd,
+            args: std::env::args_os()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            env: std::env::vars_os()
+                .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+                .collect(),
+        });
+    Some(request.and_then(|request| send_request(stream, &request)))
+}
+
+/// Send a request along with the standard streams of this process, and
+/// return the exit code reported by the server.
+///
+/// The server writes the output of the command directly to the passed file
+/// descriptors, so terminal detection and colors behave as if the command
+/// was run locally. Anything the server writes to the connection itself
+/// before the exit code is copied to stdout.
+fn send_request(mut stream: UnixStream, request: &ServerRequest) -> anyhow::Result<i32> {
+    let mut line = serde_json::to_string(request).context("Failed to serialize request")?;
+    line.push('\n');
+    flush_std();
+    send_with_fds(
+        &stream,
+        line.as_bytes(),
+        &[libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO],
+    )
+    .context("Failed to send request to the build server")?;
+
+    let mut stdout = std::io::stdout().lock();
+    let mut buf = [0u8; 8192];
+    let mut trailer: Option<Vec<u8>> = None;
+    loop {
+        let n = stream
+            .read(&mut buf)
+            .context("Failed to read from the build server")?;
+        if n == 0 {
+            break;
+        }
+        let chunk = &buf[..n];
+        match &mut trailer {
+            Some(trailer) => trailer.extend_from_slice(chunk),
+            None => match chunk.iter().position(|&b| b == EXIT_CODE_SEPARATOR) {
+                Some(i) => {
+                    stdout.write_all(&chunk[..i])?;
+                    trailer = Some(chunk[i + 1..].to_vec());
+                }
+                None => stdout.write_all(chunk)?,
+            },
+        }
+        stdout.flush()?;
+    }
+
+    let trailer = trailer.context("The build server closed the connection unexpectedly")?;
+    std::str::from_utf8(&trailer)
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .context("Malformed exit code from the build server")
+}
+
+/// Watches the project of the server and invalidates the resolve cache on
+/// every change that may affect it.
+///
+/// Events arrive asynchronously, so before serving a request the server
+/// writes a cookie file into the target directory and waits until the
+/// watcher reports it. Events of a watcher are delivered in order, so by then
+/// every change made before the request has been seen.
+struct ProjectWatcher {
+    _watcher: RecommendedWatcher,
+    cookie_dir: PathBuf,
+    last_cookie: u64,
+    seen_cookie: Arc<(Mutex<u64>, Condvar)>,
+}
+
+impl ProjectWatcher {
+    fn new(source_dir: &Path, target_dir: &Path) -> anyhow::Result<Self> {
+        let seen_cookie = Arc::new((Mutex::new(0), Condvar::new()));
+        let handler = {
+            let seen_cookie = Arc::clone(&seen_cookie);
+            let target_dir = target_dir.to_owned();
+            move |res: notify::Result<notify::Event>| {
+                let Ok(event) = res else {
+                    // Events may have been lost
+                    invalidate_resolve_cache();
+                    return;
+                };
+                let cookie = event
+                    .paths
+                    .iter()
+                    .filter(|path| path.parent() == Some(target_dir.as_path()))
+                    .filter_map(|path| path.file_name()?.to_str()?.strip_prefix(COOKIE_PREFIX))
+                    .filter_map(|n| n.parse::<u64>().ok())
+                    .max();
+                if affects_resolve(&event, &target_dir) {
+                    invalidate_resolve_cache();
+                }
+                if let Some(cookie) = cookie {
+                    let (seen, cvar) = &*seen_cookie;
+                    let mut seen = seen.lock().unwrap();
+                    *seen = (*seen).max(cookie);
+                    cvar.notify_all();
+                }
+            }
+        };
+        let mut watcher = watch_recursive(source_dir, handler)?;
+        // Cookies must be reported by the same watcher to be ordered with
+        // the changes in the project
+        if !target_dir.starts_with(source_dir) {
+            watcher
+                .watch(target_dir, RecursiveMode::NonRecursive)
+                .with_context(|| {
+                    format!("Failed to watch directory: '{}'", target_dir.display())
+                })?;
+        }
+        Ok(ProjectWatcher {
+            _watcher: watcher,
+            cookie_dir: target_dir.to_owned(),
+            last_cookie: 0,
+            seen_cookie,
+        })
+    }
+
+    /// Wait until the watcher has seen every change made before now. If it
+    /// falls behind, the cached resolve output is dropped instead.
+    fn sync(&mut self) {
+        self.last_cookie += 1;
+        let last_cookie = self.last_cookie;
+        let cookie = self
+            .cookie_dir
+            .join(format!("{COOKIE_PREFIX}{last_cookie}"));
+        if let Err(e) = std::fs::write(&cookie, b"") {
+            debug!("Failed to write `{}`: {}", cookie.display(), e);
+            invalidate_resolve_cache();
+            return;
+        }
+        let (seen, cvar) = &*self.seen_cookie;
+        let (_, wait) = cvar
+            .wait_timeout_while(seen.lock().unwrap(), COOKIE_TIMEOUT, |seen| {
+                *seen < last_cookie
+            })
+            .unwrap();
+        if wait.timed_out() {
+            debug!("The file watcher fell behind, resolving from scratch");
+            invalidate_resolve_cache();
+        }
+        let _ = std::fs::remove_file(&cookie);
+    }
+}
+
+fn handle_connection(
+    stream: UnixStream,
+    project: &mut ProjectWatcher,
+) -> anyhow::Result<ControlFlow<()>> {
+    let (line, fds) = read_request(&stream)?;
+    let request: ServerRequest = serde_json::from_str(&line).context("Malformed request")?;
+
+    let (cwd, args, env) = match request {
+        ServerRequest::Stop => {
+            write_exit_code(&stream, 0)?;
+            return Ok(ControlFlow::Break(()));
+        }
+        ServerRequest::Run { cwd, args, env } => (cwd, args, env),
+    };
+    info!("Serving `{}` in {}", args.join(" "), cwd.display());
+
+    project.sync();
+    warm_resolve_cache(&cwd, &args);
+
+    // Without the client's standard streams, fall back to writing the output
+    // to the connection
+    let stdio = match fds.as_slice() {
+        [stdin, stdout, stderr] => [
+            Some(stdin.as_raw_fd()),
+            Some(stdout.as_raw_fd()),
+            Some(stderr.as_raw_fd()),
+        ],
+        _ => [None, Some(stream.as_raw_fd()), Some(stream.as_raw_fd())],
+    };
+    let code = run_forked(&cwd, args, &env, stdio)?;
+    write_exit_code(&stream, code)?;
+    Ok(ControlFlow::Continue(()))
+}
+
+/// Resolve the project of a request in the server itself, so the output stays
+/// cached for later requests rather than only in the child serving this one.
+/// This uses the environment of the server, such as `MOON_HOME`. Errors are
+/// left for the child to report.
+fn warm_resolve_cache(cwd: &Path, args: &[String]) {
+    let Ok(cli) = MoonBuildCli::try_parse_from(args) else {
+        return;
+    };
+    let frozen = match &cli.subcommand {
+        MoonBuildSubcommands::Build(b) => b.auto_sync_flags.frozen,
+        MoonBuildSubcommands::Check(c) => c.auto_sync_flags.frozen,
+        MoonBuildSubcommands::Test(t) => t.auto_sync_flags.frozen,
+        _ => return,
+    };
+    // Relative to the client's working directory, not the server's
+    let dirs = &cli.flags.source_tgt_dir;
+    let dirs = SourceTargetDirs {
+        source_dir: Some(cwd.join(dirs.source_dir.as_deref().unwrap_or(Path::new(".")))),
+        target_dir: dirs.target_dir.as_ref().map(|dir| cwd.join(dir)),
+    };
+    let Ok(PackageDirs { source_dir, .. }) = dirs.try_into_package_dirs() else {
+        return;
+    };
+    if let Err(e) = resolve_cached(frozen, &source_dir) {
+        debug!("Failed to resolve {}: {}", source_dir.display(), e);
+    }
+}
+
+/// Read a request line, along with the file descriptors passed with it.
+fn read_request(mut stream: &UnixStream) -> anyhow::Result<(String, Vec<OwnedFd>)> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let (n, fds) = recv_with_fds(stream, &mut buf).context("Failed to read request")?;
+    buf.truncate(n);
+    // The request may not fit in a single message
+    while !buf.ends_with(b"\n") {
+        let mut byte = [0u8];
+        if stream.read(&mut byte).context("Failed to read request")? == 0 {
+            bail!("Connection closed before the request was complete");
+        }
+        buf.push(byte[0]);
+    }
+    Ok((String::from_utf8(buf).context("Malformed request")?, fds))
+}
+
+fn write_exit_code(mut stream: &UnixStream, code: i32) -> std::io::Result<()> {
+    stream.write_all(&[EXIT_CODE_SEPARATOR])?;
+    writeln!(stream, "{code}")
+}
+
+/// Serve a request in a child process forked from the server, and return its
+/// exit code.
+///
+/// The child inherits the warm resolve cache, and is the only process whose
+/// working directory, environment and standard streams are switched to the
+/// client's. The server itself never changes them.
+fn run_forked(
+    cwd: &Path,
+    args: Vec<String>,
+    env: &[(String, String)],
+    stdio: [Option<RawFd>; 3],
+) -> anyhow::Result<i32> {
+    flush_std();
+    let pid = {
+        // Other threads must not hold the locks of the standard streams while
+        // forking, or they would stay locked forever in the child
+        let _stdout = std::io::stdout().lock();
+        let _stderr = std::io::stderr().lock();
+        // SAFETY: besides the standard streams held above, the other threads
+        // of the server (the file watcher) only hold locks the child never
+        // takes. The child leaves with `_exit` and never returns here.
+        unsafe { libc::fork() }
+    };
+    match pid {
+        -1 => Err(std::io::Error::last_os_error()).context("Failed to fork the build server"),
+        0 => {
+            // A bug in a single request should not take down the whole server
+            let code =
+                std::panic::catch_unwind(AssertUnwindSafe(|| run_in_child(cwd, args, env, stdio)))
+                    .unwrap_or(101);
+            flush_std();
+            // SAFETY: exits the child without running the destructors and exit
+            // handlers of the server, which the child only has a copy of
+            unsafe { libc::_exit(code) }
+        }
+        pid => wait_for_child(pid),
+    }
+}
+
+/// Run a command line in the client's working directory, environment and
+/// standard streams, printing errors the same way `main` does, and return the
+/// exit code. Must only be called in a freshly forked child.
+fn run_in_child(
+    cwd: &Path,
+    args: Vec<String>,
+    env: &[(String, String)],
+    stdio: [Option<RawFd>; 3],
+) -> i32 {
+    let streams = [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO];
+    for (fd, target) in streams.into_iter().zip(stdio) {
+        // SAFETY: both file descriptors are valid for the duration of the call
+        if let Some(target) = target
+            && unsafe { libc::dup2(target, fd) } < 0
+        {
+            return -1;
+        }
+    }
+    // SAFETY: after `fork`, the calling thread is the only thread of the
+    // child, so nothing can read the environment concurrently
+    unsafe {
+        for (key, _) in std::env::vars_os() {
+            std::env::remove_var(key);
+        }
+        for (key, value) in env {
+            std::env::set_var(key, value);
+        }
+    }
+    // `colored` only checks the environment and the terminal once per
+    // process, so decide again for the client
+    colored::control::set_override(colored::control::ShouldColorize::from_env().should_colorize());
+
+    let res = MoonBuildCli::try_parse_from(args)
+        .map_err(|e| {
+            let _ = e.print();
+            e.exit_code()
+        })
+        .map(|cli| {
+            std::env::set_current_dir(cwd)
+                .with_context(|| format!("Failed to enter directory: '{}'", cwd.display()))
+                .and_then(|()| run_request(cli))
+        });
+    match res {
+        Ok(Ok(code)) => code,
+        Ok(Err(e)) => {
+            eprintln!("{}: {:?}", "error".red().bold(), e);
+            -1
+        }
+        Err(code) => code,
+    }
+}
+
+/// Wait for a forked child and translate its status into an exit code the
+/// way a shell does.
+fn wait_for_child(pid: libc::pid_t) -> anyhow::Result<i32> {
+    let mut status = 0;
+    // SAFETY: `pid` is a child of this process, which is waited for only here
+    while unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err).context("Failed to wait for the request to finish");
+        }
+    }
+    if libc::WIFEXITED(status) {
+        Ok(libc::WEXITSTATUS(status))
+    } else if libc::WIFSIGNALED(status) {
+        Ok(128 + libc::WTERMSIG(status))
+    } else {
+        Ok(-1)
+    }
+}
+
+fn run_request(mut cli: MoonBuildCli) -> anyhow::Result<i32> {
+    if !is_forwardable(&cli.subcommand) {
+        bail!("only one-shot `build`, `check` and `test` can be served by the build server");
+    }
+    // The resolve cache only applies to Rupes Recta builds
+    cli.flags.unstable_feature.rupes_recta = true;
+    match cli.subcommand {
+        MoonBuildSubcommands::Build(b) => cli::run_build(&cli.flags, &b),
+        MoonBuildSubcommands::Check(c) => cli::run_check(&cli.flags, &c),
+        MoonBuildSubcommands::Test(t) => cli::run_test(cli.flags, t),
+        _ => unreachable!("checked by is_forwardable"),
+    }
+}
+
+fn flush_std() {
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+}
+
+/// Send `data` with the given file descriptors attached as `SCM_RIGHTS`.
+fn send_with_fds(mut stream: &UnixStream, data: &[u8], fds: &[RawFd]) -> std::io::Result<()> {
+    let fds_len = std::mem::size_of_val(fds) as u32;
+    // SAFETY: `CMSG_SPACE` only computes a size
+    let space = unsafe { libc::CMSG_SPACE(fds_len) } as usize;
+    // `u64` keeps the control buffer aligned for `cmsghdr`
+    let mut control = vec![0u64; space.div_ceil(8)];
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    // SAFETY: an all-zero `msghdr` is valid, and all pointers set below stay
+    // alive until `sendmsg` returns
+    let sent = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = space as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+        std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        libc::sendmsg(stream.as_raw_fd(), &msg, 0)
+    };
+    if sent < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    stream.write_all(&data[sent as usize..])
+}
+
+/// Receive data into `buf`, along with any file descriptors attached to it.
+fn recv_with_fds(stream: &UnixStream, buf: &mut [u8]) -> std::io::Result<(usize, Vec<OwnedFd>)> {
+    // Room for a few descriptors, more than any client sends
+    // SAFETY: `CMSG_SPACE` only computes a size
+    let space = unsafe { libc::CMSG_SPACE(8 * std::mem::size_of::<RawFd>() as u32) } as usize;
+    let mut control = vec![0u64; space.div_ceil(8)];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    // SAFETY: an all-zero `msghdr` is valid, and all pointers set below stay
+    // alive until `recvmsg` returns
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+    // SAFETY: see above
+    let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, 0) };
+    if received < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let mut fds = vec![];
+    // SAFETY: the control buffer was filled in by `recvmsg`, and every
+    // descriptor received with `SCM_RIGHTS` is newly owned by this process
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                for i in 0..len / std::mem::size_of::<RawFd>() {
+                    fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((received as usize, fds))
+}
@@ -18,28 +18,36 @@
 
 use anyhow::Context;
 use colored::*;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use notify::{Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 
-use std::path::Path;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
-use tracing::info;
+use tracing::{info, warn};
+
+use crate::cli::WatchFlags;
 
 /// Run a watcher that watches on `watch_dir`, and calls `run` when a file
-/// changes. The watcher ignores changes in `original_target_dir`, and will
+/// changes. The watcher ignores changes in `original_target_dir`, as well as
+/// files matched by the module's `.gitignore` and `exclude` list, and will
 /// repopulate `target_dir` if it is deleted.
+///
+/// `run` receives the list of changed files that triggered the run, or `None`
+/// for the initial run, in which case everything should be processed.
 pub fn watching(
-    run: impl Fn() -> anyhow::Result<i32>,
+    run: impl Fn(Option<&[PathBuf]>) -> anyhow::Result<i32>,
+    flags: &WatchFlags,
     watch_dir: &Path,
     target_dir: &Path,
     original_target_dir: &Path,
 ) -> anyhow::Result<i32> {
     // Initial run
-    run_and_print(&run);
+    run_and_print(|| run(None), flags);
 
-    let (tx, rx) = std::sync::mpsc::channel();
-    let mut watcher = RecommendedWatcher::new(tx, Config::default())
-        .context("Failed to create a directory watcher")?;
+    let ignore = build_ignore_matcher(watch_dir);
 
     {
         // make sure the handler is only set once when --watch --target all
@@ -56,9 +64,8 @@ pub fn watching(
 
     {
         // main thread
-        watcher
-            .watch(watch_dir, RecursiveMode::Recursive)
-            .with_context(|| format!("Failed to watch directory: '{}'", watch_dir.display()))?;
+        let (tx, rx) = std::sync::mpsc::channel();
+        let _watcher = watch_recursive(watch_dir, tx)?;
 
         // in watch mode, moon is a long-running process that should handle errors as much as possible rather than throwing them up and then exiting.
         const DEBOUNCE_TIME: Duration = Duration::from_millis(300);
@@ -78,7 +85,14 @@ pub fn watching(
                 }
             }
 
-            if let Err(e) = handle_file_change(&run, target_dir, original_target_dir, &evt_list) {
+            if let Err(e) = handle_file_change(
+                &run,
+                flags,
+                &ignore,
+                target_dir,
+                original_target_dir,
+                &evt_list,
+            ) {
                 println!(
                     "{:?}\n{}",
                     e,
@@ -90,48 +104,112 @@ pub fn watching(
     Ok(0)
 }
 
-fn is_event_relevant(event: &notify::Event, original_target_dir: &Path) -> bool {
-    match event.kind {
-        EventKind::Modify(notify::event::ModifyKind::Metadata(_)) => return false,
+/// Start watching `watch_dir` recursively. Events are passed to `handler` on
+/// the watcher's own thread until the returned watcher is dropped.
+pub fn watch_recursive(
+    watch_dir: &Path,
+    handler: impl notify::EventHandler,
+) -> anyhow::Result<RecommendedWatcher> {
+    let mut watcher = RecommendedWatcher::new(handler, Config::default())
+        .context("Failed to create a directory watcher")?;
+    watcher
+        .watch(watch_dir, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch directory: '{}'", watch_dir.display()))?;
+    Ok(watcher)
+}
+
+/// Build a matcher from the `.gitignore` file at the root of `watch_dir` and
+/// the `exclude` list in `moon.mod.json`. Failing to read either of them is
+/// not fatal -- we simply watch more files than necessary.
+pub(crate) fn build_ignore_matcher(watch_dir: &Path) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(watch_dir);
+
+    let gitignore = watch_dir.join(".gitignore");
+    if gitignore.exists()
+        && let Some(e) = builder.add(&gitignore)
+    {
+        warn!("Failed to read `{}`: {}", gitignore.display(), e);
+    }
+
+    if let Ok(moon_mod) = moonutil::common::read_module_desc_file_in_dir(watch_dir) {
+        for pattern in moon_mod.exclude.iter().flatten() {
+            if let Err(e) = builder.add_line(None, pattern) {
+                warn!(
+                    "Invalid exclude pattern `{}` in moon.mod.json: {}",
+                    pattern, e
+                );
+            }
+        }
+    }
 
-        EventKind::Create(_) => (),
-        EventKind::Modify(_) => (),
-        EventKind::Remove(_) => (),
+    builder.build().unwrap_or_else(|e| {
+        warn!("Failed to build ignore rules for watch mode: {}", e);
+        Gitignore::empty()
+    })
+}
+
+pub(crate) fn is_path_ignored(path: &Path, ignore: &Gitignore, original_target_dir: &Path) -> bool {
+    // Skip if the change happens in the target dir, which is related to the
+    // build output (of any kind) and should not trigger a rebuild.
+    if path.starts_with(original_target_dir) {
+        return true;
+    }
+    // `Gitignore` panics on paths outside of its root
+    if !path.starts_with(ignore.path()) {
+        return false;
+    }
+    ignore
+        .matched_path_or_any_parents(path, path.is_dir())
+        .is_ignore()
+}
+
+/// Returns the list of paths in the event that should trigger a rerun.
+fn relevant_paths<'a>(
+    event: &'a notify::Event,
+    ignore: &Gitignore,
+    original_target_dir: &Path,
+) -> impl Iterator<Item = &'a PathBuf> {
+    let relevant_kind = match event.kind {
+        EventKind::Modify(notify::event::ModifyKind::Metadata(_)) => false,
+
+        EventKind::Create(_) => true,
+        EventKind::Modify(_) => true,
+        EventKind::Remove(_) => true,
         _ => {
             info!(
                 "Unknown file event: {:?}. Currently we skip them, but if this is a problem, please report to the developers.",
                 event
             );
-            return false;
+            false
         }
     };
 
-    // Skip if the change happens in the target dir, which is related to the
-    // build output (of any kind) and should not trigger a rebuild.
-    if event
+    event
         .paths
         .iter()
-        .all(|p| p.starts_with(original_target_dir))
-    {
-        return false;
-    }
-    true
+        .filter(move |p| relevant_kind && !is_path_ignored(p, ignore, original_target_dir))
 }
 
 /// Determine if we should rerun based on the event, and run if so.
 fn handle_file_change(
-    run: impl FnOnce() -> anyhow::Result<i32>,
+    run: impl FnOnce(Option<&[PathBuf]>) -> anyhow::Result<i32>,
+    flags: &WatchFlags,
+    ignore: &Gitignore,
     target_dir: &Path,
     original_target_dir: &Path,
     event_lst: &[notify::Event],
 ) -> anyhow::Result<()> {
-    let is_relevant = event_lst
+    let mut changed = event_lst
         .iter()
-        .any(|evt| is_event_relevant(evt, original_target_dir));
+        .flat_map(|evt| relevant_paths(evt, ignore, original_target_dir))
+        .cloned()
+        .collect::<Vec<_>>();
 
-    if !is_relevant {
+    if changed.is_empty() {
         return Ok(());
     }
+    changed.sort();
+    changed.dedup();
 
     // prevent the case that the whole target_dir was deleted
     // FIXME: legacy code, might not need it
@@ -144,14 +222,21 @@ fn handle_file_change(
         })?;
     }
 
-    run_and_print(run);
+    run_and_print(|| run(Some(&changed)), flags);
     Ok(())
 }
 
-/// Clear the terminal and run the given function, printing success or error
-fn run_and_print(run: impl FnOnce() -> anyhow::Result<i32>) {
-    print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
-    let result = run();
+/// Clear the terminal (unless `--no-clear`) and run the given function,
+/// printing success or error. If the run succeeds and `--exec` is given, the
+/// follow-up command is executed afterwards.
+fn run_and_print(run: impl FnOnce() -> anyhow::Result<i32>, flags: &WatchFlags) {
+    if !flags.no_clear {
+        print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
+    }
+    let result = run().and_then(|code| match (code, flags.exec.as_deref()) {
+        (0, Some(exec)) => run_exec(exec),
+        _ => Ok(code),
+    });
     match result {
         Ok(0) => {
             println!(
@@ -174,3 +259,118 @@ fn run_and_print(run: impl FnOnce() -> anyhow::Result<i32>) {
         }
     }
 }
+
+/// Run the `--exec` command after a successful build.
+fn run_exec(exec: &str) -> anyhow::Result<i32> {
+    let args = shlex::split(exec).with_context(|| format!("Failed to parse command: `{exec}`"))?;
+    let Some((program, args)) = args.split_first() else {
+        return Ok(0);
+    };
+    let status = std::process::Command::new(program)
+        .args(args)
+        .status()
+        .with_context(|| format!("Failed to execute command: `{exec}`"))?;
+    Ok(status.code().unwrap_or(-1))
+}
+
+/// Find the package owning the changed `path`, given `(id, root_path)` pairs.
+///
+/// Returns `None` for files outside of any package, such as `moon.mod.json`.
+/// Callers should fall back to a full rerun in that case.
+pub fn find_owning_package<'a, T>(
+    path: &Path,
+    packages: impl Iterator<Item = (T, &'a Path)>,
+) -> Option<T> {
+    // Nested packages are allowed, so pick the deepest package root
+    packages
+        .filter(|(_, root)| path.starts_with(root))
+        .max_by_key(|(_, root)| root.components().count())
+        .map(|(id, _)| id)
+}
+
+/// Extend `affected` with every package that transitively depends on one of
+/// its packages, given a function returning the direct dependents of a
+/// package.
+pub fn propagate_to_dependents<T, I>(affected: &mut HashSet<T>, dependents: impl Fn(&T) -> I)
+where
+    T: Eq + Hash + Clone,
+    I: IntoIterator<Item = T>,
+{
+    let mut queue = affected.iter().cloned().collect::<Vec<_>>();
+    while let Some(pkg) = queue.pop() {
+        for dependent in dependents(&pkg) {
+            if affected.insert(dependent.clone()) {
+                queue.push(dependent);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_owning_package() {
+        let packages = [
+            ("lib", Path::new("/m/src/lib")),
+            ("lib/nested", Path::new("/m/src/lib/nested")),
+            ("libx", Path::new("/m/src/libx")),
+        ];
+        let owner = |path: &str| find_owning_package(Path::new(path), packages.iter().copied());
+        assert_eq!(owner("/m/src/lib/a.mbt"), Some("lib"));
+        assert_eq!(owner("/m/src/lib/nested/b.mbt"), Some("lib/nested"));
+        assert_eq!(owner("/m/src/libx/c.mbt"), Some("libx"));
+        assert_eq!(owner("/m/moon.mod.json"), None);
+    }
+
+    #[test]
+    fn test_propagate_to_dependents() {
+        // a <- b <- c, a <- d, e is unrelated
+        let dependents = |pkg: &&str| match *pkg {
+            "a" => vec!["b", "d"],
+            "b" => vec!["c"],
+            _ => vec![],
+        };
+
+        let mut affected = HashSet::from(["b"]);
+        propagate_to_dependents(&mut affected, dependents);
+        assert_eq!(affected, HashSet::from(["b", "c"]));
+
+        let mut affected = HashSet::from(["a"]);
+        propagate_to_dependents(&mut affected, dependents);
+        assert_eq!(affected, HashSet::from(["a", "b", "c", "d"]));
+
+        let mut affected = HashSet::from(["e"]);
+        propagate_to_dependents(&mut affected, dependents);
+        assert_eq!(affected, HashSet::from(["e"]));
+    }
+
+    #[test]
+    fn test_ignore_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dunce::canonicalize(dir.path()).unwrap();
+        std::fs::write(root.join(".gitignore"), "*.log\ngenerated/\n").unwrap();
+        std::fs::write(
+            root.join("moon.mod.json"),
+            r#"{ "name": "m", "exclude": ["fixtures"] }"#,
+        )
+        .unwrap();
+        std::fs::create_dir_all(root.join("generated")).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+
+        let ignore = build_ignore_matcher(&root);
+        let target_dir = root.join("target");
+        let ignored = |path: &str| is_path_ignored(&root.join(path), &ignore, &target_dir);
+        assert!(ignored("build.log"));
+        assert!(ignored("generated/a.mbt"));
+        assert!(ignored("fixtures/data.txt"));
+        assert!(ignored("target/wasm-gc/debug/build/main.wasm"));
+        assert!(!ignored("src/main.mbt"));
+        assert!(!is_path_ignored(
+            Path::new("/elsewhere/a.mbt"),
+            &ignore,
+            &target_dir
+        ));
+    }
+}
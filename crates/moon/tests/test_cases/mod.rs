@@ -2597,6 +2597,363 @@ fn test_moon_update_failed() {
     );
 }
 
+#[test]
+fn test_watch_reruns_affected_packages() {
+    use std::io::BufRead;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    let dir = TestDir::new("watch_affected.in");
+    let mut child = std::process::Command::new(moon_bin())
+        .current_dir(&dir)
+        .args(["test", "--watch", "--no-clear"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in std::io::BufReader::new(stdout)
+            .lines()
+            .map_while(Result::ok)
+        {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    const DEADLINE: Duration = Duration::from_secs(120);
+    const END_OF_RUN: &str = "waiting for filesystem changes";
+
+    let started = Instant::now();
+    let mut initial = String::new();
+    while !initial.contains(END_OF_RUN) {
+        let line = rx
+            .recv_timeout(DEADLINE.saturating_sub(started.elapsed()))
+            .expect("the initial run did not finish in time");
+        initial.push_str(&line);
+        initial.push('\n');
+    }
+    assert!(initial.contains("Total tests: 3, passed: 3"), "{initial}");
+
+    // Write `content` to `path` until a run printing `expected` shows up. The
+    // watcher may not be watching yet right after a run, so a single write
+    // can go unnoticed; runs triggered by earlier writes are skipped.
+    let edit_until = |path: &str, content: &str, expected: &str| {
+        let started = Instant::now();
+        let mut retry_after = Duration::ZERO;
+        let mut last_write = started;
+        let mut runs = vec![];
+        let mut current = vec![];
+        while started.elapsed() < DEADLINE {
+            if current.is_empty() && last_write.elapsed() >= retry_after {
+                std::fs::write(dir.join(path), content).unwrap();
+                last_write = Instant::now();
+                retry_after = (retry_after * 2).max(Duration::from_millis(500));
+            }
+            let Ok(line) = rx.recv_timeout(Duration::from_millis(100)) else {
+                continue;
+            };
+            let done = line.contains(END_OF_RUN);
+            current.push(line);
+            if done {
+                let run = current.join("\n");
+                current.clear();
+                if run.contains(expected) {
+                    return;
+                }
+                runs.push(run);
+            }
+        }
+        panic!(
+            "no run printed `{expected}` within {DEADLINE:?}:\n{}",
+            runs.join("\n---\n")
+        );
+    };
+
+    // Only `other` is affected by this change
+    edit_until(
+        "other/other.mbt",
+        "///|\npub fn three() -> Int {\n  1 + 2\n}\n\n///|\ntest {\n  assert_eq(three(), 3)\n}\n",
+        "Total tests: 1, passed: 1",
+    );
+
+    // `app` imports `lib`, so both are rerun
+    edit_until(
+        "lib/lib.mbt",
+        "///|\npub fn one() -> Int {\n  2 - 1\n}\n\n///|\ntest {\n  assert_eq(one(), 1)\n}\n",
+        "Total tests: 2, passed: 2",
+    );
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_build_server() {
+    use std::process::{Child, Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    /// Stops the server even if the test fails halfway
+    struct Server(Child);
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    let dir = TestDir::new("hello");
+    let server = Server(
+        Command::new(moon_bin())
+            .current_dir(&dir)
+            .arg("server")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let socket = dir.join("target/moon.server.sock");
+    let start = Instant::now();
+    while !socket.exists() {
+        assert!(
+            start.elapsed() < Duration::from_secs(60),
+            "server did not start"
+        );
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let forwarded = |envs: &[(&str, &str)]| {
+        Command::new(moon_bin())
+            .current_dir(&dir)
+            .args(["build", "-Z", "build_server"])
+            .envs(envs.iter().copied())
+            .output()
+            .unwrap()
+    };
+    assert!(forwarded(&[]).status.success());
+
+    // A package added right before the request is picked up
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(dir.join("lib/moon.pkg.json"), "{}").unwrap();
+    std::fs::write(
+        dir.join("lib/lib.mbt"),
+        "///|\npub fn greeting() -> String {\n  \"Hello\"\n}\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("main/moon.pkg.json"),
+        r#"{ "is-main": true, "import": ["hello/lib"] }"#,
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("main/main.mbt"),
+        "fn main {\n  println(@lib.greeting())\n}\n",
+    )
+    .unwrap();
+    let out = forwarded(&[]);
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    // Errors go to the client's stderr, colored according to the client's
+    // environment
+    std::fs::write(dir.join("main/main.mbt"), "fn main {\n  @lib.nope()\n}\n").unwrap();
+    let plain = forwarded(&[("NO_COLOR", "1")]);
+    assert!(!plain.status.success());
+    assert!(!String::from_utf8_lossy(&plain.stderr).contains('\x1b'));
+    let colored = forwarded(&[("CLICOLOR_FORCE", "1")]);
+    assert!(!colored.status.success());
+    assert!(String::from_utf8_lossy(&colored.stderr).contains('\x1b'));
+
+    let stop = Command::new(moon_bin())
+        .current_dir(&dir)
+        .args(["server", "--stop"])
+        .output()
+        .unwrap();
+    assert!(stop.status.success());
+    drop(server);
+}
+
+#[test]
+fn test_build_timings() {
+    let dir = TestDir::new("hello");
+    get_stdout(&dir, ["build", "--target", "wasm-gc,js", "--timings"]);
+    // one report for each backend
+    for backend in ["wasm-gc", "js"] {
+        let timings = dir.join("target").join("timings");
+        assert!(timings.join(format!("{backend}.html")).exists());
+        let content = read(timings.join(format!("{backend}.json")));
+        let report: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(report["backend"], backend);
+        let tasks = report["tasks"].as_array().unwrap();
+        assert!(tasks.iter().any(|t| t["package"] == "hello/main"));
+        let critical_path = report["critical_path"].as_array().unwrap();
+        assert!(!critical_path.is_empty());
+    }
+}
+
+#[test]
+fn test_build_explain() {
+    let dir = TestDir::new("hello");
+    get_stdout(&dir, ["build"]);
+
+    let touch = || {
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("main/main.mbt"))
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+    };
+
+    touch();
+    let stderr = get_stderr(&dir, ["build", "--explain"]);
+    assert!(stderr.contains("main/main.mbt changed"), "{stderr}");
+
+    touch();
+    let stdout = get_stdout(&dir, ["build", "--explain", "json"]);
+    let explanations = stdout
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert!(explanations.iter().any(|x| {
+        x["reason"] == "input-changed"
+            && x["inputs"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|input| input.as_str().unwrap().ends_with("main.mbt"))
+    }));
+
+    // Release and debug builds have their own directories, so change the
+    // command lines of release steps without moving their outputs
+    get_stdout(&dir, ["build", "--release"]);
+    let stdout = get_stdout(
+        &dir,
+        ["build", "--release", "--no-strip", "--explain", "json"],
+    );
+    let reasons = stdout
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["reason"].clone())
+        .collect::<Vec<_>>();
+    assert!(reasons.iter().any(|x| x == "command-changed"), "{stdout}");
+
+    let stderr = get_stderr(&dir, ["build", "--release", "--explain"]);
+    assert!(stderr.contains("command line changed"), "{stderr}");
+}
+
+#[test]
+#[cfg(unix)]
+fn test_build_cache_shared_between_checkouts() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let cache = tempfile::tempdir().unwrap();
+    let envs = [("MOON_BUILD_CACHE_DIR", cache.path())];
+    let count_entries = || {
+        WalkDir::new(cache.path())
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().is_some_and(|x| x == "zip"))
+            .count()
+    };
+
+    let first = TestDir::new("hello");
+    get_stdout_with_envs(&first, ["build"], envs);
+    let entries = count_entries();
+    assert!(entries > 0);
+
+    // Debug info embeds absolute paths, so debug builds are not cached
+    get_stdout_with_envs(&first, ["build", "--debug"], envs);
+    assert_eq!(count_entries(), entries);
+
+    // A second checkout of the same project only hits the cache, so a
+    // `moonc` that fails on anything but `-v` is never noticed
+    let moonc_version = std::process::Command::new("moonc")
+        .arg("-v")
+        .output()
+        .unwrap()
+        .stdout;
+    let moonc_version = String::from_utf8(moonc_version).unwrap();
+    let fake_bin = tempfile::tempdir().unwrap();
+    let fake_moonc = fake_bin.path().join("moonc");
+    std::fs::write(
+        &fake_moonc,
+        format!(
+            r#"#!/bin/sh
+if [ "$1" = "-v" ]; then
+  echo '{}'
+  exit 0
+fi
+echo "moonc should not run on a cache hit: $*" >&2
+exit 1
+"#,
+            moonc_version.trim()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&fake_moonc, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = std::env::join_paths(
+        std::iter::once(fake_bin.path().to_path_buf())
+            .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+
+    let second = TestDir::new("hello");
+    get_stdout_with_envs(
+        &second,
+        ["build"],
+        [
+            ("MOON_BUILD_CACHE_DIR", cache.path().as_os_str()),
+            ("PATH", path.as_os_str()),
+        ],
+    );
+    assert_eq!(count_entries(), entries);
+    let outputs = WalkDir::new(second.join("target"))
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().is_some_and(|x| x == "wasm"))
+        .count();
+    assert!(outputs > 0);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_build_cache_skips_c_stubs() {
+    let cache = tempfile::tempdir().unwrap();
+    let dir = TestDir::new("native_stub.in");
+    let native_1 = dir.join("native_1.in");
+
+    // Only `moonc` steps are cached, as the key of a C stub would miss the C
+    // compiler and the headers it includes
+    let dry_run = get_stdout(&native_1, ["build", "--target", "native", "--dry-run"]);
+    let moonc_steps = dry_run
+        .lines()
+        .filter(|line| {
+            (line.starts_with("moonc build-package ") || line.starts_with("moonc link-core "))
+                && !line.split(' ').any(|arg| arg == "-g")
+        })
+        .count();
+    assert!(dry_run.contains("stub1.c"), "{dry_run}");
+
+    get_stdout_with_envs(
+        &native_1,
+        ["build", "--target", "native"],
+        [("MOON_BUILD_CACHE_DIR", cache.path())],
+    );
+    let entries = WalkDir::new(cache.path())
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().is_some_and(|x| x == "zip"))
+        .count();
+    assert_eq!(entries, moonc_steps);
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TraceResult(Vec<TraceEvent>);
 
@@ -3065,6 +3422,55 @@ fn test_use_cc_for_native_release() {
     }
 }
 
+#[cfg(unix)]
+#[test]
+fn test_native_test_sanitize() {
+    let dir = TestDir::new("moon_test/hello_exec_fntest");
+    let out = get_stdout(
+        &dir,
+        [
+            "test",
+            "--target",
+            "native",
+            "--sanitize",
+            "address,undefined",
+            "--sort-input",
+            "--dry-run",
+        ],
+    );
+    // sanitized executables are never run by `tcc -run`
+    assert!(!out.contains("libruntime"));
+    let sanitized = out
+        .lines()
+        .filter(|line| line.contains(".exe"))
+        .collect::<Vec<_>>();
+    assert!(!sanitized.is_empty());
+    for line in sanitized {
+        assert!(
+            line.contains(
+                "-fsanitize=address,undefined -fno-omit-frame-pointer -fno-sanitize-recover=undefined"
+            ),
+            "{line}"
+        );
+    }
+
+    let err = get_err_stderr(
+        &dir,
+        ["test", "--target", "native", "--sanitize", "address,thread"],
+    );
+    assert!(err.contains("`--sanitize address` cannot be combined with `--sanitize thread`"));
+}
+
+#[test]
+fn test_module_runners() {
+    let dir = TestDir::new("module_runners.in");
+    let out = get_stdout(&dir, ["run", "main", "--target", "js", "--", "a", "b"]);
+    let out = out.trim();
+    // the runner replaces node, receiving the artifact and the program args
+    assert!(out.starts_with("runner: "), "{out}");
+    assert!(out.ends_with("main.js a b"), "{out}");
+}
+
 #[test]
 fn test_moon_package_list() {
     let dir = TestDir::new("test_publish.in");
@@ -3218,6 +3624,180 @@ fn test_native_backend_cc_flags() {
     );
 }
 
+#[test]
+#[cfg(unix)]
+fn test_native_cross_triple() {
+    let dir = TestDir::new("native_backend_cc_flags.in");
+    let out = get_stdout_with_envs(
+        &dir,
+        [
+            "build",
+            "--target",
+            "native",
+            "--triple",
+            "aarch64-unknown-linux-gnu",
+            "--dry-run",
+            "--sort-input",
+        ],
+        [("MOON_CC", "clang")],
+    );
+    assert!(out.contains(
+        "clang --target=aarch64-unknown-linux-gnu -o ./target/native/aarch64-unknown-linux-gnu/release/build/runtime.o"
+    ));
+    // the prebuilt runtime only works on the host
+    assert!(!out.contains("libmoonbitrun.o"));
+
+    let err = get_err_stderr(
+        &dir,
+        [
+            "build",
+            "--target",
+            "wasm-gc",
+            "--triple",
+            "aarch64-unknown-linux-gnu",
+        ],
+    );
+    assert!(err.contains("`--triple` is only supported for the native backend"));
+}
+
+#[test]
+fn test_build_profile() {
+    let dir = TestDir::new("build_profile.in");
+    let out = get_stdout(
+        &dir,
+        [
+            "build",
+            "--build-profile",
+            "bench",
+            "--dry-run",
+            "--sort-input",
+        ],
+    );
+    assert!(out.contains("-o ./target/wasm-gc/bench/build/main/main.core"));
+    assert!(out.contains(" -g "));
+    assert!(!out.contains(" -O0"));
+    assert!(out.contains(" -w -2"));
+
+    // built-in profiles keep their usual directories
+    let out = get_stdout(
+        &dir,
+        [
+            "build",
+            "--build-profile",
+            "debug",
+            "--dry-run",
+            "--sort-input",
+        ],
+    );
+    assert!(out.contains("-o ./target/wasm-gc/debug/build/main/main.core"));
+
+    get_stdout(&dir, ["build", "--build-profile", "bench"]);
+    assert!(
+        dir.join("target/wasm-gc/bench/build/main/main.wasm")
+            .exists()
+    );
+
+    // other commands share the flag
+    let out = get_stdout(
+        &dir,
+        [
+            "test",
+            "--build-profile",
+            "bench",
+            "--dry-run",
+            "--sort-input",
+        ],
+    );
+    assert!(out.contains("./target/wasm-gc/bench/"), "{out}");
+
+    let err = get_err_stderr(&dir, ["build", "--build-profile", "nope"]);
+    assert!(err.contains("profile `nope` is not defined in `moon.mod.json`"));
+}
+
+#[test]
+fn test_cfg_features() {
+    let dir = TestDir::new("cfg_features.in");
+    check(
+        get_stdout(&dir, ["run", "main"]),
+        expect![[r#"
+            slow
+            wasmgc
+        "#]],
+    );
+    // `extra` enables `fast`
+    check(
+        get_stdout(&dir, ["run", "main", "--features", "extra"]),
+        expect![[r#"
+            fast
+            wasmgc
+        "#]],
+    );
+    check(
+        get_stdout(&dir, ["run", "main", "--target", "js"]),
+        expect![[r#"
+            slow
+            other
+        "#]],
+    );
+
+    let err = get_err_stderr(&dir, ["check", "--features", "nope"]);
+    assert!(err.contains("feature `nope` is not declared in `moon.mod.json`"));
+
+    std::fs::write(
+        dir.join("main/moon.pkg.json"),
+        r#"{ "is-main": true, "targets": { "greet_fast.mbt": "feature(gpu)" } }"#,
+    )
+    .unwrap();
+    let err = get_err_stderr(&dir, ["check"]);
+    assert!(err.contains("`greet_fast.mbt` depends on feature `gpu`"));
+}
+
+#[test]
+fn test_optional_deps() {
+    let dir = TestDir::new("optional_deps.in");
+    check(
+        get_stdout(&dir, ["run", "main"]),
+        expect![[r#"
+            zlib
+            deflate(hello)
+        "#]],
+    );
+
+    // Without the `zlib` feature, `just/zlib` is never resolved and the
+    // package of `just/codec` importing it is left out
+    std::fs::write(
+        dir.join("moon.mod.json"),
+        r#"{ "name": "username/app", "deps": { "just/codec": { "path": "./deps/codec" } } }"#,
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("main/moon.pkg.json"),
+        r#"{ "is-main": true, "import": ["just/codec/lib"] }"#,
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("main/main.mbt"),
+        "fn main {\n  println(@lib.name())\n}\n",
+    )
+    .unwrap();
+    std::fs::remove_dir_all(dir.join("deps/zlib")).unwrap();
+    check(
+        get_stdout(&dir, ["run", "main"]),
+        expect![[r#"
+            plain
+        "#]],
+    );
+    let stderr = get_stderr(&dir, ["check"]);
+    assert!(
+        stderr.contains(
+            "Skipping package `just/codec/compress` because it imports `just/zlib/lib` of \
+             optional dependency `just/zlib`, which is not enabled; enable feature `zlib` of \
+             module `just/codec` to build it"
+        ),
+        "{stderr}"
+    );
+}
+
 #[test]
 #[cfg(unix)]
 fn test_native_backend_cc_flags_with_env_override() {
@@ -4729,6 +5309,186 @@ fn test_native_stub_in_pkg_json() {
     );
 }
 
+#[test]
+fn test_native_compile_commands_json() {
+    let dir = TestDir::new("native_stub.in");
+    let native_1 = dir.join("native_1.in");
+
+    get_stdout(&native_1, ["build", "--target", "native"]);
+    let content =
+        std::fs::read_to_string(native_1.join("target").join("compile_commands.json")).unwrap();
+    let commands: Vec<serde_json::Value> = serde_json::from_str(&content).unwrap();
+
+    let mut stubs = commands
+        .iter()
+        .map(|c| c["file"].as_str().unwrap().replace('\\', "/"))
+        .filter(|f| f.contains("/lib/stub"))
+        .collect::<Vec<_>>();
+    stubs.sort();
+    assert_eq!(stubs.len(), 2);
+    assert!(stubs[0].ends_with("lib/stub1.c"));
+    assert!(stubs[1].ends_with("lib/stub2.c"));
+
+    for c in &commands {
+        let file = c["file"].as_str().unwrap();
+        let c_files = c["arguments"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|a| a.as_str())
+            .filter(|a| a.ends_with(".c"))
+            .collect::<Vec<_>>();
+        // each entry compiles exactly its own C file
+        assert_eq!(c_files, [file]);
+    }
+    // the generated C of the main package is listed as well
+    assert!(
+        commands
+            .iter()
+            .any(|c| c["file"].as_str().unwrap().ends_with("main.c"))
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_native_library() {
+    let dir = TestDir::new("native_library.in");
+    get_stdout(&dir, ["build", "--target", "native"]);
+
+    let build_dir = dir.join("target/native/release/build");
+    assert!(
+        build_dir
+            .join(format!("dlib/dlib.{}", moonutil::common::DYN_EXT))
+            .exists()
+    );
+    let header = std::fs::read_to_string(build_dir.join("dlib/dlib.h")).unwrap();
+    assert!(
+        header.contains("int32_t multiply(int32_t, int32_t);"),
+        "{header}"
+    );
+
+    // a C program linking the static library
+    let exe = build_dir.join("c_main");
+    let status = std::process::Command::new("cc")
+        .arg(dir.join("c/main.c"))
+        .arg(format!("-I{}", build_dir.join("slib").display()))
+        .arg(format!(
+            "-I{}",
+            moonutil::moon_dir::MOON_DIRS.moon_include_path.display()
+        ))
+        .arg(build_dir.join("slib/slib.a"))
+        .arg("-lm")
+        .arg("-o")
+        .arg(&exe)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = std::process::Command::new(&exe).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+}
+
+#[test]
+#[cfg(unix)]
+fn test_native_system_deps() {
+    let dir = TestDir::new("native_system_deps.in");
+    let has_zlib = std::process::Command::new("pkg-config")
+        .args(["--exists", "zlib"])
+        .status()
+        .is_ok_and(|s| s.success());
+    if has_zlib {
+        check(
+            get_stdout(&dir, ["run", "main", "--target", "native"]),
+            expect![[r#"
+                1
+            "#]],
+        );
+    }
+
+    std::fs::write(
+        dir.join("zlib/moon.pkg.json"),
+        r#"{
+  "native-stub": ["stub.c"],
+  "link": { "native": { "system-deps": { "moonbit-no-such-lib": "*" } } }
+}"#,
+    )
+    .unwrap();
+    let stderr = get_err_stderr(&dir, ["build", "--target", "native"]);
+    assert!(
+        stderr.contains("system library `moonbit-no-such-lib`"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("username/native_system_deps/zlib"),
+        "{stderr}"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_native_system_deps_fake_pkg_config() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TestDir::new("native_system_deps.in");
+    let pkg_config = dir.join("fake-pkg-config");
+    std::fs::write(
+        &pkg_config,
+        r#"#!/bin/sh
+case "$1" in
+  --exists) [ "$3" = "zlib >= 1.2" ] || { echo "Package $3 was not found" >&2; exit 1; } ;;
+  --cflags) echo "-I$PKG_CONFIG_SYSROOT_DIR/fake/include -DFAKE_ZLIB" ;;
+  --libs) echo "-L$PKG_CONFIG_SYSROOT_DIR/fake/lib -lfakez" ;;
+esac
+"#,
+    )
+    .unwrap();
+    std::fs::set_permissions(&pkg_config, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let pkg_config = pkg_config.to_str().unwrap();
+
+    let out = get_stdout_with_envs(
+        &dir,
+        ["build", "--target", "native", "--dry-run", "--sort-input"],
+        [("PKG_CONFIG", pkg_config)],
+    );
+    assert!(out.contains("-I/fake/include -DFAKE_ZLIB"), "{out}");
+    assert!(out.contains("-L/fake/lib -lfakez"), "{out}");
+
+    // The host libraries don't do for another target
+    let cross = [
+        "build",
+        "--target",
+        "native",
+        "--triple",
+        "aarch64-unknown-linux-gnu",
+        "--dry-run",
+        "--sort-input",
+    ];
+    let stderr = get_err_stderr_without_replace(
+        &dir,
+        cross,
+        [("PKG_CONFIG", pkg_config), ("MOON_CC", "clang")],
+    );
+    assert!(
+        stderr.contains("for target `aarch64-unknown-linux-gnu`"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("PKG_CONFIG_SYSROOT_DIR"), "{stderr}");
+
+    let out = get_stdout_with_envs(
+        &dir,
+        cross,
+        [
+            ("PKG_CONFIG", pkg_config),
+            ("MOON_CC", "clang"),
+            (
+                "PKG_CONFIG_SYSROOT_DIR_aarch64_unknown_linux_gnu",
+                "/sysroot",
+            ),
+        ],
+    );
+    assert!(out.contains("-I/sysroot/fake/include -DFAKE_ZLIB"), "{out}");
+    assert!(out.contains("-L/sysroot/fake/lib -lfakez"), "{out}");
+}
+
 #[test]
 fn test_run_md_test() {
     let dir = TestDir::new("run_md_test.in");
@@ -5791,3 +6551,53 @@ fn moon_test_target_js_panic_with_sourcemap() {
             Total tests: 1, passed: 0, failed: 1."#]],
     );
 }
+
+#[test]
+fn test_moon_test_limits() {
+    let dir = TestDir::new("test_limits.in");
+
+    let output = snapbox::cmd::Command::new(moon_bin())
+        .current_dir(&dir)
+        .args(["test", "--target", "wasm-gc"])
+        .assert()
+        .failure()
+        .get_output()
+        .to_owned();
+    let out = String::from_utf8_lossy(&output.stderr);
+    assert!(out.contains("wall time limit of 1s exceeded"), "{out}");
+    // the limit is blamed on the test that hit it
+    let out = String::from_utf8_lossy(&output.stdout);
+    assert!(out.contains("spin"), "{out}");
+    assert!(out.contains("wall time limit of 1s exceeded"), "{out}");
+    assert!(!out.contains("MOONRUN LIMIT EXCEEDED"), "{out}");
+
+    std::fs::write(
+        dir.join("lib/moon.pkg.json"),
+        r#"{ "test-limits": { "max-heap": "12T" } }"#,
+    )
+    .unwrap();
+    let out = snapbox::cmd::Command::new(moon_bin())
+        .current_dir(&dir)
+        .args(["test"])
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .to_owned();
+    let out = String::from_utf8_lossy(&out);
+    assert!(
+        out.contains("invalid `max-heap` in `test-limits`: invalid size unit in `12T`"),
+        "{out}"
+    );
+
+    let out = snapbox::cmd::Command::new(moon_bin())
+        .current_dir(&dir)
+        .args(["test", "--max-wall-time", "soon"])
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .to_owned();
+    let out = String::from_utf8_lossy(&out);
+    assert!(out.contains("invalid duration `soon`"), "{out}");
+}
@@ -37,6 +37,7 @@ fn test_moon_help() {
               doc                    Generate documentation
               info                   Generate public interface (`.mbti`) files for all packages in the module
               bench                  Run benchmarks in the current package
+              server                 Start a build server that keeps the resolved project in memory between builds
               add                    Add a dependency
               remove                 Remove a dependency
               install                Install dependencies
@@ -0,0 +1,8 @@
+#include <stdio.h>
+
+#include "slib.h"
+
+int main(void) {
+  printf("%d\n", add(1, 2));
+  return 0;
+}
@@ -29,6 +29,7 @@ use moonutil::{
     common::{MBTI_GENERATED, RunMode, TargetBackend},
     cond_expr::OptLevel,
     mooncakes::{ModuleName, ModuleSource},
+    package::NativeLinkKind,
 };
 
 use crate::{
@@ -56,8 +57,16 @@ pub struct LegacyLayout {
 
     /// The optimization level, debug or release
     opt_level: OptLevel,
+    /// The named build profile, whose outputs go to a directory of its name
+    /// instead of that of `opt_level`
+    #[builder(default)]
+    profile: Option<String>,
     /// The operation done
     run_mode: RunMode,
+    /// The triple when cross-compiling, whose outputs are kept apart from the
+    /// host ones under the backend directory
+    #[builder(default)]
+    target_triple: Option<String>,
 }
 
 const LEGACY_NON_MAIN_MODULE_DIR: &str = ".mooncakes";
@@ -96,13 +105,16 @@ impl LegacyLayout {
     /// For modules determined as the "main module", this path is
     /// `target/<backend>[/<opt_level>/build]/<...package>/`. Otherwise, it's
     /// `target/<backend>[/<opt_level>/build]/.mooncakes/<...module>/<...package>`.
+    /// When cross-compiling, the triple follows `<backend>`. A named profile
+    /// replaces `<opt_level>`.
     pub fn package_dir(&self, pkg: &PackageFQN, backend: TargetBackend) -> PathBuf {
         let mut dir = self.target_base_dir.clone();
-        push_backend(&mut dir, backend);
+        self.push_backend(&mut dir, backend);
 
-        match self.opt_level {
-            OptLevel::Release => dir.push("release"),
-            OptLevel::Debug => dir.push("debug"),
+        match (&self.profile, self.opt_level) {
+            (Some(profile), _) => dir.push(profile),
+            (None, OptLevel::Release) => dir.push("release"),
+            (None, OptLevel::Debug) => dir.push("debug"),
         }
         dir.push(self.run_mode.to_dir_name());
 
@@ -117,6 +129,13 @@ impl LegacyLayout {
         dir
     }
 
+    fn push_backend(&self, path: &mut PathBuf, backend: TargetBackend) {
+        path.push(backend.to_dir_name());
+        if let Some(triple) = &self.target_triple {
+            path.push(triple);
+        }
+    }
+
     fn push_package_dir_no_backend(&self, dir: &mut PathBuf, pkg: &PackageFQN) {
         if self.main_module.as_ref().is_some_and(|m| pkg.module() == m) {
             // no nested directory for the working module
@@ -229,6 +248,62 @@ impl LegacyLayout {
         base_dir
     }
 
+    /// Returns the path of the library built from a package with
+    /// `link.native.kind` set to `shared` or `static`.
+    ///
+    /// Format: `target/{backend}/{opt_level}/build/{package_path}/{package_name}.{so,a}`
+    pub fn native_library_of_build_target(
+        &self,
+        pkg_list: &DiscoverResult,
+        target: &BuildTarget,
+        backend: TargetBackend,
+        os: OperatingSystem,
+        kind: NativeLinkKind,
+    ) -> PathBuf {
+        let pkg_fqn = &pkg_list.get_package(target.package).fqn;
+        let mut base_dir = self.package_dir(pkg_fqn, backend);
+        let ext = match kind {
+            NativeLinkKind::Shared => dynamic_library_ext(os),
+            NativeLinkKind::Static => static_library_ext(os),
+            NativeLinkKind::Executable => panic!("Executables are not native libraries"),
+        };
+        base_dir.push(format!("{}{}", artifact(pkg_fqn, target.kind), ext));
+        base_dir
+    }
+
+    /// Returns the path of the object compiled from the generated C file of a
+    /// static library, to be archived into it.
+    pub fn native_library_object_of_build_target(
+        &self,
+        pkg_list: &DiscoverResult,
+        target: &BuildTarget,
+        backend: TargetBackend,
+        os: OperatingSystem,
+    ) -> PathBuf {
+        let pkg_fqn = &pkg_list.get_package(target.package).fqn;
+        let mut base_dir = self.package_dir(pkg_fqn, backend);
+        base_dir.push(format!(
+            "{}.c{}",
+            artifact(pkg_fqn, target.kind),
+            object_file_ext(os)
+        ));
+        base_dir
+    }
+
+    /// Returns the path of the C header declaring the exports of a native
+    /// library.
+    pub fn c_header_of_build_target(
+        &self,
+        pkg_list: &DiscoverResult,
+        target: &BuildTarget,
+        backend: TargetBackend,
+    ) -> PathBuf {
+        let pkg_fqn = &pkg_list.get_package(target.package).fqn;
+        let mut base_dir = self.package_dir(pkg_fqn, backend);
+        base_dir.push(format!("{}.h", artifact(pkg_fqn, target.kind)));
+        base_dir
+    }
+
     pub fn generated_test_driver(
         &self,
         pkg_list: &DiscoverResult,
@@ -262,14 +337,14 @@ impl LegacyLayout {
 
     pub fn bundle_result_path(&self, backend: TargetBackend, module: &ModuleName) -> PathBuf {
         let mut result = self.target_base_dir.clone();
-        push_backend(&mut result, backend);
+        self.push_backend(&mut result, backend);
         result.push(format!("{}.core", module.last_segment()));
         result
     }
 
     pub fn runtime_output_path(&self, backend: TargetBackend, os: OperatingSystem) -> PathBuf {
         let mut result = self.target_base_dir.clone();
-        push_backend(&mut result, backend);
+        self.push_backend(&mut result, backend);
         result.push(format!("runtime{}", object_file_ext(os)));
         result
     }
@@ -355,10 +430,6 @@ impl LegacyLayout {
     }
 }
 
-fn push_backend(path: &mut PathBuf, backend: TargetBackend) {
-    path.push(backend.to_dir_name())
-}
-
 fn build_kind_suffix(kind: TargetKind) -> &'static str {
     match kind {
         TargetKind::Source => "",
@@ -420,7 +491,6 @@ fn static_library_ext(os: OperatingSystem) -> &'static str {
 }
 
 /// Returns the file extension for dynamic libraries on the given OS
-#[allow(unused)]
 fn dynamic_library_ext(os: OperatingSystem) -> &'static str {
     match os {
         OperatingSystem::Windows => ".dll",
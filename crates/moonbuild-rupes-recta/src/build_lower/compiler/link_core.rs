@@ -57,6 +57,9 @@ pub struct MooncLinkCore<'a> {
     // JavaScript specific configuration
     pub js_config: Option<JsConfig>,
 
+    /// Function exports of the native backend
+    pub native_exports: Option<&'a [String]>,
+
     // Extra options
     pub extra_link_opts: &'a [&'a str],
 }
@@ -200,6 +203,17 @@ impl<'a> MooncLinkCore<'a> {
             }
         }
 
+        // Native exports
+        if self.target_backend == TargetBackend::Native
+            && let Some(exports) = self.native_exports
+        {
+            if exports.is_empty() {
+                args.push("".to_string());
+            } else {
+                args.push(format!("-exported_functions={}", exports.join(",")));
+            }
+        }
+
         // JavaScript configuration
         if let Some(js_config) = &self.js_config {
             if let Some(format) = js_config.format {
@@ -21,7 +21,11 @@
 use std::path::PathBuf;
 
 use log::debug;
-use moonutil::mooncakes::{DirSyncResult, result::ResolvedEnv};
+use moonutil::{
+    common::TargetBackend,
+    mooncakes::{DirSyncResult, result::ResolvedEnv},
+    package::NativeLinkKind,
+};
 use n2::graph::{Build, Graph as N2Graph};
 use tracing::{Level, instrument};
 
@@ -30,7 +34,7 @@ use crate::{
     build_lower::artifact::LegacyLayout,
     build_plan::{BuildPlan, FileDependencyKind},
     discover::{DiscoverResult, DiscoveredPackage},
-    model::{BuildPlanNode, BuildTarget},
+    model::{BuildPlanNode, BuildTarget, TargetKind},
     pkg_solve::DepRelationship,
 };
 
@@ -131,7 +135,10 @@ impl<'a> BuildPlanLowerContext<'a> {
                     .build_plan
                     .get_make_executable_info(&target)
                     .expect("Make executable info should be present for MakeExecutable nodes");
-                self.lower_make_exe(target, info)
+                match self.native_link_kind(target) {
+                    NativeLinkKind::Executable => self.lower_make_exe(target, info),
+                    kind => self.lower_make_native_lib(node, target, info, kind)?,
+                }
             }
             BuildPlanNode::GenerateMbti(target) => self.lower_generate_mbti(target),
             BuildPlanNode::BuildVirtual(target) => self.lower_parse_mbti(node, target),
@@ -271,6 +278,15 @@ impl<'a> BuildPlanLowerContext<'a> {
                     self.opt.output_wat,
                 ));
             }
+            BuildPlanNode::MakeExecutable(target) if self.native_link_kind(target).is_library() => {
+                out.push(self.layout.native_library_of_build_target(
+                    self.packages,
+                    &target,
+                    self.opt.target_backend,
+                    self.opt.os,
+                    self.native_link_kind(target),
+                ))
+            }
             BuildPlanNode::MakeExecutable(target) => {
                 out.push(self.layout.executable_of_build_target(
                     self.packages,
@@ -339,11 +355,52 @@ impl<'a> BuildPlanLowerContext<'a> {
         self.append_artifact_of(node, FileDependencyKind::AllFiles, out);
     }
 
+    /// The kind of native artifact to make from the given target. Only the
+    /// source target of a package may be built into a library.
+    pub(super) fn native_link_kind(&self, target: BuildTarget) -> NativeLinkKind {
+        if self.opt.target_backend != TargetBackend::Native || target.kind != TargetKind::Source {
+            return NativeLinkKind::Executable;
+        }
+        self.get_package(target)
+            .raw
+            .link
+            .as_ref()
+            .and_then(|link| link.native.as_ref()?.kind)
+            .unwrap_or_default()
+    }
+
     fn lowered(&mut self, build: Build) -> Result<(), anyhow::Error> {
         self.graph.add_build(build)?;
         Ok(())
     }
 
+    /// Lower an intermediate step of `node` that has no build plan node of
+    /// its own into a separate n2 build.
+    pub(super) fn lowered_aux(
+        &mut self,
+        node: BuildPlanNode,
+        desc: String,
+        ins: Vec<PathBuf>,
+        outs: Vec<PathBuf>,
+        commandline: Vec<String>,
+    ) -> Result<(), LoweringError> {
+        let ins = build_ins(&mut self.graph, ins);
+        let outs = build_outs(&mut self.graph, outs);
+        let mut build = Build::new(build_n2_fileloc(desc), ins, outs);
+        build.cmdline = Some(
+            shlex::try_join(commandline.iter().map(|x| x.as_str()))
+                .expect("No `nul` should occur here"),
+        );
+        self.lowered(build).map_err(|e| LoweringError::N2 {
+            package: node
+                .extract_target()
+                .map(|x| self.get_package(x).fqn.clone())
+                .into(),
+            node,
+            source: e,
+        })
+    }
+
     /// **For debug use only.** Prints debug information about a specific build
     /// plan node, the n2 build it's mapped into, and the input and output files
     /// of it.
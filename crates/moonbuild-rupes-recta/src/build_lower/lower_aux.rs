@@ -21,8 +21,8 @@
 use moonutil::{
     common::DriverKind,
     compiler_flags::{
-        CC, CCConfigBuilder, OptLevel as CCOptLevel, OutputType as CCOutputType,
-        make_cc_command_pure, resolve_cc,
+        CCConfigBuilder, OptLevel as CCOptLevel, OutputType as CCOutputType, make_cc_command_pure,
+        resolve_cc,
     },
     mooncakes::{ModuleId, ModuleSourceKind},
 };
@@ -129,7 +129,7 @@ impl<'a> super::BuildPlanLowerContext<'a> {
         // TODO: this part might need more simplification?
         let runtime_c_path = self.opt.runtime_dot_c_path.clone();
         let cc_cmd = make_cc_command_pure::<&'static str>(
-            resolve_cc(CC::default(), None),
+            resolve_cc(self.opt.native_cc(), None),
             CCConfigBuilder::default()
                 .no_sys_header(true)
                 .output_ty(CCOutputType::Object)
@@ -22,13 +22,13 @@ use moonutil::{
     common::TargetBackend,
     compiler_flags::{
         ArchiverConfigBuilder, CC, CCConfigBuilder, OptLevel as CCOptLevel,
-        OutputType as CCOutputType, make_archiver_command, make_cc_command, make_cc_command_pure,
-        resolve_cc,
+        OutputType as CCOutputType, make_archiver_command, make_archiver_command_pure,
+        make_cc_command, make_cc_command_pure, resolve_cc,
     },
     cond_expr::OptLevel,
     moon_dir::MOON_DIRS,
     mooncakes::{CORE_MODULE, ModuleId},
-    package::JsFormat,
+    package::{JsFormat, NativeLinkKind},
 };
 use petgraph::Direction;
 use tracing::{Level, instrument};
@@ -47,7 +47,7 @@ use crate::{
     pkg_name::{PackageFQN, PackagePath},
 };
 
-use super::{BuildCommand, compiler, context::BuildPlanLowerContext};
+use super::{BuildCommand, LoweringError, compiler, context::BuildPlanLowerContext};
 
 impl<'a> BuildPlanLowerContext<'a> {
     fn is_module_third_party(&self, mid: ModuleId) -> bool {
@@ -66,6 +66,41 @@ impl<'a> BuildPlanLowerContext<'a> {
         }
     }
 
+    /// The optimization level of the C compiler, overridable by the build
+    /// profile
+    fn cc_opt_level(&self, default: CCOptLevel) -> CCOptLevel {
+        self.opt
+            .profile
+            .as_ref()
+            .and_then(|p| p.config.cc_opt_level)
+            .map(CCOptLevel::from)
+            .unwrap_or(default)
+    }
+
+    /// Whether the C compiler emits debug information, overridable by the
+    /// build profile
+    fn cc_debug_info(&self, default: bool) -> bool {
+        self.opt
+            .profile
+            .as_ref()
+            .and_then(|p| p.config.debug_info)
+            .unwrap_or(default)
+    }
+
+    /// Extra moonc flags of the build profile, for compiling packages or
+    /// linking
+    fn profile_moonc_flags(&self, link: bool) -> Vec<&'a str> {
+        let Some(profile) = &self.opt.profile else {
+            return vec![];
+        };
+        let flags = if link {
+            &profile.config.link_flags
+        } else {
+            &profile.config.compile_flags
+        };
+        flags.iter().flatten().map(|s| s.as_str()).collect()
+    }
+
     fn set_build_commons(
         &self,
         pkg: &DiscoveredPackage,
@@ -267,6 +302,7 @@ impl<'a> BuildPlanLowerContext<'a> {
             TargetKind::InlineTest | TargetKind::WhiteboxTest | TargetKind::BlackboxTest => true,
         };
 
+        let extra_build_opts = self.profile_moonc_flags(false);
         let mut cmd = compiler::MooncBuildPackage {
             required: BuildCommonInput::new(
                 &files,
@@ -281,7 +317,7 @@ impl<'a> BuildPlanLowerContext<'a> {
             core_out: core_output.into(),
             mi_out: mi_output.into(),
             flags: self.set_flags(),
-            extra_build_opts: &[],
+            extra_build_opts: &extra_build_opts,
         };
         // Propagate debug/coverage flags and common settings
         cmd.flags.enable_coverage = self.opt.enable_coverage;
@@ -359,6 +395,7 @@ impl<'a> BuildPlanLowerContext<'a> {
             .collect::<Vec<_>>();
 
         let config_path = package.config_path();
+        let extra_link_opts = self.profile_moonc_flags(true);
         let cmd = compiler::MooncLinkCore {
             core_deps: &core_input_files,
             main_package: compiler::CompiledPackageName {
@@ -374,7 +411,13 @@ impl<'a> BuildPlanLowerContext<'a> {
             test_mode: target.kind.is_test(),
             wasm_config: self.get_wasm_config(package),
             js_config: self.get_js_config(target, package),
-            extra_link_opts: &[],
+            native_exports: package
+                .raw
+                .link
+                .as_ref()
+                .and_then(|x| x.native.as_ref()?.exports.as_deref())
+                .filter(|_| target.kind == TargetKind::Source),
+            extra_link_opts: &extra_link_opts,
         };
 
         // Ensure n2 sees stdlib core bundle changes as inputs
@@ -479,15 +522,17 @@ impl<'a> BuildPlanLowerContext<'a> {
         let config = CCConfigBuilder::default()
             .no_sys_header(true)
             .output_ty(CCOutputType::Object)
-            .opt_level(opt_level)
-            .debug_info(self.opt.debug_symbols)
+            .opt_level(self.cc_opt_level(opt_level))
+            .debug_info(self.cc_debug_info(self.opt.debug_symbols))
             .link_moonbitrun(true) // TODO: support use_tcc_run flag when available
             .define_use_shared_runtime_macro(false) // TODO: support use_tcc_run flag when available
+            .sanitizers(self.opt.sanitizers.clone())
+            .system_flags(info.system_cflags.clone())
             .build()
             .expect("Failed to build CC configuration for C stub");
 
         let cc_cmd = make_cc_command(
-            CC::default(),
+            self.opt.native_cc(),
             info.stub_cc.clone(),
             config,
             &info.cc_flags,
@@ -533,7 +578,7 @@ impl<'a> BuildPlanLowerContext<'a> {
             .expect("Failed to build archiver configuration");
 
         let archiver_cmd = make_archiver_command(
-            CC::default(),
+            self.opt.native_cc(),
             info.stub_cc.clone(), // TODO: no clone
             config,
             &object_files
@@ -587,14 +632,15 @@ impl<'a> BuildPlanLowerContext<'a> {
         let config = CCConfigBuilder::default()
             .no_sys_header(true)
             .output_ty(CCOutputType::Executable) // TODO: support compiling to library
-            .opt_level(opt_level)
-            .debug_info(self.opt.opt_level == OptLevel::Debug)
+            .opt_level(self.cc_opt_level(opt_level))
+            .debug_info(self.cc_debug_info(self.opt.opt_level == OptLevel::Debug))
             .link_moonbitrun(true) // TODO: support `tcc run`
             .define_use_shared_runtime_macro(false)
+            .sanitizers(self.opt.sanitizers.clone())
             .build()
             .expect("Failed to build CC configuration for executable");
         let cc_cmd = make_cc_command_pure(
-            resolve_cc(CC::default(), info.cc.clone()), // TODO: no clone
+            resolve_cc(self.opt.native_cc(), info.cc.clone()), // TODO: no clone
             config,
             &info.c_flags,
             sources.iter().map(|x| x.display().to_string()),
@@ -620,6 +666,182 @@ impl<'a> BuildPlanLowerContext<'a> {
         }
     }
 
+    /// Lower making a native library from a package with `link.native.kind`
+    /// set. The C header of the library, and the object file of the generated
+    /// C for static libraries, are produced by auxiliary builds.
+    #[instrument(level = Level::DEBUG, skip(self, info))]
+    pub(super) fn lower_make_native_lib(
+        &mut self,
+        node: BuildPlanNode,
+        target: BuildTarget,
+        info: &MakeExecutableInfo,
+        kind: NativeLinkKind,
+    ) -> Result<BuildCommand, LoweringError> {
+        let c_file = self.layout.linked_core_of_build_target(
+            self.packages,
+            &target,
+            self.opt.target_backend,
+            self.opt.os,
+            self.opt.output_wat,
+        );
+        let library = self.layout.native_library_of_build_target(
+            self.packages,
+            &target,
+            self.opt.target_backend,
+            self.opt.os,
+            kind,
+        );
+
+        // C header, declaring the exports with the signatures in the interface
+        let mi = self
+            .layout
+            .mi_of_build_target(self.packages, &target, self.opt.target_backend);
+        let header =
+            self.layout
+                .c_header_of_build_target(self.packages, &target, self.opt.target_backend);
+        let exports = self
+            .get_package(target)
+            .raw
+            .link
+            .as_ref()
+            .and_then(|link| link.native.as_ref()?.exports.as_ref())
+            .map(|exports| exports.join(","))
+            .unwrap_or_default();
+        let mut header_cmd = vec![
+            std::env::current_exe()
+                .map_or_else(|_| "moon".into(), |x| x.to_string_lossy().into_owned()),
+            "tool".into(),
+            "c-header".into(),
+            "--mi".into(),
+            mi.display().to_string(),
+            "--output".into(),
+            header.display().to_string(),
+        ];
+        if !exports.is_empty() {
+            header_cmd.push("--exports".into());
+            header_cmd.push(exports);
+        }
+        self.lowered_aux(
+            node,
+            format!("gen-c-header: {}", header.display()),
+            vec![mi],
+            vec![header.clone()],
+            header_cmd,
+        )?;
+
+        let opt_level = match self.opt.opt_level {
+            OptLevel::Release => CCOptLevel::Speed,
+            OptLevel::Debug => CCOptLevel::Debug,
+        };
+        let cc = resolve_cc(self.opt.native_cc(), info.cc.clone());
+
+        if kind == NativeLinkKind::Shared {
+            // The runtime is compiled from source instead of reusing the
+            // runtime object, as it must be position independent code
+            let mut sources = vec![c_file, self.opt.runtime_dot_c_path.clone()];
+            for &stub_tgt in &info.link_c_stubs {
+                self.append_all_artifacts_of(
+                    BuildPlanNode::ArchiveCStubs(stub_tgt.package),
+                    &mut sources,
+                );
+            }
+            let config = CCConfigBuilder::default()
+                .no_sys_header(true)
+                .output_ty(CCOutputType::SharedLib)
+                .opt_level(self.cc_opt_level(opt_level))
+                .debug_info(self.cc_debug_info(self.opt.opt_level == OptLevel::Debug))
+                // libmoonbitrun.o is not position independent
+                .link_moonbitrun(false)
+                .define_use_shared_runtime_macro(false)
+                .sanitizers(self.opt.sanitizers.clone())
+                .build()
+                .expect("Failed to build CC configuration for shared library");
+            let cc_cmd = make_cc_command_pure(
+                cc,
+                config,
+                &info.c_flags,
+                sources.iter().map(|x| x.display().to_string()),
+                &self.opt.target_dir_root.display().to_string(),
+                &library.display().to_string(),
+                &self.opt.compiler_paths,
+            );
+            return Ok(BuildCommand {
+                // The header depends on the generated C only, but is wanted
+                // together with the library
+                extra_inputs: vec![self.opt.runtime_dot_c_path.clone(), header],
+                commandline: cc_cmd,
+            });
+        }
+
+        // Static library: compile the generated C, then archive it together
+        // with the runtime and all C stub objects
+        let object = self.layout.native_library_object_of_build_target(
+            self.packages,
+            &target,
+            self.opt.target_backend,
+            self.opt.os,
+        );
+        let config = CCConfigBuilder::default()
+            .no_sys_header(true)
+            .output_ty(CCOutputType::Object)
+            .opt_level(self.cc_opt_level(opt_level))
+            .debug_info(self.cc_debug_info(self.opt.opt_level == OptLevel::Debug))
+            .link_moonbitrun(true)
+            .define_use_shared_runtime_macro(false)
+            .sanitizers(self.opt.sanitizers.clone())
+            .build()
+            .expect("Failed to build CC configuration for static library");
+        let cc_cmd = make_cc_command_pure(
+            cc.clone(),
+            config,
+            &info.c_flags,
+            [c_file.display().to_string()],
+            &self.opt.target_dir_root.display().to_string(),
+            &object.display().to_string(),
+            &self.opt.compiler_paths,
+        );
+        self.lowered_aux(
+            node,
+            format!("compile-static-lib: {}", object.display()),
+            vec![c_file],
+            vec![object.clone()],
+            cc_cmd,
+        )?;
+
+        // The runtime object is already an input from the dependency edges
+        let mut extra_inputs = vec![object];
+        for &stub_tgt in &info.link_c_stubs {
+            let pkg = self.packages.get_package(stub_tgt.package);
+            for index in 0..pkg.c_stub_files.len() {
+                self.append_all_artifacts_of(
+                    BuildPlanNode::BuildCStub(stub_tgt.package, index as u32),
+                    &mut extra_inputs,
+                );
+            }
+        }
+        let mut objects = extra_inputs.clone();
+        self.append_all_artifacts_of(BuildPlanNode::BuildRuntimeLib, &mut objects);
+        extra_inputs.push(header);
+        let config = ArchiverConfigBuilder::default()
+            .archive_moonbitrun(true)
+            .build()
+            .expect("Failed to build archiver configuration");
+        let archiver_cmd = make_archiver_command_pure(
+            cc,
+            config,
+            &objects
+                .iter()
+                .map(|s| s.to_string_lossy())
+                .collect::<Vec<_>>(),
+            &library.display().to_string(),
+        );
+
+        Ok(BuildCommand {
+            extra_inputs,
+            commandline: archiver_cmd,
+        })
+    }
+
     #[instrument(level = Level::DEBUG, skip(self))]
     pub(super) fn lower_parse_mbti(&mut self, node: BuildPlanNode, pid: PackageId) -> BuildCommand {
         let pkg = self.packages.get_package(pid);
@@ -24,9 +24,10 @@ use indexmap::IndexMap;
 use log::{debug, info};
 use moonutil::{
     common::{RunMode, TargetBackend},
-    compiler_flags::CompilerPaths,
+    compiler_flags::{CC, CompilerPaths, Sanitizer},
     cond_expr::OptLevel,
     mooncakes::ModuleSource,
+    profile::Profile,
 };
 use n2::graph::Graph as N2Graph;
 use tracing::instrument;
@@ -57,8 +58,14 @@ pub struct BuildOptions {
     // FIXME: This overlaps with `crate::build_plan::BuildEnvironment`
     pub target_backend: TargetBackend,
     pub os: OperatingSystem,
+    /// The triple to cross-compile native code for, or `None` for the host
+    pub target_triple: Option<String>,
+    /// The C compiler for `target_triple`
+    pub cross_cc: Option<CC>,
     pub opt_level: OptLevel,
     pub action: RunMode,
+    /// The named build profile, if any, overriding some of the options below
+    pub profile: Option<Profile>,
 
     // Detailed configuration -- some of them might live better in configs
     pub debug_symbols: bool,
@@ -68,6 +75,7 @@ pub struct BuildOptions {
     pub docs_serve: bool,
     pub deny_warn: bool,
     pub info_no_alias: bool,
+    pub sanitizers: Vec<Sanitizer>,
 
     // Environments
     /// Only `Some` if we import standard library.
@@ -76,6 +84,13 @@ pub struct BuildOptions {
     pub compiler_paths: CompilerPaths,
 }
 
+impl BuildOptions {
+    /// The C compiler for native code, unless a package selects its own
+    fn native_cc(&self) -> CC {
+        self.cross_cc.clone().unwrap_or_default()
+    }
+}
+
 /// An error that may be raised during build plan lowering
 #[derive(thiserror::Error, Debug)]
 pub enum LoweringError {
@@ -130,7 +145,9 @@ pub fn lower_build_plan(
         .main_module(opt.main_module.clone())
         .stdlib_dir(opt.stdlib_path.clone())
         .opt_level(opt.opt_level)
+        .profile(opt.profile.as_ref().map(|p| p.name.clone()))
         .run_mode(opt.action)
+        .target_triple(opt.target_triple.clone())
         .build()
         .expect("Failed to build legacy layout");
 
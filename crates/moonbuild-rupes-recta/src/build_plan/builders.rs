@@ -265,6 +265,7 @@ impl<'a> BuildPlanConstructor<'a> {
             optlevel: self.build_env.opt_level,
             test_kind: target.kind.into(),
             backend: self.build_env.target_backend,
+            cfg: self.build_env.cfg.env_for(pkg.module),
         };
 
         // Iterator of all existing source files in the package
@@ -470,9 +471,16 @@ impl<'a> BuildPlanConstructor<'a> {
             .transpose()?
             .unwrap_or_default();
 
+        let system_cflags = self
+            .prebuild_config
+            .and_then(|prebuild| prebuild.system_deps.get(&target))
+            .map(|flags| flags.cflags.clone())
+            .unwrap_or_default();
+
         let c_info = BuildCStubsInfo {
             stub_cc,
             cc_flags,
+            system_cflags,
             link_flags,
         };
         self.res.c_stubs_info.insert(target, c_info);
@@ -745,6 +753,10 @@ impl<'a> BuildPlanConstructor<'a> {
         };
         let is_msvc_like = cc.unwrap_or(&*DETECTED_CC).is_msvc();
         for pkg in pkgs {
+            if let Some(system_deps) = prebuild.system_deps.get(&pkg) {
+                out.extend(system_deps.libs.iter().cloned());
+            }
+
             let Some(link_config) = prebuild.package_configs.get(&pkg) else {
                 continue;
             };
@@ -56,6 +56,7 @@ use tracing::instrument;
 
 use crate::{
     ResolveOutput,
+    cond_comp::CfgContext,
     model::{BuildPlanNode, BuildTarget, PackageId},
     pkg_name::PackageFQNWithSource,
     prebuild::PrebuildOutput,
@@ -265,6 +266,8 @@ pub struct BuildCStubsInfo {
     pub(crate) stub_cc: Option<CC>,
     /// Additional flags to pass to the C compiler when compiling the C stubs
     pub(crate) cc_flags: Vec<String>,
+    /// Flags of the system libraries the C stubs use, from `pkg-config`
+    pub(crate) system_cflags: Vec<String>,
     /// Additional flags to pass to the linker (TCC only)
     #[allow(unused)]
     pub(crate) link_flags: Vec<String>,
@@ -298,6 +301,8 @@ pub struct BuildEnvironment {
     /// Commandline_level warnings to enable/disable
     pub warn_list: Option<String>,
     pub alert_list: Option<String>,
+    /// The target platform and module features for conditional compilation
+    pub cfg: CfgContext,
     // Can have more, e.g. cross compile
 }
 
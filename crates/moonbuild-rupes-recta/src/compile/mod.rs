@@ -22,15 +22,17 @@ use indexmap::IndexMap;
 use log::{debug, info};
 use moonutil::{
     common::{RunMode, TargetBackend},
-    compiler_flags::CompilerPaths,
+    compiler_flags::{CC, CompilerPaths, Sanitizer},
     cond_expr::OptLevel,
     moon_dir::MOON_DIRS,
+    profile::Profile,
 };
 use tracing::{Level, instrument};
 
 use crate::{
     build_lower,
     build_plan::{self, BuildEnvironment, InputDirective},
+    cond_comp::CfgContext,
     model::{Artifacts, BuildPlanNode, OperatingSystem},
     prebuild::PrebuildOutput,
     resolve::ResolveOutput,
@@ -49,6 +51,9 @@ pub struct CompileConfig {
     pub action: RunMode,
     /// Whether to emit debug symbols.
     pub debug_symbols: bool,
+    /// The named build profile selected with `moon build --profile`, if any.
+    /// `opt_level` and `debug_symbols` are already derived from it.
+    pub profile: Option<Profile>,
 
     /// The path to the standard library's project root, or `None` if to not
     /// import the standard library during compilation.
@@ -78,6 +83,15 @@ pub struct CompileConfig {
     pub alert_list: Option<String>,
     /// Whether to not emit alias when running `mooninfo`
     pub info_no_alias: bool,
+    /// Sanitizers to build native executables and C stubs with
+    pub sanitizers: Vec<Sanitizer>,
+    /// The triple to cross-compile native code for, or `None` for the host
+    pub target_triple: Option<String>,
+    /// The C compiler for `target_triple`, see [`CC::for_target`]
+    pub cross_cc: Option<CC>,
+    /// The target platform and module features that `targets` in
+    /// `moon.pkg.json` are evaluated against.
+    pub cfg: CfgContext,
 }
 
 /// The output information of the compilation.
@@ -124,6 +138,7 @@ pub fn compile(
         std: cx.stdlib_path.is_some(),
         warn_list: cx.warn_list.clone(),
         alert_list: cx.alert_list.clone(),
+        cfg: cx.cfg.clone(),
     };
     let plan = build_plan::build_plan(
         resolve_output,
@@ -145,6 +160,7 @@ pub fn compile(
         target_dir_root: cx.target_dir.clone(),
         target_backend: cx.target_backend,
         opt_level: cx.opt_level,
+        profile: cx.profile.clone(),
         action: cx.action,
 
         enable_coverage: cx.enable_coverage,
@@ -154,10 +170,16 @@ pub fn compile(
         docs_serve: cx.docs_serve,
         deny_warn: cx.deny_warn,
         info_no_alias: cx.info_no_alias,
+        sanitizers: cx.sanitizers.clone(),
 
         stdlib_path: cx.stdlib_path.clone(),
         compiler_paths: CompilerPaths::from_moon_dirs(), // change to external
-        os: OperatingSystem::from_str(std::env::consts::OS).expect("Unknown"),
+        os: match &cx.target_triple {
+            Some(triple) => OperatingSystem::from_triple(triple).expect("Unknown"),
+            None => OperatingSystem::from_str(std::env::consts::OS).expect("Unknown"),
+        },
+        target_triple: cx.target_triple.clone(),
+        cross_cc: cx.cross_cc.clone(),
         runtime_dot_c_path: MOON_DIRS.moon_lib_path.join("runtime.c"), // FIXME: don't calculate here
     };
     let res = build_lower::lower_build_plan(resolve_output, &plan, &lower_env)?;
@@ -18,11 +18,16 @@
 
 //! Solves conditional compilation directives
 
-use std::path::Path;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
 
 use moonutil::{
+    build_script::TargetInfo,
     common::TargetBackend,
-    cond_expr::{CompileCondition as MetadataCompileCondition, CondExpr, OptLevel},
+    cond_expr::{CfgEnv, CompileCondition as MetadataCompileCondition, CondExpr, OptLevel},
+    mooncakes::ModuleId,
     package::MoonPkg,
 };
 
@@ -53,6 +58,36 @@ pub(crate) struct CompileCondition {
     pub optlevel: OptLevel,
     pub test_kind: Option<TestKind>,
     pub backend: TargetBackend,
+    /// The platform and features for `os(..)`, `arch(..)` and `feature(..)`
+    pub cfg: CfgEnv,
+}
+
+/// The target platform and the features enabled for each module, which
+/// `targets` in `moon.pkg.json` are evaluated against.
+#[derive(Debug, Clone, Default)]
+pub struct CfgContext {
+    pub target_os: String,
+    pub target_arch: String,
+    pub features: HashMap<ModuleId, HashSet<String>>,
+}
+
+impl CfgContext {
+    pub fn new(target: &TargetInfo, features: HashMap<ModuleId, HashSet<String>>) -> Self {
+        CfgContext {
+            target_os: target.os.clone(),
+            target_arch: target.arch.clone(),
+            features,
+        }
+    }
+
+    /// The environment to evaluate conditions of packages in `module` with.
+    pub fn env_for(&self, module: ModuleId) -> CfgEnv {
+        CfgEnv {
+            os: self.target_os.clone(),
+            arch: self.target_arch.clone(),
+            features: self.features.get(&module).cloned().unwrap_or_default(),
+        }
+    }
 }
 
 /// Get the list of files that should get included in the compile list under
@@ -95,6 +130,7 @@ pub(crate) fn filter_files<'a, P: AsRef<Path> + 'a>(
 pub(crate) fn file_metadatas<'a>(
     pkg: &'a MoonPkg,
     files: impl Iterator<Item = &'a Path> + 'a,
+    cfg: &'a CfgEnv,
 ) -> impl Iterator<Item = (&'a Path, FileTestKind, MetadataCompileCondition)> + 'a {
     files.map(|path| {
         let filename = path
@@ -110,7 +146,7 @@ pub(crate) fn file_metadatas<'a>(
             .as_ref()
             .and_then(|targets| targets.get(&*str_filename))
         {
-            (expect_cond.to_compile_condition(), without_mbt)
+            (expect_cond.to_compile_condition(cfg), without_mbt)
         } else {
             let (backend, remaining) = get_file_target_backend(without_mbt);
             let cond = MetadataCompileCondition {
@@ -131,7 +167,7 @@ fn should_compile_using_pkg_cond_expr(
     actual: &CompileCondition,
 ) -> Option<FileTestKind> {
     // TODO: Put the parsing earlier, not here
-    if !cond_expr.eval(actual.optlevel, actual.backend) {
+    if !cond_expr.eval(actual.optlevel, actual.backend, &actual.cfg) {
         None // Fails the condition in pkg.json
     } else if let Some(stripped) = name.strip_suffix(".mbt") {
         let spec = get_file_test_kind(stripped);
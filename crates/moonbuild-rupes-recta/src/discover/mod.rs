@@ -38,6 +38,9 @@ use moonutil::common::{
     IGNORE_DIRS, MBTI_USER_WRITTEN, MOON_MOD_JSON, MOON_PKG_JSON, read_module_desc_file_in_dir,
     read_package_desc_file_in_dir,
 };
+use moonutil::module_features::{
+    check_target_features, disabled_deps, import_of_disabled_dep, skipped_package_message,
+};
 use moonutil::mooncakes::{DirSyncResult, ModuleId, ModuleSource, result::ResolvedEnv};
 use moonutil::package::MoonPkg;
 use relative_path::{PathExt, RelativePath};
@@ -122,6 +125,9 @@ pub(crate) fn discover_packages_for_mod(
     let source_dir_name = m.source.as_deref().unwrap_or("");
     let scan_source_root = dir.join(source_dir_name);
     let is_core = module_name_is_core(&m.name);
+    // Optional dependencies left out by the features enabled for this module
+    // were not resolved, so packages importing them can't be built
+    let disabled_deps = disabled_deps(&m, env.features(id));
 
     // Recursively walk through the module's directories
     let mut walkdir = WalkDir::new(&scan_source_root)
@@ -177,6 +183,20 @@ pub(crate) fn discover_packages_for_mod(
         // Begin discovering the package
         debug!("Discovering package at {}", abs_path.display());
         let pkg = discover_one_package(id, module_source, abs_path, &rel_path, is_core)?;
+        if let Some((import, dep)) = import_of_disabled_dep(&pkg.raw, &disabled_deps) {
+            let pkg_name = pkg.fqn.to_string();
+            warn!("{}", skipped_package_message(&m, &pkg_name, import, dep));
+            continue;
+        }
+        if let Some(targets) = &pkg.raw.targets {
+            check_target_features(targets, m.features.as_ref()).map_err(|e| {
+                DiscoverError::InvalidTargets {
+                    module: module_source.clone(),
+                    package: pkg.fqn.package().clone(),
+                    inner: e,
+                }
+            })?;
+        }
         debug!(
             "Found package: {} with {} source files",
             pkg.fqn,
@@ -554,4 +574,11 @@ pub enum DiscoverError {
 
     #[error("Cannot find `pkg.mbti` declaration file for virtual package {0}")]
     MissingVirtualMbtiFile(PackageFQNWithSource),
+
+    #[error("Invalid `targets` in package '{package}' of module '{module}', error: {inner}")]
+    InvalidTargets {
+        module: ModuleSource,
+        package: PackagePath,
+        inner: anyhow::Error,
+    },
 }
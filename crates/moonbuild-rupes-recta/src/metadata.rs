@@ -32,7 +32,7 @@ use moonutil::{
 use crate::{
     ResolveOutput,
     build_lower::artifact::{LegacyLayout, LegacyLayoutBuilder},
-    cond_comp::file_metadatas,
+    cond_comp::{CfgContext, file_metadatas},
     model::{BuildTarget, PackageId, TargetKind},
     pkg_solve::DepEdge,
 };
@@ -45,6 +45,7 @@ pub fn gen_metadata_json(
     target_dir: &Path,
     opt_level: OptLevel,
     backend: TargetBackend,
+    cfg: &CfgContext,
 ) -> ModuleDBJSON {
     // Get the main module info
     let &[main_module_id] = ctx.local_modules() else {
@@ -65,7 +66,7 @@ pub fn gen_metadata_json(
     let packages = ctx
         .pkg_dirs
         .all_packages()
-        .map(|(id, _)| gen_package_json(ctx, &layout, id, backend))
+        .map(|(id, _)| gen_package_json(ctx, &layout, id, backend, cfg))
         .collect();
 
     ModuleDBJSON {
@@ -84,17 +85,21 @@ fn gen_package_json(
     layout: &LegacyLayout,
     pkg_id: PackageId,
     backend: TargetBackend,
+    cfg: &CfgContext,
 ) -> PackageJSON {
     let pkg = ctx.pkg_dirs.get_package(pkg_id);
+    let cfg_env = cfg.env_for(pkg.module);
     let is_in_workspace = ctx.local_modules().contains(&pkg.module);
 
     // Source file collection
     let mut files = IndexMap::new();
     let mut wbtest_files = IndexMap::new();
     let mut test_files = IndexMap::new();
-    for (path, test_kind, cond) in
-        file_metadatas(&pkg.raw, pkg.source_files.iter().map(|x| x.as_path()))
-    {
+    for (path, test_kind, cond) in file_metadatas(
+        &pkg.raw,
+        pkg.source_files.iter().map(|x| x.as_path()),
+        &cfg_env,
+    ) {
         match test_kind {
             crate::cond_comp::FileTestKind::NoTest => files.insert(path.to_owned(), cond),
             crate::cond_comp::FileTestKind::Whitebox => wbtest_files.insert(path.to_owned(), cond),
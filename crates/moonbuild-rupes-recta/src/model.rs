@@ -246,6 +246,22 @@ impl std::fmt::Display for OperatingSystem {
     }
 }
 
+impl OperatingSystem {
+    /// The operating system in a target triple like `aarch64-apple-darwin`
+    pub fn from_triple(triple: &str) -> Result<Self, String> {
+        let parts = triple.split('-').collect::<Vec<_>>();
+        if parts.contains(&"windows") {
+            Ok(OperatingSystem::Windows)
+        } else if parts.contains(&"linux") {
+            Ok(OperatingSystem::Linux)
+        } else if parts.contains(&"darwin") || parts.contains(&"macos") {
+            Ok(OperatingSystem::MacOS)
+        } else {
+            Err(format!("Unsupported OS in target triple: {}", triple))
+        }
+    }
+}
+
 impl std::str::FromStr for OperatingSystem {
     type Err = String;
 
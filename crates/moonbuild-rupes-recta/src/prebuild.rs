@@ -27,8 +27,10 @@ use std::collections::HashMap;
 use anyhow::Context;
 use moonbuild::build_script::make_prebuild_input_from_module;
 use moonutil::{
-    build_script::{LinkConfig, RerunIfKind},
+    build_script::{BuildInfo, LinkConfig, RerunIfKind},
+    common::TargetBackend,
     mooncakes::{ModuleId, ModuleSource},
+    system_deps::{SystemDepFlags, probe_system_dep},
 };
 use tracing::instrument;
 
@@ -39,6 +41,8 @@ use crate::{ResolveOutput, model::PackageId};
 pub struct PrebuildOutput {
     pub module_outputs: HashMap<ModuleId, ModulePrebuildOutput>,
     pub package_configs: HashMap<PackageId, LinkConfig>,
+    /// The `pkg-config` flags of each native package's `system-deps`
+    pub system_deps: HashMap<PackageId, SystemDepFlags>,
 }
 
 /// A module's prebuild output
@@ -50,24 +54,65 @@ pub struct ModulePrebuildOutput {
     pub vars: HashMap<String, String>,
 }
 
+/// Run the prebuild scripts of all modules, telling them the target being
+/// built for: `target_backend`, cross-compiled to `target_triple` if given.
 #[instrument(skip_all)]
-pub fn run_prebuild_config(resolve_output: &ResolveOutput) -> anyhow::Result<PrebuildOutput> {
+pub fn run_prebuild_config(
+    resolve_output: &ResolveOutput,
+    target_backend: TargetBackend,
+    target_triple: Option<&str>,
+) -> anyhow::Result<PrebuildOutput> {
     let env_vars: HashMap<String, String> = std::env::vars().collect();
+    let build = BuildInfo::new(target_backend, target_triple)?;
     let mut output = PrebuildOutput::default();
 
     // Run prebuild scripts
     for (m, ms) in resolve_output.module_rel.all_modules_and_id() {
-        run_prebuild_for_module(m, ms, resolve_output, &env_vars, &mut output)?;
+        run_prebuild_for_module(m, ms, resolve_output, &env_vars, &build, &mut output)?;
+    }
+
+    // Resolve system libraries
+    if target_backend.is_native() {
+        resolve_system_deps(resolve_output, target_triple, &mut output)?;
     }
 
     Ok(output)
 }
 
+/// Query `pkg-config` for the `native.system-deps` of every package on
+/// `target_triple`, failing early if any of them is missing.
+fn resolve_system_deps(
+    resolve_output: &ResolveOutput,
+    target_triple: Option<&str>,
+    ret: &mut PrebuildOutput,
+) -> anyhow::Result<()> {
+    for (pkg_id, pkg) in resolve_output.pkg_dirs.all_packages() {
+        let Some(system_deps) = pkg
+            .raw
+            .link
+            .as_ref()
+            .and_then(|link| link.native.as_ref())
+            .and_then(|native| native.system_deps.as_ref())
+        else {
+            continue;
+        };
+
+        let pkg_name = pkg.fqn.to_string();
+        let mut flags = SystemDepFlags::default();
+        for (name, version) in system_deps {
+            flags.extend(probe_system_dep(&pkg_name, name, version, target_triple)?);
+        }
+        ret.system_deps.insert(pkg_id, flags);
+    }
+    Ok(())
+}
+
 fn run_prebuild_for_module(
     m: ModuleId,
     ms: &ModuleSource,
     resolve_output: &ResolveOutput,
     env_vars: &HashMap<String, String>,
+    build: &BuildInfo,
     ret: &mut PrebuildOutput,
 ) -> anyhow::Result<()> {
     let m_info = &**resolve_output.module_rel.module_info(m);
@@ -77,7 +122,7 @@ fn run_prebuild_for_module(
     };
 
     // Run the prebuild script
-    let input = make_prebuild_input_from_module(m_dir, env_vars);
+    let input = make_prebuild_input_from_module(m_dir, env_vars, build);
     let output = moonbuild::build_script::run_build_script_for_module(ms, m_dir, input, prebuild)
         .context(format!(
         "Failed to run prebuild script for module {}",
@@ -118,6 +118,8 @@ pub fn f() -> Unit {{
                 virtual_pkg: None,
                 implement: None,
                 overrides: None,
+                test_limits: None,
+                js_test_runtime: None,
                 sub_package: None,
             };
             moonutil::common::write_package_json_to_file(&pkg, &moon_pkg).unwrap();
@@ -156,6 +158,9 @@ pub fn write(config: &Config, base_dir: &Path) {
         exclude: None,
 
         scripts: None,
+        runners: None,
+        profiles: None,
+        features: None,
         preferred_target: None,
 
         __moonbit_unstable_prebuild: None,
@@ -215,6 +220,8 @@ pub fn write(config: &Config, base_dir: &Path) {
         virtual_pkg: None,
         implement: None,
         overrides: None,
+        test_limits: None,
+        js_test_runtime: None,
         sub_package: None,
     };
 
@@ -40,13 +40,18 @@ pub fn load_moon_proj(
     r#gen::gen_build::gen_n2_build_state(&n2_input, target_dir, moonc_opt, moonbuild_opt)
 }
 
-pub fn run_wat(path: &Path, args: &[String], verbose: bool) -> anyhow::Result<()> {
+pub fn run_wat(
+    path: &Path,
+    moonrun_args: &[String],
+    args: &[String],
+    verbose: bool,
+) -> anyhow::Result<()> {
     let mut cmd = Command::new(
         MOONRUN_EXECUTABLE
             .as_deref()
             .context("Unable to find the `moonrun` executable, please reinstall")?,
     );
-    cmd.arg(path).args(args);
+    cmd.args(moonrun_args).arg(path).args(args);
     run(cmd, verbose)
 }
 
@@ -66,6 +71,45 @@ pub fn run_native(path: &Path, args: &[String], verbose: bool) -> anyhow::Result
     run(cmd, verbose)
 }
 
+/// The environment variable holding the JSON-encoded test arguments when a
+/// test executable is run through a custom runner.
+pub const MOON_TEST_ARGS_ENV: &str = "MOON_TEST_ARGS";
+
+/// Split a runner command template from `moon.mod.json` into a program and
+/// its arguments. `$artifact` is replaced by `artifact`, which is appended
+/// when the template does not mention it.
+pub fn runner_command(template: &str, artifact: &Path) -> anyhow::Result<Vec<String>> {
+    let mut parts =
+        shlex::split(template).with_context(|| format!("invalid runner command `{template}`"))?;
+    if parts.is_empty() {
+        anyhow::bail!("runner command is empty");
+    }
+    let artifact = artifact.display().to_string();
+    let mut used = false;
+    for part in parts.iter_mut() {
+        if part.contains("$artifact") {
+            *part = part.replace("$artifact", &artifact);
+            used = true;
+        }
+    }
+    if !used {
+        parts.push(artifact);
+    }
+    Ok(parts)
+}
+
+pub fn run_with_runner(
+    template: &str,
+    path: &Path,
+    args: &[String],
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let parts = runner_command(template, path)?;
+    let mut cmd = Command::new(&parts[0]);
+    cmd.args(&parts[1..]).args(args);
+    run(cmd, verbose)
+}
+
 fn run(mut subprocess: Command, verbose: bool) -> anyhow::Result<()> {
     if verbose {
         eprintln!("{:?}", subprocess);
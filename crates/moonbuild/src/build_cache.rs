@@ -0,0 +1,509 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! A content-addressed cache of build step outputs, shared between checkouts
+//! of a project and between CI runs.
+//!
+//! The cache is opt-in. Setting `MOON_BUILD_CACHE_DIR` stores entries in a
+//! local directory, and setting `MOON_BUILD_CACHE_URL` additionally consults a
+//! remote store over HTTP, where an entry is read with `GET <url>/<key>` and
+//! written with `PUT <url>/<key>`.
+//!
+//! Cacheable steps are rewritten to run through `moon tool build-cache`,
+//! which computes the key of the step from the `moonc` version, the command
+//! line and the content of its input files. Paths below the project and
+//! target directories are replaced by placeholders before hashing, so the
+//! same step in two different checkouts shares a key. On a hit the outputs
+//! and the captured diagnostics are restored instead of running the command.
+//!
+//! Steps emitting debug info are never cached: the outputs embed the absolute
+//! paths of the sources, which would leak from one checkout into another.
+//! Neither are C stubs, whose outputs also depend on the version of the C
+//! compiler and on the headers found through its include paths.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use anyhow::Context;
+use n2::graph::{Build, Graph};
+use sha2::{Digest, Sha256};
+
+/// Local directory holding cache entries
+pub const MOON_BUILD_CACHE_DIR: &str = "MOON_BUILD_CACHE_DIR";
+/// Base URL of a remote store holding cache entries
+pub const MOON_BUILD_CACHE_URL: &str = "MOON_BUILD_CACHE_URL";
+
+const KEY_VERSION: &str = "moon-build-cache-v1";
+
+/// Give up on a remote store that does not respond, building locally instead
+const REMOTE_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(60);
+
+static REMOTE_CLIENT: LazyLock<reqwest::Result<reqwest::blocking::Client>> = LazyLock::new(|| {
+    reqwest::blocking::Client::builder()
+        .connect_timeout(REMOTE_CONNECT_TIMEOUT)
+        .timeout(REMOTE_TIMEOUT)
+        .build()
+});
+
+fn remote_client() -> anyhow::Result<&'static reqwest::blocking::Client> {
+    REMOTE_CLIENT
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("failed to create HTTP client: {e}"))
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|it| !it.is_empty())
+}
+
+pub fn build_cache_enabled() -> bool {
+    env_var(MOON_BUILD_CACHE_DIR).is_some() || env_var(MOON_BUILD_CACHE_URL).is_some()
+}
+
+/// Whether a command asks for debug info, either from `moonc` or from a C
+/// compiler.
+fn emits_debug_info(command: &[String]) -> bool {
+    command.iter().any(|arg| {
+        matches!(arg.as_str(), "-g" | "-source-map" | "/Z7" | "/Zi" | "/ZI")
+            || arg.starts_with("-ggdb")
+            || arg.starts_with("-gdwarf")
+            || (arg.len() == 3 && arg.starts_with("-g") && arg.ends_with(['1', '2', '3']))
+    })
+}
+
+/// Rewrite the command of every build accepted by `is_cacheable` to run
+/// through `moon tool build-cache`. Builds emitting debug info are left
+/// untouched. `roots` are the directories whose
+/// location must not affect the cache key, usually the source and target
+/// directories.
+pub fn wrap_cacheable_builds(
+    graph: &mut Graph,
+    roots: &[&Path],
+    is_cacheable: impl Fn(&Build) -> bool,
+) -> anyhow::Result<()> {
+    let moonc_version = moonutil::common::get_moonc_version()?;
+    let current_exe = std::env::current_exe().context("failed to get current executable")?;
+    let files = &graph.files;
+    for build in graph.builds.iter_mut() {
+        if !is_cacheable(build) {
+            continue;
+        }
+        let Some(cmdline) = &build.cmdline else {
+            continue;
+        };
+        let Some(command) = shlex::split(cmdline) else {
+            continue;
+        };
+        if emits_debug_info(&command) {
+            continue;
+        }
+        let mut args = vec![
+            current_exe.display().to_string(),
+            "tool".to_string(),
+            "build-cache".to_string(),
+            "--moonc-version".to_string(),
+            moonc_version.clone(),
+        ];
+        for root in roots {
+            args.push("--root".to_string());
+            args.push(root.display().to_string());
+        }
+        for &id in build.outs() {
+            args.push("--output".to_string());
+            args.push(files.by_id[id].name.clone());
+        }
+        // Inputs that are not visible on the command line still have to be
+        // part of the key
+        for &id in build.ins.ids.iter() {
+            let name = &files.by_id[id].name;
+            if !command.iter().any(|arg| arg.contains(name.as_str())) {
+                args.push("--input".to_string());
+                args.push(name.clone());
+            }
+        }
+        args.push("--".to_string());
+        args.extend(command);
+        build.cmdline = Some(shlex::try_join(args.iter().map(|it| it.as_str()))?);
+    }
+    Ok(())
+}
+
+/// Strip the `moon tool build-cache` wrapper added by
+/// [`wrap_cacheable_builds`] from a command, if any.
+pub fn unwrap_command(args: &[String]) -> &[String] {
+    if args.get(1).map(|it| it.as_str()) == Some("tool")
+        && args.get(2).map(|it| it.as_str()) == Some("build-cache")
+        && let Some(i) = args.iter().position(|it| it == "--")
+    {
+        &args[i + 1..]
+    } else {
+        args
+    }
+}
+
+/// A build step run through the cache.
+#[derive(Debug)]
+pub struct CachedStep {
+    pub moonc_version: String,
+    pub roots: Vec<PathBuf>,
+    pub inputs: Vec<PathBuf>,
+    pub outputs: Vec<PathBuf>,
+    pub command: Vec<String>,
+}
+
+/// Replace every root, and the moon home directory, with a placeholder.
+fn normalize(s: &str, roots: &[PathBuf]) -> String {
+    let mut s = s.to_string();
+    for (i, root) in roots.iter().enumerate() {
+        s = s.replace(&root.display().to_string(), &format!("$ROOT{i}"));
+    }
+    s.replace(
+        &moonutil::moon_dir::home().display().to_string(),
+        "$MOON_HOME",
+    )
+}
+
+/// The inverse of [`normalize`] for the current roots.
+fn denormalize(s: &str, roots: &[PathBuf]) -> String {
+    let mut s = s.to_string();
+    // Replace in reverse so that `$ROOT1` is not matched by `$ROOT10`
+    for (i, root) in roots.iter().enumerate().rev() {
+        s = s.replace(&format!("$ROOT{i}"), &root.display().to_string());
+    }
+    s.replace(
+        "$MOON_HOME",
+        &moonutil::moon_dir::home().display().to_string(),
+    )
+}
+
+/// The input file referred to by an argument, either the argument itself or
+/// the part before an alias, as in `-i path/to/pkg.mi:alias`.
+fn input_file<'a>(arg: &'a str, outputs: &[PathBuf]) -> Option<&'a Path> {
+    let candidates = std::iter::once(arg).chain(arg.rsplit_once(':').map(|(path, _)| path));
+    candidates
+        .map(Path::new)
+        .find(|path| path.is_file() && !outputs.iter().any(|out| out == path))
+}
+
+impl CachedStep {
+    pub fn key(&self) -> anyhow::Result<String> {
+        let mut hasher = Sha256::new();
+        let mut field = |bytes: &[u8]| {
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        };
+        field(KEY_VERSION.as_bytes());
+        field(self.moonc_version.as_bytes());
+        for arg in self.command.iter() {
+            field(normalize(arg, &self.roots).as_bytes());
+            if let Some(path) = input_file(arg, &self.outputs) {
+                let content = std::fs::read(path)
+                    .with_context(|| format!("failed to read `{}`", path.display()))?;
+                field(&content);
+            }
+        }
+        for input in self.inputs.iter() {
+            field(normalize(&input.display().to_string(), &self.roots).as_bytes());
+            let content = std::fs::read(input)
+                .with_context(|| format!("failed to read `{}`", input.display()))?;
+            field(&content);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    /// Run the step, restoring it from the cache when possible. Returns the
+    /// exit code of the step.
+    pub fn run(&self) -> anyhow::Result<i32> {
+        let store = Store::from_env();
+        let key = self.key()?;
+        if let Some(entry) = store.get(&key)? {
+            match self.restore(&entry) {
+                Ok(()) => return Ok(0),
+                Err(e) => log::warn!("ignoring corrupted build cache entry {key}: {e:#}"),
+            }
+        }
+
+        let (program, args) = self
+            .command
+            .split_first()
+            .context("missing command to run")?;
+        let output = std::process::Command::new(program)
+            .args(args)
+            .output()
+            .with_context(|| format!("failed to run `{program}`"))?;
+        std::io::stdout().write_all(&output.stdout)?;
+        std::io::stderr().write_all(&output.stderr)?;
+        if !output.status.success() {
+            return Ok(output.status.code().unwrap_or(1));
+        }
+
+        match self.pack(&output.stdout, &output.stderr) {
+            Ok(entry) => store.put(&key, &entry),
+            Err(e) => log::warn!("failed to create build cache entry {key}: {e:#}"),
+        }
+        Ok(0)
+    }
+
+    fn pack(&self, stdout: &[u8], stderr: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        let options = zip::write::FileOptions::default();
+        for (i, output) in self.outputs.iter().enumerate() {
+            let content = std::fs::read(output)
+                .with_context(|| format!("failed to read `{}`", output.display()))?;
+            zip.start_file(format!("out/{i}"), options)?;
+            zip.write_all(&content)?;
+        }
+        zip.start_file("stdout", options)?;
+        zip.write_all(normalize(&String::from_utf8_lossy(stdout), &self.roots).as_bytes())?;
+        zip.start_file("stderr", options)?;
+        zip.write_all(normalize(&String::from_utf8_lossy(stderr), &self.roots).as_bytes())?;
+        Ok(zip.finish()?.into_inner())
+    }
+
+    fn restore(&self, entry: &[u8]) -> anyhow::Result<()> {
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(entry))?;
+        let mut read = |name: &str| -> anyhow::Result<Vec<u8>> {
+            let mut content = vec![];
+            zip.by_name(name)?.read_to_end(&mut content)?;
+            Ok(content)
+        };
+        let outputs = (0..self.outputs.len())
+            .map(|i| read(&format!("out/{i}")))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let stdout = read("stdout")?;
+        let stderr = read("stderr")?;
+
+        for (path, content) in self.outputs.iter().zip(outputs) {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, content)
+                .with_context(|| format!("failed to write `{}`", path.display()))?;
+        }
+        std::io::stdout()
+            .write_all(denormalize(&String::from_utf8_lossy(&stdout), &self.roots).as_bytes())?;
+        std::io::stderr()
+            .write_all(denormalize(&String::from_utf8_lossy(&stderr), &self.roots).as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Where cache entries are read from and written to.
+#[derive(Debug, Default)]
+pub struct Store {
+    pub dir: Option<PathBuf>,
+    pub url: Option<String>,
+}
+
+impl Store {
+    pub fn from_env() -> Self {
+        Store {
+            dir: env_var(MOON_BUILD_CACHE_DIR).map(PathBuf::from),
+            url: env_var(MOON_BUILD_CACHE_URL).map(|it| it.trim_end_matches('/').to_string()),
+        }
+    }
+
+    fn local_path(dir: &Path, key: &str) -> PathBuf {
+        dir.join(&key[..2]).join(format!("{key}.zip"))
+    }
+
+    pub fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        if let Some(dir) = &self.dir {
+            let path = Self::local_path(dir, key);
+            if path.exists() {
+                let entry = std::fs::read(&path)
+                    .with_context(|| format!("failed to read `{}`", path.display()))?;
+                return Ok(Some(entry));
+            }
+        }
+        if let Some(url) = &self.url {
+            match Self::get_remote(url, key) {
+                Ok(Some(entry)) => {
+                    // Keep a local copy so the next build does not hit the
+                    // network again
+                    if let Some(dir) = &self.dir
+                        && let Err(e) = Self::put_local(dir, key, &entry)
+                    {
+                        log::warn!("failed to store build cache entry {key}: {e:#}");
+                    }
+                    return Ok(Some(entry));
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("failed to read remote build cache entry {key}: {e:#}"),
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn put(&self, key: &str, entry: &[u8]) {
+        if let Some(dir) = &self.dir
+            && let Err(e) = Self::put_local(dir, key, entry)
+        {
+            log::warn!("failed to store build cache entry {key}: {e:#}");
+        }
+        if let Some(url) = &self.url
+            && let Err(e) = Self::put_remote(url, key, entry)
+        {
+            log::warn!("failed to write remote build cache entry {key}: {e:#}");
+        }
+    }
+
+    fn put_local(dir: &Path, key: &str, entry: &[u8]) -> anyhow::Result<()> {
+        let path = Self::local_path(dir, key);
+        let parent = path.parent().unwrap();
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create `{}`", parent.display()))?;
+        // Write to a temporary file first, so concurrent builds never see a
+        // partially written entry
+        let mut file = tempfile::NamedTempFile::new_in(parent)?;
+        file.write_all(entry)?;
+        file.persist(&path)?;
+        Ok(())
+    }
+
+    fn get_remote(url: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let response = remote_client()?.get(format!("{url}/{key}")).send()?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.bytes()?.to_vec()))
+    }
+
+    fn put_remote(url: &str, key: &str, entry: &[u8]) -> anyhow::Result<()> {
+        remote_client()?
+            .put(format!("{url}/{key}"))
+            .body(entry.to_vec())
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(root: &Path, outputs: Vec<PathBuf>, command: Vec<String>) -> CachedStep {
+        CachedStep {
+            moonc_version: "v0.0.1".to_string(),
+            roots: vec![root.to_path_buf()],
+            inputs: vec![],
+            outputs,
+            command,
+        }
+    }
+
+    #[test]
+    fn test_key_is_independent_of_checkout_location() {
+        let a = tempfile::tempdir().unwrap();
+        let b = tempfile::tempdir().unwrap();
+        let mut keys = vec![];
+        for dir in [a.path(), b.path()] {
+            std::fs::write(dir.join("main.mbt"), "fn main {}").unwrap();
+            let command = vec![
+                "moonc".to_string(),
+                "build-package".to_string(),
+                dir.join("main.mbt").display().to_string(),
+                "-o".to_string(),
+                dir.join("main.core").display().to_string(),
+            ];
+            keys.push(
+                step(dir, vec![dir.join("main.core")], command)
+                    .key()
+                    .unwrap(),
+            );
+        }
+        assert_eq!(keys[0], keys[1]);
+
+        std::fs::write(b.path().join("main.mbt"), "fn main { () }").unwrap();
+        let command = vec![
+            "moonc".to_string(),
+            "build-package".to_string(),
+            b.path().join("main.mbt").display().to_string(),
+            "-o".to_string(),
+            b.path().join("main.core").display().to_string(),
+        ];
+        let changed = step(b.path(), vec![b.path().join("main.core")], command)
+            .key()
+            .unwrap();
+        assert_ne!(keys[0], changed);
+    }
+
+    #[test]
+    fn test_pack_and_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("_build").join("main.core");
+        std::fs::create_dir_all(out.parent().unwrap()).unwrap();
+        std::fs::write(&out, "core").unwrap();
+        let step = step(dir.path(), vec![out.clone()], vec!["moonc".to_string()]);
+        let entry = step.pack(b"", b"warning").unwrap();
+
+        let store = Store {
+            dir: Some(dir.path().join("cache")),
+            url: None,
+        };
+        assert!(store.get("abcd").unwrap().is_none());
+        store.put("abcd", &entry);
+        assert!(dir.path().join("cache/ab/abcd.zip").exists());
+
+        std::fs::remove_dir_all(out.parent().unwrap()).unwrap();
+        step.restore(&store.get("abcd").unwrap().unwrap()).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "core");
+    }
+
+    #[test]
+    fn test_emits_debug_info() {
+        let command = |args: &[&str]| args.iter().map(|it| it.to_string()).collect::<Vec<_>>();
+        assert!(emits_debug_info(&command(&[
+            "moonc",
+            "build-package",
+            "-g",
+            "-O0"
+        ])));
+        assert!(emits_debug_info(&command(&["cc", "-ggdb", "-c", "a.c"])));
+        assert!(emits_debug_info(&command(&["cc", "-g3", "-c", "a.c"])));
+        assert!(emits_debug_info(&command(&["cl.exe", "/Z7", "a.c"])));
+        assert!(!emits_debug_info(&command(&[
+            "moonc",
+            "link-core",
+            "-target",
+            "wasm-gc"
+        ])));
+        assert!(!emits_debug_info(&command(&["cc", "-O2", "-c", "a.c"])));
+    }
+
+    #[test]
+    fn test_unwrap_command() {
+        let args = [
+            "moon",
+            "tool",
+            "build-cache",
+            "--root",
+            "/a",
+            "--",
+            "moonc",
+            "-o",
+            "x",
+        ]
+        .map(String::from);
+        assert_eq!(unwrap_command(&args), &args[6..]);
+        assert_eq!(unwrap_command(&args[6..]), &args[6..]);
+    }
+}
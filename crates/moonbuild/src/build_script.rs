@@ -30,10 +30,12 @@ use std::{
 use anyhow::{Context, anyhow};
 use log::warn;
 use moonutil::{
-    build_script::{BuildScriptEnvironment, BuildScriptOutput},
+    build_script::{BuildInfo, BuildScriptEnvironment, BuildScriptOutput},
+    common::TargetBackend,
     module::ModuleDB,
     mooncakes::{DirSyncResult, ModuleName, result::ResolvedEnv},
     path::PathComponent,
+    system_deps::{SystemDepFlags, probe_system_dep},
 };
 use regex::{Captures, Regex};
 
@@ -43,12 +45,15 @@ pub fn run_prebuild_config(
     dir_sync_result: &DirSyncResult,
     mods: &ResolvedEnv,
     mdb: &mut ModuleDB,
+    target_backend: TargetBackend,
+    target_triple: Option<&str>,
 ) -> anyhow::Result<()> {
     // This script currently uses the quickest and dirtiest way to
     // achieve the goals.
     // TODO: refactor and make it efficient and cleaner
 
     let env_vars: HashMap<String, String> = std::env::vars().collect();
+    let build = BuildInfo::new(target_backend, target_triple)?;
     let mut pkg_outputs = HashMap::<PathComponent, BuildScriptOutput>::new();
 
     for (id, module) in mods.all_modules_and_id() {
@@ -57,7 +62,7 @@ pub fn run_prebuild_config(
         if let Some(prebuild) = &def.__moonbit_unstable_prebuild {
             // just run `node {prebuild.js}` and read the output
             let dir = dir_sync_result.get(id).expect("module not found");
-            let input = make_prebuild_input_from_module(dir, &env_vars);
+            let input = make_prebuild_input_from_module(dir, &env_vars, &build);
 
             let output = run_build_script_for_module(module, dir, input, prebuild)?;
             pkg_outputs.insert(
@@ -89,6 +94,44 @@ pub fn run_prebuild_config(
         apply_output(output, mdb);
     }
 
+    // Resolve the system libraries of native packages
+    if target_backend.is_native() {
+        for (_name, pkg) in mdb.get_all_packages_mut().iter_mut() {
+            resolve_system_deps(pkg, target_triple)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Query `pkg-config` for the `native.system-deps` of `pkg` on `target_triple`.
+/// The cflags go to the C stub compilation, and the libs are propagated to
+/// dependents like the link flags from prebuild scripts.
+fn resolve_system_deps(
+    pkg: &mut moonutil::package::Package,
+    target_triple: Option<&str>,
+) -> anyhow::Result<()> {
+    let pkg_name = pkg.full_name();
+    let Some(native) = pkg.link.as_mut().and_then(|link| link.native.as_mut()) else {
+        return Ok(());
+    };
+    let Some(system_deps) = &native.system_deps else {
+        return Ok(());
+    };
+
+    let mut flags = SystemDepFlags::default();
+    for (name, version) in system_deps {
+        flags.extend(probe_system_dep(&pkg_name, name, version, target_triple)?);
+    }
+    native.system_deps_cflags = Some(flags.cflags);
+
+    if !flags.libs.is_empty() {
+        let libs = flags.libs.join(" ");
+        pkg.link_flags = Some(match pkg.link_flags.take() {
+            Some(link_flags) => format!("{link_flags} {libs}"),
+            None => libs,
+        });
+    }
     Ok(())
 }
 
@@ -256,9 +299,10 @@ pub fn run_build_script_for_module(
 pub fn make_prebuild_input_from_module(
     m_dir: &Path,
     env_vars: &HashMap<String, String>,
+    build: &BuildInfo,
 ) -> BuildScriptEnvironment {
     BuildScriptEnvironment {
-        // build: BuildInfo { host: TargetInfo },
+        build: build.clone(),
         env: env_vars.clone(),
         paths: moonutil::build_script::Paths {
             module_root: m_dir.to_string_lossy().to_string(),
@@ -0,0 +1,103 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Generation of a [JSON compilation database] from a lowered n2 graph, so
+//! that C language servers like `clangd` know how native stubs and the
+//! generated C files are compiled.
+//!
+//! [JSON compilation database]: https://clang.llvm.org/docs/JSONCompilationDatabase.html
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use n2::graph::Graph;
+use serde::Serialize;
+
+use crate::build_cache::unwrap_command;
+
+pub const COMPILE_COMMANDS_JSON: &str = "compile_commands.json";
+
+/// A single entry of `compile_commands.json`.
+#[derive(Debug, Serialize)]
+pub struct CompileCommand {
+    pub directory: PathBuf,
+    pub file: String,
+    pub arguments: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// Collect the compile command of every C file compiled by the build graph.
+///
+/// A build is considered to compile a C file if one of its inputs is a `.c`
+/// file that also appears on its commandline. Other input files of the same
+/// command (object files, archives, other C files) are dropped from the
+/// arguments, so each entry describes the compilation of exactly one file.
+pub fn collect_compile_commands(graph: &Graph, directory: &Path) -> Vec<CompileCommand> {
+    let files = &graph.files;
+    let mut commands = vec![];
+    for build in graph.builds.iter() {
+        let Some(cmdline) = &build.cmdline else {
+            continue;
+        };
+        let Some(arguments) = shlex::split(cmdline) else {
+            continue;
+        };
+        let arguments = unwrap_command(&arguments).to_vec();
+        let inputs = build
+            .ins
+            .ids
+            .iter()
+            .map(|id| files.by_id[*id].name.as_str())
+            .collect::<Vec<_>>();
+        let output = arguments
+            .iter()
+            .position(|x| x == "-o")
+            .and_then(|i| arguments.get(i + 1))
+            .cloned();
+
+        for file in inputs.iter().filter(|x| x.ends_with(".c")) {
+            if !arguments.iter().any(|x| x.as_str() == *file) {
+                continue;
+            }
+            let arguments = arguments
+                .iter()
+                .filter(|x| x.as_str() == *file || !inputs.contains(&x.as_str()))
+                .cloned()
+                .collect();
+            commands.push(CompileCommand {
+                directory: directory.to_path_buf(),
+                file: file.to_string(),
+                arguments,
+                output: output.clone(),
+            });
+        }
+    }
+    commands
+}
+
+/// Write `compile_commands.json` for the build graph into `target_dir`.
+pub fn write_compile_commands(graph: &Graph, target_dir: &Path) -> anyhow::Result<()> {
+    let directory = std::env::current_dir().context("failed to get current directory")?;
+    let commands = collect_compile_commands(graph, &directory);
+    let path = target_dir.join(COMPILE_COMMANDS_JSON);
+    std::fs::create_dir_all(target_dir)
+        .with_context(|| format!("failed to create `{}`", target_dir.display()))?;
+    let content = serde_json::to_string_pretty(&commands)?;
+    std::fs::write(&path, content).with_context(|| format!("failed to write `{}`", path.display()))
+}
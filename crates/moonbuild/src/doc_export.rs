@@ -0,0 +1,551 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Export of the public API documentation to formats that can be consumed by
+//! other tools, e.g. static site generators.
+//!
+//! The exported data is read from the markdown pages that `moondoc` writes to
+//! the doc directory when `moon doc` runs without `--serve`, so it is derived
+//! from the same build as the documentation itself and never touches the
+//! source tree.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DocFormat {
+    // One page per package, plus an index page
+    Markdown,
+    // A single `docs.json` describing all packages
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModuleDocs {
+    pub module: String,
+    pub packages: Vec<PackageDocs>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PackageDocs {
+    pub name: String,
+    pub items: Vec<ItemDocs>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ItemDocs {
+    pub name: String,
+    pub kind: ItemKind,
+    /// The declaration as rendered by `moondoc`
+    pub signature: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemKind {
+    Value,
+    Function,
+    Error,
+    Type,
+    Method,
+    Impl,
+    TypeAlias,
+    Trait,
+    TraitAlias,
+    Other,
+}
+
+/// The page `moondoc` writes for each package, at `<doc dir>/<package>/members.md`.
+const MEMBERS_PAGE: &str = "members.md";
+
+/// The name of the search index file in the doc directory.
+pub const SEARCH_INDEX_FILE: &str = "search_index.json";
+
+/// An entry of the search index served by `moon doc --serve`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchEntry {
+    pub name: String,
+    pub kind: ItemKind,
+    pub package: String,
+    pub signature: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+    /// Whether the item comes from a dependency rather than the current module
+    #[serde(default)]
+    pub dependency: bool,
+}
+
+/// Build the search index from the pages generated by `moondoc` in
+/// `pages_dir`, and write it to `doc_dir`.
+///
+/// `moondoc` documents the dependencies of the module `module_name` too, and
+/// their items are marked as such.
+pub fn write_search_index(
+    module_name: &str,
+    pages_dir: &Path,
+    doc_dir: &Path,
+) -> anyhow::Result<()> {
+    let entries: Vec<_> = read_doc_pages(pages_dir)?
+        .into_iter()
+        .flat_map(|pkg| {
+            let dependency = !is_in_module(module_name, &pkg.name);
+            let package = pkg.name;
+            pkg.items.into_iter().map(move |item| SearchEntry {
+                name: item.name,
+                kind: item.kind,
+                package: package.clone(),
+                signature: item.signature,
+                doc: item.doc,
+                dependency,
+            })
+        })
+        .collect();
+    std::fs::create_dir_all(doc_dir)?;
+    let path = doc_dir.join(SEARCH_INDEX_FILE);
+    std::fs::write(&path, serde_json::to_string(&entries)?)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+impl ItemKind {
+    pub fn title(self) -> &'static str {
+        match self {
+            ItemKind::Value => "Values",
+            ItemKind::Function => "Functions",
+            ItemKind::Error => "Errors",
+            ItemKind::Type => "Types",
+            ItemKind::Method => "Methods",
+            ItemKind::Impl => "Trait implementations",
+            ItemKind::TypeAlias => "Type aliases",
+            ItemKind::Trait => "Traits",
+            ItemKind::TraitAlias => "Trait aliases",
+            ItemKind::Other => "Others",
+        }
+    }
+}
+
+/// Collect the documentation of all packages in the module `module_name`
+/// from the pages generated by `moondoc` in `doc_dir`.
+///
+/// `moondoc` also documents the dependencies of the module, which are left
+/// out.
+pub fn collect_module_docs(module_name: &str, doc_dir: &Path) -> anyhow::Result<ModuleDocs> {
+    let packages = read_doc_pages(doc_dir)?
+        .into_iter()
+        .filter(|pkg| is_in_module(module_name, &pkg.name))
+        .collect();
+    Ok(ModuleDocs {
+        module: module_name.to_string(),
+        packages,
+    })
+}
+
+/// Read the documentation of all packages documented in `doc_dir`.
+pub fn read_doc_pages(doc_dir: &Path) -> anyhow::Result<Vec<PackageDocs>> {
+    let mut packages = vec![];
+    for entry in WalkDir::new(doc_dir).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() || entry.file_name() != MEMBERS_PAGE {
+            continue;
+        }
+        let Some(pkg_dir) = entry
+            .path()
+            .parent()
+            .and_then(|p| p.strip_prefix(doc_dir).ok())
+        else {
+            continue;
+        };
+        let name = pkg_dir
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let content = std::fs::read_to_string(entry.path())
+            .with_context(|| format!("failed to read {}", entry.path().display()))?;
+        packages.push(PackageDocs {
+            name,
+            items: parse_members_page(&content),
+        });
+    }
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(packages)
+}
+
+fn is_in_module(module_name: &str, package: &str) -> bool {
+    package
+        .strip_prefix(module_name)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Parse a `members.md` page written by `moondoc` into the items it documents.
+///
+/// The page starts with tables listing the items of each kind, e.g. a
+/// `|Value|description|` table with a `|[hello](#hello)||` row, followed by a
+/// `## hello` section per item. A section holds a `moonbit` code block with
+/// the declaration, prefixed by its `:::source,<file>,<line>:::` location,
+/// and then the doc comment of the item.
+pub fn parse_members_page(content: &str) -> Vec<ItemDocs> {
+    let mut kinds: HashMap<&str, &str> = HashMap::new();
+    let mut items = vec![];
+    let mut table_header = None;
+    let mut lines = content.lines().peekable();
+    while let Some(line) = lines.next() {
+        if let Some(row) = line.strip_prefix('|') {
+            let cell = row.split('|').next().unwrap_or_default().trim();
+            match cell.strip_prefix('[').and_then(|c| c.split_once("](")) {
+                Some((name, _)) => {
+                    if let Some(header) = table_header {
+                        kinds.insert(name, header);
+                    }
+                }
+                None if !cell.starts_with('-') => table_header = Some(cell),
+                None => {}
+            }
+            continue;
+        }
+        let Some(name) = line.strip_prefix("## ") else {
+            continue;
+        };
+        let name = name.trim();
+
+        let mut signature: Vec<&str> = vec![];
+        let mut doc: Vec<&str> = vec![];
+        let mut in_code = false;
+        let mut seen_code = false;
+        while let Some(line) = lines.next_if(|l| !l.starts_with("## ") && !l.starts_with("# ")) {
+            if !seen_code && line.starts_with("```") {
+                seen_code = in_code;
+                in_code = !in_code;
+            } else if in_code {
+                signature.push(strip_source_location(line));
+            } else if seen_code {
+                doc.push(line);
+            }
+        }
+        let signature = signature.join("\n");
+        let doc = doc.join("\n").trim().to_string();
+        items.push(ItemDocs {
+            name: name.to_string(),
+            kind: item_kind(kinds.get(name).copied(), name, &signature),
+            signature,
+            doc: (!doc.is_empty()).then_some(doc),
+        });
+    }
+    items
+}
+
+fn strip_source_location(line: &str) -> &str {
+    line.strip_prefix(":::source,")
+        .and_then(|rest| rest.split_once(":::"))
+        .map_or(line, |(_, decl)| decl)
+}
+
+/// Determine the kind of an item from the header of the table `moondoc`
+/// lists it in.
+fn item_kind(table_header: Option<&str>, name: &str, signature: &str) -> ItemKind {
+    if name.contains("::") {
+        return ItemKind::Method;
+    }
+    match table_header.map(str::to_ascii_lowercase).as_deref() {
+        // Functions are listed as values
+        Some("value") if signature.starts_with("fn ") || signature.starts_with("fn[") => {
+            ItemKind::Function
+        }
+        Some("value") => ItemKind::Value,
+        Some("function") => ItemKind::Function,
+        Some("type") => ItemKind::Type,
+        Some("error" | "error type") => ItemKind::Error,
+        Some("trait") => ItemKind::Trait,
+        Some("type alias" | "typealias") => ItemKind::TypeAlias,
+        Some("trait alias" | "traitalias") => ItemKind::TraitAlias,
+        _ => ItemKind::Other,
+    }
+}
+
+/// Write `docs` to `out_dir` in the given format.
+pub fn write_module_docs(
+    docs: &ModuleDocs,
+    format: DocFormat,
+    out_dir: &Path,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("failed to create {}", out_dir.display()))?;
+    match format {
+        DocFormat::Json => {
+            let path = out_dir.join("docs.json");
+            let content = serde_json::to_string_pretty(docs)?;
+            std::fs::write(&path, content)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+        }
+        DocFormat::Markdown => {
+            let mut index = format!("# `{}`\n\n", docs.module);
+            for pkg in &docs.packages {
+                let path = markdown_page_of(out_dir, &pkg.name);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&path, render_package_markdown(pkg))
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+                index.push_str(&format!("- [`{}`]({}.md)\n", pkg.name, pkg.name));
+            }
+            let path = out_dir.join("index.md");
+            std::fs::write(&path, index)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn markdown_page_of(out_dir: &Path, pkg_name: &str) -> PathBuf {
+    let mut path = out_dir.to_path_buf();
+    path.extend(pkg_name.split('/'));
+    path.set_extension("md");
+    path
+}
+
+pub fn render_package_markdown(pkg: &PackageDocs) -> String {
+    let mut out = format!("# `{}`\n", pkg.name);
+    let mut items: Vec<_> = pkg.items.iter().collect();
+    items.sort_by_key(|item| item.kind);
+    let mut current_kind = None;
+    for item in items {
+        if current_kind != Some(item.kind) {
+            current_kind = Some(item.kind);
+            out.push_str(&format!("\n## {}\n", item.kind.title()));
+        }
+        out.push_str(&format!(
+            "\n### `{}`\n\n```moonbit\n{}\n```\n",
+            item.name, item.signature
+        ));
+        if let Some(doc) = &item.doc {
+            out.push('\n');
+            out.push_str(doc);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_members_page() {
+        let page = r#"# Documentation
+|Value|description|
+|---|---|
+|[hello](#hello)||
+|[answer](#answer)||
+
+|Type|description|
+|---|---|
+|[Point](#Point)||
+
+## hello
+
+```moonbit
+:::source,username/hello/lib/hello.mbt,3:::fn hello() -> String
+```
+
+Says hello.
+
+```moonbit
+hello()
+```
+
+## answer
+
+```moonbit
+:::source,username/hello/lib/hello.mbt,8:::let answer : Int
+```
+
+## Point
+
+```moonbit
+:::source,username/hello/lib/point.mbt,1:::pub(all) struct Point {
+  x : Int
+}
+```
+
+## Point::new
+
+```moonbit
+:::source,username/hello/lib/point.mbt,6:::fn Point::new(x : Int) -> Point
+```
+"#;
+        let items = parse_members_page(page);
+        let summary: Vec<_> = items
+            .iter()
+            .map(|item| format!("{:?} {}: {}", item.kind, item.name, item.signature))
+            .collect();
+        expect_test::expect![[r#"
+            [
+                "Function hello: fn hello() -> String",
+                "Value answer: let answer : Int",
+                "Type Point: pub(all) struct Point {\n  x : Int\n}",
+                "Method Point::new: fn Point::new(x : Int) -> Point",
+            ]
+        "#]]
+        .assert_debug_eq(&summary);
+        assert_eq!(
+            items[0].doc.as_deref(),
+            Some("Says hello.\n\n```moonbit\nhello()\n```")
+        );
+        assert_eq!(items[1].doc, None);
+    }
+
+    #[test]
+    fn test_collect_module_docs() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        let page = "# Documentation\n|Value|description|\n|---|---|\n|[f](#f)||\n\n## f\n\n```moonbit\n:::source,a.mbt,1:::fn f() -> Unit\n```\n";
+        write("user/app/members.md", page);
+        write("user/app/lib/members.md", page);
+        write("user/application/members.md", page);
+        write("moonbitlang/core/list/members.md", page);
+        write("_sidebar.md", "- [user/app](user/app/)");
+
+        let docs = collect_module_docs("user/app", dir.path()).unwrap();
+        let packages: Vec<_> = docs.packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(packages, ["user/app", "user/app/lib"]);
+        assert_eq!(docs.packages[1].items[0].name, "f");
+    }
+
+    #[test]
+    fn test_write_search_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let pages_dir = dir.path().join("pages");
+        let write = |path: &str, content: &str| {
+            let path = pages_dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        write(
+            "user/app/lib/members.md",
+            "# Documentation\n|Value|description|\n|---|---|\n|[parse](#parse)||\n\n## parse\n\n```moonbit\n:::source,user/app/lib/lib.mbt,2:::fn parse(String) -> @json.Json\n```\n\nParse a document\n",
+        );
+        write(
+            "x/json/members.md",
+            "# Documentation\n|Type|description|\n|---|---|\n|[Json](#Json)||\n\n## Json\n\n```moonbit\n:::source,x/json/json.mbt,1:::pub enum Json {\n  Null\n}\n```\n",
+        );
+
+        let doc_dir = dir.path().join("doc");
+        write_search_index("user/app", &pages_dir, &doc_dir).unwrap();
+        let index: Vec<SearchEntry> = serde_json::from_str(
+            &std::fs::read_to_string(doc_dir.join(SEARCH_INDEX_FILE)).unwrap(),
+        )
+        .unwrap();
+        let entries = index
+            .iter()
+            .map(|e| {
+                (
+                    e.package.as_str(),
+                    e.name.as_str(),
+                    e.kind,
+                    e.dependency,
+                    e.doc.as_deref(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                (
+                    "user/app/lib",
+                    "parse",
+                    ItemKind::Function,
+                    false,
+                    Some("Parse a document")
+                ),
+                ("x/json", "Json", ItemKind::Type, true, None),
+            ]
+        );
+        assert_eq!(index[1].signature, "pub enum Json {\n  Null\n}");
+    }
+
+    #[test]
+    fn test_write_module_docs_markdown() {
+        let docs = ModuleDocs {
+            module: "user/app".to_string(),
+            packages: vec![PackageDocs {
+                name: "user/app/lib".to_string(),
+                items: vec![
+                    ItemDocs {
+                        name: "Token".to_string(),
+                        kind: ItemKind::Type,
+                        signature: "pub struct Token {\n  text : String\n}".to_string(),
+                        doc: None,
+                    },
+                    ItemDocs {
+                        name: "parse".to_string(),
+                        kind: ItemKind::Function,
+                        signature: "fn parse(String) -> Token".to_string(),
+                        doc: Some("Parse a token".to_string()),
+                    },
+                ],
+            }],
+        };
+        let dir = tempfile::tempdir().unwrap();
+        write_module_docs(&docs, DocFormat::Markdown, dir.path()).unwrap();
+
+        let index = std::fs::read_to_string(dir.path().join("index.md")).unwrap();
+        expect_test::expect![[r#"
+            # `user/app`
+
+            - [`user/app/lib`](user/app/lib.md)
+        "#]]
+        .assert_eq(&index);
+        let page = std::fs::read_to_string(dir.path().join("user/app/lib.md")).unwrap();
+        expect_test::expect![[r#"
+            # `user/app/lib`
+
+            ## Functions
+
+            ### `parse`
+
+            ```moonbit
+            fn parse(String) -> Token
+            ```
+
+            Parse a token
+
+            ## Types
+
+            ### `Token`
+
+            ```moonbit
+            pub struct Token {
+              text : String
+            }
+            ```
+        "#]]
+        .assert_eq(&page);
+    }
+}
@@ -16,43 +16,423 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
+use std::collections::BTreeMap;
 use std::io::Error as IoError;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock};
+use std::task::{Context as TaskContext, Poll};
 
 use anyhow::Context;
 use colored::Colorize;
+use futures::Stream;
 use http::response::Builder as ResponseBuilder;
 use http::{StatusCode, header};
+use hyper::body::{Bytes, Frame};
 use hyper::service::service_fn;
 use hyper::{Request, Response};
 use hyper_staticfile::{Body, Static};
 use hyper_util::rt::TokioIo;
+use regex::Regex;
 use tokio::net::TcpListener;
 use tokio::runtime::Runtime;
+use tokio::sync::watch;
 
-async fn handle_request<B>(req: Request<B>, static_: Static) -> Result<Response<Body>, IoError> {
-    if req.uri().path() == "/" {
-        let res = ResponseBuilder::new()
-            .status(StatusCode::MOVED_PERMANENTLY)
-            .header(header::LOCATION, "/index.html#/")
-            .body(Body::Empty)
+use crate::doc_export::{SEARCH_INDEX_FILE, SearchEntry};
+
+/// The path of the server-sent events endpoint used for live reload.
+const RELOAD_EVENTS_PATH: &str = "/__moon_reload";
+
+/// The script injected into `index.html` when live reload is enabled.
+const RELOAD_SCRIPT: &str = r#"<script>
+new EventSource("/__moon_reload").addEventListener("reload", () => location.reload());
+</script>"#;
+
+/// The path prefix of the pages rendered for dependency packages.
+const PACKAGE_PAGE_PATH: &str = "/package/";
+
+/// The maximum number of results returned by `/search`.
+const MAX_SEARCH_RESULTS: usize = 50;
+
+/// The script injected into `index.html` to provide a search box backed by
+/// the `/search` endpoint.
+const SEARCH_SCRIPT: &str = r#"<script>
+(() => {
+  const box = document.createElement("div");
+  box.style = "position:fixed;top:8px;right:8px;z-index:1000;width:360px;font-family:sans-serif";
+  box.innerHTML = '<input type="search" placeholder="Search..." style="width:100%;padding:4px">'
+    + '<ul style="list-style:none;margin:0;padding:0;background:#fff;max-height:70vh;overflow:auto"></ul>';
+  const [input, list] = box.children;
+  let pending;
+  input.addEventListener("input", () => {
+    clearTimeout(pending);
+    pending = setTimeout(async () => {
+      list.innerHTML = "";
+      if (!input.value) return;
+      const results = await (await fetch("/search?q=" + encodeURIComponent(input.value))).json();
+      for (const r of results) {
+        const li = document.createElement("li");
+        const a = document.createElement("a");
+        a.href = r.url;
+        a.textContent = r.package + "." + r.name;
+        a.title = r.signature;
+        li.append(a, " (" + r.kind + ")");
+        list.append(li);
+      }
+    }, 150);
+  });
+  document.body.append(box);
+})();
+</script>"#;
+
+/// A reference to an item of another package in a signature, like `@list.List`.
+static PACKAGE_REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"@([A-Za-z0-9_]+)\.([A-Za-z0-9_]+)").unwrap());
+
+/// A handle to notify browsers connected to the doc server to reload the page.
+#[derive(Clone)]
+pub struct ReloadHandle {
+    generation: Arc<watch::Sender<u64>>,
+}
+
+impl Default for ReloadHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReloadHandle {
+    pub fn new() -> Self {
+        ReloadHandle {
+            generation: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// Ask all connected browsers to reload the page.
+    pub fn reload(&self) {
+        self.generation.send_modify(|g| *g += 1);
+    }
+
+    fn subscribe(&self) -> watch::Receiver<u64> {
+        self.generation.subscribe()
+    }
+}
+
+/// The response body of the doc server.
+enum DocBody {
+    /// A static file from the doc directory.
+    Static(Body),
+    /// A body generated by the server in full.
+    Full(Option<Bytes>),
+    /// A never-ending stream of server-sent events.
+    Events(Pin<Box<dyn Stream<Item = Bytes> + Send>>),
+}
+
+impl hyper::body::Body for DocBody {
+    type Data = Bytes;
+    type Error = IoError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, IoError>>> {
+        match self.get_mut() {
+            DocBody::Static(body) => Pin::new(body).poll_frame(cx),
+            DocBody::Full(bytes) => Poll::Ready(bytes.take().map(|b| Ok(Frame::data(b)))),
+            DocBody::Events(stream) => stream
+                .as_mut()
+                .poll_next(cx)
+                .map(|b| b.map(|b| Ok(Frame::data(b)))),
+        }
+    }
+}
+
+async fn handle_request<B>(
+    req: Request<B>,
+    static_: Static,
+    root_dir: PathBuf,
+    reload: Option<ReloadHandle>,
+) -> Result<Response<DocBody>, IoError> {
+    match (req.uri().path(), reload) {
+        ("/", _) => {
+            let res = ResponseBuilder::new()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header(header::LOCATION, "/index.html#/")
+                .body(DocBody::Static(Body::Empty))
+                .expect("unable to build response");
+            Ok(res)
+        }
+        (RELOAD_EVENTS_PATH, Some(reload)) => Ok(reload_events(reload)),
+        ("/index.html", reload) => serve_index(req, static_, root_dir, reload.is_some()).await,
+        ("/search", _) => {
+            let query = query_param(req.uri().query().unwrap_or(""), "q").unwrap_or_default();
+            Ok(search(&root_dir, &query).await)
+        }
+        (path, _) if path.starts_with(PACKAGE_PAGE_PATH) => {
+            let package = percent_decode(&path[PACKAGE_PAGE_PATH.len()..]);
+            Ok(package_page(&root_dir, &package).await)
+        }
+        _ => Ok(static_.serve(req).await?.map(DocBody::Static)),
+    }
+}
+
+/// Serve `index.html` with the search box and optionally the live reload
+/// script injected.
+async fn serve_index<B>(
+    req: Request<B>,
+    static_: Static,
+    root_dir: PathBuf,
+    reload: bool,
+) -> Result<Response<DocBody>, IoError> {
+    let Ok(index) = tokio::fs::read_to_string(root_dir.join("index.html")).await else {
+        // Let the static server produce the appropriate error response
+        return Ok(static_.serve(req).await?.map(DocBody::Static));
+    };
+    let index = inject_scripts(&index, &index_scripts(reload));
+    let res = ResponseBuilder::new()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(DocBody::Full(Some(Bytes::from(index))))
+        .expect("unable to build response");
+    Ok(res)
+}
+
+/// The scripts injected into `index.html`.
+fn index_scripts(reload: bool) -> String {
+    let mut scripts = SEARCH_SCRIPT.to_string();
+    if reload {
+        scripts.push_str(RELOAD_SCRIPT);
+    }
+    scripts
+}
+
+/// Insert `scripts` at the end of `index`'s body.
+fn inject_scripts(index: &str, scripts: &str) -> String {
+    match index.rfind("</body>") {
+        Some(pos) => format!("{}{}{}", &index[..pos], scripts, &index[pos..]),
+        None => format!("{index}{scripts}"),
+    }
+}
+
+async fn load_search_index(root_dir: &Path) -> Vec<SearchEntry> {
+    // The index is small, and re-reading it picks up regenerated docs
+    match tokio::fs::read_to_string(root_dir.join(SEARCH_INDEX_FILE)).await {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+        Err(_) => vec![],
+    }
+}
+
+/// The page documenting `entry`.
+///
+/// Every package gets a page rendered by the server from the search index,
+/// so that references to items of other packages, including the ones from
+/// dependencies, are linked when the page is generated.
+fn url_of(entry: &SearchEntry) -> String {
+    format!("{PACKAGE_PAGE_PATH}{}#{}", entry.package, entry.name)
+}
+
+#[derive(serde::Serialize)]
+struct SearchResult<'a> {
+    #[serde(flatten)]
+    entry: &'a SearchEntry,
+    url: String,
+}
+
+/// Rank the entries matching `query`: exact matches first, then prefix
+/// matches, then the rest.
+fn search_entries<'a>(entries: &'a [SearchEntry], query: &str) -> Vec<&'a SearchEntry> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return vec![];
+    }
+    let mut matches: Vec<_> = entries
+        .iter()
+        .filter_map(|entry| {
+            let name = entry.name.to_lowercase();
+            // Methods can be searched without their type
+            let short = name.rsplit("::").next().unwrap_or(&name);
+            let rank = if name == query || short == query {
+                0
+            } else if name.starts_with(&query) || short.starts_with(&query) {
+                1
+            } else if name.contains(&query) {
+                2
+            } else if format!("{}.{}", entry.package, name).contains(&query) {
+                3
+            } else {
+                return None;
+            };
+            Some((rank, entry))
+        })
+        .collect();
+    matches.sort_by(|(r1, e1), (r2, e2)| {
+        (r1, e1.dependency, &e1.name).cmp(&(r2, e2.dependency, &e2.name))
+    });
+    matches
+        .into_iter()
+        .take(MAX_SEARCH_RESULTS)
+        .map(|(_, e)| e)
+        .collect()
+}
+
+async fn search(root_dir: &Path, query: &str) -> Response<DocBody> {
+    let entries = load_search_index(root_dir).await;
+    let results: Vec<_> = search_entries(&entries, query)
+        .into_iter()
+        .map(|entry| SearchResult {
+            entry,
+            url: url_of(entry),
+        })
+        .collect();
+    let body = serde_json::to_string(&results).expect("failed to serialize search results");
+    ResponseBuilder::new()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(DocBody::Full(Some(Bytes::from(body))))
+        .expect("unable to build response")
+}
+
+/// Render the page of a package from the search index.
+async fn package_page(root_dir: &Path, package: &str) -> Response<DocBody> {
+    let entries = load_search_index(root_dir).await;
+    let items: Vec<_> = entries.iter().filter(|e| e.package == package).collect();
+    if items.is_empty() {
+        return ResponseBuilder::new()
+            .status(StatusCode::NOT_FOUND)
+            .body(DocBody::Full(Some(Bytes::from(format!(
+                "package `{package}` not found"
+            )))))
             .expect("unable to build response");
-        Ok(res)
-    } else {
-        static_.clone().serve(req).await
     }
+
+    // Packages of the current module are also documented by the web UI
+    let local = items.iter().any(|item| !item.dependency);
+    let mut by_kind = BTreeMap::new();
+    for item in items {
+        by_kind.entry(item.kind).or_insert_with(Vec::new).push(item);
+    }
+    let mut html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{0}</title></head><body><h1>{0}</h1>",
+        html_escape(package)
+    );
+    if local {
+        html.push_str(&format!(
+            "<p><a href=\"/index.html#/{}/\">Open in the documentation</a></p>",
+            html_escape(package)
+        ));
+    }
+    for (kind, items) in by_kind {
+        html.push_str(&format!("<h2>{}</h2>", kind.title()));
+        for item in items {
+            html.push_str(&format!(
+                "<section id=\"{0}\"><h3>{0}</h3><pre><code>{1}</code></pre>",
+                html_escape(&item.name),
+                link_signature(&item.signature, &entries)
+            ));
+            if let Some(doc) = &item.doc {
+                html.push_str(&format!("<pre>{}</pre>", html_escape(doc)));
+            }
+            html.push_str("</section>");
+        }
+    }
+    html.push_str("</body></html>");
+    ResponseBuilder::new()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(DocBody::Full(Some(Bytes::from(html))))
+        .expect("unable to build response")
+}
+
+/// Escape `signature` for HTML, turning references like `@list.List` into
+/// links to the referenced items.
+fn link_signature(signature: &str, entries: &[SearchEntry]) -> String {
+    let mut out = String::new();
+    let mut last = 0;
+    for cap in PACKAGE_REFERENCE.captures_iter(signature) {
+        let whole = cap.get(0).unwrap();
+        out.push_str(&html_escape(&signature[last..whole.start()]));
+        last = whole.end();
+        // Packages are referred to by their last segment by default
+        let target = entries
+            .iter()
+            .find(|e| e.name == cap[2] && e.package.rsplit('/').next() == Some(&cap[1]));
+        match target {
+            Some(target) => out.push_str(&format!(
+                "<a href=\"{}\">{}</a>",
+                url_of(target),
+                html_escape(whole.as_str())
+            )),
+            None => out.push_str(&html_escape(whole.as_str())),
+        }
+    }
+    out.push_str(&html_escape(&signature[last..]));
+    out
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
 }
 
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        (k == key).then(|| percent_decode(&v.replace('+', " ")))
+    })
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(b) = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(b);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Stream a `reload` event every time the documentation is regenerated.
+fn reload_events(reload: ReloadHandle) -> Response<DocBody> {
+    let mut rx = reload.subscribe();
+    rx.mark_unchanged();
+    let events = futures::stream::unfold(rx, |mut rx| async move {
+        rx.changed().await.ok()?;
+        Some((Bytes::from_static(b"event: reload\ndata:\n\n"), rx))
+    });
+    ResponseBuilder::new()
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(DocBody::Events(Box::pin(events)))
+        .expect("unable to build response")
+}
+
+/// Serve the documentation at `root_dir`.
+///
+/// If `reload` is given, pages served will reload themselves every time
+/// [`ReloadHandle::reload`] is called.
 pub fn start_server(
     root_dir: impl Into<PathBuf>,
     cake_full_name: &str,
     bind: String,
     port: u16,
+    reload: Option<ReloadHandle>,
 ) -> anyhow::Result<()> {
+    let root_dir = root_dir.into();
     let runtime = Runtime::new()?;
     runtime.block_on(async {
-        let static_ = Static::new(root_dir);
+        let static_ = Static::new(&root_dir);
 
         let addr = format!("{bind}:{port}")
             .parse::<SocketAddr>()
@@ -75,11 +455,15 @@ pub fn start_server(
                 .expect("Failed to accept TCP connection");
 
             let static_ = static_.clone();
+            let root_dir = root_dir.clone();
+            let reload = reload.clone();
             tokio::spawn(async move {
                 if let Err(err) = hyper::server::conn::http1::Builder::new()
                     .serve_connection(
                         TokioIo::new(stream),
-                        service_fn(move |req| handle_request(req, static_.clone())),
+                        service_fn(move |req| {
+                            handle_request(req, static_.clone(), root_dir.clone(), reload.clone())
+                        }),
                     )
                     .await
                 {
@@ -89,3 +473,140 @@ pub fn start_server(
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::doc_export::ItemKind;
+
+    fn entry(package: &str, name: &str, kind: ItemKind, dependency: bool) -> SearchEntry {
+        SearchEntry {
+            name: name.to_string(),
+            kind,
+            package: package.to_string(),
+            signature: String::new(),
+            doc: None,
+            dependency,
+        }
+    }
+
+    #[test]
+    fn test_search_entries() {
+        let entries = vec![
+            entry("user/app/lib", "parse_all", ItemKind::Function, false),
+            entry("user/app/lib", "parse", ItemKind::Function, false),
+            entry("user/app/lib", "Parser::parse", ItemKind::Method, false),
+            entry("moonbitlang/x/json", "parse", ItemKind::Function, true),
+            entry("user/app/lib", "unparse", ItemKind::Function, false),
+            entry("user/app/util", "helper", ItemKind::Function, false),
+        ];
+        let names = |query: &str| {
+            search_entries(&entries, query)
+                .into_iter()
+                .map(|e| format!("{}.{}", e.package, e.name))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names("Parse"),
+            [
+                "user/app/lib.Parser::parse",
+                "user/app/lib.parse",
+                "moonbitlang/x/json.parse",
+                "user/app/lib.parse_all",
+                "user/app/lib.unparse",
+            ]
+        );
+        assert_eq!(names("util.help"), ["user/app/util.helper"]);
+        assert!(names("  ").is_empty());
+        assert!(names("nothing").is_empty());
+    }
+
+    #[test]
+    fn test_url_of() {
+        assert_eq!(
+            url_of(&entry("user/app/lib", "parse", ItemKind::Function, false)),
+            "/package/user/app/lib#parse"
+        );
+        assert_eq!(
+            url_of(&entry(
+                "moonbitlang/x/json",
+                "parse",
+                ItemKind::Function,
+                true
+            )),
+            "/package/moonbitlang/x/json#parse"
+        );
+    }
+
+    #[test]
+    fn test_link_signature() {
+        let entries = vec![
+            entry("moonbitlang/x/json", "Json", ItemKind::Type, true),
+            entry("user/app/lib", "Token", ItemKind::Type, false),
+        ];
+        assert_eq!(
+            link_signature(
+                "fn f(@json.Json, @lib.Token, @other.T) -> Array[Int]",
+                &entries
+            ),
+            "fn f(<a href=\"/package/moonbitlang/x/json#Json\">@json.Json</a>, \
+             <a href=\"/package/user/app/lib#Token\">@lib.Token</a>, @other.T) -&gt; Array[Int]"
+        );
+    }
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(query_param("q=foo&x=1", "q").as_deref(), Some("foo"));
+        assert_eq!(
+            query_param("x=1&q=a+b%3A%3Ac", "q").as_deref(),
+            Some("a b::c")
+        );
+        assert_eq!(query_param("q", "q").as_deref(), Some(""));
+        assert_eq!(query_param("x=1", "q"), None);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(
+            percent_decode("moonbitlang%2Fcore%2Flist"),
+            "moonbitlang/core/list"
+        );
+        assert_eq!(percent_decode("%E4%BD%A0"), "你");
+        // Malformed escapes are kept as-is
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn test_inject_scripts() {
+        let index = "<html><body><div id=\"app\"></div></body></html>";
+        assert_eq!(
+            inject_scripts(index, RELOAD_SCRIPT),
+            format!("<html><body><div id=\"app\"></div>{RELOAD_SCRIPT}</body></html>")
+        );
+        assert!(RELOAD_SCRIPT.contains(RELOAD_EVENTS_PATH));
+        // The search box is always there, and live reload only when enabled
+        assert_eq!(index_scripts(false), SEARCH_SCRIPT);
+        assert_eq!(
+            index_scripts(true),
+            format!("{SEARCH_SCRIPT}{RELOAD_SCRIPT}")
+        );
+        assert!(SEARCH_SCRIPT.contains("/search?q="));
+        // Without a body, the scripts go at the end
+        assert_eq!(
+            inject_scripts("<p>", "<script></script>"),
+            "<p><script></script>"
+        );
+    }
+
+    #[test]
+    fn test_reload_handle() {
+        let reload = ReloadHandle::new();
+        let mut rx = reload.subscribe();
+        assert!(!rx.has_changed().unwrap());
+        reload.reload();
+        assert!(rx.has_changed().unwrap());
+        rx.mark_unchanged();
+        assert!(!rx.has_changed().unwrap());
+    }
+}
@@ -20,7 +20,7 @@ use ariadne::ReportKind;
 use indexmap::IndexMap;
 use log::warn;
 use moonutil::module::ModuleDB;
-use moonutil::package::Package;
+use moonutil::package::{JsRuntime, Package};
 use moonutil::path::PathComponent;
 use n2::graph::FileId;
 use n2::load::State;
@@ -38,15 +38,18 @@ use anyhow::Context;
 use colored::Colorize;
 
 use crate::benchmark::{BATCHBENCH, render_batch_bench_summary};
+use crate::build_cache::{build_cache_enabled, wrap_cacheable_builds};
 use crate::check::normal::write_pkg_lst;
 use crate::expect::{apply_snapshot, render_snapshot_fail};
+use crate::explain::{CommandLog, CommandLogProgress, ExplainProgress};
 use crate::runtest::TestStatistics;
 use crate::test_utils::indices_to_ranges;
+use crate::timings::{BuildTimings, TimingProgress};
 
 use moonutil::common::{
     DOT_MBT_DOT_MD, DiagnosticLevel, DriverKind, FileLock, FileName, MbtTestInfo, MoonbuildOpt,
-    MooncGenTestInfo, MooncOpt, PrePostBuild, TEST_INFO_FILE, TargetBackend, TestArtifacts,
-    TestBlockIndex,
+    MooncGenTestInfo, MooncOpt, PrePostBuild, ProfileFlags, RunMode, TEST_INFO_FILE, TargetBackend,
+    TestArtifacts, TestBlockIndex,
 };
 
 use std::sync::{Arc, Mutex};
@@ -222,6 +225,7 @@ pub fn n2_simple_run_interface(
     }
 
     let res = trace::scope("work.run", || work.run())?;
+    commands.lock().unwrap().save()?;
     Ok(res)
 }
 
@@ -251,6 +255,9 @@ pub struct N2RunStats {
 
     pub n_errors: usize,
     pub n_warnings: usize,
+
+    /// The time taken by each build step, if requested
+    pub timings: Option<BuildTimings>,
 }
 
 impl N2RunStats {
@@ -268,10 +275,27 @@ impl N2RunStats {
         render_result(self, quiet, mode)?;
         Ok(())
     }
+
+    /// Write the timing report of the build into `target/timings`, if the
+    /// timings were recorded.
+    pub fn write_timings(
+        &self,
+        raw_target_dir: &Path,
+        backend: TargetBackend,
+        quiet: bool,
+    ) -> anyhow::Result<()> {
+        if let Some(timings) = &self.timings {
+            let path = timings.write_report(raw_target_dir, backend)?;
+            if !quiet {
+                eprintln!("Timing report saved to {}", path.display());
+            }
+        }
+        Ok(())
+    }
 }
 
 pub fn n2_run_interface(
-    state: n2::load::State,
+    mut state: n2::load::State,
     moonbuild_opt: &MoonbuildOpt,
 ) -> anyhow::Result<N2RunStats> {
     let logger = Arc::new(Mutex::new(ResultCatcher::default()));
@@ -305,8 +329,51 @@ pub fn n2_run_interface(
         vis_build_graph(&state, moonbuild_opt);
     }
 
+    if build_cache_enabled() {
+        wrap_cacheable_builds(
+            &mut state.graph,
+            &[
+                moonbuild_opt.source_dir.as_path(),
+                moonbuild_opt.raw_target_dir.as_path(),
+            ],
+            |build| {
+                build.desc.as_ref().is_some_and(|desc| {
+                    ["build-package: ", "link-core: "]
+                        .iter()
+                        .any(|prefix| desc.starts_with(prefix))
+                })
+            },
+        )?;
+    }
+
     let mut progress =
         create_progress_console(Some(Box::new(render_and_catch)), moonbuild_opt.verbose);
+    let commands_path = moonbuild_opt
+        .target_dir
+        .join(format!("{}.commands", moonbuild_opt.run_mode.to_dir_name()));
+    if let Some(format) = moonbuild_opt.build_opt.as_ref().and_then(|it| it.explain) {
+        progress = Box::new(ExplainProgress::new(
+            progress,
+            &state.graph,
+            format,
+            &moonbuild_opt.source_dir,
+            CommandLog::load(&commands_path),
+        ));
+    }
+    let commands = Arc::new(Mutex::new(CommandLog::load(&commands_path)));
+    progress = Box::new(CommandLogProgress::new(
+        progress,
+        &state.graph,
+        Arc::clone(&commands),
+    ));
+    let timings = moonbuild_opt
+        .build_opt
+        .as_ref()
+        .is_some_and(|it| it.timings)
+        .then(|| Arc::new(Mutex::new(BuildTimings::default())));
+    if let Some(timings) = &timings {
+        progress = Box::new(TimingProgress::new(progress, Arc::clone(timings)));
+    }
     let options = work::Options {
         parallelism: get_parallelism(moonbuild_opt)?,
         failures_left: Some(10),
@@ -374,6 +441,7 @@ pub fn n2_run_interface(
         n_tasks_executed: res,
         n_errors: logger.n_errors,
         n_warnings: logger.n_warnings,
+        timings: timings.map(|it| it.lock().unwrap().clone()),
     })
 }
 
@@ -506,7 +574,15 @@ pub fn run_build(
     let state = trace::scope("moonbit::build::read", || {
         crate::build::load_moon_proj(module, moonc_opt, moonbuild_opt)
     })?;
+    if moonbuild_opt.run_mode == RunMode::Build && moonc_opt.build_opt.target_backend.is_native() {
+        crate::compdb::write_compile_commands(&state.graph, &moonbuild_opt.raw_target_dir)?;
+    }
     let result = n2_run_interface(state, moonbuild_opt)?;
+    result.write_timings(
+        &moonbuild_opt.raw_target_dir,
+        moonc_opt.build_opt.target_backend,
+        moonbuild_opt.quiet,
+    )?;
     render_result(&result, moonbuild_opt.quiet, "building")
 }
 
@@ -516,11 +592,16 @@ pub fn run_run(
     moonbuild_opt: &MoonbuildOpt,
     module: &ModuleDB,
     build_only: bool,
+    moonrun_args: &[String],
+    profile: &ProfileFlags,
 ) -> anyhow::Result<i32> {
     run_build(moonc_opt, moonbuild_opt, module)?;
     let (source_dir, target_dir) = (&moonbuild_opt.source_dir, &moonbuild_opt.target_dir);
 
     let moon_mod = moonutil::common::read_module_desc_file_in_dir(source_dir)?;
+    let runner = moon_mod
+        .runner(moonc_opt.link_opt.target_backend)
+        .map(str::to_owned);
     let package_path = {
         let root = if let Some(src) = &moon_mod.source {
             dunce::canonicalize(moonbuild_opt.source_dir.join(src))
@@ -571,10 +652,31 @@ pub fn run_run(
         return Ok(0);
     }
 
+    if let Some(runner) = &runner {
+        let artifact = match moonc_opt.link_opt.target_backend {
+            TargetBackend::Native | TargetBackend::LLVM => wat_path.with_extension("exe"),
+            _ => wat_path,
+        };
+        trace::scope("run", || {
+            crate::build::run_with_runner(
+                runner,
+                &artifact,
+                &moonbuild_opt.args,
+                moonbuild_opt.verbose,
+            )
+        })?;
+        return Ok(0);
+    }
+
+    let mut moonrun_args = moonrun_args.to_vec();
+    moonrun_args.extend(profile.to_moonrun_args(&wat_path));
     trace::scope("run", || match moonc_opt.link_opt.target_backend {
-        TargetBackend::Wasm | TargetBackend::WasmGC => {
-            crate::build::run_wat(&wat_path, &moonbuild_opt.args, moonbuild_opt.verbose)
-        }
+        TargetBackend::Wasm | TargetBackend::WasmGC => crate::build::run_wat(
+            &wat_path,
+            &moonrun_args,
+            &moonbuild_opt.args,
+            moonbuild_opt.verbose,
+        ),
         TargetBackend::Js => {
             crate::build::run_js(&wat_path, &moonbuild_opt.args, moonbuild_opt.verbose)
         }
@@ -816,6 +918,20 @@ pub fn run_test(
                 test_artifacts.artifacts_path.push(artifact_path.clone());
             }
 
+            // Limits given on the command line take precedence over the ones
+            // in moon.pkg.json
+            let moonrun_args = test_opt.as_ref().map_or(vec![], |opt| {
+                let mut args = opt.moonrun_args.clone();
+                args.extend(
+                    opt.test_limits
+                        .or(pkg.test_limits.as_ref())
+                        .to_moonrun_args(),
+                );
+                args.extend(opt.profile.to_moonrun_args(&artifact_path));
+                args
+            });
+            let js_runtime = pkg.js_test_runtime.unwrap_or_default();
+
             let printed = Arc::clone(&printed);
             let moonc_opt = Arc::clone(&moonc_opt);
             let moonbuild_opt = Arc::clone(&moonbuild_opt);
@@ -825,6 +941,8 @@ pub fn run_test(
                     "test",
                     execute_test(
                         &moonbuild_opt,
+                        &moonrun_args,
+                        js_runtime,
                         moonc_opt.build_opt.target_backend,
                         &artifact_path,
                         &moonbuild_opt.target_dir,
@@ -839,6 +957,8 @@ pub fn run_test(
                             test_res_for_cur_pkg,
                             &moonc_opt,
                             &moonbuild_opt,
+                            &moonrun_args,
+                            js_runtime,
                             &module,
                             auto_update,
                             test_verbose_output,
@@ -953,10 +1073,22 @@ impl TestArgs {
         }
         args.join("/")
     }
+
+    /// The argument passed after the artifact to a custom runner: the native
+    /// test selection for native backends, the JSON-encoded args otherwise.
+    pub fn to_runner_arg(&self, backend: TargetBackend) -> String {
+        match backend {
+            TargetBackend::Native | TargetBackend::LLVM => self.to_cli_args_for_native(),
+            _ => serde_json::to_string(self).expect("Failed to serialize test args"),
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn execute_test(
     moonbuild_opt: &MoonbuildOpt,
+    moonrun_args: &[String],
+    js_runtime: JsRuntime,
     target_backend: TargetBackend,
     artifact_path: &Path,
     target_dir: &Path,
@@ -964,14 +1096,45 @@ async fn execute_test(
     file_test_info_map: &FileTestInfo,
 ) -> anyhow::Result<Vec<Result<TestStatistics, TestFailedStatus>>> {
     let verbose = moonbuild_opt.verbose;
+    if let Some(template) = moonbuild_opt
+        .test_opt
+        .as_ref()
+        .and_then(|opt| opt.backend_runner.as_deref())
+    {
+        // The JS test driver is the artifact to run for JS tests
+        let artifact = match target_backend {
+            TargetBackend::Js => artifact_path.with_extension("cjs"),
+            _ => artifact_path.to_path_buf(),
+        };
+        return crate::runtest::run_with_backend_runner(
+            moonbuild_opt,
+            template,
+            target_backend,
+            &artifact,
+            target_dir,
+            args,
+            file_test_info_map,
+            verbose,
+        )
+        .await;
+    }
     match target_backend {
         TargetBackend::Wasm | TargetBackend::WasmGC => {
-            crate::runtest::run_wat(artifact_path, target_dir, args, file_test_info_map, verbose)
-                .await
+            crate::runtest::run_wat(
+                artifact_path,
+                moonrun_args,
+                target_dir,
+                args,
+                file_test_info_map,
+                verbose,
+            )
+            .await
         }
         TargetBackend::Js => {
             crate::runtest::run_js(
                 &artifact_path.with_extension("cjs"),
+                js_runtime,
+                moonrun_args,
                 target_dir,
                 args,
                 file_test_info_map,
@@ -991,8 +1154,15 @@ async fn execute_test(
             .await
         }
         TargetBackend::LLVM => {
-            crate::runtest::run_llvm(artifact_path, target_dir, args, file_test_info_map, verbose)
-                .await
+            crate::runtest::run_llvm(
+                moonbuild_opt,
+                artifact_path,
+                target_dir,
+                args,
+                file_test_info_map,
+                verbose,
+            )
+            .await
         }
     }
 }
@@ -1106,6 +1276,8 @@ async fn handle_test_result(
     test_res_for_cur_pkg: &mut Vec<Result<TestStatistics, TestFailedStatus>>,
     moonc_opt: &MooncOpt,
     moonbuild_opt: &MoonbuildOpt,
+    moonrun_args: &[String],
+    js_runtime: JsRuntime,
     module: &ModuleDB,
     auto_update: bool,
     test_verbose_output: bool,
@@ -1171,6 +1343,8 @@ async fn handle_test_result(
                     };
                     let rerun = execute_test(
                         moonbuild_opt,
+                        moonrun_args,
+                        js_runtime,
                         moonc_opt.build_opt.target_backend,
                         artifact_path,
                         target_dir,
@@ -1197,6 +1371,8 @@ async fn handle_test_result(
 
                     let cur_res = execute_test(
                         moonbuild_opt,
+                        moonrun_args,
+                        js_runtime,
                         moonc_opt.build_opt.target_backend,
                         artifact_path,
                         target_dir,
@@ -1265,6 +1441,8 @@ async fn handle_test_result(
                     };
                     let rerun = execute_test(
                         moonbuild_opt,
+                        moonrun_args,
+                        js_runtime,
                         moonc_opt.build_opt.target_backend,
                         artifact_path,
                         target_dir,
@@ -1301,6 +1479,8 @@ async fn handle_test_result(
 
                     let mut cur_res = execute_test(
                         moonbuild_opt,
+                        moonrun_args,
+                        js_runtime,
                         moonc_opt.build_opt.target_backend,
                         artifact_path,
                         target_dir,
@@ -1338,6 +1518,8 @@ async fn handle_test_result(
 
                         cur_res = execute_test(
                             moonbuild_opt,
+                            moonrun_args,
+                            js_runtime,
                             moonc_opt.build_opt.target_backend,
                             artifact_path,
                             target_dir,
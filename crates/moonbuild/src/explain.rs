@@ -0,0 +1,397 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+//! Explanations for `moon build --explain`.
+//!
+//! n2 decides whether a build step is dirty by comparing a hash of its
+//! command line and the modification times of its files with the one recorded
+//! in its database, which doesn't tell what actually changed. Instead,
+//! [`ExplainProgress`] looks at the files of each step right before n2 runs
+//! it, and reports an output that is missing or the inputs modified since the
+//! outputs were written.
+//!
+//! For the same reason, the command line of each step is recorded on its own
+//! in a [`CommandLog`] kept next to the database of n2, so that a step whose
+//! command line changed can be told apart. Anything else, like a step without
+//! a record, is reported as unknown.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use moonutil::common::ExplainFormat;
+use n2::graph::{Build, BuildId, FileId, Graph};
+use n2::progress::Progress;
+use n2::task::TaskResult;
+use n2::work::StateCounts;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Why a build step is rerun.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum DirtyReason {
+    /// An output of the step does not exist
+    OutputMissing { output: String },
+    /// Inputs were modified after the outputs were written
+    InputChanged { inputs: Vec<String> },
+    /// The command line differs from the one the step last ran with
+    CommandChanged,
+    /// The outputs are newer than every input and the command line is the
+    /// same or was never recorded. The step is rerun for a reason only n2
+    /// knows
+    Unknown,
+}
+
+impl std::fmt::Display for DirtyReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DirtyReason::OutputMissing { output } => write!(f, "output {output} missing"),
+            DirtyReason::InputChanged { inputs } if inputs.len() == 1 => {
+                write!(f, "input {} changed", inputs[0])
+            }
+            DirtyReason::InputChanged { inputs } => {
+                write!(f, "inputs {} changed", inputs.join(", "))
+            }
+            DirtyReason::CommandChanged => write!(f, "command line changed"),
+            DirtyReason::Unknown => write!(f, "unknown, no input is newer than the outputs"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Explanation<'a> {
+    step: &'a str,
+    #[serde(flatten)]
+    reason: &'a DirtyReason,
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Find out why a step with the given input and output files is rerun.
+/// `command_changed` tells whether its command line differs from the recorded
+/// one, which only matters when the files don't tell.
+pub fn dirty_reason<'a>(
+    ins: impl IntoIterator<Item = &'a str>,
+    outs: impl IntoIterator<Item = &'a str>,
+    command_changed: bool,
+) -> DirtyReason {
+    let mut oldest_output = None;
+    for out in outs {
+        let Some(time) = modified(out) else {
+            return DirtyReason::OutputMissing {
+                output: out.to_string(),
+            };
+        };
+        oldest_output = Some(oldest_output.map_or(time, |x: SystemTime| x.min(time)));
+    }
+    let inputs = ins
+        .into_iter()
+        .filter(|x| match (modified(x), oldest_output) {
+            (Some(time), Some(oldest)) => time > oldest,
+            _ => false,
+        })
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if !inputs.is_empty() {
+        DirtyReason::InputChanged { inputs }
+    } else if command_changed {
+        DirtyReason::CommandChanged
+    } else {
+        DirtyReason::Unknown
+    }
+}
+
+fn hash_command(cmdline: &str) -> String {
+    format!("{:x}", Sha256::digest(cmdline.as_bytes()))
+}
+
+/// The command lines the steps of a build graph last ran with, by the
+/// first output of each step. n2 only records a hash mixing the command line
+/// with the modification times of the files, so it is kept separately.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CommandLog {
+    #[serde(skip)]
+    path: PathBuf,
+    commands: HashMap<String, String>,
+}
+
+impl CommandLog {
+    /// Load the log at `path`. A missing or unreadable log is empty.
+    pub fn load(path: &Path) -> Self {
+        let log = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<CommandLog>(&content).ok())
+            .unwrap_or_default();
+        CommandLog {
+            path: path.to_path_buf(),
+            ..log
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let content = serde_json::to_string(self)?;
+        std::fs::write(&self.path, content)
+            .with_context(|| format!("failed to write `{}`", self.path.display()))
+    }
+
+    /// Whether `cmdline` differs from the one recorded for `output`. A step
+    /// without a record is not considered changed.
+    pub fn changed(&self, output: &str, cmdline: &str) -> bool {
+        self.commands
+            .get(output)
+            .is_some_and(|hash| *hash != hash_command(cmdline))
+    }
+
+    pub fn record(&mut self, output: &str, cmdline: &str) {
+        self.commands
+            .insert(output.to_string(), hash_command(cmdline));
+    }
+}
+
+fn file_names(graph: &Graph) -> HashMap<FileId, String> {
+    graph
+        .files
+        .all_ids()
+        .map(|id| (id, graph.files.by_id[id].name.clone()))
+        .collect()
+}
+
+/// A [`Progress`] that records the command line of each step that is run
+/// into a shared [`CommandLog`] before forwarding to the wrapped progress
+/// console.
+pub struct CommandLogProgress {
+    inner: Box<dyn Progress>,
+    log: Arc<Mutex<CommandLog>>,
+    names: HashMap<FileId, String>,
+}
+
+impl CommandLogProgress {
+    pub fn new(inner: Box<dyn Progress>, graph: &Graph, log: Arc<Mutex<CommandLog>>) -> Self {
+        CommandLogProgress {
+            inner,
+            log,
+            names: file_names(graph),
+        }
+    }
+}
+
+impl Progress for CommandLogProgress {
+    fn update(&mut self, counts: &StateCounts) {
+        self.inner.update(counts);
+    }
+
+    fn task_started(&mut self, id: BuildId, build: &Build) {
+        if let (Some(cmdline), Some(output)) = (&build.cmdline, build.outs().first()) {
+            self.log
+                .lock()
+                .unwrap()
+                .record(&self.names[output], cmdline);
+        }
+        self.inner.task_started(id, build);
+    }
+
+    fn task_output(&mut self, id: BuildId, line: Vec<u8>) {
+        self.inner.task_output(id, line);
+    }
+
+    fn task_finished(&mut self, id: BuildId, build: &Build, result: &TaskResult) {
+        self.inner.task_finished(id, build, result);
+    }
+
+    fn log(&mut self, msg: &str) {
+        self.inner.log(msg);
+    }
+}
+
+/// A [`Progress`] that prints why each build step is rerun before forwarding
+/// to the wrapped progress console.
+pub struct ExplainProgress {
+    inner: Box<dyn Progress>,
+    format: ExplainFormat,
+    source_dir: PathBuf,
+    /// The command lines of the previous builds
+    commands: CommandLog,
+    /// Names of the files in the build graph, since n2 only tells the ids
+    names: HashMap<FileId, String>,
+}
+
+impl ExplainProgress {
+    pub fn new(
+        inner: Box<dyn Progress>,
+        graph: &Graph,
+        format: ExplainFormat,
+        source_dir: &Path,
+        commands: CommandLog,
+    ) -> Self {
+        ExplainProgress {
+            inner,
+            format,
+            source_dir: source_dir.to_path_buf(),
+            commands,
+            names: file_names(graph),
+        }
+    }
+
+    /// Make `path` relative to the source directory, if it is inside.
+    fn relative(&self, path: &str) -> String {
+        match Path::new(path).strip_prefix(&self.source_dir) {
+            Ok(rel) => rel.display().to_string(),
+            Err(_) => path.to_string(),
+        }
+    }
+
+    fn explain(&self, build: &Build) -> String {
+        let name = |id: &FileId| self.names[id].as_str();
+        let command_changed = match (build.outs().first(), &build.cmdline) {
+            (Some(output), Some(cmdline)) => self.commands.changed(name(output), cmdline),
+            _ => false,
+        };
+        let reason = match dirty_reason(
+            build.ins.ids.iter().map(name),
+            build.outs().iter().map(name),
+            command_changed,
+        ) {
+            DirtyReason::OutputMissing { output } => DirtyReason::OutputMissing {
+                output: self.relative(&output),
+            },
+            DirtyReason::InputChanged { inputs } => DirtyReason::InputChanged {
+                inputs: inputs.iter().map(|x| self.relative(x)).collect(),
+            },
+            reason @ (DirtyReason::CommandChanged | DirtyReason::Unknown) => reason,
+        };
+        let step = build
+            .desc
+            .clone()
+            .unwrap_or_else(|| build.location.filename.display().to_string())
+            .replace(&self.source_dir.display().to_string(), ".");
+        match self.format {
+            ExplainFormat::Human => format!("explain: {step}: {reason}"),
+            ExplainFormat::Json => serde_json::to_string(&Explanation {
+                step: &step,
+                reason: &reason,
+            })
+            .expect("Explanations should be serializable"),
+        }
+    }
+}
+
+impl Progress for ExplainProgress {
+    fn update(&mut self, counts: &StateCounts) {
+        self.inner.update(counts);
+    }
+
+    fn task_started(&mut self, id: BuildId, build: &Build) {
+        if build.cmdline.is_some() {
+            let explanation = self.explain(build);
+            // Text goes along with other messages of moon, JSON to tools
+            match self.format {
+                ExplainFormat::Human => eprintln!("{explanation}"),
+                ExplainFormat::Json => println!("{explanation}"),
+            }
+        }
+        self.inner.task_started(id, build);
+    }
+
+    fn task_output(&mut self, id: BuildId, line: Vec<u8>) {
+        self.inner.task_output(id, line);
+    }
+
+    fn task_finished(&mut self, id: BuildId, build: &Build, result: &TaskResult) {
+        self.inner.task_finished(id, build, result);
+    }
+
+    fn log(&mut self, msg: &str) {
+        self.inner.log(msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dirty_reason() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        let (input, output) = (path("a.mbt"), path("a.core"));
+
+        std::fs::write(&input, "").unwrap();
+        assert_eq!(
+            dirty_reason([input.as_str()], [output.as_str()], false),
+            DirtyReason::OutputMissing {
+                output: output.clone()
+            }
+        );
+
+        std::fs::write(&output, "").unwrap();
+        let past = SystemTime::now() - std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&input)
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+        assert_eq!(
+            dirty_reason([input.as_str()], [output.as_str()], false),
+            DirtyReason::Unknown
+        );
+        assert_eq!(
+            serde_json::to_string(&DirtyReason::Unknown).unwrap(),
+            r#"{"reason":"unknown"}"#
+        );
+        assert_eq!(
+            dirty_reason([input.as_str()], [output.as_str()], true),
+            DirtyReason::CommandChanged
+        );
+
+        std::fs::File::options()
+            .write(true)
+            .open(&output)
+            .unwrap()
+            .set_modified(past - std::time::Duration::from_secs(60))
+            .unwrap();
+        let reason = dirty_reason([input.as_str()], [output.as_str()], false);
+        assert_eq!(
+            reason,
+            DirtyReason::InputChanged {
+                inputs: vec![input.clone()]
+            }
+        );
+        assert_eq!(reason.to_string(), format!("input {input} changed"));
+    }
+
+    #[test]
+    fn test_command_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.commands");
+
+        let mut log = CommandLog::load(&path);
+        assert!(!log.changed("a.core", "moonc build-package a.mbt"));
+        log.record("a.core", "moonc build-package a.mbt");
+        log.save().unwrap();
+
+        let log = CommandLog::load(&path);
+        assert!(!log.changed("a.core", "moonc build-package a.mbt"));
+        assert!(log.changed("a.core", "moonc build-package a.mbt -g"));
+        assert!(!log.changed("b.core", "moonc build-package b.mbt"));
+    }
+}
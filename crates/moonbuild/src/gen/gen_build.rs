@@ -20,11 +20,11 @@ use anyhow::{Context, Ok, bail};
 use colored::Colorize;
 use moonutil::compiler_flags::{
     ArchiverConfigBuilder, CC, CCConfigBuilder, LinkerConfigBuilder, OptLevel, OutputType,
-    make_archiver_command, make_cc_command, make_linker_command,
+    Sanitizer, make_archiver_command, make_cc_command, make_linker_command,
 };
 use moonutil::module::ModuleDB;
 use moonutil::moon_dir::MOON_DIRS;
-use moonutil::package::{JsFormat, LinkDepItem, Package};
+use moonutil::package::{JsFormat, LinkDepItem, NativeLinkKind, Package};
 
 use super::cmd_builder::CommandBuilder;
 use super::n2_errors::{N2Error, N2ErrorKind};
@@ -95,6 +95,36 @@ fn to_opt_level(release: bool, debug: bool) -> OptLevel {
     }
 }
 
+/// The optimization level of the C compiler, overridable by the build profile
+fn cc_opt_level(moonc_opt: &MooncOpt) -> OptLevel {
+    let debug_flag = moonc_opt.build_opt.debug_flag;
+    moonc_opt
+        .profile
+        .as_ref()
+        .and_then(|p| p.config.cc_opt_level)
+        .map(OptLevel::from)
+        .unwrap_or_else(|| to_opt_level(!debug_flag, debug_flag))
+}
+
+/// Whether the C compiler emits debug information, overridable by the build
+/// profile
+fn cc_debug_info(moonc_opt: &MooncOpt) -> bool {
+    moonc_opt
+        .profile
+        .as_ref()
+        .and_then(|p| p.config.debug_info)
+        .unwrap_or(moonc_opt.build_opt.debug_flag)
+}
+
+/// The sanitizers requested by `moon test --sanitize`
+fn test_sanitizers(moonbuild_opt: &MoonbuildOpt) -> Vec<Sanitizer> {
+    moonbuild_opt
+        .test_opt
+        .as_ref()
+        .map(|opt| opt.sanitizers.clone())
+        .unwrap_or_default()
+}
+
 pub fn gen_build_interface_item(m: &ModuleDB, pkg: &Package) -> anyhow::Result<BuildInterfaceItem> {
     let virtual_mbti_file_path = pkg.virtual_mbti_file.as_ref().unwrap();
 
@@ -763,6 +793,7 @@ pub fn gen_link_command(
 pub fn gen_compile_runtime_command(
     graph: &mut n2graph::Graph,
     target_dir: &Path,
+    cc: CC,
 ) -> (Build, PathBuf) {
     let runtime_dot_c_path = &MOON_DIRS.moon_lib_path.join("runtime.c");
 
@@ -793,7 +824,7 @@ pub fn gen_compile_runtime_command(
     };
 
     let cc_cmd = make_cc_command::<&'static str>(
-        CC::default(),
+        cc,
         None,
         CCConfigBuilder::default()
             .no_sys_header(true)
@@ -959,7 +990,7 @@ pub fn gen_compile_exe_command(
     }
 
     let cc_cmd = make_cc_command(
-        CC::default(),
+        moonc_opt.native_cc(),
         native_cc.map(|cc| {
             CC::try_from_path(cc)
                 .context(format!(
@@ -972,13 +1003,11 @@ pub fn gen_compile_exe_command(
         CCConfigBuilder::default()
             .no_sys_header(true)
             .output_ty(OutputType::Executable)
-            .opt_level(to_opt_level(
-                !moonc_opt.build_opt.debug_flag,
-                moonc_opt.build_opt.debug_flag,
-            ))
-            .debug_info(moonc_opt.build_opt.debug_flag)
+            .opt_level(cc_opt_level(moonc_opt))
+            .debug_info(cc_debug_info(moonc_opt))
             .link_moonbitrun(!moonbuild_opt.use_tcc_run) // if use tcc, we cannot link moonbitrun
             .define_use_shared_runtime_macro(moonbuild_opt.use_tcc_run)
+            .sanitizers(test_sanitizers(moonbuild_opt))
             .build()
             .unwrap(),
         &native_flags,
@@ -994,6 +1023,266 @@ pub fn gen_compile_exe_command(
     (build, artifact_id)
 }
 
+/// The C compiler and flags for the C code generated for a linked package
+fn native_cc_and_flags<'a>(
+    item: &'a BuildLinkDepItem,
+    moonc_opt: &MooncOpt,
+) -> (Option<CC>, Vec<&'a str>) {
+    let native_cc = item.native_cc(moonc_opt.link_opt.target_backend).map(|cc| {
+        CC::try_from_path(cc)
+            .context(format!(
+                "{}: failed to find native cc: {}",
+                "Error".red(),
+                cc
+            ))
+            .unwrap()
+    });
+    let mut native_flags = vec![];
+    native_flags.extend(
+        item.native_cc_flags(moonc_opt.link_opt.target_backend)
+            .map(|it| it.split(" ").collect::<Vec<_>>())
+            .unwrap_or_default(),
+    );
+    native_flags.extend(
+        item.native_cc_link_flags(moonc_opt.link_opt.target_backend)
+            .map(|it| it.split(" ").collect::<Vec<_>>())
+            .unwrap_or_default(),
+    );
+    (native_cc, native_flags)
+}
+
+/// Compile the C code of a `"kind": "shared"` package, the runtime and the C
+/// stubs it depends on into a shared library.
+pub fn gen_compile_shared_lib_command(
+    graph: &mut n2graph::Graph,
+    item: &BuildLinkDepItem,
+    moonc_opt: &MooncOpt,
+    moonbuild_opt: &MoonbuildOpt,
+) -> (Build, n2graph::FileId) {
+    let path = PathBuf::from(&item.out);
+    let target_dir = path.parent().unwrap();
+    let artifact_output_path = path.with_extension(DYN_EXT);
+    let artifact_id = graph
+        .files
+        .id_from_canonical(artifact_output_path.display().to_string());
+
+    // The runtime is compiled from source instead of reusing `runtime.o`, as
+    // it must be position independent code
+    let mut sources = vec![
+        path.with_extension("c").display().to_string(),
+        MOON_DIRS
+            .moon_lib_path
+            .join("runtime.c")
+            .display()
+            .to_string(),
+    ];
+    if let Some(native_stub_deps) = item.native_stub_deps() {
+        sources.extend(native_stub_deps.iter().cloned());
+    }
+
+    let ins = BuildIns {
+        ids: sources
+            .iter()
+            .map(|f| graph.files.id_from_canonical(f.clone()))
+            .collect(),
+        explicit: sources.len(),
+        implicit: 0,
+        order_only: 0,
+    };
+    let outs = BuildOuts {
+        ids: vec![artifact_id],
+        explicit: 1,
+    };
+    let loc = FileLoc {
+        filename: Rc::new(PathBuf::from("compile-shared-lib")),
+        line: 0,
+    };
+    let mut build = Build::new(loc, ins, outs);
+
+    let (native_cc, native_flags) = native_cc_and_flags(item, moonc_opt);
+    let cc_cmd = make_cc_command(
+        moonc_opt.native_cc(),
+        native_cc,
+        CCConfigBuilder::default()
+            .no_sys_header(true)
+            .output_ty(OutputType::SharedLib)
+            .opt_level(cc_opt_level(moonc_opt))
+            .debug_info(cc_debug_info(moonc_opt))
+            // libmoonbitrun.o is not position independent
+            .link_moonbitrun(false)
+            .define_use_shared_runtime_macro(false)
+            .sanitizers(test_sanitizers(moonbuild_opt))
+            .build()
+            .unwrap(),
+        &native_flags,
+        sources,
+        &target_dir.display().to_string(),
+        &artifact_output_path.display().to_string(),
+    );
+
+    let command = CommandBuilder::from_iter(cc_cmd).build();
+    log::debug!("Command: {}", command);
+    build.cmdline = Some(command);
+    build.desc = Some(format!("compile-shared-lib: {}", item.package_full_name));
+    (build, artifact_id)
+}
+
+/// Compile the C code of a `"kind": "static"` package, and archive it together
+/// with the runtime and the C stubs it depends on into a static library.
+pub fn gen_compile_static_lib_commands(
+    graph: &mut n2graph::Graph,
+    item: &BuildLinkDepItem,
+    moonc_opt: &MooncOpt,
+    moonbuild_opt: &MoonbuildOpt,
+    runtime_path: String,
+) -> (Vec<Build>, n2graph::FileId) {
+    let path = PathBuf::from(&item.out);
+    let target_dir = path.parent().unwrap();
+    let c_artifact_path = path.with_extension("c").display().to_string();
+    let object_path = path
+        .with_extension(format!("c.{O_EXT}"))
+        .display()
+        .to_string();
+    let artifact_output_path = path.with_extension(A_EXT).display().to_string();
+
+    let object_id = graph.files.id_from_canonical(object_path.clone());
+    let ins = BuildIns {
+        ids: vec![graph.files.id_from_canonical(c_artifact_path.clone())],
+        explicit: 1,
+        implicit: 0,
+        order_only: 0,
+    };
+    let outs = BuildOuts {
+        ids: vec![object_id],
+        explicit: 1,
+    };
+    let loc = FileLoc {
+        filename: Rc::new(PathBuf::from("compile-static-lib")),
+        line: 0,
+    };
+    let mut compile = Build::new(loc, ins, outs);
+
+    let (native_cc, native_flags) = native_cc_and_flags(item, moonc_opt);
+    let cc_cmd = make_cc_command(
+        moonc_opt.native_cc(),
+        native_cc.clone(),
+        CCConfigBuilder::default()
+            .no_sys_header(true)
+            .output_ty(OutputType::Object)
+            .opt_level(cc_opt_level(moonc_opt))
+            .debug_info(cc_debug_info(moonc_opt))
+            .link_moonbitrun(true)
+            .define_use_shared_runtime_macro(false)
+            .sanitizers(test_sanitizers(moonbuild_opt))
+            .build()
+            .unwrap(),
+        &native_flags,
+        [c_artifact_path],
+        &target_dir.display().to_string(),
+        &object_path,
+    );
+    let command = CommandBuilder::from_iter(cc_cmd).build();
+    log::debug!("Command: {}", command);
+    compile.cmdline = Some(command);
+    compile.desc = Some(format!("compile-static-lib: {}", item.package_full_name));
+
+    let mut objects = vec![object_path, runtime_path];
+    if let Some(stub_obj_deps) = item.native_stub_obj_deps() {
+        objects.extend(stub_obj_deps.iter().cloned());
+    }
+    let artifact_id = graph.files.id_from_canonical(artifact_output_path.clone());
+    let ins = BuildIns {
+        ids: objects
+            .iter()
+            .map(|f| graph.files.id_from_canonical(f.clone()))
+            .collect(),
+        explicit: objects.len(),
+        implicit: 0,
+        order_only: 0,
+    };
+    let outs = BuildOuts {
+        ids: vec![artifact_id],
+        explicit: 1,
+    };
+    let loc = FileLoc {
+        filename: Rc::new(PathBuf::from("archive-static-lib")),
+        line: 0,
+    };
+    let mut archive = Build::new(loc, ins, outs);
+
+    let ar_cmd = make_archiver_command(
+        moonc_opt.native_cc(),
+        native_cc,
+        ArchiverConfigBuilder::default()
+            .archive_moonbitrun(true)
+            .build()
+            .unwrap(),
+        &objects,
+        &artifact_output_path,
+    );
+    let command = CommandBuilder::from_iter(ar_cmd).build();
+    log::debug!("Command: {}", command);
+    archive.cmdline = Some(command);
+    archive.desc = Some(format!("archive-static-lib: {}", item.package_full_name));
+
+    (vec![compile, archive], artifact_id)
+}
+
+/// Generate the C header declaring the exports of a native library package.
+pub fn gen_c_header_command(
+    graph: &mut n2graph::Graph,
+    item: &BuildLinkDepItem,
+    moonc_opt: &MooncOpt,
+) -> (Build, n2graph::FileId) {
+    let path = PathBuf::from(&item.out);
+    // The package itself comes last in its dependencies
+    let mi_path = PathBuf::from(item.core_deps.last().expect("the package should be linked"))
+        .with_extension("mi")
+        .display()
+        .to_string();
+    let header_path = path.with_extension("h").display().to_string();
+
+    let header_id = graph.files.id_from_canonical(header_path.clone());
+    let ins = BuildIns {
+        ids: vec![graph.files.id_from_canonical(mi_path.clone())],
+        explicit: 1,
+        implicit: 0,
+        order_only: 0,
+    };
+    let outs = BuildOuts {
+        ids: vec![header_id],
+        explicit: 1,
+    };
+    let loc = FileLoc {
+        filename: Rc::new(PathBuf::from("gen-c-header")),
+        line: 0,
+    };
+    let mut build = Build::new(loc, ins, outs);
+
+    let exports = item
+        .exports(moonc_opt.link_opt.target_backend)
+        .unwrap_or_default();
+    let command = CommandBuilder::new(
+        &std::env::current_exe()
+            .map_or_else(|_| "moon".into(), |x| x.to_string_lossy().into_owned()),
+    )
+    .arg("tool")
+    .arg("c-header")
+    .arg("--mi")
+    .arg(&mi_path)
+    .arg("--output")
+    .arg(&header_path)
+    .args_with_cond(
+        !exports.is_empty(),
+        ["--exports".to_string(), exports.join(",")],
+    )
+    .build();
+    log::debug!("Command: {}", command);
+    build.cmdline = Some(command);
+    build.desc = Some(format!("gen-c-header: {}", item.package_full_name));
+    (build, header_id)
+}
+
 pub fn gen_archive_stub_to_static_lib_command(
     graph: &mut n2graph::Graph,
     item: &LinkDepItem,
@@ -1049,7 +1338,7 @@ pub fn gen_archive_stub_to_static_lib_command(
     let native_stub_cc = item.native_stub_cc(moonc_opt.link_opt.target_backend);
 
     let cc_cmd = make_archiver_command(
-        CC::default(),
+        moonc_opt.native_cc(),
         native_stub_cc.map(|cc| {
             CC::try_from_path(cc)
                 .context(format!(
@@ -1152,7 +1441,7 @@ pub fn gen_link_stub_to_dynamic_lib_command(
 
     let shared_runtime_dir = Some(runtime_path.parent().unwrap());
     let cc_cmd = make_linker_command::<_, &Path>(
-        CC::default(),
+        moonc_opt.native_cc(),
         native_stub_cc.map(|cc| {
             CC::try_from_path(cc)
                 .context(format!(
@@ -1165,6 +1454,7 @@ pub fn gen_link_stub_to_dynamic_lib_command(
         LinkerConfigBuilder::default()
             .link_moonbitrun(!moonbuild_opt.use_tcc_run)
             .link_shared_runtime(shared_runtime_dir)
+            .sanitizers(test_sanitizers(moonbuild_opt))
             .output_ty(OutputType::SharedLib)
             .build()
             .unwrap(),
@@ -1233,7 +1523,7 @@ pub fn gen_compile_stub_command(
         let sources: Vec<&str> = vec![cpath];
 
         let cc_cmd = make_cc_command(
-            CC::default(),
+            moonc_opt.native_cc(),
             native_stub_cc.map(|cc| {
                 CC::try_from_path(cc)
                     .context(format!(
@@ -1246,13 +1536,15 @@ pub fn gen_compile_stub_command(
             CCConfigBuilder::default()
                 .no_sys_header(true)
                 .output_ty(OutputType::Object)
-                .opt_level(to_opt_level(
-                    !moonc_opt.build_opt.debug_flag,
-                    moonc_opt.build_opt.debug_flag,
-                ))
-                .debug_info(moonc_opt.build_opt.debug_flag)
+                .opt_level(cc_opt_level(moonc_opt))
+                .debug_info(cc_debug_info(moonc_opt))
                 .link_moonbitrun(!moonbuild_opt.use_tcc_run) // if use tcc, we cannot link moonbitrun
                 .define_use_shared_runtime_macro(moonbuild_opt.use_tcc_run)
+                .sanitizers(test_sanitizers(moonbuild_opt))
+                .system_flags(
+                    item.native_system_deps_cflags(moonc_opt.link_opt.target_backend)
+                        .to_vec(),
+                )
                 .build()
                 .unwrap(),
             &native_stub_cc_flags,
@@ -1344,7 +1636,7 @@ pub fn gen_link_exe_command(
     }
 
     let cc_cmd = make_cc_command(
-        CC::default(),
+        moonc_opt.native_cc(),
         native_cc.map(|cc| {
             CC::try_from_path(cc)
                 .context(format!(
@@ -1357,13 +1649,11 @@ pub fn gen_link_exe_command(
         CCConfigBuilder::default()
             .no_sys_header(true)
             .output_ty(OutputType::Executable)
-            .opt_level(to_opt_level(
-                !moonc_opt.build_opt.debug_flag,
-                moonc_opt.build_opt.debug_flag,
-            ))
-            .debug_info(moonc_opt.build_opt.debug_flag)
+            .opt_level(cc_opt_level(moonc_opt))
+            .debug_info(cc_debug_info(moonc_opt))
             .link_moonbitrun(!moonbuild_opt.use_tcc_run) // if use tcc, we cannot link moonbitrun
             .define_use_shared_runtime_macro(moonbuild_opt.use_tcc_run)
+            .sanitizers(test_sanitizers(moonbuild_opt))
             .build()
             .unwrap(),
         &native_flags,
@@ -1419,8 +1709,12 @@ pub fn gen_n2_build_state(
             Ok(path)
         }
 
-        fn gen_runtime(graph: &mut n2graph::Graph, target_dir: &Path) -> anyhow::Result<PathBuf> {
-            let (build, path) = gen_compile_runtime_command(graph, target_dir);
+        fn gen_runtime(
+            graph: &mut n2graph::Graph,
+            target_dir: &Path,
+            cc: CC,
+        ) -> anyhow::Result<PathBuf> {
+            let (build, path) = gen_compile_runtime_command(graph, target_dir, cc);
             graph.add_build(build)?;
             Ok(path)
         }
@@ -1428,7 +1722,7 @@ pub fn gen_n2_build_state(
         runtime_path = Some(if moonbuild_opt.use_tcc_run {
             gen_shared_runtime(&mut graph, target_dir, &mut default)?
         } else {
-            gen_runtime(&mut graph, target_dir)?
+            gen_runtime(&mut graph, target_dir, moonc_opt.native_cc())?
         });
     }
 
@@ -1442,7 +1736,33 @@ pub fn gen_n2_build_state(
         let mut default_fid = fid;
         graph.add_build(build)?;
 
-        if is_native_backend && !moonbuild_opt.use_tcc_run {
+        let native_link_kind = item.native_link_kind(moonc_opt.link_opt.target_backend);
+        if is_native_backend && native_link_kind.is_library() {
+            default_fid = match native_link_kind {
+                NativeLinkKind::Shared => {
+                    let (build, fid) =
+                        gen_compile_shared_lib_command(&mut graph, item, moonc_opt, moonbuild_opt);
+                    graph.add_build(build)?;
+                    fid
+                }
+                _ => {
+                    let (builds, fid) = gen_compile_static_lib_commands(
+                        &mut graph,
+                        item,
+                        moonc_opt,
+                        moonbuild_opt,
+                        runtime_path.as_ref().unwrap().display().to_string(),
+                    );
+                    for build in builds {
+                        graph.add_build(build)?;
+                    }
+                    fid
+                }
+            };
+            let (build, fid) = gen_c_header_command(&mut graph, item, moonc_opt);
+            graph.add_build(build)?;
+            default.push(fid);
+        } else if is_native_backend && !moonbuild_opt.use_tcc_run {
             let (build, fid) = gen_compile_exe_command(
                 &mut graph,
                 item,
@@ -1396,8 +1396,9 @@ pub fn gen_n2_runtest_state(
         fn gen_runtime(
             graph: &mut n2graph::Graph,
             target_dir: &std::path::Path,
+            cc: CC,
         ) -> anyhow::Result<PathBuf> {
-            let (build, path) = gen_compile_runtime_command(graph, target_dir);
+            let (build, path) = gen_compile_runtime_command(graph, target_dir, cc);
             graph.add_build(build)?;
             Ok(path)
         }
@@ -1405,7 +1406,7 @@ pub fn gen_n2_runtest_state(
         runtime_path = Some(if moonbuild_opt.use_tcc_run {
             gen_shared_runtime(&mut graph, &moonbuild_opt.target_dir, &mut default)?
         } else {
-            gen_runtime(&mut graph, &moonbuild_opt.target_dir)?
+            gen_runtime(&mut graph, &moonbuild_opt.target_dir, moonc_opt.native_cc())?
         });
     }
 
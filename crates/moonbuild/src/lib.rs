@@ -21,13 +21,17 @@
 pub mod bench;
 pub mod benchmark;
 pub mod build;
+pub mod build_cache;
 pub mod build_script;
 pub mod bundle;
 pub mod check;
+pub mod compdb;
+pub mod doc_export;
 pub mod doc_http;
 pub mod dry_run;
 pub mod entry;
 pub mod expect;
+pub mod explain;
 pub mod fmt;
 pub mod r#gen;
 pub mod new;
@@ -35,6 +39,7 @@ pub mod pre_build;
 pub mod runtest;
 pub mod section_capture;
 pub mod test_utils;
+pub mod timings;
 pub mod upgrade;
 
 use std::sync::LazyLock;
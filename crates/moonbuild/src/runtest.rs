@@ -25,13 +25,15 @@ use super::r#gen;
 use anyhow::{Context, bail};
 use moonutil::common::{
     DYN_EXT, MOON_COVERAGE_DELIMITER_BEGIN, MOON_COVERAGE_DELIMITER_END, MOON_TEST_DELIMITER_BEGIN,
-    MOON_TEST_DELIMITER_END, MoonbuildOpt, MooncOpt,
+    MOON_TEST_DELIMITER_END, MOONRUN_LIMIT_DELIMITER_BEGIN, MOONRUN_LIMIT_DELIMITER_END,
+    MoonbuildOpt, MooncOpt, TargetBackend,
 };
 use moonutil::module::ModuleDB;
 use moonutil::moon_dir::MOON_DIRS;
+use moonutil::package::JsRuntime;
 use n2::load::State;
 use serde::{Deserialize, Serialize};
-use std::{path::Path, process::Stdio};
+use std::{io::Write, path::Path, process::Stdio};
 use tokio::io::AsyncReadExt;
 
 pub fn load_moon_proj(
@@ -65,6 +67,7 @@ impl std::fmt::Display for TestStatistics {
 
 pub async fn run_wat(
     path: &Path,
+    moonrun_args: &[String],
     target_dir: &Path,
     args: &TestArgs,
     file_test_info_map: &FileTestInfo,
@@ -75,28 +78,62 @@ pub async fn run_wat(
             .as_deref()
             .context("Unable to find the `moonrun` executable, please reinstall")?,
     );
-    cmd.arg(path)
+    cmd.args(moonrun_args)
+        .arg(path)
         .arg("--test-args")
         .arg(serde_json_lenient::to_string(args).expect("valid JSON"));
-    run(path, cmd, target_dir, file_test_info_map, verbose).await
+    run(
+        path,
+        cmd,
+        target_dir,
+        file_test_info_map,
+        args,
+        false,
+        verbose,
+    )
+    .await
 }
 
 pub async fn run_js(
     path: &Path,
+    runtime: JsRuntime,
+    moonrun_args: &[String],
     target_dir: &Path,
     args: &TestArgs,
     file_test_info_map: &FileTestInfo,
     verbose: bool,
 ) -> anyhow::Result<Vec<Result<TestStatistics, TestFailedStatus>>> {
-    let mut cmd = tokio::process::Command::new(
-        crate::NODE_EXECUTABLE
-            .as_deref()
-            .context("Unable to find the `node` executable in PATH")?,
-    );
-    cmd.arg("--enable-source-maps")
-        .arg(path)
-        .arg(serde_json_lenient::to_string(args).expect("valid JSON"));
-    run(path, cmd, target_dir, file_test_info_map, verbose).await
+    let mut cmd = match runtime {
+        JsRuntime::Moonrun => {
+            let mut cmd = tokio::process::Command::new(
+                crate::MOONRUN_EXECUTABLE
+                    .as_deref()
+                    .context("Unable to find the `moonrun` executable, please reinstall")?,
+            );
+            cmd.args(moonrun_args).arg(path);
+            cmd
+        }
+        JsRuntime::Node => {
+            let mut cmd = tokio::process::Command::new(
+                crate::NODE_EXECUTABLE
+                    .as_deref()
+                    .context("Unable to find the `node` executable in PATH")?,
+            );
+            cmd.arg("--enable-source-maps").arg(path);
+            cmd
+        }
+    };
+    cmd.arg(serde_json_lenient::to_string(args).expect("valid JSON"));
+    run(
+        path,
+        cmd,
+        target_dir,
+        file_test_info_map,
+        args,
+        false,
+        verbose,
+    )
+    .await
 }
 
 pub async fn run_native(
@@ -107,6 +144,7 @@ pub async fn run_native(
     file_test_info_map: &FileTestInfo,
     verbose: bool,
 ) -> anyhow::Result<Vec<Result<TestStatistics, TestFailedStatus>>> {
+    let test_args = args;
     let args = args.to_cli_args_for_native();
     let cmd = if moonbuild_opt.use_tcc_run {
         let path = path.with_extension("c");
@@ -123,30 +161,123 @@ pub async fn run_native(
             .arg(args);
         cmd
     } else {
-        let mut cmd = tokio::process::Command::new(path);
+        let mut cmd = with_test_runner(moonbuild_opt, path);
         cmd.arg(args);
         cmd
     };
-    run(path, cmd, target_dir, file_test_info_map, verbose).await
+    run(
+        path,
+        cmd,
+        target_dir,
+        file_test_info_map,
+        test_args,
+        reports_crashes(moonbuild_opt),
+        verbose,
+    )
+    .await
 }
 
 pub async fn run_llvm(
+    moonbuild_opt: &MoonbuildOpt,
     path: &Path,
     target_dir: &Path,
     args: &TestArgs,
     file_test_info_map: &FileTestInfo,
     verbose: bool,
 ) -> anyhow::Result<Vec<Result<TestStatistics, TestFailedStatus>>> {
-    let mut cmd = tokio::process::Command::new(path);
+    let mut cmd = with_test_runner(moonbuild_opt, path);
     cmd.arg(args.to_cli_args_for_native());
-    run(path, cmd, target_dir, file_test_info_map, verbose).await
+    run(
+        path,
+        cmd,
+        target_dir,
+        file_test_info_map,
+        args,
+        reports_crashes(moonbuild_opt),
+        verbose,
+    )
+    .await
+}
+
+/// Runs the test executable at `path` through the `runners` command of the
+/// module, itself wrapped by the `--runner` of `moon test` if there is one.
+/// The test args are passed both after the artifact and in
+/// [`MOON_TEST_ARGS_ENV`](crate::build::MOON_TEST_ARGS_ENV).
+#[allow(clippy::too_many_arguments)]
+pub async fn run_with_backend_runner(
+    moonbuild_opt: &MoonbuildOpt,
+    template: &str,
+    backend: TargetBackend,
+    path: &Path,
+    target_dir: &Path,
+    args: &TestArgs,
+    file_test_info_map: &FileTestInfo,
+    verbose: bool,
+) -> anyhow::Result<Vec<Result<TestStatistics, TestFailedStatus>>> {
+    let runner = moonbuild_opt
+        .test_opt
+        .as_ref()
+        .map(|opt| opt.runner.as_slice())
+        .unwrap_or_default();
+    let parts = crate::build::runner_command(template, path)?;
+    let mut parts = runner.iter().chain(parts.iter());
+    let mut cmd = tokio::process::Command::new(parts.next().expect("runner is not empty"));
+    cmd.args(parts).arg(args.to_runner_arg(backend)).env(
+        crate::build::MOON_TEST_ARGS_ENV,
+        serde_json_lenient::to_string(args).expect("valid JSON"),
+    );
+    run(
+        path,
+        cmd,
+        target_dir,
+        file_test_info_map,
+        args,
+        true,
+        verbose,
+    )
+    .await
+}
+
+/// A command running the native executable at `path`, through the
+/// `--runner` of `moon test` if there is one
+fn with_test_runner(moonbuild_opt: &MoonbuildOpt, path: &Path) -> tokio::process::Command {
+    let runner = moonbuild_opt
+        .test_opt
+        .as_ref()
+        .map(|opt| opt.runner.as_slice())
+        .unwrap_or_default();
+    match runner.split_first() {
+        Some((program, args)) => {
+            let mut cmd = tokio::process::Command::new(program);
+            cmd.args(args).arg(path);
+            cmd
+        }
+        None => tokio::process::Command::new(path),
+    }
+}
+
+/// Whether the `stderr` of a crashing native executable, e.g. a sanitizer
+/// report, should be attached to the test it crashed in
+fn reports_crashes(moonbuild_opt: &MoonbuildOpt) -> bool {
+    moonbuild_opt
+        .test_opt
+        .as_ref()
+        .is_some_and(|opt| !opt.sanitizers.is_empty() || !opt.runner.is_empty())
 }
 
+/// Runs a test executable and collects its results.
+///
+/// If `report_crashes` is set, the `stderr` of the executable is collected
+/// instead of forwarded, and should it exit abnormally, attached as a runtime
+/// error to the first of the tests in `test_args` without a result. A limit
+/// reported by `moonrun` is attached the same way.
 async fn run(
     path: &Path,
     mut subprocess: tokio::process::Command,
     target_dir: &Path,
     file_test_info_map: &FileTestInfo,
+    test_args: &TestArgs,
+    report_crashes: bool,
     verbose: bool,
 ) -> anyhow::Result<Vec<Result<TestStatistics, TestFailedStatus>>> {
     if verbose {
@@ -156,10 +287,15 @@ async fn run(
     let mut execution = subprocess
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
+        .stderr(if report_crashes {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        })
         .spawn()
         .with_context(|| format!("failed to execute: {:?}", subprocess))?;
     let mut stdout = execution.stdout.take().unwrap();
+    let mut stderr = execution.stderr.take();
 
     let mut test_capture =
         SectionCapture::new(MOON_TEST_DELIMITER_BEGIN, MOON_TEST_DELIMITER_END, false);
@@ -168,21 +304,45 @@ async fn run(
         MOON_COVERAGE_DELIMITER_END,
         true,
     );
+    let mut limit_capture = SectionCapture::new(
+        MOONRUN_LIMIT_DELIMITER_BEGIN,
+        MOONRUN_LIMIT_DELIMITER_END,
+        false,
+    );
 
     let mut stdout_buffer = Vec::new();
-    stdout
-        .read_to_end(&mut stdout_buffer)
-        .await
-        .with_context(|| format!("failed to read stdout: {:?}", subprocess))?;
+    let mut stderr_buffer = Vec::new();
+    // read both pipes at once, so that a full `stderr` can't block the child
+    let read_stderr = async {
+        match stderr.as_mut() {
+            Some(stderr) => stderr.read_to_end(&mut stderr_buffer).await,
+            None => Ok(0),
+        }
+    };
+    let (stdout_read, stderr_read) =
+        tokio::join!(stdout.read_to_end(&mut stdout_buffer), read_stderr);
+    stdout_read.with_context(|| format!("failed to read stdout: {:?}", subprocess))?;
+    stderr_read.with_context(|| format!("failed to read stderr: {:?}", subprocess))?;
 
     handle_stdout(
         &mut std::io::BufReader::new(stdout_buffer.as_slice()),
-        &mut [&mut test_capture, &mut coverage_capture],
+        &mut [&mut test_capture, &mut coverage_capture, &mut limit_capture],
         |line| print!("{line}"),
     )?;
     let output = execution.wait().await?;
 
-    if !output.success() {
+    // `moonrun` reports the limits it enforces, whatever its exit code
+    let mut crash_report = limit_capture
+        .finish()
+        .map(|report| format!("{RUNTIME_ERROR}: {}", report.trim_end()));
+    if output.success() || crash_report.is_some() {
+        std::io::stderr().write_all(&stderr_buffer)?;
+    } else if report_crashes {
+        crash_report = Some(format!(
+            "{RUNTIME_ERROR}: test executable {output}\n{}",
+            String::from_utf8_lossy(&stderr_buffer).trim_end()
+        ));
+    } else {
         bail!(format!("Failed to run the test: {}", path.display()));
     }
     if let Some(coverage_output) = coverage_capture.finish() {
@@ -196,7 +356,12 @@ async fn run(
     }
 
     let mut res = vec![];
-    if let Some(test_output) = test_capture.finish() {
+    let test_output = if crash_report.is_some() {
+        test_capture.finish_partial()
+    } else {
+        test_capture.finish()
+    };
+    if let Some(test_output) = test_output {
         let mut test_statistics: Vec<TestStatistics> = vec![];
         for s in test_output.split('\n') {
             if s.is_empty() {
@@ -258,11 +423,76 @@ async fn run(
                 res.push(Err(TestFailedStatus::Others(return_message.to_string())));
             }
         }
-    } else {
+    } else if crash_report.is_none() {
         res.push(Err(TestFailedStatus::Others(String::from(
             "No test output found",
         ))));
     }
 
+    if let Some(report) = crash_report {
+        attach_crash_report(&mut res, test_args, file_test_info_map, report);
+    }
+
     Ok(res)
 }
+
+/// Blame the report of a crashed test executable on the first requested
+/// test without a result, or on the last test run if all of them finished,
+/// e.g. when `valgrind` finds leaks at exit.
+fn attach_crash_report(
+    res: &mut Vec<Result<TestStatistics, TestFailedStatus>>,
+    args: &TestArgs,
+    file_test_info_map: &FileTestInfo,
+    report: String,
+) {
+    fn stats(r: &Result<TestStatistics, TestFailedStatus>) -> Option<&TestStatistics> {
+        match r {
+            Ok(ts) => Some(ts),
+            Err(TestFailedStatus::ApplyExpectFailed(ts))
+            | Err(TestFailedStatus::ExpectTestFailed(ts))
+            | Err(TestFailedStatus::Failed(ts))
+            | Err(TestFailedStatus::RuntimeError(ts))
+            | Err(TestFailedStatus::SnapshotPending(ts)) => Some(ts),
+            Err(TestFailedStatus::Others(_)) => None,
+        }
+    }
+
+    let position = |file: &str, index: u32| {
+        res.iter().position(|r| {
+            stats(r).is_some_and(|ts| ts.filename == file && ts.index == index.to_string())
+        })
+    };
+    let mut last_finished = None;
+    for (file, ranges) in &args.file_and_index {
+        for index in ranges.iter().flat_map(|range| range.clone()) {
+            if let Some(i) = position(file, index) {
+                last_finished = Some(i);
+                continue;
+            }
+            let test_name = file_test_info_map
+                .get(file)
+                .and_then(|m| m.get(&index))
+                .and_then(|info| info.name.clone())
+                .unwrap_or_else(|| index.to_string());
+            res.push(Err(TestFailedStatus::RuntimeError(TestStatistics {
+                package: args.package.clone(),
+                filename: file.clone(),
+                index: index.to_string(),
+                test_name,
+                message: report,
+            })));
+            return;
+        }
+    }
+
+    match last_finished {
+        Some(i) => {
+            let ts = stats(&res[i]).cloned().expect("found by its statistics");
+            res[i] = Err(TestFailedStatus::RuntimeError(TestStatistics {
+                message: report,
+                ..ts
+            }));
+        }
+        None => eprintln!("{report}"),
+    }
+}
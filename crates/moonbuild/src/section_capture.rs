@@ -86,6 +86,20 @@ impl<'a> SectionCapture<'a> {
             None
         }
     }
+
+    /// Returns the captured section, even if the program stopped before its
+    /// end, e.g. by crashing. A trailing incomplete line is dropped.
+    pub fn finish_partial(self) -> Option<String> {
+        if !self.found_begin {
+            return None;
+        }
+        let mut buffer = self.capture_buffer;
+        if !self.found_end {
+            let complete = buffer.rfind('\n').map_or(0, |i| i + 1);
+            buffer.truncate(complete);
+        }
+        Some(buffer)
+    }
 }
 
 /// Pipes the child stdout to stdout, with the ability to capture sections of the output.
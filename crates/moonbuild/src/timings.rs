@@ -0,0 +1,459 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+//! Timing reports for `moon build --timings`.
+//!
+//! [`TimingProgress`] wraps the progress console given to n2 and records when
+//! each build step starts and finishes. The recorded [`BuildTimings`] are then
+//! written to `target/timings/<backend>.json` along with an HTML page showing
+//! a Gantt chart of the steps, the parallelism over time and the critical
+//! path of the build.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::Context;
+use moonutil::common::TargetBackend;
+use n2::graph::{Build, BuildId, FileId};
+use n2::progress::Progress;
+use n2::task::TaskResult;
+use n2::work::StateCounts;
+use serde::Serialize;
+
+pub const TIMINGS_DIR: &str = "timings";
+
+/// A build step executed by n2.
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskTiming {
+    /// The package the step belongs to, empty for steps of the whole build
+    pub package: String,
+    /// The kind of step, e.g. `build-package` or `LinkCore`
+    pub step: String,
+    /// Seconds since the build started
+    pub start: f64,
+    /// Seconds taken by the step
+    pub duration: f64,
+    /// Steps executed in the same build that produced inputs of this one
+    pub deps: Vec<usize>,
+}
+
+impl TaskTiming {
+    pub fn end(&self) -> f64 {
+        self.start + self.duration
+    }
+}
+
+/// The build steps executed by n2, in the order they started.
+#[derive(Debug, Clone, Default)]
+pub struct BuildTimings {
+    pub tasks: Vec<TaskTiming>,
+}
+
+impl BuildTimings {
+    /// Seconds from the start of the build to the end of the last step.
+    pub fn total(&self) -> f64 {
+        self.tasks.iter().map(TaskTiming::end).fold(0.0, f64::max)
+    }
+
+    /// The chain of steps that determined the length of the build, from the
+    /// first to the last.
+    ///
+    /// Starting from the step that finished last, each step is preceded by
+    /// its dependency that finished last, i.e. the one it waited for.
+    pub fn critical_path(&self) -> Vec<usize> {
+        let end_of = |i: &usize| self.tasks[*i].end();
+        let mut path = vec![];
+        let mut curr = (0..self.tasks.len()).max_by(|a, b| end_of(a).total_cmp(&end_of(b)));
+        while let Some(i) = curr {
+            path.push(i);
+            curr = self.tasks[i]
+                .deps
+                .iter()
+                .copied()
+                .max_by(|a, b| end_of(a).total_cmp(&end_of(b)));
+        }
+        path.reverse();
+        path
+    }
+
+    /// The number of steps running at each point in time where it changes.
+    pub fn concurrency(&self) -> Vec<(f64, usize)> {
+        let mut events = self
+            .tasks
+            .iter()
+            .flat_map(|t| [(t.start, 1), (t.end(), -1)])
+            .collect::<Vec<(f64, i32)>>();
+        // Process ends before starts at the same time, so that a step
+        // directly following another doesn't count as parallel
+        events.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+        let mut res: Vec<(f64, usize)> = vec![(0.0, 0)];
+        let mut running = 0i32;
+        for (time, delta) in events {
+            running += delta;
+            match res.last_mut() {
+                Some(last) if last.0 == time => last.1 = running as usize,
+                _ => res.push((time, running as usize)),
+            }
+        }
+        res
+    }
+
+    /// Write `<backend>.json` and `<backend>.html` into `target/timings`, and
+    /// return the path of the HTML report.
+    pub fn write_report(
+        &self,
+        raw_target_dir: &Path,
+        backend: TargetBackend,
+    ) -> anyhow::Result<PathBuf> {
+        let dir = raw_target_dir.join(TIMINGS_DIR);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create `{}`", dir.display()))?;
+
+        let critical_path = self.critical_path();
+        let report = TimingReport {
+            backend: backend.to_flag(),
+            total: self.total(),
+            tasks: &self.tasks,
+            critical_path: &critical_path,
+            concurrency: self.concurrency(),
+        };
+        let json_path = dir.join(format!("{}.json", backend.to_dir_name()));
+        std::fs::write(&json_path, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("failed to write `{}`", json_path.display()))?;
+
+        let html_path = dir.join(format!("{}.html", backend.to_dir_name()));
+        std::fs::write(&html_path, render_html(&report))
+            .with_context(|| format!("failed to write `{}`", html_path.display()))?;
+        Ok(html_path)
+    }
+}
+
+#[derive(Serialize)]
+struct TimingReport<'a> {
+    backend: &'a str,
+    total: f64,
+    tasks: &'a [TaskTiming],
+    critical_path: &'a [usize],
+    concurrency: Vec<(f64, usize)>,
+}
+
+/// Split the label of an n2 build into its package and the kind of step.
+///
+/// Legacy builds are described like `build-package: username/hello/lib`,
+/// while rupes-recta builds are located at e.g.
+/// `username/hello/lib@Source@BuildCore`.
+fn split_label(label: &str) -> (String, String) {
+    if let Some((step, package)) = label.split_once(": ") {
+        return (package.to_string(), step.to_string());
+    }
+    match (label.split_once('@'), label.rsplit_once('@')) {
+        (Some((package, _)), Some((_, step))) => (package.to_string(), step.to_string()),
+        _ => (String::new(), label.to_string()),
+    }
+}
+
+/// A [`Progress`] that records the time of each build step into a shared
+/// [`BuildTimings`] before forwarding to the wrapped progress console.
+pub struct TimingProgress {
+    inner: Box<dyn Progress>,
+    timings: Arc<Mutex<BuildTimings>>,
+    epoch: Instant,
+    running: HashMap<BuildId, usize>,
+    /// The step that produced each output file
+    producers: HashMap<FileId, usize>,
+}
+
+impl TimingProgress {
+    pub fn new(inner: Box<dyn Progress>, timings: Arc<Mutex<BuildTimings>>) -> Self {
+        TimingProgress {
+            inner,
+            timings,
+            epoch: Instant::now(),
+            running: HashMap::new(),
+            producers: HashMap::new(),
+        }
+    }
+}
+
+impl Progress for TimingProgress {
+    fn update(&mut self, counts: &StateCounts) {
+        self.inner.update(counts);
+    }
+
+    fn task_started(&mut self, id: BuildId, build: &Build) {
+        let label = build
+            .desc
+            .clone()
+            .unwrap_or_else(|| build.location.filename.display().to_string());
+        let (package, step) = split_label(&label);
+        let mut deps = build
+            .ins
+            .ids
+            .iter()
+            .filter_map(|file| self.producers.get(file).copied())
+            .collect::<Vec<_>>();
+        deps.sort_unstable();
+        deps.dedup();
+
+        let mut timings = self.timings.lock().unwrap();
+        self.running.insert(id, timings.tasks.len());
+        timings.tasks.push(TaskTiming {
+            package,
+            step,
+            start: self.epoch.elapsed().as_secs_f64(),
+            duration: 0.0,
+            deps,
+        });
+        drop(timings);
+
+        self.inner.task_started(id, build);
+    }
+
+    fn task_output(&mut self, id: BuildId, line: Vec<u8>) {
+        self.inner.task_output(id, line);
+    }
+
+    fn task_finished(&mut self, id: BuildId, build: &Build, result: &TaskResult) {
+        if let Some(index) = self.running.remove(&id) {
+            let mut timings = self.timings.lock().unwrap();
+            let task = &mut timings.tasks[index];
+            task.duration = self.epoch.elapsed().as_secs_f64() - task.start;
+            for &out in build.outs() {
+                self.producers.insert(out, index);
+            }
+        }
+        self.inner.task_finished(id, build, result);
+    }
+
+    fn log(&mut self, msg: &str) {
+        self.inner.log(msg);
+    }
+}
+
+const CHART_WIDTH: f64 = 960.0;
+const LABEL_WIDTH: f64 = 360.0;
+const ROW_HEIGHT: f64 = 16.0;
+const CONCURRENCY_HEIGHT: f64 = 120.0;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; font-size: 13px; margin: 24px; }
+h1 { font-size: 20px; }
+h2 { font-size: 16px; margin-top: 32px; }
+table { border-collapse: collapse; }
+th, td { padding: 2px 12px; text-align: left; border-bottom: 1px solid #ddd; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+svg text { font-size: 11px; font-family: monospace; }
+rect.task { fill: #7aa6da; }
+rect.critical { fill: #e06c75; }
+polyline { fill: none; stroke: #4b8bbe; stroke-width: 1.5; }
+"#;
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn task_name(task: &TaskTiming) -> String {
+    if task.package.is_empty() {
+        task.step.clone()
+    } else {
+        format!("{} {}", task.package, task.step)
+    }
+}
+
+fn render_html(report: &TimingReport) -> String {
+    let scale = CHART_WIDTH / report.total.max(1e-3);
+    let max_running = report.concurrency.iter().map(|x| x.1).max().unwrap_or(0);
+    let mut html = String::new();
+    write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>moon build timings ({backend})</title>\n<style>{STYLE}</style>\n</head>\n<body>\n\
+         <h1>moon build timings ({backend})</h1>\n\
+         <p>{n} steps in {total:.2}s, at most {max_running} in parallel.</p>\n",
+        backend = escape(report.backend),
+        n = report.tasks.len(),
+        total = report.total,
+    )
+    .unwrap();
+
+    // Gantt chart of the steps, grouped by package
+    let mut order = (0..report.tasks.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| {
+        let (a, b) = (&report.tasks[a], &report.tasks[b]);
+        a.package.cmp(&b.package).then(a.start.total_cmp(&b.start))
+    });
+    let height = ROW_HEIGHT * order.len() as f64;
+    writeln!(
+        html,
+        "<h2>Steps</h2>\n<svg width=\"{}\" height=\"{height}\">",
+        LABEL_WIDTH + CHART_WIDTH + 8.0
+    )
+    .unwrap();
+    for (row, &i) in order.iter().enumerate() {
+        let task = &report.tasks[i];
+        let y = ROW_HEIGHT * row as f64;
+        let class = if report.critical_path.contains(&i) {
+            "critical"
+        } else {
+            "task"
+        };
+        let name = escape(&task_name(task));
+        writeln!(
+            html,
+            "<text x=\"0\" y=\"{:.1}\">{name}</text>\
+             <rect class=\"{class}\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\">\
+             <title>{name}: {:.3}s</title></rect>",
+            y + ROW_HEIGHT - 4.0,
+            LABEL_WIDTH + task.start * scale,
+            y + 2.0,
+            (task.duration * scale).max(1.0),
+            ROW_HEIGHT - 4.0,
+            task.duration,
+        )
+        .unwrap();
+    }
+    html.push_str("</svg>\n");
+
+    // Parallelism over time
+    let y_scale = CONCURRENCY_HEIGHT / max_running.max(1) as f64;
+    let mut points = String::new();
+    let mut prev = 0;
+    for &(time, running) in &report.concurrency {
+        let x = time * scale;
+        write!(
+            points,
+            "{x:.1},{:.1} {x:.1},{:.1} ",
+            CONCURRENCY_HEIGHT - prev as f64 * y_scale,
+            CONCURRENCY_HEIGHT - running as f64 * y_scale,
+        )
+        .unwrap();
+        prev = running;
+    }
+    writeln!(
+        html,
+        "<h2>Parallelism</h2>\n<svg width=\"{CHART_WIDTH}\" height=\"{}\">\
+         <polyline points=\"{}\"/></svg>",
+        CONCURRENCY_HEIGHT + 2.0,
+        points.trim_end(),
+    )
+    .unwrap();
+
+    // The critical path
+    html.push_str(
+        "<h2>Critical path</h2>\n<table>\n\
+         <tr><th>Step</th><th>Package</th><th>Start (s)</th><th>Duration (s)</th></tr>\n",
+    );
+    for &i in report.critical_path {
+        let task = &report.tasks[i];
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td class=\"num\">{:.3}</td><td class=\"num\">{:.3}</td></tr>",
+            escape(&task.step),
+            escape(&task.package),
+            task.start,
+            task.duration,
+        ).unwrap();
+    }
+    html.push_str("</table>\n");
+
+    // Time spent on each package
+    let mut packages: Vec<(&str, usize, f64)> = vec![];
+    for task in report.tasks {
+        match packages.iter_mut().find(|x| x.0 == task.package) {
+            Some(entry) => {
+                entry.1 += 1;
+                entry.2 += task.duration;
+            }
+            None => packages.push((&task.package, 1, task.duration)),
+        }
+    }
+    packages.sort_by(|a, b| b.2.total_cmp(&a.2));
+    html.push_str(
+        "<h2>Packages</h2>\n<table>\n\
+         <tr><th>Package</th><th>Steps</th><th>Total (s)</th></tr>\n",
+    );
+    for (package, n, total) in packages {
+        writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"num\">{n}</td><td class=\"num\">{total:.3}</td></tr>",
+            escape(package),
+        )
+        .unwrap();
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(start: f64, duration: f64, deps: Vec<usize>) -> TaskTiming {
+        TaskTiming {
+            package: String::new(),
+            step: String::new(),
+            start,
+            duration,
+            deps,
+        }
+    }
+
+    #[test]
+    fn test_split_label() {
+        assert_eq!(
+            split_label("build-package: username/hello/lib"),
+            (
+                "username/hello/lib".to_string(),
+                "build-package".to_string()
+            )
+        );
+        assert_eq!(
+            split_label("username/hello/lib@Source@BuildCore"),
+            ("username/hello/lib".to_string(), "BuildCore".to_string())
+        );
+        assert_eq!(
+            split_label("BuildRuntimeLib"),
+            (String::new(), "BuildRuntimeLib".to_string())
+        );
+    }
+
+    #[test]
+    fn test_critical_path_and_concurrency() {
+        // 0 and 1 run in parallel, 2 waits for both and 3 only for 0
+        let timings = BuildTimings {
+            tasks: vec![
+                task(0.0, 1.0, vec![]),
+                task(0.0, 2.0, vec![]),
+                task(2.0, 1.0, vec![0, 1]),
+                task(1.0, 0.5, vec![0]),
+            ],
+        };
+        assert_eq!(timings.total(), 3.0);
+        assert_eq!(timings.critical_path(), vec![1, 2]);
+        assert_eq!(
+            timings.concurrency(),
+            vec![(0.0, 2), (1.0, 2), (1.5, 1), (2.0, 1), (3.0, 0)]
+        );
+    }
+}
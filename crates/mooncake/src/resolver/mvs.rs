@@ -27,6 +27,7 @@ use colored::Colorize;
 use moonutil::{
     dependency::SourceDependencyInfo,
     module::MoonMod,
+    module_features::{dep_enabled, resolve_features},
     mooncakes::{ModuleName, ModuleSource, ModuleSourceKind, result::ResolvedEnv},
     version::as_caret_comparator,
 };
@@ -194,6 +195,20 @@ fn warn_about_skipped_local_or_git_dep(ms: &ModuleSource) {
     }
 }
 
+/// The features enabled for a non-root module, given the features its
+/// dependents requested.
+fn enabled_features(
+    module: &MoonMod,
+    requested: Option<&BTreeSet<String>>,
+) -> Result<HashSet<String>, ResolverError> {
+    resolve_features(
+        &module.name,
+        module.features.as_ref(),
+        requested.into_iter().flatten(),
+    )
+    .map_err(ResolverError::Other)
+}
+
 fn mvs_resolve(
     env: &mut ResolverEnv,
     res: &mut ResolvedEnv,
@@ -206,6 +221,14 @@ fn mvs_resolve(
     let mut working_list = vec![];
     let mut visited = HashSet::new();
 
+    // Features requested of each module by its dependents, unioned across the
+    // graph. Optional dependencies of a module are only followed once one of
+    // these features enables them. Root modules follow all of their optional
+    // dependencies, since their features are selected at build time.
+    let root_sources = root.iter().map(|(ms, _)| ms).collect::<HashSet<_>>();
+    let mut requested_features = HashMap::<ModuleName, BTreeSet<String>>::new();
+    let mut visited_by_name = HashMap::<ModuleName, Vec<(ModuleSource, Arc<MoonMod>)>>::new();
+
     log::debug!("Begin MVS solving");
 
     working_list.extend_from_slice(root);
@@ -219,6 +242,17 @@ fn mvs_resolve(
     // Do a DFS in the graph
     while let Some((source, module)) = working_list.pop() {
         log::debug!("-- Solving for {}", source);
+        let enabled = if root_sources.contains(&source) {
+            None
+        } else {
+            match enabled_features(&module, requested_features.get(source.name())) {
+                Ok(enabled) => Some(enabled),
+                Err(e) => {
+                    env.report_error(e);
+                    continue;
+                }
+            }
+        };
         let mut all_deps = module.deps.clone();
         all_deps.extend(
             module
@@ -229,7 +263,13 @@ fn mvs_resolve(
                 .map(|(k, v)| (k, v.into())),
         );
         for (name, req) in &all_deps {
-            let pkg_name = match name.parse() {
+            if let Some(enabled) = &enabled
+                && !dep_enabled(&module, name, enabled)
+            {
+                log::debug!("---- Skipping disabled optional dependency {}", name);
+                continue;
+            }
+            let pkg_name: ModuleName = match name.parse() {
                 Ok(v) => v,
                 Err(_) => {
                     env.report_error(ResolverError::MalformedModuleName(
@@ -248,8 +288,24 @@ fn mvs_resolve(
                 }
             };
 
+            // Union the features requested by this dependent. If that enables
+            // new features, revisit the module to follow the optional
+            // dependencies they turn on.
+            let requested = requested_features.entry(pkg_name.clone()).or_default();
+            let requested_count = requested.len();
+            requested.extend(req.features.iter().cloned());
+            if requested.len() != requested_count
+                && let Some(seen) = visited_by_name.get(&pkg_name)
+            {
+                working_list.extend(seen.iter().cloned());
+            }
+
             // Add module to working list
             if visited.insert(ms.clone()) {
+                visited_by_name
+                    .entry(pkg_name.clone())
+                    .or_default()
+                    .push((ms.clone(), Arc::clone(&module)));
                 working_list.push((ms.clone(), module));
             }
 
@@ -326,6 +382,23 @@ fn mvs_resolve(
 
         let curr_id = *visited.get(&pkg).unwrap();
 
+        let enabled = if root_sources.contains(&pkg) {
+            None
+        } else {
+            // Unknown features are usually reported in the previous round, but
+            // the selected version is checked against the final requests here
+            match enabled_features(&module, requested_features.get(pkg.name())) {
+                Ok(enabled) => {
+                    res.set_features(curr_id, enabled.clone());
+                    Some(enabled)
+                }
+                Err(e) => {
+                    env.report_error(e);
+                    continue;
+                }
+            }
+        };
+
         let mut all_deps = module.deps.clone();
         all_deps.extend(
             module
@@ -336,6 +409,11 @@ fn mvs_resolve(
                 .map(|(k, v)| (k, v.into())),
         );
         for (dep_name, req) in &all_deps {
+            if let Some(enabled) = &enabled
+                && !dep_enabled(&module, dep_name, enabled)
+            {
+                continue;
+            }
             let dep_name = dep_name.parse().unwrap();
             // If any malformed name, it should be reported in the previous round
 
@@ -365,7 +443,7 @@ fn mvs_resolve(
 
     log::debug!("Finished MVS solving");
 
-    true
+    !env.any_errors()
 }
 
 fn resolve_pkg(
@@ -521,6 +599,9 @@ mod test {
                 exclude: None,
                 preferred_target: None,
                 scripts: None,
+                runners: None,
+                profiles: None,
+                features: None,
                 __moonbit_unstable_prebuild: None,
             }
         "#]]
@@ -1782,4 +1863,64 @@ mod test {
             "#]],
         );
     }
+
+    fn create_optional_dep_registry() -> MockRegistry {
+        let mut registry = MockRegistry::new();
+        registry.add_module_full("opt/zlib", "0.1.0", []);
+        let mut codec = create_mock_module("lib/codec", "0.1.0", [("opt/zlib", "0.1.0")]);
+        codec.deps["opt/zlib"].optional = true;
+        codec.features = Some(indexmap::IndexMap::from([(
+            "zlib".to_string(),
+            vec!["dep:opt/zlib".to_string()],
+        )]));
+        registry.add_module(codec);
+        let mut app = create_mock_module("lib/app", "0.1.0", [("lib/codec", "0.1.0")]);
+        app.deps["lib/codec"].features = vec!["zlib".to_string()];
+        registry.add_module(app);
+        registry
+    }
+
+    #[test]
+    fn test_optional_deps() {
+        let rl = RegistryList::with_registry(Box::new(create_optional_dep_registry()));
+        let root = create_mock_module("root/module", "0.1.0", [("lib/codec", "0.1.0")]);
+        check_resolve_result(
+            &rl,
+            Arc::new(root),
+            expect![[r#"
+                [
+                    root/module@0.1.0,
+                    lib/codec@0.1.0,
+                ]
+            "#]],
+        );
+
+        // `lib/app` enables the `zlib` feature of `lib/codec` for everyone
+        let root = create_mock_module(
+            "root/module",
+            "0.1.0",
+            [("lib/codec", "0.1.0"), ("lib/app", "0.1.0")],
+        );
+        let mut env = ResolverEnv::new(&rl);
+        let mut result = ResolvedEnv::new();
+        let status = MvsSolver.resolve(&mut env, &mut result, &create_mock_root(root));
+        assert!(status, "Resolve failed");
+        assert_depends_on(&result, "lib/codec@0.1.0", "opt/zlib@0.1.0");
+        let codec = id_from_mod_name(&result, &"lib/codec@0.1.0".parse().unwrap()).unwrap();
+        assert_eq!(
+            result.features(codec),
+            Some(&HashSet::from(["zlib".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_unknown_dep_feature() {
+        let rl = RegistryList::with_registry(Box::new(create_optional_dep_registry()));
+        let mut root = create_mock_module("root/module", "0.1.0", [("lib/codec", "0.1.0")]);
+        root.deps["lib/codec"].features = vec!["brotli".to_string()];
+        let mut env = ResolverEnv::new(&rl);
+        let mut result = ResolvedEnv::new();
+        let status = MvsSolver.resolve(&mut env, &mut result, &create_mock_root(root));
+        assert!(!status);
+    }
 }
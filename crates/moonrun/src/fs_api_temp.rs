@@ -18,6 +18,8 @@
 
 //! Temporary-use FS API. Only has whole-file read/write and no other features.
 
+use crate::permissions;
+
 /// `fn read_file_to_string(path: JSString) -> JSString`
 fn read_file_to_string(
     scope: &mut v8::HandleScope,
@@ -27,6 +29,9 @@ fn read_file_to_string(
     let path = args.get(0);
     let path = path.to_string(scope).unwrap();
     let path = path.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_read(&path) {
+        panic!("{e}");
+    }
 
     let contents =
         std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("Failed to read file: {path}"));
@@ -42,6 +47,9 @@ fn read_file_to_bytes(
     let path = args.get(0);
     let path = path.to_string(scope).unwrap();
     let path = path.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_read(&path) {
+        panic!("{e}");
+    }
 
     let contents = std::fs::read(&path).unwrap_or_else(|_| panic!("Failed to read file: {path}"));
     let len = contents.len();
@@ -63,6 +71,9 @@ fn write_string_to_file(
     let path = args.get(0);
     let path = path.to_string(scope).unwrap();
     let path = path.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_write(&path) {
+        panic!("{e}");
+    }
 
     let contents = args.get(1);
     let contents = contents.to_string(scope).unwrap();
@@ -81,6 +92,9 @@ fn write_bytes_to_file(
     let path = args.get(0);
     let path = path.to_string(scope).unwrap();
     let path = path.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_write(&path) {
+        panic!("{e}");
+    }
 
     let contents = args.get(1);
 
@@ -102,6 +116,9 @@ fn create_dir(
     let path = args.get(0);
     let path = path.to_string(scope).unwrap();
     let path = path.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_write(&path) {
+        panic!("{e}");
+    }
 
     std::fs::create_dir_all(&path).unwrap_or_else(|_| panic!("Failed to create directory: {path}"));
 
@@ -117,6 +134,9 @@ fn read_dir(
     let path = args.get(0);
     let path = path.to_string(scope).unwrap();
     let path = path.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_read(&path) {
+        panic!("{e}");
+    }
 
     let entries =
         std::fs::read_dir(&path).unwrap_or_else(|_| panic!("Failed to read directory: {path}"));
@@ -147,6 +167,9 @@ fn is_file(
     let path = args.get(0);
     let path = path.to_string(scope).unwrap();
     let path = path.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_read(&path) {
+        panic!("{e}");
+    }
 
     let is_file = std::path::Path::new(&path).is_file();
     ret.set_bool(is_file);
@@ -160,6 +183,9 @@ fn is_dir(
     let path = args.get(0);
     let path = path.to_string(scope).unwrap();
     let path = path.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_read(&path) {
+        panic!("{e}");
+    }
 
     let is_dir = std::path::Path::new(&path).is_dir();
     ret.set_bool(is_dir);
@@ -173,6 +199,9 @@ fn remove_file(
     let path = args.get(0);
     let path = path.to_string(scope).unwrap();
     let path = path.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_write(&path) {
+        panic!("{e}");
+    }
 
     std::fs::remove_file(&path).unwrap_or_else(|_| panic!("Failed to remove file: {path}"));
 
@@ -187,6 +216,9 @@ fn remove_dir(
     let path = args.get(0);
     let path = path.to_string(scope).unwrap();
     let path = path.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_write(&path) {
+        panic!("{e}");
+    }
 
     std::fs::remove_dir_all(&path).unwrap_or_else(|_| panic!("Failed to remove directory: {path}"));
 
@@ -201,6 +233,9 @@ fn path_exists(
     let path = args.get(0);
     let path = path.to_string(scope).unwrap();
     let path = path.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_read(&path) {
+        panic!("{e}");
+    }
 
     let exists = std::path::Path::new(&path).exists();
     ret.set_bool(exists);
@@ -236,6 +271,14 @@ struct GlobalState {
     error_message: String,
 }
 
+/// Forget the results of the calls made by the programs run so far.
+pub fn reset() {
+    let mut state = GLOBAL_STATE.lock().unwrap();
+    state.file_content.clear();
+    state.dir_files.clear();
+    state.error_message.clear();
+}
+
 fn write_bytes_to_file_new(
     scope: &mut v8::HandleScope,
     args: v8::FunctionCallbackArguments,
@@ -246,6 +289,11 @@ fn write_bytes_to_file_new(
         .to_string(scope)
         .unwrap()
         .to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_write(&path) {
+        GLOBAL_STATE.lock().unwrap().error_message = e;
+        ret.set_int32(-1);
+        return;
+    }
 
     let contents = args.get(1);
     let uint8_array = match v8::Local::<v8::Uint8Array>::try_from(contents) {
@@ -284,6 +332,11 @@ fn read_file_to_bytes_new(
         .to_string(scope)
         .unwrap()
         .to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_read(&path) {
+        GLOBAL_STATE.lock().unwrap().error_message = e;
+        ret.set_int32(-1);
+        return;
+    }
 
     match std::fs::read(&path) {
         Ok(contents) => {
@@ -338,6 +391,11 @@ fn create_dir_new(
         .to_string(scope)
         .unwrap()
         .to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_write(&path) {
+        GLOBAL_STATE.lock().unwrap().error_message = e;
+        ret.set_int32(-1);
+        return;
+    }
 
     match std::fs::create_dir_all(&path) {
         Ok(_) => {
@@ -362,6 +420,11 @@ fn read_dir_new(
         .to_string(scope)
         .unwrap()
         .to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_read(&path) {
+        GLOBAL_STATE.lock().unwrap().error_message = e;
+        ret.set_int32(-1);
+        return;
+    }
 
     let entries = match std::fs::read_dir(&path) {
         Ok(entries) => entries,
@@ -399,6 +462,11 @@ fn is_file_new(
         .to_string(scope)
         .unwrap()
         .to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_read(&path) {
+        GLOBAL_STATE.lock().unwrap().error_message = e;
+        ret.set_int32(-1);
+        return;
+    }
 
     let is_file = match std::fs::metadata(&path) {
         Ok(metadata) => {
@@ -426,6 +494,11 @@ fn is_dir_new(
         .to_string(scope)
         .unwrap()
         .to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_read(&path) {
+        GLOBAL_STATE.lock().unwrap().error_message = e;
+        ret.set_int32(-1);
+        return;
+    }
 
     let is_dir = match std::fs::metadata(&path) {
         Ok(metadata) => {
@@ -453,6 +526,11 @@ fn remove_file_new(
         .to_string(scope)
         .unwrap()
         .to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_write(&path) {
+        GLOBAL_STATE.lock().unwrap().error_message = e;
+        ret.set_int32(-1);
+        return;
+    }
 
     match std::fs::remove_file(&path) {
         Ok(_) => {
@@ -476,6 +554,11 @@ fn remove_dir_new(
         .to_string(scope)
         .unwrap()
         .to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_write(&path) {
+        GLOBAL_STATE.lock().unwrap().error_message = e;
+        ret.set_int32(-1);
+        return;
+    }
 
     match std::fs::remove_dir_all(&path) {
         Ok(_) => {
@@ -489,6 +572,11 @@ fn remove_dir_new(
     }
 }
 
+/// Record `message` to be returned by the next `get_error_message` call.
+pub(crate) fn set_error_message(message: String) {
+    GLOBAL_STATE.lock().unwrap().error_message = message;
+}
+
 fn get_error_message(
     scope: &mut v8::HandleScope,
     _args: v8::FunctionCallbackArguments,
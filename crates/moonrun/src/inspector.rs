@@ -0,0 +1,536 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Chrome DevTools protocol support, enabled with `--inspect` or
+//! `--inspect-brk`.
+//!
+//! A server thread accepts HTTP requests for target discovery and a single
+//! WebSocket session at a time. Incoming protocol messages are queued and
+//! dispatched on the isolate's thread, either from an interrupt while the
+//! program runs or from the message loop while it is paused.
+
+use std::ffi::c_void;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use once_cell::sync::OnceCell;
+use rand::Rng;
+use v8::inspector::{
+    ChannelBase, ChannelImpl, StringBuffer, StringView, V8Inspector, V8InspectorClientBase,
+    V8InspectorClientImpl, V8InspectorClientTrustLevel, V8InspectorSession,
+};
+use v8::{UniquePtr, UniqueRef};
+
+use crate::sourcemap::find_source_map;
+use crate::websocket;
+
+const CONTEXT_GROUP_ID: i32 = 1;
+pub const DEFAULT_INSPECT_ADDR: &str = "127.0.0.1:9229";
+
+static OPTIONS: OnceCell<InspectOptions> = OnceCell::new();
+
+#[derive(Debug, Clone)]
+pub struct InspectOptions {
+    pub addr: SocketAddr,
+    /// Wait for a frontend and pause before the program starts
+    pub break_on_start: bool,
+}
+
+pub fn init(options: InspectOptions) {
+    OPTIONS
+        .set(options)
+        .expect("inspector options are already initialized");
+}
+
+pub fn options() -> Option<&'static InspectOptions> {
+    OPTIONS.get()
+}
+
+/// Parse the value of `--inspect`, which may be a port, a host or both.
+pub fn parse_addr(s: &str) -> Result<SocketAddr, String> {
+    let s = if s.is_empty() {
+        DEFAULT_INSPECT_ADDR
+    } else {
+        s
+    };
+    let addr = if let Ok(port) = s.parse::<u16>() {
+        format!("127.0.0.1:{port}")
+    } else if s.contains(':') {
+        s.to_string()
+    } else {
+        format!("{s}:9229")
+    };
+    use std::net::ToSocketAddrs;
+    addr.to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| format!("invalid inspector address `{s}`"))
+}
+
+enum Inbound {
+    Message(String),
+    Disconnected,
+}
+
+type Connection = Arc<Mutex<Option<TcpStream>>>;
+
+struct Client {
+    base: V8InspectorClientBase,
+    session: *mut V8InspectorSession,
+    inbound: Receiver<Inbound>,
+    paused: bool,
+    waiting_for_debugger: bool,
+}
+
+impl Client {
+    fn dispatch(&mut self, message: &str) {
+        // SAFETY: the session is owned by `Inspector`, which outlives the
+        // client's use
+        let session = unsafe { &mut *self.session };
+        session.dispatch_protocol_message(StringView::from(message.as_bytes()));
+    }
+
+    /// Dispatch the messages received while the program is running.
+    fn dispatch_pending(&mut self) {
+        while let Ok(inbound) = self.inbound.try_recv() {
+            if let Inbound::Message(message) = inbound {
+                self.dispatch(&message);
+            }
+        }
+    }
+
+    /// Dispatch messages until `done` returns true. A disconnected frontend
+    /// resumes the program.
+    fn dispatch_until(&mut self, done: fn(&Client) -> bool) {
+        while !done(self) {
+            match self.inbound.recv() {
+                Ok(Inbound::Message(message)) => self.dispatch(&message),
+                Ok(Inbound::Disconnected) if self.paused => {
+                    self.dispatch(r#"{"id":0,"method":"Debugger.resume"}"#)
+                }
+                Ok(Inbound::Disconnected) => {}
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl V8InspectorClientImpl for Client {
+    fn base(&self) -> &V8InspectorClientBase {
+        &self.base
+    }
+
+    fn base_mut(&mut self) -> &mut V8InspectorClientBase {
+        &mut self.base
+    }
+
+    unsafe fn base_ptr(this: *const Self) -> *const V8InspectorClientBase {
+        // SAFETY: `this` points to a live client
+        unsafe { std::ptr::addr_of!((*this).base) }
+    }
+
+    fn run_message_loop_on_pause(&mut self, _context_group_id: i32) {
+        self.paused = true;
+        self.dispatch_until(|client| !client.paused);
+    }
+
+    fn quit_message_loop_on_pause(&mut self) {
+        self.paused = false;
+    }
+
+    fn run_if_waiting_for_debugger(&mut self, _context_group_id: i32) {
+        self.waiting_for_debugger = false;
+    }
+}
+
+struct Channel {
+    base: ChannelBase,
+    connection: Connection,
+    /// URL of the source map of the program, served by the inspector server
+    source_map_url: Option<String>,
+}
+
+impl Channel {
+    fn send(&self, message: String) {
+        if let Some(stream) = self.connection.lock().unwrap().as_mut() {
+            let _ = websocket::write_message(stream, &message);
+        }
+    }
+
+    /// V8 only knows about source maps referenced from within the module,
+    /// so point DevTools to the one next to the program.
+    fn add_source_map(&self, message: String) -> String {
+        let Some(url) = &self.source_map_url else {
+            return message;
+        };
+        if !message.contains("\"Debugger.scriptParsed\"") {
+            return message;
+        }
+        let Ok(mut value) = serde_json_lenient::from_str::<serde_json_lenient::Value>(&message)
+        else {
+            return message;
+        };
+        let params = &mut value["params"];
+        let is_wasm = params["scriptLanguage"].as_str() == Some("WebAssembly");
+        let has_map = params["sourceMapURL"]
+            .as_str()
+            .is_some_and(|u| u.contains("://"));
+        if !is_wasm || has_map {
+            return message;
+        }
+        params["sourceMapURL"] = serde_json_lenient::Value::String(url.clone());
+        serde_json_lenient::to_string(&value).unwrap_or(message)
+    }
+}
+
+impl ChannelImpl for Channel {
+    fn base(&self) -> &ChannelBase {
+        &self.base
+    }
+
+    fn base_mut(&mut self) -> &mut ChannelBase {
+        &mut self.base
+    }
+
+    unsafe fn base_ptr(this: *const Self) -> *const ChannelBase {
+        // SAFETY: `this` points to a live channel
+        unsafe { std::ptr::addr_of!((*this).base) }
+    }
+
+    fn send_response(&mut self, _call_id: i32, message: UniquePtr<StringBuffer>) {
+        self.send(message.unwrap().string().to_string());
+    }
+
+    fn send_notification(&mut self, message: UniquePtr<StringBuffer>) {
+        let message = self.add_source_map(message.unwrap().string().to_string());
+        self.send(message);
+    }
+
+    fn flush_protocol_notifications(&mut self) {}
+}
+
+/// The inspector attached to the isolate running the program.
+pub struct Inspector {
+    // Fields are dropped in order: the session must go before the inspector,
+    // and both before the client and channel they point to.
+    _session: UniqueRef<V8InspectorSession>,
+    _inspector: UniqueRef<V8Inspector>,
+    _channel: Box<Channel>,
+    client: Box<Client>,
+    alive: Arc<AtomicBool>,
+}
+
+impl Inspector {
+    pub fn new(
+        scope: &mut v8::HandleScope,
+        context: v8::Local<v8::Context>,
+        options: &InspectOptions,
+        program: &str,
+    ) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(options.addr)?;
+        let addr = listener.local_addr()?;
+        let id = new_target_id();
+
+        let (inbound_tx, inbound_rx) = mpsc::channel();
+        let connection: Connection = Arc::new(Mutex::new(None));
+
+        let mut client = Box::new(Client {
+            base: V8InspectorClientBase::new::<Client>(),
+            session: std::ptr::null_mut(),
+            inbound: inbound_rx,
+            paused: false,
+            waiting_for_debugger: false,
+        });
+        let mut channel = Box::new(Channel {
+            base: ChannelBase::new::<Channel>(),
+            connection: connection.clone(),
+            source_map_url: find_source_map(Path::new(program))
+                .map(|map| format!("http://{addr}/{}", url_path(&map))),
+        });
+
+        let mut inspector = V8Inspector::create(scope, &mut *client);
+        let aux_data = r#"{"isDefault":true}"#;
+        inspector.context_created(
+            context,
+            CONTEXT_GROUP_ID,
+            StringView::from(&b"moonrun"[..]),
+            StringView::from(aux_data.as_bytes()),
+        );
+        let mut session = inspector.connect(
+            CONTEXT_GROUP_ID,
+            &mut *channel,
+            StringView::empty(),
+            V8InspectorClientTrustLevel::FullyTrusted,
+        );
+        client.session = &mut *session;
+
+        let alive = Arc::new(AtomicBool::new(true));
+        let server = Server {
+            id: id.clone(),
+            addr,
+            program: std::fs::canonicalize(program).map_or_else(
+                |_| program.to_string(),
+                |p| p.to_string_lossy().into_owned(),
+            ),
+            connection,
+            inbound: inbound_tx,
+            isolate: scope.thread_safe_handle(),
+            client: &mut *client as *mut Client as usize,
+            alive: alive.clone(),
+        };
+        std::thread::spawn(move || server.serve(listener));
+
+        eprintln!("Debugger listening on ws://{addr}/{id}");
+
+        Ok(Inspector {
+            _session: session,
+            _inspector: inspector,
+            _channel: channel,
+            client,
+            alive,
+        })
+    }
+
+    /// Block until a frontend has connected and asked the program to run.
+    pub fn wait_for_debugger(&mut self) {
+        eprintln!("Waiting for the debugger to connect...");
+        self.client.waiting_for_debugger = true;
+        self.client
+            .dispatch_until(|client| !client.waiting_for_debugger);
+    }
+}
+
+impl Drop for Inspector {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::SeqCst);
+    }
+}
+
+extern "C" fn dispatch_interrupt(_isolate: &mut v8::Isolate, data: *mut c_void) {
+    // SAFETY: interrupts are only requested while the inspector is alive
+    let client = unsafe { &mut *(data as *mut Client) };
+    client.dispatch_pending();
+}
+
+struct Server {
+    id: String,
+    addr: SocketAddr,
+    program: String,
+    connection: Connection,
+    inbound: Sender<Inbound>,
+    isolate: v8::IsolateHandle,
+    /// Address of the `Client`, passed to the interrupt callback
+    client: usize,
+    alive: Arc<AtomicBool>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Target {
+    description: &'static str,
+    devtools_frontend_url: String,
+    id: String,
+    title: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    url: String,
+    web_socket_debugger_url: String,
+}
+
+impl Server {
+    fn serve(self, listener: TcpListener) {
+        let server = Arc::new(self);
+        for stream in listener.incoming().flatten() {
+            let server = server.clone();
+            std::thread::spawn(move || server.handle(stream));
+        }
+    }
+
+    fn handle(&self, mut stream: TcpStream) {
+        let Ok(reader) = stream.try_clone() else {
+            return;
+        };
+        let mut reader = BufReader::new(reader);
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).is_err() {
+            return;
+        }
+        let mut websocket_key = None;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).is_err() || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.trim().eq_ignore_ascii_case("sec-websocket-key")
+            {
+                websocket_key = Some(value.trim().to_string());
+            }
+        }
+        let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+
+        match (path, websocket_key) {
+            (p, Some(key)) if p.trim_start_matches('/') == self.id => {
+                self.run_session(stream, reader, &key)
+            }
+            ("/json" | "/json/list", _) => {
+                let target = self.target();
+                let body = serde_json_lenient::to_string(&[target]).unwrap();
+                respond(&mut stream, "200 OK", "application/json", body.as_bytes());
+            }
+            ("/json/version", _) => {
+                let body = format!(
+                    r#"{{"Browser":"moonrun/{}","Protocol-Version":"1.3"}}"#,
+                    env!("CARGO_PKG_VERSION")
+                );
+                respond(&mut stream, "200 OK", "application/json", body.as_bytes());
+            }
+            (p, _) => match read_source_file(p) {
+                Some(content) => respond(&mut stream, "200 OK", "text/plain", &content),
+                None => respond(&mut stream, "404 Not Found", "text/plain", b"not found"),
+            },
+        }
+    }
+
+    fn target(&self) -> Target {
+        let ws = format!("{}/{}", self.addr, self.id);
+        Target {
+            description: "moonrun instance",
+            devtools_frontend_url: format!(
+                "devtools://devtools/bundled/js_app.html?experiments=true&v8only=true&ws={ws}"
+            ),
+            id: self.id.clone(),
+            title: self.program.clone(),
+            kind: "node",
+            url: format!("file:///{}", url_path(Path::new(&self.program))),
+            web_socket_debugger_url: format!("ws://{ws}"),
+        }
+    }
+
+    fn run_session(&self, mut stream: TcpStream, mut reader: BufReader<TcpStream>, key: &str) {
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+            websocket::accept_key(key)
+        );
+        if stream.write_all(response.as_bytes()).is_err() {
+            return;
+        }
+        *self.connection.lock().unwrap() = Some(stream);
+
+        // `reader` may have buffered the start of the first frame
+        struct Duplex<'a>(&'a mut BufReader<TcpStream>);
+        impl std::io::Read for Duplex<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+        impl std::io::Write for Duplex<'_> {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.get_mut().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.0.get_mut().flush()
+            }
+        }
+
+        while let Ok(Some(message)) = websocket::read_message(&mut Duplex(&mut reader)) {
+            self.send(Inbound::Message(message));
+        }
+        *self.connection.lock().unwrap() = None;
+        self.send(Inbound::Disconnected);
+    }
+
+    fn send(&self, inbound: Inbound) {
+        if self.inbound.send(inbound).is_ok() && self.alive.load(Ordering::SeqCst) {
+            self.isolate
+                .request_interrupt(dispatch_interrupt, self.client as *mut c_void);
+        }
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) {
+    let header = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}; charset=UTF-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    let _ = stream
+        .write_all(header.as_bytes())
+        .and_then(|()| stream.write_all(body));
+}
+
+/// Serve source maps and the MoonBit sources they refer to, and nothing else.
+fn read_source_file(path: &str) -> Option<Vec<u8>> {
+    let path = percent_decode(path.split('?').next()?);
+    let path = PathBuf::from(if cfg!(windows) {
+        path.trim_start_matches('/')
+    } else {
+        &path
+    });
+    let ext = path.extension()?.to_str()?;
+    if !matches!(ext, "map" | "mbt" | "md") {
+        return None;
+    }
+    std::fs::read(path).ok()
+}
+
+fn url_path(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    path.trim_start_matches('/').to_string()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let decoded = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn new_target_id() -> String {
+    let mut rng = rand::thread_rng();
+    let b: [u8; 16] = rng.r#gen();
+    let hex: String = b.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
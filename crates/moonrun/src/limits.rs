@@ -0,0 +1,168 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Resource limits for a single execution.
+//!
+//! The heap limit is enforced by V8 itself; when it is about to be reached,
+//! the process exits. The wall time limit is enforced by a watchdog thread
+//! that terminates the running script.
+//!
+//! Programs can exit with any code, so the exit codes used here cannot tell
+//! a limit from a program's own failure. Exceeding a limit is also reported
+//! on stdout between [`MOONRUN_LIMIT_DELIMITER_BEGIN`] and
+//! [`MOONRUN_LIMIT_DELIMITER_END`], which is what `moon test` looks for.
+
+use std::ffi::c_void;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, mpsc};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use moonutil::common::{MOONRUN_LIMIT_DELIMITER_BEGIN, MOONRUN_LIMIT_DELIMITER_END};
+use moonutil::limits::format_size;
+use once_cell::sync::OnceCell;
+
+/// Exit code used when the heap limit is exceeded.
+pub const HEAP_LIMIT_EXIT_CODE: i32 = 3;
+/// Exit code used when the wall time limit is exceeded.
+pub const WALL_TIME_EXIT_CODE: i32 = 4;
+
+const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+static LIMITS: OnceCell<Limits> = OnceCell::new();
+
+#[derive(Debug, Default, Clone)]
+pub struct Limits {
+    pub max_heap: Option<usize>,
+    pub max_wall_time: Option<Duration>,
+}
+
+/// Set the limits for all executions. Must be called before V8 is
+/// initialized.
+pub fn init(limits: Limits) {
+    if let Some(max_heap) = limits.max_heap {
+        // Linear memories live outside of the V8 heap, so cap them separately
+        let pages = (max_heap / WASM_PAGE_SIZE).max(1);
+        v8::V8::set_flags_from_string(&format!("--wasm-max-mem-pages={pages}"));
+    }
+    LIMITS.set(limits).expect("limits are already initialized");
+}
+
+fn limits() -> &'static Limits {
+    LIMITS.get_or_init(Limits::default)
+}
+
+/// Parameters for a new isolate, with the heap limit applied.
+pub fn create_params() -> v8::CreateParams {
+    let params = v8::CreateParams::default();
+    match limits().max_heap {
+        Some(max_heap) => params.heap_limits(0, max_heap),
+        None => params,
+    }
+}
+
+/// Report that a limit was exceeded, on stderr for users and on stdout for
+/// `moon test`.
+fn report(message: &str) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(
+        stdout,
+        "{MOONRUN_LIMIT_DELIMITER_BEGIN}\n{message}\n{MOONRUN_LIMIT_DELIMITER_END}"
+    );
+    let _ = stdout.flush();
+    eprintln!("error: {message}");
+}
+
+fn exit_with(code: i32, message: &str) -> ! {
+    report(message);
+    std::process::exit(code)
+}
+
+extern "C" fn near_heap_limit(
+    _data: *mut c_void,
+    _current_heap_limit: usize,
+    _initial_heap_limit: usize,
+) -> usize {
+    let max_heap = limits().max_heap.unwrap_or_default();
+    exit_with(
+        HEAP_LIMIT_EXIT_CODE,
+        &format!("heap limit of {} exceeded", format_size(max_heap)),
+    )
+}
+
+/// Guards an execution in `isolate` against the configured limits until it
+/// is dropped.
+pub struct Watchdog {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    pub fn start(isolate: &mut v8::Isolate) -> Self {
+        let limits = limits();
+        if limits.max_heap.is_some() {
+            isolate.add_near_heap_limit_callback(near_heap_limit, std::ptr::null_mut());
+        }
+
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let Some(max_wall_time) = limits.max_wall_time else {
+            return Watchdog {
+                stop: None,
+                thread: None,
+                timed_out,
+            };
+        };
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = isolate.thread_safe_handle();
+        let flag = timed_out.clone();
+        let thread = std::thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(max_wall_time) {
+                flag.store(true, Ordering::SeqCst);
+                handle.terminate_execution();
+            }
+        });
+        Watchdog {
+            stop: Some(stop),
+            thread: Some(thread),
+            timed_out,
+        }
+    }
+
+    /// If the execution was terminated by the watchdog, report it and return
+    /// the exit code to use.
+    pub fn status(&self) -> Option<i32> {
+        if !self.timed_out.load(Ordering::SeqCst) {
+            return None;
+        }
+        let max_wall_time = limits().max_wall_time.unwrap_or_default();
+        report(&format!("wall time limit of {max_wall_time:?} exceeded"));
+        Some(WALL_TIME_EXIT_CODE)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -16,17 +16,28 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
+use anyhow::Context;
 use clap::Parser;
 use std::any::Any;
 use std::io::{self, Write};
 use std::path::Path;
-use std::{cell::Cell, io::Read, path::PathBuf, time::Instant};
+use std::{cell::Cell, cell::RefCell, io::Read, path::PathBuf, time::Instant};
 use v8::V8::set_flags_from_string;
 
 mod fs_api_temp;
+mod inspector;
 mod js;
+mod limits;
+mod net;
+mod node;
+mod permissions;
+mod profiler;
+mod sourcemap;
 mod sys_api;
 mod util;
+mod wasi;
+mod websocket;
+mod worker;
 
 use rand::Rng;
 use rand::SeedableRng;
@@ -163,8 +174,7 @@ pub fn get_array_buffer_ptr(ab: v8::Local<v8::ArrayBuffer>) -> *mut u8 {
 
 fn read_utf8_char() -> io::Result<Option<char>> {
     let mut buffer = [0; 4];
-    let stdin = io::stdin();
-    let mut handle = stdin.lock();
+    let mut handle = worker::program_stdin();
 
     let size = handle.read(&mut buffer[0..1])?;
     if size == 0 {
@@ -217,8 +227,7 @@ fn read_bytes_from_stdin(
     mut ret: v8::ReturnValue,
 ) {
     let mut buffer = Vec::new();
-    let stdin = io::stdin();
-    let mut handle = stdin.lock();
+    let mut handle = worker::program_stdin();
 
     let size = handle.read_to_end(&mut buffer).unwrap();
 
@@ -327,6 +336,11 @@ fn exit(
     mut _ret: v8::ReturnValue,
 ) {
     let code = args.get(0).to_int32(scope).unwrap();
+    if worker::is_enabled() {
+        worker::request_exit(scope, code.value());
+        return;
+    }
+    profiler::finish();
     std::process::exit(code.value());
 }
 
@@ -449,6 +463,49 @@ fn init_env(
         let ident = v8::String::new(scope, "exit").unwrap();
         obj.set(scope, ident.into(), exit.into());
     }
+
+    {
+        // The network API and the primitives WASI is implemented with are only
+        // created for the programs that request them
+        let identifier = v8::String::new(scope, "__moonrun_host_api").unwrap();
+        let dtors = v8::External::new(scope, dtors as *mut Vec<_> as *mut std::ffi::c_void);
+        let value = v8::Function::builder(host_api)
+            .data(dtors.into())
+            .build(scope)
+            .unwrap();
+        global_proxy.set(scope, identifier.into(), value.into());
+    }
+}
+
+/// `fn __moonrun_host_api(name) -> Object or undefined`
+///
+/// Create the object of an API that is not installed for every program:
+/// `"net"` for `__moonbit_net_unstable`, and `"wasi"` for the primitives
+/// used by `template/wasi.js` and `template/node.js`.
+fn host_api(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let dtors = {
+        let data: v8::Local<v8::Data> = args.data().into();
+        let ptr = v8::Local::<v8::External>::try_from(data).unwrap().value();
+        // SAFETY: the callers of `init_env` keep `dtors` alive until the
+        // program is done running
+        unsafe { &mut *(ptr as *mut Vec<Box<dyn Any>>) }
+    };
+    let name = args.get(0).to_rust_string_lossy(scope);
+    let obj = v8::Object::new(scope);
+    match name.as_str() {
+        "net" => {
+            let state = Box::<RefCell<net::NetState>>::default();
+            let obj = net::init_net(obj, scope, &state);
+            dtors.push(state);
+            ret.set(obj.into());
+        }
+        "wasi" => ret.set(wasi::init_wasi(obj, scope).into()),
+        _ => ret.set_undefined(),
+    }
 }
 
 fn create_script_origin<'s>(scope: &mut v8::HandleScope<'s>, name: &str) -> v8::ScriptOrigin<'s> {
@@ -478,9 +535,10 @@ fn wasm_mode(
     source: Source,
     args: &[String],
     no_stack_trace: bool,
-    test_args: Option<String>,
-) -> anyhow::Result<()> {
-    let isolate = &mut v8::Isolate::new(Default::default());
+    test_args: Option<TestArgs>,
+) -> anyhow::Result<i32> {
+    let isolate = &mut v8::Isolate::new(limits::create_params());
+    let watchdog = limits::Watchdog::start(isolate);
     let scope = &mut v8::HandleScope::new(isolate);
     let context = v8::Context::new(scope, Default::default());
     let scope = &mut v8::ContextScope::new(scope, context);
@@ -518,14 +576,25 @@ fn wasm_mode(
     let mut dtors = Vec::new();
     init_env(&mut dtors, scope, &wasm_file_name, args);
 
-    if let Some(ref test_args) = test_args {
-        let test_args = serde_json_lenient::from_str::<TestArgs>(test_args).unwrap();
-        let file_and_index = test_args.file_and_index;
+    let mut inspector = match inspector::options() {
+        Some(options) => Some(inspector::Inspector::new(
+            scope,
+            context,
+            options,
+            &wasm_file_name,
+        )?),
+        None => None,
+    };
+    let break_on_start = inspector::options().is_some_and(|o| o.break_on_start);
+    profiler::start(scope, context, &wasm_file_name);
+
+    if let Some(test_args) = &test_args {
+        let file_and_index = &test_args.file_and_index;
 
         let mut test_params: Vec<[String; 2]> = vec![];
         for (file, index) in file_and_index {
             for range in index {
-                for i in range {
+                for i in range.clone() {
                     test_params.push([file.clone(), i.to_string()]);
                 }
             }
@@ -535,6 +604,9 @@ fn wasm_mode(
     }
     script.push_str(&format!("const no_stack_trace = {no_stack_trace};"));
     script.push_str(&format!("const test_mode = {};", test_args.is_some()));
+    script.push_str(&format!("const break_on_start = {break_on_start};"));
+    let wasi = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/template/wasi.js"));
+    script.push_str(wasi);
     let js_glue = include_str!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/src/template/js_glue.js"
@@ -545,9 +617,53 @@ fn wasm_mode(
     let script_origin = create_script_origin(scope, "wasm_mode_entry");
     let script = v8::Script::compile(scope, code, Some(&script_origin)).unwrap();
 
+    if break_on_start && let Some(inspector) = &mut inspector {
+        inspector.wait_for_debugger();
+    }
     script.run(scope);
+    profiler::finish();
+    let exit_code = watchdog.status().or_else(worker::take_exit_code);
+    drop(inspector);
     drop(dtors);
-    Ok(())
+    Ok(exit_code.unwrap_or(0))
+}
+
+/// Run the output of the JS backend, returning its exit code.
+fn js_mode(file: &Path, args: &[String]) -> anyhow::Result<i32> {
+    let isolate = &mut v8::Isolate::new(limits::create_params());
+    let watchdog = limits::Watchdog::start(isolate);
+    let scope = &mut v8::HandleScope::new(isolate);
+    let context = v8::Context::new(scope, Default::default());
+    let scope = &mut v8::ContextScope::new(scope, context);
+
+    let file_name = std::fs::canonicalize(file)?.to_string_lossy().into_owned();
+    let mut dtors = Vec::new();
+    init_env(&mut dtors, scope, &file_name, args);
+    {
+        let global_proxy = scope.get_current_context().global(scope);
+        let identifier = v8::String::new(scope, "__moonrun_node").unwrap();
+        let obj = v8::Object::new(scope);
+        let obj = node::init_node(obj, scope);
+        global_proxy.set(scope, identifier.into(), obj.into());
+    }
+    profiler::start(scope, context, &file_name);
+
+    let mut script = format!(
+        "const main_module = {};",
+        serde_json_lenient::to_string(&file_name)?
+    );
+    let node = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/template/node.js"));
+    script.push_str(node);
+
+    let code = v8::String::new(scope, &script).unwrap();
+    let script_origin = create_script_origin(scope, "js_mode_entry");
+    let script = v8::Script::compile(scope, code, Some(&script_origin)).unwrap();
+    script.run(scope);
+    let exit_code = node::run_event_loop(scope);
+    profiler::finish();
+    let exit_code = watchdog.status().or(exit_code);
+    drop(dtors);
+    Ok(exit_code.unwrap_or(1))
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -568,8 +684,9 @@ pub fn get_moonrun_version() -> String {
 #[derive(Debug, clap::Parser)]
 #[command(version = get_moonrun_version())]
 struct Commandline {
-    /// The path of the file to run
-    #[clap(required_unless_present = "interactive")]
+    /// The path of the file to run: a `.wasm` module, or the `.js` or `.cjs`
+    /// output of the JS backend in CommonJS format
+    #[clap(required_unless_present_any = ["interactive", "worker"])]
     path: Option<PathBuf>,
 
     /// Additional arguments
@@ -588,6 +705,118 @@ struct Commandline {
 
     #[clap(short, long)]
     interactive: bool,
+
+    /// Run the WebAssembly programs requested on stdin one after another,
+    /// reusing the process. See `worker.rs` for the protocol
+    #[clap(
+        long,
+        conflicts_with_all = ["path", "interactive", "inspect", "inspect_brk", "cpu_prof"]
+    )]
+    worker: bool,
+
+    /// Exit with code 3 if the program's memory use exceeds this size, e.g.
+    /// `512M`. Applies to both the JS heap and WebAssembly linear memories
+    #[clap(long, value_name = "SIZE", value_parser = moonutil::limits::parse_size)]
+    max_heap: Option<usize>,
+
+    /// Exit with code 4 if the program runs longer than this, e.g. `30s` or
+    /// `500ms`
+    #[clap(long, value_name = "DURATION", value_parser = moonutil::limits::parse_duration)]
+    max_wall_time: Option<std::time::Duration>,
+
+    /// Deny file system and environment variable access unless granted with
+    /// `--allow-read`, `--allow-write` or `--allow-env`
+    #[clap(long)]
+    sandbox: bool,
+
+    /// Allow reading the given comma-separated paths, or any path if none is
+    /// given. Implies `--sandbox`
+    #[clap(
+        long,
+        value_name = "PATHS",
+        value_delimiter = ',',
+        num_args = 0..,
+        require_equals = true
+    )]
+    allow_read: Option<Vec<PathBuf>>,
+
+    /// Allow writing the given comma-separated paths, or any path if none is
+    /// given. Implies `--sandbox`
+    #[clap(
+        long,
+        value_name = "PATHS",
+        value_delimiter = ',',
+        num_args = 0..,
+        require_equals = true
+    )]
+    allow_write: Option<Vec<PathBuf>>,
+
+    /// Allow accessing the given comma-separated environment variables, or
+    /// all of them if none is given. Implies `--sandbox`
+    #[clap(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        num_args = 0..,
+        require_equals = true
+    )]
+    allow_env: Option<Vec<String>>,
+
+    /// Allow network access to the given comma-separated hosts, as `HOST` or
+    /// `HOST:PORT`, or to any host if none is given. Denied by default
+    #[clap(
+        long,
+        value_name = "HOSTS",
+        value_delimiter = ',',
+        num_args = 0..,
+        require_equals = true
+    )]
+    allow_net: Option<Vec<String>>,
+
+    /// Accept DevTools protocol connections on `[HOST:]PORT`, by default
+    /// 127.0.0.1:9229
+    #[clap(
+        long,
+        value_name = "HOST:PORT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        value_parser = inspector::parse_addr,
+        conflicts_with = "interactive"
+    )]
+    inspect: Option<std::net::SocketAddr>,
+
+    /// Like `--inspect`, but wait for a debugger to attach and pause before
+    /// the program starts
+    #[clap(
+        long,
+        value_name = "HOST:PORT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        value_parser = inspector::parse_addr,
+        conflicts_with_all = ["interactive", "inspect"]
+    )]
+    inspect_brk: Option<std::net::SocketAddr>,
+
+    /// Write a CPU profile of the program to this file when it exits, in the
+    /// `.cpuprofile` format of Chrome DevTools
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["interactive", "inspect", "inspect_brk"]
+    )]
+    cpu_prof: Option<PathBuf>,
+
+    /// Also write the CPU profile as folded stacks, the input format of
+    /// flamegraph tools
+    #[clap(long, value_name = "FILE", requires = "cpu_prof")]
+    cpu_prof_folded: Option<PathBuf>,
+
+    /// Make a host directory available to WASI programs, as `<HOST>` or
+    /// `<HOST>::<GUEST>`. Can be given multiple times
+    #[clap(long = "dir", value_name = "DIR")]
+    dirs: Vec<String>,
 }
 
 fn run_interactive() -> anyhow::Result<()> {
@@ -630,9 +859,62 @@ fn main() -> anyhow::Result<()> {
 
     let matches = Commandline::parse();
 
+    let permissions = if matches.sandbox
+        || matches.allow_read.is_some()
+        || matches.allow_write.is_some()
+        || matches.allow_env.is_some()
+    {
+        permissions::Permissions::sandboxed(
+            matches.allow_read.clone(),
+            matches.allow_write.clone(),
+            matches.allow_env.clone(),
+        )
+    } else {
+        permissions::Permissions::default()
+    };
+    permissions::init(permissions.with_net(matches.allow_net.clone()));
+
+    if let Some(addr) = matches.inspect.or(matches.inspect_brk) {
+        inspector::init(inspector::InspectOptions {
+            addr,
+            break_on_start: matches.inspect_brk.is_some(),
+        });
+    }
+    if let Some(output) = matches.cpu_prof.clone() {
+        profiler::init(profiler::ProfileOptions {
+            output,
+            folded: matches.cpu_prof_folded.clone(),
+        });
+    }
+    limits::init(limits::Limits {
+        max_heap: matches.max_heap,
+        max_wall_time: matches.max_wall_time,
+    });
+    wasi::init_preopens(
+        matches
+            .dirs
+            .iter()
+            .map(|d| wasi::parse_preopen(d))
+            .collect(),
+    );
+
+    if let Some(stack_size) = &matches.stack_size {
+        set_flags_from_string(&format!("--stack-size={stack_size}"));
+    }
+
     if matches.interactive {
         initialize_v8()?;
         run_interactive()
+    } else if matches.worker {
+        initialize_v8()?;
+        worker::serve(|request| {
+            wasm_mode(
+                Source::File(&request.path),
+                &request.args,
+                matches.no_stack_trace,
+                request.test_args,
+            )
+        })
     } else {
         let file = matches.path.as_ref().unwrap();
 
@@ -640,19 +922,34 @@ fn main() -> anyhow::Result<()> {
             anyhow::bail!("no such file");
         }
 
-        if let Some(stack_size) = matches.stack_size {
-            set_flags_from_string(&format!("--stack-size={stack_size}"));
-        }
-
         match file.extension().unwrap().to_str() {
             Some("wasm") => {
+                let test_args = match &matches.test_args {
+                    Some(test_args) => Some(
+                        serde_json_lenient::from_str::<TestArgs>(test_args)
+                            .context("invalid `--test-args`")?,
+                    ),
+                    None => None,
+                };
                 initialize_v8()?;
-                wasm_mode(
+                let code = wasm_mode(
                     Source::File(file),
                     &matches.args,
                     matches.no_stack_trace,
-                    matches.test_args,
-                )
+                    test_args,
+                )?;
+                if code != 0 {
+                    std::process::exit(code);
+                }
+                Ok(())
+            }
+            Some("js" | "cjs") => {
+                initialize_v8()?;
+                let code = js_mode(file, &matches.args)?;
+                if code != 0 {
+                    std::process::exit(code);
+                }
+                Ok(())
             }
             _ => anyhow::bail!("Unsupported file type"),
         }
@@ -0,0 +1,651 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Networking for the running program, under `__moonbit_net_unstable`. It
+//! is only created for wasm modules importing it, and for JS programs on
+//! first use.
+//!
+//! Sockets are blocking and referred to by integer handles. Failing calls
+//! return -1 and leave a message for `get_error_message`. Network access is
+//! denied unless granted with `--allow-net`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+
+use crate::permissions;
+
+/// The most bytes returned by one `read`.
+const MAX_READ_SIZE: usize = 64 * 1024;
+
+enum Socket {
+    Stream(TcpStream),
+    Listener(TcpListener),
+}
+
+/// The state of the network API of one program. Its sockets are closed when
+/// it is dropped.
+#[derive(Default)]
+pub struct NetState {
+    sockets: HashMap<i32, Socket>,
+    next_handle: i32,
+    read_bytes: Vec<u8>,
+    response_headers: String,
+    response_body: Vec<u8>,
+    error_message: String,
+}
+
+impl NetState {
+    fn insert(&mut self, socket: Socket) -> i32 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.sockets.insert(handle, socket);
+        handle
+    }
+
+    fn stream(&mut self, handle: i32) -> Result<&mut TcpStream, String> {
+        match self.sockets.get_mut(&handle) {
+            Some(Socket::Stream(stream)) => Ok(stream),
+            Some(Socket::Listener(_)) => Err(format!("handle {handle} is not a TCP stream")),
+            None => Err(format!("invalid socket handle {handle}")),
+        }
+    }
+
+    fn listener(&mut self, handle: i32) -> Result<&mut TcpListener, String> {
+        match self.sockets.get_mut(&handle) {
+            Some(Socket::Listener(listener)) => Ok(listener),
+            Some(Socket::Stream(_)) => Err(format!("handle {handle} is not a TCP listener")),
+            None => Err(format!("invalid socket handle {handle}")),
+        }
+    }
+
+    fn connect(&mut self, host: &str, port: u16) -> Result<i32, String> {
+        permissions::check_net(host, port)?;
+        let stream = TcpStream::connect((host, port))
+            .map_err(|e| format!("Failed to connect to {host}:{port}: {e}"))?;
+        Ok(self.insert(Socket::Stream(stream)))
+    }
+
+    fn listen(&mut self, host: &str, port: u16) -> Result<i32, String> {
+        permissions::check_net(host, port)?;
+        let listener = TcpListener::bind((host, port))
+            .map_err(|e| format!("Failed to listen on {host}:{port}: {e}"))?;
+        Ok(self.insert(Socket::Listener(listener)))
+    }
+
+    fn local_port(&mut self, handle: i32) -> Result<i32, String> {
+        let addr = match self.sockets.get(&handle) {
+            Some(Socket::Stream(stream)) => stream.local_addr(),
+            Some(Socket::Listener(listener)) => listener.local_addr(),
+            None => return Err(format!("invalid socket handle {handle}")),
+        };
+        addr.map(|a| a.port() as i32).map_err(|e| e.to_string())
+    }
+
+    fn accept(&mut self, handle: i32) -> Result<i32, String> {
+        let (stream, _) = self
+            .listener(handle)?
+            .accept()
+            .map_err(|e| format!("Failed to accept a connection: {e}"))?;
+        Ok(self.insert(Socket::Stream(stream)))
+    }
+
+    fn read(&mut self, handle: i32, max_len: usize) -> Result<i32, String> {
+        // A read returns what is available anyway, so don't let the program
+        // pick the size of the buffer
+        let mut buffer = vec![0; max_len.min(MAX_READ_SIZE)];
+        let n = self
+            .stream(handle)?
+            .read(&mut buffer)
+            .map_err(|e| format!("Failed to read from socket {handle}: {e}"))?;
+        buffer.truncate(n);
+        self.read_bytes = buffer;
+        Ok(n as i32)
+    }
+
+    fn write(&mut self, handle: i32, bytes: &[u8]) -> Result<i32, String> {
+        self.stream(handle)?
+            .write_all(bytes)
+            .map_err(|e| format!("Failed to write to socket {handle}: {e}"))?;
+        Ok(bytes.len() as i32)
+    }
+
+    fn shutdown(&mut self, handle: i32) -> Result<i32, String> {
+        self.stream(handle)?
+            .shutdown(Shutdown::Write)
+            .map_err(|e| format!("Failed to shut down socket {handle}: {e}"))?;
+        Ok(0)
+    }
+
+    fn close(&mut self, handle: i32) -> Result<i32, String> {
+        match self.sockets.remove(&handle) {
+            Some(_) => Ok(0),
+            None => Err(format!("invalid socket handle {handle}")),
+        }
+    }
+
+    fn http_request(
+        &mut self,
+        method: &str,
+        url: &str,
+        headers: &str,
+        body: &[u8],
+    ) -> Result<i32, String> {
+        let url = HttpUrl::parse(url)?;
+        permissions::check_net(&url.host, url.port)?;
+        let response = http_request(method, &url, headers, body)
+            .map_err(|e| format!("HTTP request to {} failed: {e}", url.host))?;
+        self.response_headers = response.headers;
+        self.response_body = response.body;
+        Ok(response.status)
+    }
+}
+
+/// An `http://` URL. HTTPS is not supported.
+#[derive(Debug, PartialEq, Eq)]
+struct HttpUrl {
+    host: String,
+    port: u16,
+    /// The path and query, starting with `/`
+    target: String,
+}
+
+impl HttpUrl {
+    fn parse(url: &str) -> Result<Self, String> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            return Err(format!(
+                "unsupported URL `{url}`, only http:// URLs are supported"
+            ));
+        };
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            // `[::1]` without a port
+            Some((_, port)) if port.ends_with(']') => (authority, 80),
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("invalid port in URL `{url}`"))?,
+            ),
+            None => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(format!("missing host in URL `{url}`"));
+        }
+        Ok(HttpUrl {
+            host: host.to_string(),
+            port,
+            target,
+        })
+    }
+
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match self.port {
+            80 => host,
+            port => format!("{host}:{port}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HttpResponse {
+    status: i32,
+    /// `Name: value` lines
+    headers: String,
+    body: Vec<u8>,
+}
+
+/// Send an HTTP/1.1 request with `Connection: close` and read the response.
+/// `headers` are extra `Name: value` lines.
+fn http_request(
+    method: &str,
+    url: &HttpUrl,
+    headers: &str,
+    body: &[u8],
+) -> std::io::Result<HttpResponse> {
+    let mut stream = TcpStream::connect((url.host.as_str(), url.port))?;
+    let mut request = format!(
+        "{method} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        url.target,
+        url.host_header(),
+        body.len()
+    );
+    for line in headers.lines().filter(|l| !l.trim().is_empty()) {
+        request.push_str(line.trim_end());
+        request.push_str("\r\n");
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(body)?;
+    read_http_response(BufReader::new(stream), method == "HEAD")
+}
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+fn read_http_response(mut reader: impl BufRead, no_body: bool) -> std::io::Result<HttpResponse> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid_data(format!("invalid status line `{}`", line.trim_end())))?;
+
+    let mut headers = String::new();
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_data("unexpected end of headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+        headers.push_str(header);
+        headers.push('\n');
+    }
+
+    // 1xx, 204 and 304 responses have no body
+    let body = if no_body || (100..200).contains(&status) || status == 204 || status == 304 {
+        vec![]
+    } else if chunked {
+        read_chunked_body(&mut reader)?
+    } else if let Some(length) = content_length {
+        let mut body = vec![];
+        read_exact_len(&mut reader, length, &mut body)?;
+        body
+    } else {
+        let mut body = vec![];
+        reader.read_to_end(&mut body)?;
+        body
+    };
+    Ok(HttpResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+/// Append exactly `len` bytes from `reader` to `buf`. The buffer grows with
+/// the data actually received rather than with the length the peer claims.
+fn read_exact_len(reader: impl Read, len: usize, buf: &mut Vec<u8>) -> std::io::Result<()> {
+    let n = reader.take(len as u64).read_to_end(buf)?;
+    if n < len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("expected {len} bytes of body, got {n}"),
+        ));
+    }
+    Ok(())
+}
+
+fn read_chunked_body(mut reader: impl BufRead) -> std::io::Result<Vec<u8>> {
+    let mut body = vec![];
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let size = line.trim_end().split(';').next().unwrap_or_default();
+        let size = usize::from_str_radix(size.trim(), 16)
+            .map_err(|_| invalid_data(format!("invalid chunk size `{}`", line.trim_end())))?;
+        if size == 0 {
+            break;
+        }
+        read_exact_len(&mut reader, size, &mut body)?;
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+    // Skip the trailers
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            return Ok(body);
+        }
+    }
+}
+
+fn state<'a>(args: &v8::FunctionCallbackArguments) -> &'a RefCell<NetState> {
+    let data: v8::Local<v8::Data> = args.data().into();
+    let ptr = v8::Local::<v8::External>::try_from(data).unwrap().value();
+    unsafe { &*(ptr as *const RefCell<NetState>) }
+}
+
+/// Return the result of a call, or record its error and return -1.
+fn set_result(state: &RefCell<NetState>, ret: &mut v8::ReturnValue, result: Result<i32, String>) {
+    match result {
+        Ok(value) => ret.set_int32(value),
+        Err(e) => {
+            state.borrow_mut().error_message = e;
+            ret.set_int32(-1);
+        }
+    }
+}
+
+fn string_arg(scope: &mut v8::HandleScope, args: &v8::FunctionCallbackArguments, i: i32) -> String {
+    args.get(i).to_rust_string_lossy(scope)
+}
+
+fn int_arg(scope: &mut v8::HandleScope, args: &v8::FunctionCallbackArguments, i: i32) -> i32 {
+    args.get(i).int32_value(scope).unwrap_or(-1)
+}
+
+fn bytes_arg(args: &v8::FunctionCallbackArguments, i: i32) -> Result<Vec<u8>, String> {
+    let array = v8::Local::<v8::Uint8Array>::try_from(args.get(i))
+        .map_err(|_| "Failed to convert contents to Uint8Array".to_string())?;
+    let mut buffer = vec![0; array.byte_length()];
+    array.copy_contents(&mut buffer);
+    Ok(buffer)
+}
+
+fn set_bytes(scope: &mut v8::HandleScope, ret: &mut v8::ReturnValue, bytes: Vec<u8>) {
+    let len = bytes.len();
+    let array_buffer = v8::ArrayBuffer::with_backing_store(
+        scope,
+        &v8::ArrayBuffer::new_backing_store_from_bytes(bytes).make_shared(),
+    );
+    let uint8_array = v8::Uint8Array::new(scope, array_buffer, 0, len).unwrap();
+    ret.set(uint8_array.into());
+}
+
+fn port_arg(
+    scope: &mut v8::HandleScope,
+    args: &v8::FunctionCallbackArguments,
+    i: i32,
+) -> Result<u16, String> {
+    let port = int_arg(scope, args, i);
+    u16::try_from(port).map_err(|_| format!("invalid port {port}"))
+}
+
+/// `fn tcp_connect(host: JSString, port: Int) -> Int`
+fn tcp_connect(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let state = state(&args);
+    let host = string_arg(scope, &args, 0);
+    let result = port_arg(scope, &args, 1).and_then(|port| state.borrow_mut().connect(&host, port));
+    set_result(state, &mut ret, result);
+}
+
+/// `fn tcp_listen(host: JSString, port: Int) -> Int`
+///
+/// Port 0 picks a free port, which `tcp_local_port` returns.
+fn tcp_listen(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let state = state(&args);
+    let host = string_arg(scope, &args, 0);
+    let result = port_arg(scope, &args, 1).and_then(|port| state.borrow_mut().listen(&host, port));
+    set_result(state, &mut ret, result);
+}
+
+/// `fn tcp_local_port(handle: Int) -> Int`
+fn tcp_local_port(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let state = state(&args);
+    let handle = int_arg(scope, &args, 0);
+    let result = state.borrow_mut().local_port(handle);
+    set_result(state, &mut ret, result);
+}
+
+/// `fn tcp_accept(listener: Int) -> Int`
+fn tcp_accept(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let state = state(&args);
+    let handle = int_arg(scope, &args, 0);
+    let result = state.borrow_mut().accept(handle);
+    set_result(state, &mut ret, result);
+}
+
+/// `fn tcp_read(handle: Int, max_len: Int) -> Int`
+///
+/// Returns the number of bytes read, 0 at the end of the stream. The bytes
+/// are returned by `get_read_bytes`.
+fn tcp_read(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let state = state(&args);
+    let handle = int_arg(scope, &args, 0);
+    let max_len = int_arg(scope, &args, 1).max(0) as usize;
+    let result = state.borrow_mut().read(handle, max_len);
+    set_result(state, &mut ret, result);
+}
+
+/// `fn get_read_bytes() -> Uint8Array`
+fn get_read_bytes(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let bytes = std::mem::take(&mut state(&args).borrow_mut().read_bytes);
+    set_bytes(scope, &mut ret, bytes);
+}
+
+/// `fn tcp_write(handle: Int, bytes: Uint8Array) -> Int`
+///
+/// Writes all the bytes, returning their count.
+fn tcp_write(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let state = state(&args);
+    let handle = int_arg(scope, &args, 0);
+    let result = bytes_arg(&args, 1).and_then(|bytes| state.borrow_mut().write(handle, &bytes));
+    set_result(state, &mut ret, result);
+}
+
+/// `fn tcp_shutdown(handle: Int) -> Int`
+///
+/// Shuts down the writing half of a stream, so that the peer reads its end.
+fn tcp_shutdown(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let state = state(&args);
+    let handle = int_arg(scope, &args, 0);
+    let result = state.borrow_mut().shutdown(handle);
+    set_result(state, &mut ret, result);
+}
+
+/// `fn close(handle: Int) -> Int`
+fn close(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let state = state(&args);
+    let handle = int_arg(scope, &args, 0);
+    let result = state.borrow_mut().close(handle);
+    set_result(state, &mut ret, result);
+}
+
+/// `fn http_request(method: JSString, url: JSString, headers: JSString, body: Uint8Array) -> Int`
+///
+/// `headers` are `Name: value` lines. Returns the status code of the
+/// response, whose headers and body are returned by `get_response_headers`
+/// and `get_response_body`.
+fn http_request_native(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let state = state(&args);
+    let method = string_arg(scope, &args, 0);
+    let url = string_arg(scope, &args, 1);
+    let headers = string_arg(scope, &args, 2);
+    let result = bytes_arg(&args, 3).and_then(|body| {
+        state
+            .borrow_mut()
+            .http_request(&method, &url, &headers, &body)
+    });
+    set_result(state, &mut ret, result);
+}
+
+/// `fn get_response_headers() -> JSString`
+fn get_response_headers(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let headers = v8::String::new(scope, &state(&args).borrow().response_headers).unwrap();
+    ret.set(headers.into());
+}
+
+/// `fn get_response_body() -> Uint8Array`
+fn get_response_body(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let body = std::mem::take(&mut state(&args).borrow_mut().response_body);
+    set_bytes(scope, &mut ret, body);
+}
+
+/// `fn get_error_message() -> JSString`
+fn get_error_message(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let message = v8::String::new(scope, &state(&args).borrow().error_message).unwrap();
+    ret.set(message.into());
+}
+
+pub fn init_net<'s>(
+    obj: v8::Local<'s, v8::Object>,
+    scope: &mut v8::HandleScope<'s>,
+    state: &RefCell<NetState>,
+) -> v8::Local<'s, v8::Object> {
+    let state = v8::External::new(scope, state as *const _ as *mut std::ffi::c_void);
+    set_function(scope, obj, state, "tcp_connect", tcp_connect);
+    set_function(scope, obj, state, "tcp_listen", tcp_listen);
+    set_function(scope, obj, state, "tcp_local_port", tcp_local_port);
+    set_function(scope, obj, state, "tcp_accept", tcp_accept);
+    set_function(scope, obj, state, "tcp_read", tcp_read);
+    set_function(scope, obj, state, "get_read_bytes", get_read_bytes);
+    set_function(scope, obj, state, "tcp_write", tcp_write);
+    set_function(scope, obj, state, "tcp_shutdown", tcp_shutdown);
+    set_function(scope, obj, state, "close", close);
+    set_function(scope, obj, state, "http_request", http_request_native);
+    set_function(
+        scope,
+        obj,
+        state,
+        "get_response_headers",
+        get_response_headers,
+    );
+    set_function(scope, obj, state, "get_response_body", get_response_body);
+    set_function(scope, obj, state, "get_error_message", get_error_message);
+    obj
+}
+
+fn set_function<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    obj: v8::Local<'s, v8::Object>,
+    state: v8::Local<'s, v8::External>,
+    name: &str,
+    f: impl v8::MapFnTo<v8::FunctionCallback>,
+) {
+    let ident = v8::String::new(scope, name).unwrap();
+    let value = v8::Function::builder(f)
+        .data(state.into())
+        .build(scope)
+        .unwrap();
+    obj.set(scope, ident.into(), value.into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(raw: &str) -> std::io::Result<HttpResponse> {
+        read_http_response(raw.as_bytes(), false)
+    }
+
+    #[test]
+    fn test_read_http_response() {
+        let res = response("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello, world").unwrap();
+        assert_eq!(res.status, 200);
+        assert_eq!(res.headers, "Content-Length: 5\n");
+        assert_eq!(res.body, b"hello");
+
+        let res = response(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7;ext\r\n, world\r\n0\r\nTrailer: x\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(res.body, b"hello, world");
+
+        let res = response("HTTP/1.1 204 No Content\r\nContent-Length: 5\r\n\r\n").unwrap();
+        assert_eq!(res.status, 204);
+        assert!(res.body.is_empty());
+    }
+
+    #[test]
+    fn test_read_http_response_short_body() {
+        // The claimed length must not be allocated up front
+        let huge = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\nhello",
+            usize::MAX
+        );
+        let err = response(&huge).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        let huge = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffff\r\nhello";
+        let err = response(huge).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        let bad = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\n";
+        let err = response(bad).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
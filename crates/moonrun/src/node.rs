@@ -0,0 +1,149 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Running the output of the JS backend without Node.js.
+//!
+//! `template/node.js` provides the globals and builtin modules the generated
+//! code and the test driver use, on top of the natives of `wasi.rs` and the
+//! ones here. Only CommonJS modules are supported.
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::permissions;
+
+const MODULE_WRAPPER_BEGIN: &str = "(function (exports, require, module, __filename, __dirname) {";
+const MODULE_WRAPPER_END: &str = "\n})";
+
+/// The value of `process.platform` in Node.js.
+fn platform() -> &'static str {
+    match std::env::consts::OS {
+        "windows" => "win32",
+        "macos" => "darwin",
+        os => os,
+    }
+}
+
+/// `fn load_module(path, is_main) -> Function or undefined`
+///
+/// Compiles a CommonJS module into a function taking the module's scope as
+/// arguments. Returns `undefined` if the file can't be read, so that the
+/// loader can try other candidates. Like a WebAssembly program, the program
+/// itself is loaded regardless of `--allow-read`, but the modules it requires
+/// are not.
+fn load_module(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let path = args.get(0).to_rust_string_lossy(scope);
+    let is_main = args.get(1).boolean_value(scope);
+    if !is_main && let Err(e) = permissions::check_read(&path) {
+        let message = v8::String::new(scope, &e).unwrap();
+        let exception = v8::Exception::error(scope, message);
+        scope.throw_exception(exception);
+        return;
+    }
+    if !Path::new(&path).is_file() {
+        return;
+    }
+    let Ok(source) = std::fs::read_to_string(&path) else {
+        return;
+    };
+    // Keep the line numbers of a shebang-prefixed script
+    let source = match source.strip_prefix("#!") {
+        Some(rest) => format!("//{rest}"),
+        None => source,
+    };
+    let code = format!("{MODULE_WRAPPER_BEGIN}{source}{MODULE_WRAPPER_END}");
+    let code = v8::String::new(scope, &code).unwrap();
+    let name = v8::String::new(scope, &path).unwrap();
+    let origin = v8::ScriptOrigin::new(
+        scope,
+        name.into(),
+        0,
+        0,
+        false,
+        0,
+        None,
+        false,
+        false,
+        false,
+        None,
+    );
+    // A syntax error is left pending and thrown to the caller
+    let Some(script) = v8::Script::compile(scope, code, Some(&origin)) else {
+        return;
+    };
+    if let Some(function) = script.run(scope) {
+        ret.set(function);
+    }
+}
+
+pub fn init_node<'s>(
+    obj: v8::Local<'s, v8::Object>,
+    scope: &mut v8::HandleScope<'s>,
+) -> v8::Local<'s, v8::Object> {
+    let ident = v8::String::new(scope, "load_module").unwrap();
+    let value = v8::Function::builder(load_module).build(scope).unwrap();
+    obj.set(scope, ident.into(), value.into());
+
+    let ident = v8::String::new(scope, "platform").unwrap();
+    let value = v8::String::new(scope, platform()).unwrap();
+    obj.set(scope, ident.into(), value.into());
+
+    obj
+}
+
+/// Run timers until there are none left, with microtasks run in between like
+/// Node.js does. Returns the exit code of the program, or `None` if its
+/// execution was terminated.
+pub fn run_event_loop(scope: &mut v8::HandleScope) -> Option<i32> {
+    let global = scope.get_current_context().global(scope);
+    let key = v8::String::new(scope, "__moonrun_event_loop").unwrap();
+    let event_loop: v8::Local<v8::Object> = global.get(scope, key.into())?.try_into().ok()?;
+    let next_delay = get_function(scope, event_loop, "next_delay")?;
+    let run_next = get_function(scope, event_loop, "run_next")?;
+    let exit_code = get_function(scope, event_loop, "exit_code")?;
+
+    loop {
+        scope.perform_microtask_checkpoint();
+        let delay = next_delay
+            .call(scope, event_loop.into(), &[])?
+            .number_value(scope)?;
+        if delay < 0.0 {
+            break;
+        }
+        if delay > 0.0 {
+            std::thread::sleep(Duration::from_secs_f64(delay / 1000.0));
+        }
+        run_next.call(scope, event_loop.into(), &[])?;
+    }
+    exit_code
+        .call(scope, event_loop.into(), &[])?
+        .int32_value(scope)
+}
+
+fn get_function<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    obj: v8::Local<v8::Object>,
+    name: &str,
+) -> Option<v8::Local<'s, v8::Function>> {
+    let key = v8::String::new(scope, name).unwrap();
+    obj.get(scope, key.into())?.try_into().ok()
+}
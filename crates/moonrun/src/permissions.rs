@@ -0,0 +1,453 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Deno-style permissions for the host APIs exposed to the running program.
+//!
+//! By default everything is allowed but network access, which has to be
+//! granted with `--allow-net`. In sandbox mode, file system and environment
+//! variable access is denied unless explicitly granted with `--allow-read`,
+//! `--allow-write` and `--allow-env`.
+
+use std::net::IpAddr;
+use std::path::{Component, Path, PathBuf};
+
+use once_cell::sync::OnceCell;
+
+static PERMISSIONS: OnceCell<Permissions> = OnceCell::new();
+
+#[derive(Debug)]
+pub struct Permissions {
+    read: Access<PathBuf>,
+    write: Access<PathBuf>,
+    env: Access<String>,
+    /// Hosts, optionally with a port as `host:port`
+    net: Access<String>,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Permissions {
+            read: Access::All,
+            write: Access::All,
+            env: Access::All,
+            net: Access::Only(vec![]),
+        }
+    }
+}
+
+/// What a permission grants access to.
+#[derive(Debug)]
+enum Access<T> {
+    All,
+    Only(Vec<T>),
+}
+
+impl<T> Default for Access<T> {
+    fn default() -> Self {
+        Access::All
+    }
+}
+
+impl<T> Access<T> {
+    /// `None` denies everything, an empty list grants everything.
+    fn from_flag(flag: Option<Vec<T>>) -> Self {
+        match flag {
+            None => Access::Only(vec![]),
+            Some(list) if list.is_empty() => Access::All,
+            Some(list) => Access::Only(list),
+        }
+    }
+}
+
+impl Permissions {
+    /// Permissions of a sandboxed program, granting only the given accesses.
+    /// See [`Access::from_flag`] for the meaning of each argument.
+    pub fn sandboxed(
+        read: Option<Vec<PathBuf>>,
+        write: Option<Vec<PathBuf>>,
+        env: Option<Vec<String>>,
+    ) -> Self {
+        let normalize = |paths: Option<Vec<PathBuf>>| {
+            paths.map(|paths| paths.iter().map(|p| normalize_path(p)).collect())
+        };
+        Permissions {
+            read: Access::from_flag(normalize(read)),
+            write: Access::from_flag(normalize(write)),
+            env: Access::from_flag(env),
+            ..Default::default()
+        }
+    }
+
+    /// Grant network access as given by `--allow-net`. See
+    /// [`Access::from_flag`].
+    pub fn with_net(self, net: Option<Vec<String>>) -> Self {
+        Permissions {
+            net: Access::from_flag(net),
+            ..self
+        }
+    }
+}
+
+/// Set the permissions of the current process. Must be called at most once,
+/// before running any program.
+pub fn init(permissions: Permissions) {
+    PERMISSIONS
+        .set(permissions)
+        .expect("permissions are already initialized");
+}
+
+fn get() -> &'static Permissions {
+    PERMISSIONS.get_or_init(Permissions::default)
+}
+
+/// Make `path` absolute and resolve `.`, `..` and symlinks the same way the
+/// OS does, so that it can be compared with the allowed paths. Parts of the
+/// path that do not exist yet are resolved lexically.
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    let path = std::env::current_dir().unwrap_or_default().join(path);
+    resolve_path(&path, 0)
+}
+
+/// The maximum number of symlinks followed, like `MAXSYMLINKS` on Linux
+const MAX_SYMLINKS: usize = 40;
+
+fn resolve_path(path: &Path, symlinks: usize) -> PathBuf {
+    let mut result = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Prefix(_) | Component::RootDir => result.push(c),
+            Component::CurDir => {}
+            // `result` never contains symlinks, so its parent is what the OS
+            // resolves `..` to
+            Component::ParentDir => {
+                result.pop();
+            }
+            Component::Normal(name) => {
+                result.push(name);
+                // Dangling symlinks are followed too, since writing to them
+                // creates their target
+                if symlinks < MAX_SYMLINKS
+                    && std::fs::symlink_metadata(&result).is_ok_and(|m| m.file_type().is_symlink())
+                    && let Ok(target) = std::fs::read_link(&result)
+                {
+                    result.pop();
+                    result = resolve_path(&result.join(target), symlinks + 1);
+                }
+            }
+        }
+    }
+    result
+}
+
+fn check_path(access: &Access<PathBuf>, path: &str) -> bool {
+    match access {
+        Access::All => true,
+        Access::Only(allowed) => {
+            let path = normalize_path(Path::new(path));
+            allowed.iter().any(|a| path.starts_with(a))
+        }
+    }
+}
+
+impl Permissions {
+    fn check_read(&self, path: &str) -> Result<(), String> {
+        if check_path(&self.read, path) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Permission denied: read access to `{path}`, run again with `--allow-read` to grant it"
+            ))
+        }
+    }
+
+    fn check_write(&self, path: &str) -> Result<(), String> {
+        if check_path(&self.write, path) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Permission denied: write access to `{path}`, run again with `--allow-write` to grant it"
+            ))
+        }
+    }
+
+    fn check_net(&self, host: &str, port: u16) -> Result<(), String> {
+        let allowed = match &self.net {
+            Access::All => true,
+            Access::Only(entries) => entries.iter().any(|entry| {
+                let (allowed_host, allowed_port) = split_host_port(entry);
+                same_host(allowed_host, host) && allowed_port.is_none_or(|p| p == port)
+            }),
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(format!(
+                "Permission denied: network access to `{host}:{port}`, run again with `--allow-net` to grant it"
+            ))
+        }
+    }
+}
+
+/// Split an `--allow-net` entry into its host and optional port, as `HOST`,
+/// `HOST:PORT` or `[IPV6]:PORT`.
+fn split_host_port(entry: &str) -> (&str, Option<u16>) {
+    if let Some((host, rest)) = entry.strip_prefix('[').and_then(|e| e.split_once(']'))
+        && let Some(port) = rest.strip_prefix(':').and_then(|p| p.parse().ok())
+    {
+        return (host, Some(port));
+    }
+    match entry.rsplit_once(':') {
+        // More than one colon is an IPv6 address without port
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (entry, None),
+        },
+        _ => (entry, None),
+    }
+}
+
+/// Spell `host` the same way as any other name of it that can be told
+/// without resolving it: lowercase, without the trailing dot of a fully
+/// qualified name or the brackets of an IPv6 address, and with IPv4-mapped
+/// IPv6 addresses as IPv4.
+fn normalize_host(host: &str) -> String {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let host = host.strip_suffix('.').unwrap_or(host);
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip.to_canonical().to_string(),
+        Err(_) => host.to_ascii_lowercase(),
+    }
+}
+
+/// Whether `a` and `b` name the same host. `localhost` and all loopback
+/// addresses are the same host.
+fn same_host(a: &str, b: &str) -> bool {
+    let is_loopback =
+        |h: &str| h == "localhost" || h.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+    let (a, b) = (normalize_host(a), normalize_host(b));
+    a == b || (is_loopback(&a) && is_loopback(&b))
+}
+
+/// Check read access to `path`, returning the permission-denied message if
+/// the access is not granted.
+pub fn check_read(path: &str) -> Result<(), String> {
+    get().check_read(path)
+}
+
+/// Check write access to `path`, returning the permission-denied message if
+/// the access is not granted.
+pub fn check_write(path: &str) -> Result<(), String> {
+    get().check_write(path)
+}
+
+/// Check access to the environment variable `name`, returning the
+/// permission-denied message if the access is not granted.
+pub fn check_env(name: &str) -> Result<(), String> {
+    let allowed = match &get().env {
+        Access::All => true,
+        Access::Only(names) => names.iter().any(|n| n == name),
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(format!(
+            "Permission denied: access to environment variable `{name}`, run again with `--allow-env` to grant it"
+        ))
+    }
+}
+
+/// Check network access to `host` on `port`, returning the
+/// permission-denied message if the access is not granted.
+pub fn check_net(host: &str, port: u16) -> Result<(), String> {
+    get().check_net(host, port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sandbox(dir: &Path, read: &[&str], write: &[&str]) -> Permissions {
+        let paths = |names: &[&str]| Some(names.iter().map(|n| dir.join(n)).collect());
+        Permissions::sandboxed(paths(read), paths(write), None)
+    }
+
+    fn path(dir: &Path, rest: &str) -> String {
+        dir.join(rest).display().to_string()
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = normalize_path(dir.path());
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+
+        assert_eq!(normalize_path(&root.join("a/./b/../b")), root.join("a/b"));
+        // Paths that do not exist are resolved lexically
+        assert_eq!(normalize_path(&root.join("a/new/../c")), root.join("a/c"));
+        assert_eq!(
+            normalize_path(&root.join("a/new/../../../etc/x")),
+            root.parent().unwrap().join("etc/x")
+        );
+        // Trailing `..` is resolved too
+        assert_eq!(normalize_path(&root.join("a/b/..")), root.join("a"));
+    }
+
+    #[test]
+    fn test_check_parent_dir_escape() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = normalize_path(dir.path());
+        std::fs::create_dir_all(root.join("allowed")).unwrap();
+        let perms = sandbox(&root, &["allowed"], &["allowed"]);
+
+        assert!(perms.check_write(&path(&root, "allowed/new/file")).is_ok());
+        assert!(
+            perms
+                .check_write(&path(&root, "allowed/new/../file"))
+                .is_ok()
+        );
+        assert!(
+            perms
+                .check_write(&path(&root, "allowed/new/../../x"))
+                .is_err()
+        );
+        assert!(
+            perms
+                .check_write(&path(&root, "allowed/../allowed2/x"))
+                .is_err()
+        );
+        assert!(perms.check_read(&path(&root, "allowed/..")).is_err());
+        assert!(perms.check_read(&path(&root, "allowed/.")).is_ok());
+        assert!(perms.check_read(&path(&root, "other")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = normalize_path(dir.path());
+        std::fs::create_dir_all(root.join("allowed")).unwrap();
+        std::fs::create_dir_all(root.join("secret")).unwrap();
+        std::fs::write(root.join("secret/key"), "").unwrap();
+        symlink(root.join("secret"), root.join("allowed/escape")).unwrap();
+        symlink("../secret/new", root.join("allowed/dangling")).unwrap();
+        symlink("inner", root.join("allowed/relative")).unwrap();
+        std::fs::create_dir_all(root.join("allowed/inner")).unwrap();
+        let perms = sandbox(&root, &["allowed"], &["allowed"]);
+
+        assert!(
+            perms
+                .check_read(&path(&root, "allowed/escape/key"))
+                .is_err()
+        );
+        assert!(
+            perms
+                .check_write(&path(&root, "allowed/escape/new"))
+                .is_err()
+        );
+        // `..` applies to the target of the symlink
+        assert!(
+            perms
+                .check_read(&path(&root, "allowed/escape/../allowed"))
+                .is_ok()
+        );
+        assert!(perms.check_write(&path(&root, "allowed/dangling")).is_err());
+        assert!(
+            perms
+                .check_write(&path(&root, "allowed/relative/x"))
+                .is_ok()
+        );
+
+        // Allowing a symlink allows its target
+        let perms = sandbox(&root, &["allowed/escape"], &[]);
+        assert!(perms.check_read(&path(&root, "secret/key")).is_ok());
+        assert!(perms.check_write(&path(&root, "secret/key")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_loop() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = normalize_path(dir.path());
+        symlink("b", root.join("a")).unwrap();
+        symlink("a", root.join("b")).unwrap();
+        let perms = sandbox(&root, &["allowed"], &[]);
+        assert!(perms.check_read(&path(&root, "a/x")).is_err());
+    }
+
+    #[test]
+    fn test_check_unrestricted() {
+        let perms = Permissions::sandboxed(Some(vec![]), None, Some(vec!["HOME".to_string()]));
+        assert!(perms.check_read("/anything").is_ok());
+        assert!(perms.check_write("/anything").is_err());
+
+        let perms = Permissions::default();
+        assert!(perms.check_read("/anything").is_ok());
+        assert!(perms.check_write("/anything").is_ok());
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("example.com"), ("example.com", None));
+        assert_eq!(split_host_port("example.com:80"), ("example.com", Some(80)));
+        assert_eq!(split_host_port("example.com:x"), ("example.com:x", None));
+        assert_eq!(split_host_port("::1"), ("::1", None));
+        assert_eq!(split_host_port("[::1]"), ("[::1]", None));
+        assert_eq!(split_host_port("[::1]:8080"), ("::1", Some(8080)));
+    }
+
+    #[test]
+    fn test_check_net() {
+        let permissions = Permissions::default().with_net(Some(vec![
+            "Example.COM.".to_string(),
+            "localhost:8080".to_string(),
+            "[2001:db8::1]:443".to_string(),
+        ]));
+        let allowed = |host, port| permissions.check_net(host, port).is_ok();
+
+        assert!(allowed("example.com", 443));
+        assert!(allowed("EXAMPLE.com.", 80));
+        assert!(!allowed("www.example.com", 443));
+        assert!(!allowed("example.com.evil", 443));
+
+        // `localhost` covers the loopback addresses, but only on its port
+        assert!(allowed("localhost", 8080));
+        assert!(allowed("LocalHost.", 8080));
+        assert!(allowed("127.0.0.1", 8080));
+        assert!(allowed("::1", 8080));
+        assert!(allowed("[::1]", 8080));
+        assert!(allowed("::ffff:127.0.0.1", 8080));
+        assert!(!allowed("127.0.0.1", 8081));
+        assert!(!allowed("0.0.0.0", 8080));
+
+        assert!(allowed("2001:db8::1", 443));
+        assert!(allowed("2001:DB8:0::1", 443));
+        assert!(!allowed("2001:db8::1", 80));
+
+        let denied = Permissions::default().check_net("example.com", 80);
+        assert_eq!(
+            denied.unwrap_err(),
+            "Permission denied: network access to `example.com:80`, run again with `--allow-net` to grant it"
+        );
+        let all = Permissions::default().with_net(Some(vec![]));
+        assert!(all.check_net("example.com", 80).is_ok());
+    }
+}
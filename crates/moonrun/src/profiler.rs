@@ -0,0 +1,315 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! CPU profiling, enabled with `--cpu-prof`.
+//!
+//! Samples are collected by the `Profiler` domain of a local inspector
+//! session, so the output is a `.cpuprofile` that Chrome DevTools and most
+//! profile viewers read. Frames of WebAssembly functions are mapped to their
+//! MoonBit sources with the program's source map, if there is one.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use once_cell::sync::OnceCell;
+use serde_json_lenient::Value;
+use v8::inspector::{
+    ChannelBase, ChannelImpl, StringBuffer, StringView, V8Inspector, V8InspectorClientBase,
+    V8InspectorClientImpl, V8InspectorClientTrustLevel, V8InspectorSession,
+};
+use v8::{UniquePtr, UniqueRef};
+
+use crate::sourcemap::{SourceMap, find_source_map};
+
+const CONTEXT_GROUP_ID: i32 = 1;
+/// Sampling interval in microseconds
+const SAMPLING_INTERVAL: u32 = 100;
+
+static OPTIONS: OnceCell<ProfileOptions> = OnceCell::new();
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Profiler>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfileOptions {
+    /// Where to write the `.cpuprofile`
+    pub output: PathBuf,
+    /// Where to write the samples as folded stacks for flamegraph tools
+    pub folded: Option<PathBuf>,
+}
+
+pub fn init(options: ProfileOptions) {
+    OPTIONS
+        .set(options)
+        .expect("profile options are already initialized");
+}
+
+pub fn options() -> Option<&'static ProfileOptions> {
+    OPTIONS.get()
+}
+
+/// Start profiling the program running in `context`, if `--cpu-prof` is
+/// given.
+pub fn start(scope: &mut v8::HandleScope, context: v8::Local<v8::Context>, program: &str) {
+    if options().is_none() {
+        return;
+    }
+    let profiler = Profiler::new(scope, context, Path::new(program));
+    ACTIVE.with(|active| *active.borrow_mut() = Some(profiler));
+}
+
+/// Stop profiling and write the profile. Also called when the program exits
+/// early, so that the profile isn't lost.
+pub fn finish() {
+    let (Some(options), Some(profiler)) = (options(), ACTIVE.with(|a| a.borrow_mut().take()))
+    else {
+        return;
+    };
+    if let Err(e) = profiler.finish(options) {
+        eprintln!("Failed to write the CPU profile: {e:#}");
+    }
+}
+
+struct Client {
+    base: V8InspectorClientBase,
+}
+
+impl V8InspectorClientImpl for Client {
+    fn base(&self) -> &V8InspectorClientBase {
+        &self.base
+    }
+
+    fn base_mut(&mut self) -> &mut V8InspectorClientBase {
+        &mut self.base
+    }
+
+    unsafe fn base_ptr(this: *const Self) -> *const V8InspectorClientBase {
+        // SAFETY: `this` points to a live client
+        unsafe { std::ptr::addr_of!((*this).base) }
+    }
+}
+
+struct Channel {
+    base: ChannelBase,
+    /// The response to the last dispatched message. Responses of the
+    /// `Profiler` domain are sent before dispatching returns.
+    response: Option<String>,
+}
+
+impl ChannelImpl for Channel {
+    fn base(&self) -> &ChannelBase {
+        &self.base
+    }
+
+    fn base_mut(&mut self) -> &mut ChannelBase {
+        &mut self.base
+    }
+
+    unsafe fn base_ptr(this: *const Self) -> *const ChannelBase {
+        // SAFETY: `this` points to a live channel
+        unsafe { std::ptr::addr_of!((*this).base) }
+    }
+
+    fn send_response(&mut self, _call_id: i32, message: UniquePtr<StringBuffer>) {
+        self.response = Some(message.unwrap().string().to_string());
+    }
+
+    fn send_notification(&mut self, _message: UniquePtr<StringBuffer>) {}
+
+    fn flush_protocol_notifications(&mut self) {}
+}
+
+struct Profiler {
+    // Fields are dropped in order: the session must go before the inspector,
+    // and both before the client and channel they point to.
+    session: UniqueRef<V8InspectorSession>,
+    _inspector: UniqueRef<V8Inspector>,
+    channel: Box<Channel>,
+    _client: Box<Client>,
+    program: PathBuf,
+    last_id: i32,
+}
+
+impl Profiler {
+    fn new(scope: &mut v8::HandleScope, context: v8::Local<v8::Context>, program: &Path) -> Self {
+        let mut client = Box::new(Client {
+            base: V8InspectorClientBase::new::<Client>(),
+        });
+        let mut channel = Box::new(Channel {
+            base: ChannelBase::new::<Channel>(),
+            response: None,
+        });
+        let mut inspector = V8Inspector::create(scope, &mut *client);
+        inspector.context_created(
+            context,
+            CONTEXT_GROUP_ID,
+            StringView::from(&b"moonrun"[..]),
+            StringView::empty(),
+        );
+        let session = inspector.connect(
+            CONTEXT_GROUP_ID,
+            &mut *channel,
+            StringView::empty(),
+            V8InspectorClientTrustLevel::FullyTrusted,
+        );
+        let mut profiler = Profiler {
+            session,
+            _inspector: inspector,
+            channel,
+            _client: client,
+            program: program.to_path_buf(),
+            last_id: 0,
+        };
+        profiler.call("Profiler.enable", "{}");
+        profiler.call(
+            "Profiler.setSamplingInterval",
+            &format!(r#"{{"interval":{SAMPLING_INTERVAL}}}"#),
+        );
+        profiler.call("Profiler.start", "{}");
+        profiler
+    }
+
+    /// Dispatch a protocol method and return its result.
+    fn call(&mut self, method: &str, params: &str) -> Option<Value> {
+        self.last_id += 1;
+        let message = format!(
+            r#"{{"id":{},"method":"{method}","params":{params}}}"#,
+            self.last_id
+        );
+        self.session
+            .dispatch_protocol_message(StringView::from(message.as_bytes()));
+        let response = self.channel.response.take()?;
+        let mut response: Value = serde_json_lenient::from_str(&response).ok()?;
+        Some(response["result"].take())
+    }
+
+    fn finish(mut self, options: &ProfileOptions) -> anyhow::Result<()> {
+        let mut result = self
+            .call("Profiler.stop", "{}")
+            .context("the profiler returned no result")?;
+        let mut profile = result["profile"].take();
+        if !profile.is_object() {
+            anyhow::bail!("the profiler returned no profile");
+        }
+
+        if let Some(path) = find_source_map(&self.program) {
+            let source_map = SourceMap::load(&path)?;
+            map_wasm_frames(&mut profile, &source_map);
+        }
+
+        std::fs::write(&options.output, serde_json_lenient::to_string(&profile)?)
+            .with_context(|| format!("failed to write `{}`", options.output.display()))?;
+        if let Some(folded) = &options.folded {
+            std::fs::write(folded, fold_stacks(&profile))
+                .with_context(|| format!("failed to write `{}`", folded.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Point the frames of WebAssembly functions to their MoonBit sources.
+///
+/// V8 reports a WebAssembly function at line 0 and its byte offset in the
+/// module as the column, which is also how source maps of WebAssembly
+/// modules address code.
+fn map_wasm_frames(profile: &mut Value, source_map: &SourceMap) {
+    let Some(nodes) = profile["nodes"].as_array_mut() else {
+        return;
+    };
+    for node in nodes {
+        let frame = &mut node["callFrame"];
+        if !frame["url"]
+            .as_str()
+            .is_some_and(|u| u.starts_with("wasm://"))
+        {
+            continue;
+        }
+        // Names from the name section are prefixed with `$`
+        if let Some(name) = frame["functionName"].as_str()
+            && let Some(name) = name.strip_prefix('$')
+        {
+            frame["functionName"] = Value::String(name.to_string());
+        }
+        let Some(offset) = frame["columnNumber"].as_u64() else {
+            continue;
+        };
+        let Some(original) = source_map.lookup(0, offset as u32) else {
+            continue;
+        };
+        if frame["functionName"].as_str().is_none_or(|n| n.is_empty())
+            && let Some(name) = original.name
+        {
+            frame["functionName"] = Value::String(name.to_string());
+        }
+        frame["url"] = Value::String(original.source.to_string());
+        frame["lineNumber"] = original.line.into();
+        frame["columnNumber"] = original.column.into();
+    }
+}
+
+/// Render the samples as folded stacks, one `caller;callee count` line per
+/// distinct stack, the input format of `flamegraph.pl` and inferno.
+fn fold_stacks(profile: &Value) -> String {
+    let empty = vec![];
+    let nodes = profile["nodes"].as_array().unwrap_or(&empty);
+    let by_id: HashMap<u64, &Value> = nodes
+        .iter()
+        .filter_map(|n| Some((n["id"].as_u64()?, n)))
+        .collect();
+    let mut parents = HashMap::new();
+    for node in nodes {
+        let Some(id) = node["id"].as_u64() else {
+            continue;
+        };
+        for child in node["children"].as_array().unwrap_or(&empty) {
+            if let Some(child) = child.as_u64() {
+                parents.insert(child, id);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for node in nodes {
+        let hits = node["hitCount"].as_u64().unwrap_or(0);
+        let Some(mut id) = node["id"].as_u64().filter(|_| hits > 0) else {
+            continue;
+        };
+        let mut stack = vec![];
+        loop {
+            // The root has no parent and is left out
+            let Some(&parent) = parents.get(&id) else {
+                break;
+            };
+            stack.push(frame_name(by_id[&id]));
+            id = parent;
+        }
+        stack.reverse();
+        out.push_str(&format!("{} {hits}\n", stack.join(";")));
+    }
+    out
+}
+
+fn frame_name(node: &Value) -> String {
+    match node["callFrame"]["functionName"].as_str() {
+        Some(name) if !name.is_empty() => name.replace(';', ":"),
+        _ => "(anonymous)".to_string(),
+    }
+}
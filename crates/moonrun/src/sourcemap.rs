@@ -0,0 +1,313 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! A minimal reader of source maps (revision 3), enough to map the code
+//! offsets of a WebAssembly module back to MoonBit sources.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// An original position a generated position is mapped to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Original<'a> {
+    pub source: &'a str,
+    /// 0-based line
+    pub line: u32,
+    /// 0-based column
+    pub column: u32,
+    pub name: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Mapping {
+    line: u32,
+    column: u32,
+    source: u32,
+    original_line: u32,
+    original_column: u32,
+    name: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    sources: Vec<String>,
+    names: Vec<String>,
+    /// Sorted by generated line and column
+    mappings: Vec<Mapping>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawSourceMap {
+    #[serde(default)]
+    source_root: Option<String>,
+    sources: Vec<Option<String>>,
+    #[serde(default)]
+    names: Vec<String>,
+    mappings: String,
+}
+
+impl SourceMap {
+    /// Load a source map, resolving relative sources against its directory.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read source map `{}`", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&content, base)
+            .with_context(|| format!("failed to parse source map `{}`", path.display()))
+    }
+
+    pub fn parse(content: &str, base: &Path) -> anyhow::Result<Self> {
+        let raw: RawSourceMap = serde_json_lenient::from_str(content)?;
+        let root = raw.source_root.unwrap_or_default();
+        let sources = raw
+            .sources
+            .into_iter()
+            .map(|s| resolve_source(base, &root, &s.unwrap_or_default()))
+            .collect();
+        Ok(SourceMap {
+            sources,
+            names: raw.names,
+            mappings: decode_mappings(&raw.mappings)?,
+        })
+    }
+
+    /// Find the original position of the closest mapping at or before the
+    /// given generated position.
+    pub fn lookup(&self, line: u32, column: u32) -> Option<Original<'_>> {
+        let idx = self
+            .mappings
+            .partition_point(|m| (m.line, m.column) <= (line, column));
+        let m = self.mappings[..idx].last().filter(|m| m.line == line)?;
+        Some(Original {
+            source: self.sources.get(m.source as usize)?,
+            line: m.original_line,
+            column: m.original_column,
+            name: m
+                .name
+                .and_then(|n| self.names.get(n as usize))
+                .map(|n| n.as_str()),
+        })
+    }
+}
+
+/// Find the source map next to a program, e.g. `main.wasm.map` or `main.map`
+/// for `main.wasm`.
+pub fn find_source_map(program: &Path) -> Option<PathBuf> {
+    let program = dunce::canonicalize(program).ok()?;
+    let candidates = [
+        program.with_extension("wasm.map"),
+        program.with_extension("map"),
+    ];
+    candidates.into_iter().find(|p| p.is_file())
+}
+
+fn resolve_source(base: &Path, root: &str, source: &str) -> String {
+    let source = format!("{root}{source}");
+    let path = source.strip_prefix("file://").unwrap_or(&source);
+    if path.contains("://") || Path::new(path).is_absolute() {
+        return path.to_string();
+    }
+    base.join(path).to_string_lossy().into_owned()
+}
+
+fn decode_mappings(mappings: &str) -> anyhow::Result<Vec<Mapping>> {
+    let mut result = Vec::new();
+    // All fields but the generated column are relative to the previous
+    // segment, across lines
+    let (mut source, mut original_line, mut original_column, mut name_index) =
+        (0i64, 0i64, 0i64, 0i64);
+    for (line, group) in mappings.split(';').enumerate() {
+        let mut column = 0i64;
+        for segment in group.split(',').filter(|s| !s.is_empty()) {
+            let fields = decode_vlq(segment)?;
+            column += fields[0];
+            if fields.len() < 4 {
+                continue;
+            }
+            source += fields[1];
+            original_line += fields[2];
+            original_column += fields[3];
+            let name = fields.get(4).map(|n| {
+                name_index += n;
+                name_index as u32
+            });
+            result.push(Mapping {
+                line: line as u32,
+                column: column as u32,
+                source: source as u32,
+                original_line: original_line as u32,
+                original_column: original_column as u32,
+                name,
+            });
+        }
+    }
+    result.sort_by_key(|m| (m.line, m.column));
+    Ok(result)
+}
+
+fn decode_vlq(segment: &str) -> anyhow::Result<Vec<i64>> {
+    let mut fields = Vec::new();
+    let (mut value, mut shift) = (0i64, 0);
+    for c in segment.bytes() {
+        let digit = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => anyhow::bail!("invalid base64 character `{}` in mappings", c as char),
+        } as i64;
+        anyhow::ensure!(
+            shift < 32,
+            "value out of range in segment `{segment}` of mappings"
+        );
+        value |= (digit & 0b11111) << shift;
+        if digit & 0b100000 != 0 {
+            shift += 5;
+            continue;
+        }
+        fields.push(if value & 1 != 0 {
+            -(value >> 1)
+        } else {
+            value >> 1
+        });
+        value = 0;
+        shift = 0;
+    }
+    if shift != 0 {
+        anyhow::bail!("truncated segment `{segment}` in mappings");
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_vlq() {
+        let cases: &[(&str, &[i64])] = &[
+            ("", &[]),
+            ("A", &[0]),
+            ("C", &[1]),
+            ("D", &[-1]),
+            ("e", &[15]),
+            ("gB", &[16]),
+            ("hB", &[-16]),
+            ("2H", &[123]),
+            ("w+B", &[1000]),
+            ("x+B", &[-1000]),
+            ("+/////D", &[i32::MAX as i64]),
+            ("AAgBC", &[0, 0, 16, 1]),
+        ];
+        for (segment, expected) in cases {
+            assert_eq!(decode_vlq(segment).unwrap(), *expected, "{segment}");
+        }
+
+        let err = |segment| decode_vlq(segment).unwrap_err().to_string();
+        assert_eq!(err("A!"), "invalid base64 character `!` in mappings");
+        assert_eq!(err("Ag"), "truncated segment `Ag` in mappings");
+        assert_eq!(
+            err("////////A"),
+            "value out of range in segment `////////A` of mappings"
+        );
+    }
+
+    #[test]
+    fn test_decode_mappings() {
+        // Generated columns restart on each line, the other fields do not
+        let mappings = decode_mappings("AAAA,EAAEC;;CACA,C,EAAEA").unwrap();
+        let fields: Vec<_> = mappings
+            .iter()
+            .map(|m| {
+                (
+                    m.line,
+                    m.column,
+                    m.source,
+                    m.original_line,
+                    m.original_column,
+                    m.name,
+                )
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                (0, 0, 0, 0, 0, None),
+                (0, 2, 0, 0, 2, Some(1)),
+                (2, 1, 1, 1, 2, None),
+                (2, 4, 1, 1, 4, Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lookup() {
+        let map = SourceMap::parse(
+            r#"{
+                "version": 3,
+                "sourceRoot": "src/",
+                "sources": ["main.mbt", "lib.mbt"],
+                "names": ["main", "helper"],
+                "mappings": "AAAAA,KAAK;ECAAC"
+            }"#,
+            Path::new("/base"),
+        )
+        .unwrap();
+        let path = |name| {
+            Path::new("/base")
+                .join(format!("src/{name}"))
+                .to_string_lossy()
+                .into_owned()
+        };
+        let (main, lib) = (path("main.mbt"), path("lib.mbt"));
+
+        assert_eq!(
+            map.lookup(0, 3),
+            Some(Original {
+                source: &main,
+                line: 0,
+                column: 0,
+                name: Some("main"),
+            })
+        );
+        assert_eq!(
+            map.lookup(0, 7),
+            Some(Original {
+                source: &main,
+                line: 0,
+                column: 5,
+                name: None,
+            })
+        );
+        assert_eq!(
+            map.lookup(1, 2),
+            Some(Original {
+                source: &lib,
+                line: 0,
+                column: 5,
+                name: Some("helper"),
+            })
+        );
+        // Nothing is mapped before the first segment of a line
+        assert_eq!(map.lookup(1, 1), None);
+        assert_eq!(map.lookup(2, 0), None);
+    }
+}
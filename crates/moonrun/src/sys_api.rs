@@ -16,6 +16,8 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
+use crate::permissions;
+
 const INIT_SYS_API: &str = r#"
     (() => function(obj, run_env) {
         // Return the value of the environment variable
@@ -52,7 +54,7 @@ fn construct_args_list<'s>(
 
 fn construct_env_vars<'s>(scope: &mut v8::HandleScope<'s>) -> v8::Local<'s, v8::Map> {
     let map = v8::Map::new(scope);
-    for (k, v) in std::env::vars() {
+    for (k, v) in std::env::vars().filter(|(k, _)| permissions::check_env(k).is_ok()) {
         let key = v8::String::new(scope, &k).unwrap();
         let val = v8::String::new(scope, &v).unwrap();
         map.set(scope, key.into(), val.into());
@@ -60,6 +62,14 @@ fn construct_env_vars<'s>(scope: &mut v8::HandleScope<'s>) -> v8::Local<'s, v8::
     map
 }
 
+/// Fail the call with a JS exception, so that a denied write to the
+/// environment cannot be mistaken for a successful one.
+fn throw_permission_denied(scope: &mut v8::HandleScope, message: &str) {
+    let message = v8::String::new(scope, message).unwrap();
+    let exception = v8::Exception::error(scope, message);
+    scope.throw_exception(exception);
+}
+
 fn set_env_var(
     scope: &mut v8::HandleScope,
     args: v8::FunctionCallbackArguments,
@@ -73,6 +83,11 @@ fn set_env_var(
     let value = value.to_string(scope).unwrap();
     let value = value.to_rust_string_lossy(scope);
 
+    if let Err(e) = permissions::check_env(&key) {
+        throw_permission_denied(scope, &e);
+        return;
+    }
+
     // TODO: Audit that the environment access only happens in single-threaded code.
     unsafe { std::env::set_var(&key, &value) };
 
@@ -87,6 +102,10 @@ fn unset_env_var(
     let key = args.get(0);
     let key = key.to_string(scope).unwrap();
     let key = key.to_rust_string_lossy(scope);
+    if let Err(e) = permissions::check_env(&key) {
+        throw_permission_denied(scope, &e);
+        return;
+    }
     // TODO: Audit that the environment access only happens in single-threaded code.
     unsafe { std::env::remove_var(&key) };
     ret.set_undefined()
@@ -99,7 +118,7 @@ fn get_env_vars(
 ) {
     let result = v8::Array::new(scope, 0);
     let mut index = 0;
-    for (k, v) in std::env::vars() {
+    for (k, v) in std::env::vars().filter(|(k, _)| permissions::check_env(k).is_ok()) {
         let key = v8::String::new(scope, &k).unwrap();
         let val = v8::String::new(scope, &v).unwrap();
         result.set_index(scope, index, key.into()).unwrap();
@@ -1,3 +1,6 @@
+// Only the APIs the module imports are created
+const host_api = __moonrun_host_api;
+delete globalThis.__moonrun_host_api;
 const tag = new WebAssembly.Tag({ parameters: [] });
 const console = {
     elog: (x) => console_elog(x),
@@ -34,7 +37,25 @@ try {
         bytes = read_file_to_bytes(module_name);
     }
     let module = new WebAssembly.Module(bytes, { builtins: ['js-string'], importedStringConstants: "_" });
+    const imports = WebAssembly.Module.imports(module);
+    const imported = (name) => imports.some((i) => i.module === name);
+    let wasi = undefined;
+    if (imported("wasi_snapshot_preview1")) {
+        wasi = __moonrun_create_wasi(host_api("wasi"));
+        spectest.wasi_snapshot_preview1 = wasi.imports;
+    }
+    if (imported("__moonbit_net_unstable")) {
+        spectest.__moonbit_net_unstable = host_api("net");
+    }
     let instance = new WebAssembly.Instance(module, spectest);
+    if (wasi !== undefined) {
+        wasi.setMemory(instance.exports.memory);
+    }
+    if (break_on_start) {
+        // The module is compiled by now, so breakpoints can be set in its
+        // sources before it starts
+        debugger;
+    }
     if (test_mode) {
         for (param of testParams) {
             try {
@@ -0,0 +1,404 @@
+// Node.js compatibility for the output of the JS backend: the globals and
+// builtin modules used by generated code and the test driver, and a
+// CommonJS loader. `main_module` is the absolute path of the program.
+(() => {
+    const host_api = __moonrun_host_api;
+    delete globalThis.__moonrun_host_api;
+    const wasi = host_api("wasi");
+    const sys = __moonbit_fs_unstable;
+    const native = __moonrun_node;
+
+    const ERRNO_CODES = {
+        2: "EACCES",
+        8: "EBADF",
+        20: "EEXIST",
+        28: "EINVAL",
+        29: "EIO",
+        31: "EISDIR",
+        44: "ENOENT",
+        54: "ENOTDIR",
+        55: "ENOTEMPTY",
+        76: "EACCES",
+    };
+    const FILETYPE_DIRECTORY = 3;
+    const FILETYPE_REGULAR_FILE = 4;
+    const FILETYPE_SYMBOLIC_LINK = 7;
+    const OFLAGS_CREAT = 1;
+    const OFLAGS_TRUNC = 8;
+    const READ_CHUNK = 65536;
+
+    const encode = (s) => wasi.encode_utf8(String(s));
+    const decode = (bytes) => wasi.decode_utf8(bytes);
+
+    // Natives return a negative errno on failure
+    function check(result, syscall, path) {
+        if (typeof result === "number" && result < 0) {
+            const code = ERRNO_CODES[-result] || "EIO";
+            const err = new Error(`${code}: ${syscall} '${path}'`);
+            err.code = code;
+            err.errno = result;
+            err.syscall = syscall;
+            err.path = path;
+            throw err;
+        }
+        return result;
+    }
+
+    function now() {
+        const [secs, nanos] = wasi.clock_time_get(1);
+        return secs * 1000 + nanos / 1e6;
+    }
+    const timeOrigin = now();
+
+    // util
+
+    function inspect(value, nested = false) {
+        switch (typeof value) {
+            case "string":
+                return nested ? JSON.stringify(value) : value;
+            case "bigint":
+                return `${value}n`;
+            case "function":
+                return `[Function: ${value.name || "(anonymous)"}]`;
+            case "object":
+                if (value === null) return "null";
+                if (value instanceof Error) return value.stack || String(value);
+                if (Array.isArray(value)) {
+                    return `[ ${value.map((v) => inspect(v, true)).join(", ")} ]`;
+                }
+                try {
+                    const entries = Object.entries(value)
+                        .map(([k, v]) => `${k}: ${inspect(v, true)}`);
+                    return entries.length ? `{ ${entries.join(", ")} }` : "{}";
+                } catch {
+                    return String(value);
+                }
+            default:
+                return String(value);
+        }
+    }
+    const format = (...args) => args.map((a) => inspect(a)).join(" ");
+
+    class TextEncoder {
+        get encoding() { return "utf-8"; }
+        encode(s = "") { return encode(s); }
+    }
+
+    class TextDecoder {
+        get encoding() { return "utf-8"; }
+        decode(bytes) {
+            if (bytes === undefined) return "";
+            if (bytes instanceof ArrayBuffer) return decode(new Uint8Array(bytes));
+            return decode(new Uint8Array(bytes.buffer, bytes.byteOffset, bytes.byteLength));
+        }
+    }
+
+    const util = { inspect, format, TextEncoder, TextDecoder };
+
+    // path
+
+    const isAbsolute = (p) => p.startsWith("/") || /^[A-Za-z]:[\\/]/.test(p);
+
+    function normalize(p) {
+        const root = isAbsolute(p) ? p.slice(0, p.indexOf("/") + 1 || p.indexOf("\\") + 1) : "";
+        const parts = [];
+        for (const part of p.slice(root.length).split(/[\\/]/)) {
+            if (part === "" || part === ".") continue;
+            if (part === ".." && parts.length && parts[parts.length - 1] !== "..") {
+                parts.pop();
+            } else if (part !== ".." || !root) {
+                parts.push(part);
+            }
+        }
+        return root + parts.join("/") || ".";
+    }
+
+    const path = {
+        sep: native.platform === "win32" ? "\\" : "/",
+        isAbsolute,
+        normalize,
+        join: (...parts) => normalize(parts.filter((p) => p !== "").join("/")),
+        resolve: (...parts) => {
+            let result = sys.current_dir();
+            for (const p of parts) {
+                result = isAbsolute(p) ? p : `${result}/${p}`;
+            }
+            return normalize(result);
+        },
+        dirname: (p) => {
+            const i = Math.max(p.lastIndexOf("/"), p.lastIndexOf("\\"));
+            return i < 0 ? "." : i === 0 ? p[0] : p.slice(0, i);
+        },
+        basename: (p, ext) => {
+            const base = p.slice(Math.max(p.lastIndexOf("/"), p.lastIndexOf("\\")) + 1);
+            return ext && base.endsWith(ext) ? base.slice(0, -ext.length) : base;
+        },
+        extname: (p) => {
+            const base = path.basename(p);
+            const i = base.lastIndexOf(".");
+            return i <= 0 ? "" : base.slice(i);
+        },
+    };
+
+    // fs
+
+    const encodingOf = (options) =>
+        typeof options === "string" ? options : options && options.encoding;
+
+    function readAll(handle, path) {
+        const chunks = [];
+        let total = 0;
+        for (;;) {
+            const chunk = check(wasi.read(handle, READ_CHUNK), "read", path);
+            if (chunk.length === 0) break;
+            chunks.push(chunk);
+            total += chunk.length;
+        }
+        const result = new Uint8Array(total);
+        let offset = 0;
+        for (const chunk of chunks) {
+            result.set(chunk, offset);
+            offset += chunk.length;
+        }
+        return result;
+    }
+
+    function writeFile(file, data, append) {
+        const oflags = OFLAGS_CREAT | (append ? 0 : OFLAGS_TRUNC);
+        const handle = check(wasi.open(String(file), oflags, false, true, append), "open", file);
+        try {
+            const bytes = typeof data === "string" ? encode(data) : new Uint8Array(data);
+            check(wasi.write(handle, bytes), "write", file);
+        } finally {
+            wasi.close(handle);
+        }
+    }
+
+    function statOf(file, follow, syscall) {
+        const [filetype, size, secs, nanos] = check(wasi.stat(String(file), follow), syscall, file);
+        const mtimeMs = secs * 1000 + nanos / 1e6;
+        return {
+            size,
+            mtimeMs,
+            mtime: new Date(mtimeMs),
+            isFile: () => filetype === FILETYPE_REGULAR_FILE,
+            isDirectory: () => filetype === FILETYPE_DIRECTORY,
+            isSymbolicLink: () => filetype === FILETYPE_SYMBOLIC_LINK,
+        };
+    }
+
+    const fs = {
+        readFileSync(file, options) {
+            const handle = check(wasi.open(String(file), 0, true, false, false), "open", file);
+            let bytes;
+            try {
+                bytes = readAll(handle, file);
+            } finally {
+                wasi.close(handle);
+            }
+            return encodingOf(options) ? decode(bytes) : bytes;
+        },
+        writeFileSync: (file, data) => writeFile(file, data, false),
+        appendFileSync: (file, data) => writeFile(file, data, true),
+        existsSync: (file) => Array.isArray(wasi.stat(String(file), true)),
+        statSync: (file) => statOf(file, true, "stat"),
+        lstatSync: (file) => statOf(file, false, "lstat"),
+        readdirSync(dir) {
+            return check(wasi.read_dir(String(dir)), "scandir", dir).map(([name]) => name);
+        },
+        mkdirSync(dir, options) {
+            if (options && options.recursive) {
+                const parent = path.dirname(String(dir));
+                if (parent !== dir && !fs.existsSync(parent)) {
+                    fs.mkdirSync(parent, options);
+                }
+                if (fs.existsSync(dir) && fs.statSync(dir).isDirectory()) return;
+            }
+            check(wasi.mkdir(String(dir)), "mkdir", dir);
+        },
+        rmdirSync: (dir) => check(wasi.rmdir(String(dir)), "rmdir", dir),
+        unlinkSync: (file) => check(wasi.unlink(String(file)), "unlink", file),
+        rmSync(target, options = {}) {
+            if (options.force && !fs.existsSync(target)) return;
+            if (fs.lstatSync(target).isDirectory()) {
+                if (!options.recursive) {
+                    const err = new Error(`EISDIR: rm '${target}' is a directory`);
+                    err.code = "EISDIR";
+                    throw err;
+                }
+                for (const name of fs.readdirSync(target)) {
+                    fs.rmSync(`${target}/${name}`, options);
+                }
+                fs.rmdirSync(target);
+            } else {
+                fs.unlinkSync(target);
+            }
+        },
+    };
+
+    // process
+
+    const env = {};
+    const vars = sys.get_env_vars();
+    for (let i = 0; i + 1 < vars.length; i += 2) {
+        env[vars[i]] = vars[i + 1];
+    }
+
+    const stream = (fd) => ({
+        isTTY: false,
+        write(chunk) {
+            wasi.write_stdio(fd, typeof chunk === "string" ? encode(chunk) : chunk);
+            return true;
+        },
+    });
+
+    const process = {
+        argv: ["moonrun", ...sys.args_get()],
+        env,
+        platform: native.platform,
+        exitCode: undefined,
+        stdout: stream(1),
+        stderr: stream(2),
+        cwd: () => sys.current_dir(),
+        exit(code) {
+            __moonbit_sys_unstable.exit(code ?? process.exitCode ?? 0);
+        },
+        hrtime: Object.assign(
+            (previous) => {
+                const [secs, nanos] = wasi.clock_time_get(1);
+                if (!previous) return [secs, nanos];
+                const diff = (secs - previous[0]) * 1e9 + nanos - previous[1];
+                return [Math.floor(diff / 1e9), diff % 1e9];
+            },
+            {
+                bigint: () => {
+                    const [secs, nanos] = wasi.clock_time_get(1);
+                    return BigInt(secs) * 1000000000n + BigInt(nanos);
+                },
+            },
+        ),
+        nextTick: (callback, ...args) => queueMicrotask(() => callback(...args)),
+        on: () => process,
+        once: () => process,
+    };
+
+    function reportUncaught(e) {
+        process.stderr.write(`Uncaught ${inspect(e)}\n`);
+        process.exit(1);
+    }
+
+    // timers
+
+    const timers = new Map();
+    let lastTimerId = 0;
+
+    function addTimer(callback, delay, args, repeat) {
+        const id = ++lastTimerId;
+        delay = Math.max(1, Number(delay) || 0);
+        timers.set(id, { callback, args, delay, due: now() + delay, repeat });
+        return id;
+    }
+    const clearTimer = (id) => { timers.delete(id); };
+
+    globalThis.__moonrun_event_loop = {
+        // Milliseconds until the next timer, or -1 if there is none
+        next_delay() {
+            let due = Infinity;
+            for (const timer of timers.values()) {
+                due = Math.min(due, timer.due);
+            }
+            return due === Infinity ? -1 : Math.max(0, due - now());
+        },
+        run_next() {
+            let next;
+            for (const entry of timers) {
+                if (!next || entry[1].due < next[1].due) next = entry;
+            }
+            const [id, timer] = next;
+            if (timer.repeat) {
+                timer.due = now() + timer.delay;
+            } else {
+                timers.delete(id);
+            }
+            try {
+                timer.callback(...timer.args);
+            } catch (e) {
+                reportUncaught(e);
+            }
+        },
+        exit_code: () => process.exitCode ?? 0,
+    };
+
+    // globals
+
+    const write = (out) => (...args) => out.write(`${format(...args)}\n`);
+    Object.assign(globalThis, {
+        global: globalThis,
+        process,
+        console: {
+            log: write(process.stdout),
+            info: write(process.stdout),
+            debug: write(process.stdout),
+            error: write(process.stderr),
+            warn: write(process.stderr),
+        },
+        TextEncoder,
+        TextDecoder,
+        performance: { now: () => now() - timeOrigin, timeOrigin },
+        setTimeout: (callback, delay, ...args) => addTimer(callback, delay, args, false),
+        setInterval: (callback, delay, ...args) => addTimer(callback, delay, args, true),
+        setImmediate: (callback, ...args) => addTimer(callback, 0, args, false),
+        clearTimeout: clearTimer,
+        clearInterval: clearTimer,
+        clearImmediate: clearTimer,
+        queueMicrotask: (callback) => { Promise.resolve().then(callback); },
+    });
+    // Networking is only set up for programs that use it
+    let net;
+    Object.defineProperty(globalThis, "__moonbit_net_unstable", {
+        get: () => (net ??= host_api("net")),
+        configurable: true,
+    });
+
+    // modules
+
+    const builtins = { fs, path, process, util };
+    const cache = new Map();
+
+    function load(filename, isMain = false) {
+        const cached = cache.get(filename);
+        if (cached) return cached.exports;
+        for (const candidate of [filename, `${filename}.js`, `${filename}.cjs`, `${filename}/index.js`]) {
+            const wrapper = native.load_module(candidate, isMain);
+            if (wrapper === undefined) continue;
+            const module = { id: candidate, filename: candidate, exports: {}, loaded: false };
+            cache.set(filename, module);
+            const dirname = path.dirname(candidate);
+            wrapper.call(module.exports, module.exports, makeRequire(dirname), module, candidate, dirname);
+            module.loaded = true;
+            return module.exports;
+        }
+        const err = new Error(`Cannot find module '${filename}'`);
+        err.code = "MODULE_NOT_FOUND";
+        throw err;
+    }
+
+    function makeRequire(dirname) {
+        return function require(specifier) {
+            const name = specifier.startsWith("node:") ? specifier.slice(5) : specifier;
+            if (Object.hasOwn(builtins, name)) return builtins[name];
+            if (!isAbsolute(specifier) && !specifier.startsWith("./") && !specifier.startsWith("../")) {
+                const err = new Error(`Cannot find module '${specifier}': only relative paths and ${Object.keys(builtins).join(", ")} can be required`);
+                err.code = "MODULE_NOT_FOUND";
+                throw err;
+            }
+            return load(isAbsolute(specifier) ? normalize(specifier) : path.join(dirname, specifier));
+        };
+    }
+
+    try {
+        load(main_module, true);
+    } catch (e) {
+        reportUncaught(e);
+    }
+})();
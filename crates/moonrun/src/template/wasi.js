@@ -0,0 +1,340 @@
+// Implementation of `wasi_snapshot_preview1` on top of the primitives exposed
+// by moonrun in `host`. File system access is limited to the directories
+// given with `--dir`.
+function __moonrun_create_wasi(host) {
+    const ERRNO_SUCCESS = 0;
+    const ERRNO_BADF = 8;
+    const ERRNO_EXIST = 20;
+    const ERRNO_INVAL = 28;
+    const ERRNO_NOENT = 44;
+    const ERRNO_NOSYS = 52;
+    const ERRNO_NOTDIR = 54;
+    const ERRNO_NOTCAPABLE = 76;
+
+    const FILETYPE_CHARACTER_DEVICE = 2;
+    const FILETYPE_DIRECTORY = 3;
+    const FILETYPE_REGULAR_FILE = 4;
+
+    const OFLAGS_DIRECTORY = 2;
+    const OFLAGS_EXCL = 4;
+    const FDFLAGS_APPEND = 1;
+    const LOOKUPFLAGS_SYMLINK_FOLLOW = 1;
+    const RIGHTS_FD_READ = 1n << 1n;
+    const RIGHTS_FD_WRITE = 1n << 6n;
+    const RIGHTS_ALL = (1n << 30n) - 1n;
+
+    let memory = undefined;
+    const view = () => new DataView(memory.buffer);
+    const bytes = () => new Uint8Array(memory.buffer);
+
+    // File descriptor table. Directories are tracked by their preopen root
+    // and the path components below it, so that `..` can never escape.
+    const fds = [{ kind: "stdio" }, { kind: "stdio" }, { kind: "stdio" }];
+    for (const [guest, root] of host.preopens()) {
+        fds.push({ kind: "dir", preopen: guest, root: root, components: [] });
+    }
+
+    const allocFd = (entry) => {
+        fds.push(entry);
+        return fds.length - 1;
+    };
+
+    const readString = (ptr, len) => host.decode_utf8(bytes().slice(ptr, ptr + len));
+
+    // Resolve `path` relative to the directory `fd`, returning
+    // `{ root, components, hostPath }` or an errno.
+    const resolve = (fd, ptr, len) => {
+        const dir = fds[fd];
+        if (dir === undefined) return ERRNO_BADF;
+        if (dir.kind !== "dir") return ERRNO_NOTDIR;
+        const path = readString(ptr, len);
+        if (path.startsWith("/")) return ERRNO_NOTCAPABLE;
+        const components = [...dir.components];
+        for (const part of path.split("/")) {
+            if (part === "" || part === ".") continue;
+            if (part === "..") {
+                if (components.length === 0) return ERRNO_NOTCAPABLE;
+                components.pop();
+            } else {
+                components.push(part);
+            }
+        }
+        const hostPath = [dir.root, ...components].join("/");
+        return { root: dir.root, components, hostPath };
+    };
+
+    const hostDirPath = (entry) => [entry.root, ...entry.components].join("/");
+
+    const writeStrings = (strings, ptrs, buf) => {
+        const v = view();
+        const mem = bytes();
+        for (const s of strings) {
+            v.setUint32(ptrs, buf, true);
+            ptrs += 4;
+            const encoded = host.encode_utf8(s);
+            mem.set(encoded, buf);
+            mem[buf + encoded.length] = 0;
+            buf += encoded.length + 1;
+        }
+        return ERRNO_SUCCESS;
+    };
+
+    const writeSizes = (strings, count_ptr, size_ptr) => {
+        const v = view();
+        v.setUint32(count_ptr, strings.length, true);
+        const size = strings.reduce((acc, s) => acc + host.encode_utf8(s).length + 1, 0);
+        v.setUint32(size_ptr, size, true);
+        return ERRNO_SUCCESS;
+    };
+
+    const environ = () => {
+        const vars = __moonbit_fs_unstable.get_env_vars();
+        const result = [];
+        for (let i = 0; i < vars.length; i += 2) {
+            result.push(`${vars[i]}=${vars[i + 1]}`);
+        }
+        return result;
+    };
+
+    const writeFilestat = (ptr, stat) => {
+        const v = view();
+        const [filetype, size, secs, nanos] = stat;
+        const time = BigInt(secs) * 1000000000n + BigInt(nanos);
+        v.setBigUint64(ptr, 0n, true); // dev
+        v.setBigUint64(ptr + 8, 0n, true); // ino
+        v.setUint8(ptr + 16, filetype);
+        v.setBigUint64(ptr + 24, 1n, true); // nlink
+        v.setBigUint64(ptr + 32, BigInt(size), true);
+        v.setBigUint64(ptr + 40, time, true); // atim
+        v.setBigUint64(ptr + 48, time, true); // mtim
+        v.setBigUint64(ptr + 56, time, true); // ctim
+        return ERRNO_SUCCESS;
+    };
+
+    const iovecs = (iovs, iovs_len) => {
+        const v = view();
+        const result = [];
+        for (let i = 0; i < iovs_len; i++) {
+            result.push([v.getUint32(iovs + i * 8, true), v.getUint32(iovs + i * 8 + 4, true)]);
+        }
+        return result;
+    };
+
+    const pathOp = (op) => (fd, path_ptr, path_len) => {
+        const resolved = resolve(fd, path_ptr, path_len);
+        if (typeof resolved === "number") return resolved;
+        const result = op(resolved.hostPath);
+        return result < 0 ? -result : ERRNO_SUCCESS;
+    };
+
+    const functions = {
+        args_get: (argv, argv_buf) =>
+            writeStrings(__moonbit_fs_unstable.args_get(), argv, argv_buf),
+        args_sizes_get: (argc_ptr, size_ptr) =>
+            writeSizes(__moonbit_fs_unstable.args_get(), argc_ptr, size_ptr),
+        environ_get: (environ_ptr, environ_buf) =>
+            writeStrings(environ(), environ_ptr, environ_buf),
+        environ_sizes_get: (count_ptr, size_ptr) => writeSizes(environ(), count_ptr, size_ptr),
+
+        clock_res_get: (id, ptr) => {
+            if (id < 0 || id > 3) return ERRNO_INVAL;
+            view().setBigUint64(ptr, 1000n, true);
+            return ERRNO_SUCCESS;
+        },
+        clock_time_get: (id, _precision, ptr) => {
+            if (id < 0 || id > 3) return ERRNO_INVAL;
+            const [secs, nanos] = host.clock_time_get(id);
+            view().setBigUint64(ptr, BigInt(secs) * 1000000000n + BigInt(nanos), true);
+            return ERRNO_SUCCESS;
+        },
+        random_get: (buf, len) => {
+            bytes().set(host.random_bytes(len), buf);
+            return ERRNO_SUCCESS;
+        },
+        proc_exit: (code) => __moonbit_sys_unstable.exit(code),
+        sched_yield: () => ERRNO_SUCCESS,
+
+        fd_write: (fd, iovs, iovs_len, nwritten_ptr) => {
+            const entry = fds[fd];
+            if (entry === undefined || entry.kind === "dir" || fd === 0) return ERRNO_BADF;
+            const chunks = iovecs(iovs, iovs_len);
+            const total = chunks.reduce((acc, [, len]) => acc + len, 0);
+            const data = new Uint8Array(total);
+            const mem = bytes();
+            let offset = 0;
+            for (const [ptr, len] of chunks) {
+                data.set(mem.subarray(ptr, ptr + len), offset);
+                offset += len;
+            }
+            const written =
+                entry.kind === "stdio" ? host.write_stdio(fd, data) : host.write(entry.handle, data);
+            if (written < 0) return -written;
+            view().setUint32(nwritten_ptr, written, true);
+            return ERRNO_SUCCESS;
+        },
+        fd_read: (fd, iovs, iovs_len, nread_ptr) => {
+            const entry = fds[fd];
+            if (entry === undefined || entry.kind === "dir" || fd === 1 || fd === 2) {
+                return ERRNO_BADF;
+            }
+            const chunks = iovecs(iovs, iovs_len);
+            const total = chunks.reduce((acc, [, len]) => acc + len, 0);
+            const data = entry.kind === "stdio" ? host.read_stdin(total) : host.read(entry.handle, total);
+            if (typeof data === "number") return -data;
+            const mem = bytes();
+            let offset = 0;
+            for (const [ptr, len] of chunks) {
+                if (offset >= data.length) break;
+                const chunk = data.subarray(offset, offset + len);
+                mem.set(chunk, ptr);
+                offset += chunk.length;
+            }
+            view().setUint32(nread_ptr, data.length, true);
+            return ERRNO_SUCCESS;
+        },
+        fd_seek: (fd, offset, whence, newoffset_ptr) => {
+            const entry = fds[fd];
+            if (entry === undefined || entry.kind !== "file") return ERRNO_BADF;
+            const pos = host.seek(entry.handle, Number(offset), whence);
+            if (pos < 0) return -pos;
+            view().setBigUint64(newoffset_ptr, BigInt(pos), true);
+            return ERRNO_SUCCESS;
+        },
+        fd_tell: (fd, offset_ptr) => {
+            const entry = fds[fd];
+            if (entry === undefined || entry.kind !== "file") return ERRNO_BADF;
+            const pos = host.seek(entry.handle, 0, 1);
+            if (pos < 0) return -pos;
+            view().setBigUint64(offset_ptr, BigInt(pos), true);
+            return ERRNO_SUCCESS;
+        },
+        fd_close: (fd) => {
+            const entry = fds[fd];
+            if (entry === undefined) return ERRNO_BADF;
+            if (entry.kind === "file") {
+                const result = host.close(entry.handle);
+                if (result < 0) return -result;
+            }
+            fds[fd] = undefined;
+            return ERRNO_SUCCESS;
+        },
+        fd_fdstat_get: (fd, ptr) => {
+            const entry = fds[fd];
+            if (entry === undefined) return ERRNO_BADF;
+            const filetype = {
+                stdio: FILETYPE_CHARACTER_DEVICE,
+                dir: FILETYPE_DIRECTORY,
+                file: FILETYPE_REGULAR_FILE,
+            }[entry.kind];
+            const v = view();
+            v.setUint8(ptr, filetype);
+            v.setUint16(ptr + 2, entry.append ? FDFLAGS_APPEND : 0, true);
+            v.setBigUint64(ptr + 8, RIGHTS_ALL, true);
+            v.setBigUint64(ptr + 16, RIGHTS_ALL, true);
+            return ERRNO_SUCCESS;
+        },
+        fd_prestat_get: (fd, ptr) => {
+            const entry = fds[fd];
+            if (entry === undefined || entry.preopen === undefined) return ERRNO_BADF;
+            const v = view();
+            v.setUint8(ptr, 0); // directory
+            v.setUint32(ptr + 4, host.encode_utf8(entry.preopen).length, true);
+            return ERRNO_SUCCESS;
+        },
+        fd_prestat_dir_name: (fd, path_ptr, path_len) => {
+            const entry = fds[fd];
+            if (entry === undefined || entry.preopen === undefined) return ERRNO_BADF;
+            const name = host.encode_utf8(entry.preopen);
+            if (name.length > path_len) return ERRNO_INVAL;
+            bytes().set(name, path_ptr);
+            return ERRNO_SUCCESS;
+        },
+        fd_filestat_get: (fd, ptr) => {
+            const entry = fds[fd];
+            if (entry === undefined) return ERRNO_BADF;
+            const stat =
+                entry.kind === "stdio" ? [FILETYPE_CHARACTER_DEVICE, 0, 0, 0]
+                    : entry.kind === "file" ? host.fstat(entry.handle)
+                        : host.stat(hostDirPath(entry), true);
+            if (typeof stat === "number") return -stat;
+            return writeFilestat(ptr, stat);
+        },
+        fd_readdir: (fd, buf, buf_len, cookie, bufused_ptr) => {
+            const entry = fds[fd];
+            if (entry === undefined) return ERRNO_BADF;
+            if (entry.kind !== "dir") return ERRNO_NOTDIR;
+            const entries = host.read_dir(hostDirPath(entry));
+            if (typeof entries === "number") return -entries;
+            // Entries are written contiguously; a truncated last entry tells
+            // the caller to come back with a larger buffer.
+            const out = new Uint8Array(buf_len);
+            let used = 0;
+            for (let i = Number(cookie); i < entries.length && used < buf_len; i++) {
+                const [name, filetype] = entries[i];
+                const encoded = host.encode_utf8(name);
+                const dirent = new Uint8Array(24 + encoded.length);
+                const dv = new DataView(dirent.buffer);
+                dv.setBigUint64(0, BigInt(i + 1), true); // d_next
+                dv.setBigUint64(8, 0n, true); // d_ino
+                dv.setUint32(16, encoded.length, true);
+                dv.setUint8(20, filetype);
+                dirent.set(encoded, 24);
+                const n = Math.min(dirent.length, buf_len - used);
+                out.set(dirent.subarray(0, n), used);
+                used += n;
+            }
+            bytes().set(out.subarray(0, used), buf);
+            view().setUint32(bufused_ptr, used, true);
+            return ERRNO_SUCCESS;
+        },
+        path_filestat_get: (fd, flags, path_ptr, path_len, ptr) => {
+            const resolved = resolve(fd, path_ptr, path_len);
+            if (typeof resolved === "number") return resolved;
+            const stat = host.stat(resolved.hostPath, (flags & LOOKUPFLAGS_SYMLINK_FOLLOW) !== 0);
+            if (typeof stat === "number") return -stat;
+            return writeFilestat(ptr, stat);
+        },
+        path_open: (fd, _dirflags, path_ptr, path_len, oflags, rights_base, _rights_inheriting, fdflags, opened_fd_ptr) => {
+            const resolved = resolve(fd, path_ptr, path_len);
+            if (typeof resolved === "number") return resolved;
+            const stat = host.stat(resolved.hostPath, true);
+            const isDir = typeof stat !== "number" && stat[0] === FILETYPE_DIRECTORY;
+            let opened;
+            if (isDir) {
+                if (oflags & OFLAGS_EXCL) return ERRNO_EXIST;
+                opened = allocFd({
+                    kind: "dir",
+                    root: resolved.root,
+                    components: resolved.components,
+                });
+            } else if (oflags & OFLAGS_DIRECTORY) {
+                return typeof stat === "number" ? ERRNO_NOENT : ERRNO_NOTDIR;
+            } else {
+                const read = (BigInt(rights_base) & RIGHTS_FD_READ) !== 0n;
+                const write = (BigInt(rights_base) & RIGHTS_FD_WRITE) !== 0n;
+                const append = (fdflags & FDFLAGS_APPEND) !== 0;
+                const handle = host.open(resolved.hostPath, oflags & ~OFLAGS_DIRECTORY, read, write, append);
+                if (handle < 0) return -handle;
+                opened = allocFd({ kind: "file", handle, append });
+            }
+            view().setUint32(opened_fd_ptr, opened, true);
+            return ERRNO_SUCCESS;
+        },
+        path_create_directory: pathOp(host.mkdir),
+        path_unlink_file: pathOp(host.unlink),
+        path_remove_directory: pathOp(host.rmdir),
+    };
+
+    // Functions that are not implemented report ENOSYS instead of failing
+    // instantiation.
+    const imports = new Proxy(functions, {
+        get: (target, name) => (name in target ? target[name] : () => ERRNO_NOSYS),
+    });
+
+    return {
+        imports,
+        setMemory: (m) => {
+            memory = m;
+        },
+    };
+}
@@ -0,0 +1,642 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Host side of the `wasi_snapshot_preview1` implementation.
+//!
+//! The WASI functions themselves live in `template/wasi.js`, which handles
+//! the guest memory layout and calls the primitives defined here through the
+//! object created by `__moonrun_host_api("wasi")`, only for modules importing
+//! `wasi_snapshot_preview1`. Errors are returned to JS as negative WASI errno
+//! values.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use once_cell::sync::{Lazy, OnceCell};
+use rand::RngCore;
+
+use crate::{fs_api_temp, permissions, worker};
+
+/// Directories made available to the guest, as `(guest path, host path)`.
+static PREOPENS: OnceCell<Vec<(String, PathBuf)>> = OnceCell::new();
+
+static START: Lazy<Instant> = Lazy::new(Instant::now);
+
+thread_local! {
+    static FILES: RefCell<FileTable> = RefCell::new(FileTable::default());
+}
+
+#[derive(Default)]
+struct FileTable {
+    next: i32,
+    files: HashMap<i32, File>,
+}
+
+// WASI errno values
+const ERRNO_ACCES: i32 = 2;
+const ERRNO_BADF: i32 = 8;
+const ERRNO_EXIST: i32 = 20;
+const ERRNO_INVAL: i32 = 28;
+const ERRNO_IO: i32 = 29;
+const ERRNO_ISDIR: i32 = 31;
+const ERRNO_NOENT: i32 = 44;
+const ERRNO_NOTDIR: i32 = 54;
+const ERRNO_NOTEMPTY: i32 = 55;
+const ERRNO_NOTCAPABLE: i32 = 76;
+
+// WASI filetype values
+const FILETYPE_UNKNOWN: i32 = 0;
+const FILETYPE_DIRECTORY: i32 = 3;
+const FILETYPE_REGULAR_FILE: i32 = 4;
+const FILETYPE_SYMBOLIC_LINK: i32 = 7;
+
+// WASI oflags
+const OFLAGS_CREAT: i32 = 1;
+const OFLAGS_EXCL: i32 = 4;
+const OFLAGS_TRUNC: i32 = 8;
+
+/// Parse a `--dir` argument of the form `<HOST>[::<GUEST>]`.
+pub fn parse_preopen(arg: &str) -> (String, PathBuf) {
+    match arg.split_once("::") {
+        Some((host, guest)) => (guest.to_string(), PathBuf::from(host)),
+        None => (arg.to_string(), PathBuf::from(arg)),
+    }
+}
+
+/// Set the directories available to WASI programs. Must be called at most
+/// once, before running any program.
+pub fn init_preopens(preopens: Vec<(String, PathBuf)>) {
+    PREOPENS
+        .set(preopens)
+        .expect("preopened directories are already initialized");
+}
+
+/// Close the files opened by the programs run so far.
+pub fn close_all() {
+    FILES.take();
+}
+
+fn errno_of(e: &io::Error) -> i32 {
+    match e.kind() {
+        io::ErrorKind::NotFound => ERRNO_NOENT,
+        io::ErrorKind::PermissionDenied => ERRNO_ACCES,
+        io::ErrorKind::AlreadyExists => ERRNO_EXIST,
+        io::ErrorKind::InvalidInput => ERRNO_INVAL,
+        io::ErrorKind::IsADirectory => ERRNO_ISDIR,
+        io::ErrorKind::NotADirectory => ERRNO_NOTDIR,
+        io::ErrorKind::DirectoryNotEmpty => ERRNO_NOTEMPTY,
+        _ => ERRNO_IO,
+    }
+}
+
+fn filetype_of(ft: std::fs::FileType) -> i32 {
+    if ft.is_dir() {
+        FILETYPE_DIRECTORY
+    } else if ft.is_file() {
+        FILETYPE_REGULAR_FILE
+    } else if ft.is_symlink() {
+        FILETYPE_SYMBOLIC_LINK
+    } else {
+        FILETYPE_UNKNOWN
+    }
+}
+
+/// Check a permission, recording the denial so that it can be reported.
+fn check(result: Result<(), String>) -> Result<(), i32> {
+    result.map_err(|e| {
+        fs_api_temp::set_error_message(e);
+        ERRNO_NOTCAPABLE
+    })
+}
+
+/// Check that `path` stays inside a preopened directory once `..` and
+/// symlinks are resolved, so that a symlink inside a preopen cannot reach the
+/// rest of the host. Unless `follow` is set, the last component is not
+/// resolved, for operations that act on a symlink rather than its target.
+fn confine(path: &str, follow: bool) -> Result<(), i32> {
+    let preopens = PREOPENS.get().map(Vec::as_slice).unwrap_or_default();
+    let roots = preopens.iter().map(|(_, host)| host.as_path());
+    if is_confined(Path::new(path), follow, roots) {
+        Ok(())
+    } else {
+        check(Err(format!(
+            "Permission denied: `{path}` is outside of the preopened directories"
+        )))
+    }
+}
+
+fn is_confined<'a>(path: &Path, follow: bool, roots: impl IntoIterator<Item = &'a Path>) -> bool {
+    let resolved = match (follow, path.parent(), path.file_name()) {
+        (false, Some(parent), Some(name)) => permissions::normalize_path(parent).join(name),
+        _ => permissions::normalize_path(path),
+    };
+    roots
+        .into_iter()
+        .any(|root| resolved.starts_with(permissions::normalize_path(root)))
+}
+
+fn string_arg(scope: &mut v8::HandleScope, args: &v8::FunctionCallbackArguments, i: i32) -> String {
+    args.get(i).to_rust_string_lossy(scope)
+}
+
+fn bytes_arg(args: &v8::FunctionCallbackArguments, i: i32) -> Vec<u8> {
+    let array = v8::Local::<v8::Uint8Array>::try_from(args.get(i)).unwrap();
+    let mut buffer = vec![0; array.byte_length()];
+    array.copy_contents(&mut buffer);
+    buffer
+}
+
+fn new_uint8_array<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    bytes: Vec<u8>,
+) -> v8::Local<'s, v8::Uint8Array> {
+    let len = bytes.len();
+    let array_buffer = v8::ArrayBuffer::with_backing_store(
+        scope,
+        &v8::ArrayBuffer::new_backing_store_from_vec(bytes).make_shared(),
+    );
+    v8::Uint8Array::new(scope, array_buffer, 0, len).unwrap()
+}
+
+/// `fn preopens() -> Array[[guest, host]]`
+fn preopens(
+    scope: &mut v8::HandleScope,
+    _args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let preopens = PREOPENS.get().map(Vec::as_slice).unwrap_or_default();
+    let result = v8::Array::new(scope, preopens.len() as i32);
+    for (i, (guest, host)) in preopens.iter().enumerate() {
+        let pair = v8::Array::new(scope, 2);
+        let guest = v8::String::new(scope, guest).unwrap();
+        let host = v8::String::new(scope, &host.to_string_lossy()).unwrap();
+        pair.set_index(scope, 0, guest.into());
+        pair.set_index(scope, 1, host.into());
+        result.set_index(scope, i as u32, pair.into());
+    }
+    ret.set(result.into());
+}
+
+/// `fn open(host_path, oflags, read, write, append) -> handle or -errno`
+///
+/// `read` and `write` come from the rights requested by the guest, and only
+/// the matching permissions are checked. A file opened without either right,
+/// e.g. to only query its metadata, is opened for reading.
+fn open(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let path = string_arg(scope, &args, 0);
+    let oflags = args.get(1).int32_value(scope).unwrap_or(0);
+    let read = args.get(2).boolean_value(scope);
+    let write = args.get(3).boolean_value(scope);
+    let append = args.get(4).boolean_value(scope);
+    let write = write || append || oflags & (OFLAGS_CREAT | OFLAGS_TRUNC) != 0;
+    let read = read || !write;
+
+    let result = (|| {
+        confine(&path, true)?;
+        if read {
+            check(permissions::check_read(&path))?;
+        }
+        if write {
+            check(permissions::check_write(&path))?;
+        }
+        let mut options = OpenOptions::new();
+        options
+            .read(read)
+            .write(write && !append)
+            .append(append)
+            .truncate(oflags & OFLAGS_TRUNC != 0);
+        if oflags & OFLAGS_EXCL != 0 {
+            options.create_new(true);
+        } else if oflags & OFLAGS_CREAT != 0 {
+            options.create(true);
+        }
+        let file = options.open(&path).map_err(|e| errno_of(&e))?;
+        Ok(FILES.with_borrow_mut(|table| {
+            let handle = table.next;
+            table.next += 1;
+            table.files.insert(handle, file);
+            handle
+        }))
+    })();
+    ret.set_int32(result.unwrap_or_else(|errno| -errno));
+}
+
+fn with_file<T>(handle: i32, f: impl FnOnce(&mut File) -> Result<T, i32>) -> Result<T, i32> {
+    FILES.with_borrow_mut(|table| match table.files.get_mut(&handle) {
+        Some(file) => f(file),
+        None => Err(ERRNO_BADF),
+    })
+}
+
+/// `fn read(handle, len) -> Uint8Array or -errno`
+fn read(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let handle = args.get(0).int32_value(scope).unwrap_or(-1);
+    let len = args.get(1).uint32_value(scope).unwrap_or(0) as usize;
+    let result = with_file(handle, |file| {
+        let mut buffer = vec![0; len];
+        let n = file.read(&mut buffer).map_err(|e| errno_of(&e))?;
+        buffer.truncate(n);
+        Ok(buffer)
+    });
+    match result {
+        Ok(bytes) => ret.set(new_uint8_array(scope, bytes).into()),
+        Err(errno) => ret.set_int32(-errno),
+    }
+}
+
+/// `fn write(handle, bytes) -> written or -errno`
+fn write(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let handle = args.get(0).int32_value(scope).unwrap_or(-1);
+    let bytes = bytes_arg(&args, 1);
+    let result = with_file(handle, |file| {
+        file.write_all(&bytes).map_err(|e| errno_of(&e))?;
+        Ok(bytes.len() as i32)
+    });
+    ret.set_int32(result.unwrap_or_else(|errno| -errno));
+}
+
+/// `fn seek(handle, offset, whence) -> new offset or -errno`
+fn seek(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let handle = args.get(0).int32_value(scope).unwrap_or(-1);
+    let offset = args.get(1).number_value(scope).unwrap_or(0.0) as i64;
+    let whence = args.get(2).int32_value(scope).unwrap_or(-1);
+    let result = with_file(handle, |file| {
+        let pos = match whence {
+            0 => SeekFrom::Start(u64::try_from(offset).map_err(|_| ERRNO_INVAL)?),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(ERRNO_INVAL),
+        };
+        file.seek(pos).map_err(|e| errno_of(&e))
+    });
+    match result {
+        Ok(pos) => ret.set_double(pos as f64),
+        Err(errno) => ret.set_int32(-errno),
+    }
+}
+
+/// `fn close(handle) -> 0 or -errno`
+fn close(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let handle = args.get(0).int32_value(scope).unwrap_or(-1);
+    let removed = FILES.with_borrow_mut(|table| table.files.remove(&handle));
+    ret.set_int32(if removed.is_some() { 0 } else { -ERRNO_BADF });
+}
+
+fn set_stat(
+    scope: &mut v8::HandleScope,
+    ret: &mut v8::ReturnValue,
+    metadata: io::Result<std::fs::Metadata>,
+) {
+    match metadata {
+        Ok(metadata) => {
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            let values = [
+                filetype_of(metadata.file_type()) as f64,
+                metadata.len() as f64,
+                mtime.as_secs() as f64,
+                mtime.subsec_nanos() as f64,
+            ];
+            let result = v8::Array::new(scope, values.len() as i32);
+            for (i, v) in values.into_iter().enumerate() {
+                let v = v8::Number::new(scope, v);
+                result.set_index(scope, i as u32, v.into());
+            }
+            ret.set(result.into());
+        }
+        Err(e) => ret.set_int32(-errno_of(&e)),
+    }
+}
+
+/// `fn fstat(handle) -> [filetype, size, mtime_secs, mtime_nanos] or -errno`
+fn fstat(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let handle = args.get(0).int32_value(scope).unwrap_or(-1);
+    match with_file(handle, |file| Ok(file.metadata())) {
+        Ok(metadata) => set_stat(scope, &mut ret, metadata),
+        Err(errno) => ret.set_int32(-errno),
+    }
+}
+
+/// `fn stat(host_path, follow) -> [filetype, size, mtime_secs, mtime_nanos] or -errno`
+fn stat(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let path = string_arg(scope, &args, 0);
+    let follow = args.get(1).boolean_value(scope);
+    let allowed = confine(&path, follow).and_then(|()| check(permissions::check_read(&path)));
+    if let Err(errno) = allowed {
+        ret.set_int32(-errno);
+        return;
+    }
+    let metadata = if follow {
+        std::fs::metadata(&path)
+    } else {
+        std::fs::symlink_metadata(&path)
+    };
+    set_stat(scope, &mut ret, metadata);
+}
+
+/// `fn read_dir(host_path) -> Array[[name, filetype]] or -errno`
+fn read_dir(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let path = string_arg(scope, &args, 0);
+    let result = confine(&path, true).and_then(|()| {
+        check(permissions::check_read(&path))?;
+        let mut entries = vec![];
+        for entry in std::fs::read_dir(&path).map_err(|e| errno_of(&e))? {
+            let entry = entry.map_err(|e| errno_of(&e))?;
+            let filetype = entry.file_type().map_or(FILETYPE_UNKNOWN, filetype_of);
+            entries.push((entry.file_name().to_string_lossy().into_owned(), filetype));
+        }
+        entries.sort();
+        Ok(entries)
+    });
+    match result {
+        Ok(entries) => {
+            let result = v8::Array::new(scope, entries.len() as i32);
+            for (i, (name, filetype)) in entries.iter().enumerate() {
+                let pair = v8::Array::new(scope, 2);
+                let name = v8::String::new(scope, name).unwrap();
+                let filetype = v8::Integer::new(scope, *filetype);
+                pair.set_index(scope, 0, name.into());
+                pair.set_index(scope, 1, filetype.into());
+                result.set_index(scope, i as u32, pair.into());
+            }
+            ret.set(result.into());
+        }
+        Err(errno) => ret.set_int32(-errno),
+    }
+}
+
+fn modify_path(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+    op: fn(&str) -> io::Result<()>,
+) {
+    let path = string_arg(scope, &args, 0);
+    let result = confine(&path, false)
+        .and_then(|()| check(permissions::check_write(&path)))
+        .and_then(|()| op(&path).map_err(|e| errno_of(&e)));
+    ret.set_int32(result.map_or_else(|errno| -errno, |()| 0));
+}
+
+/// `fn mkdir(host_path) -> 0 or -errno`
+fn mkdir(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, ret: v8::ReturnValue) {
+    modify_path(scope, args, ret, |p| std::fs::create_dir(p));
+}
+
+/// `fn unlink(host_path) -> 0 or -errno`
+fn unlink(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, ret: v8::ReturnValue) {
+    modify_path(scope, args, ret, |p| std::fs::remove_file(p));
+}
+
+/// `fn rmdir(host_path) -> 0 or -errno`
+fn rmdir(scope: &mut v8::HandleScope, args: v8::FunctionCallbackArguments, ret: v8::ReturnValue) {
+    modify_path(scope, args, ret, |p| std::fs::remove_dir(p));
+}
+
+/// `fn write_stdio(fd, bytes) -> written or -errno`
+fn write_stdio(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let fd = args.get(0).int32_value(scope).unwrap_or(-1);
+    let bytes = bytes_arg(&args, 1);
+    let result = match fd {
+        1 => {
+            let mut out = io::stdout().lock();
+            out.write_all(&bytes).and_then(|()| out.flush())
+        }
+        2 => io::stderr().lock().write_all(&bytes),
+        _ => {
+            ret.set_int32(-ERRNO_BADF);
+            return;
+        }
+    };
+    ret.set_int32(result.map_or_else(|e| -errno_of(&e), |()| bytes.len() as i32));
+}
+
+/// `fn read_stdin(len) -> Uint8Array or -errno`
+fn read_stdin(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let len = args.get(0).uint32_value(scope).unwrap_or(0) as usize;
+    let mut buffer = vec![0; len];
+    match worker::program_stdin().read(&mut buffer) {
+        Ok(n) => {
+            buffer.truncate(n);
+            ret.set(new_uint8_array(scope, buffer).into());
+        }
+        Err(e) => ret.set_int32(-errno_of(&e)),
+    }
+}
+
+/// `fn clock_time_get(id) -> [secs, nanos]`
+fn clock_time_get(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let id = args.get(0).int32_value(scope).unwrap_or(0);
+    let time = match id {
+        // realtime
+        0 => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default(),
+        // monotonic, process and thread CPU time
+        _ => START.elapsed(),
+    };
+    let result = v8::Array::new(scope, 2);
+    let secs = v8::Number::new(scope, time.as_secs() as f64);
+    let nanos = v8::Number::new(scope, time.subsec_nanos() as f64);
+    result.set_index(scope, 0, secs.into());
+    result.set_index(scope, 1, nanos.into());
+    ret.set(result.into());
+}
+
+/// `fn random_bytes(len) -> Uint8Array`
+fn random_bytes(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let len = args.get(0).uint32_value(scope).unwrap_or(0) as usize;
+    let mut buffer = vec![0; len];
+    rand::thread_rng().fill_bytes(&mut buffer);
+    ret.set(new_uint8_array(scope, buffer).into());
+}
+
+/// `fn encode_utf8(string) -> Uint8Array`
+fn encode_utf8(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let s = string_arg(scope, &args, 0);
+    ret.set(new_uint8_array(scope, s.into_bytes()).into());
+}
+
+/// `fn decode_utf8(Uint8Array) -> string`
+fn decode_utf8(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut ret: v8::ReturnValue,
+) {
+    let bytes = bytes_arg(&args, 0);
+    let s = v8::String::new(scope, &String::from_utf8_lossy(&bytes)).unwrap();
+    ret.set(s.into());
+}
+
+pub fn init_wasi<'s>(
+    obj: v8::Local<'s, v8::Object>,
+    scope: &mut v8::HandleScope<'s>,
+) -> v8::Local<'s, v8::Object> {
+    // Start the monotonic clock
+    Lazy::force(&START);
+
+    set_function(scope, obj, "preopens", preopens);
+    set_function(scope, obj, "open", open);
+    set_function(scope, obj, "read", read);
+    set_function(scope, obj, "write", write);
+    set_function(scope, obj, "seek", seek);
+    set_function(scope, obj, "close", close);
+    set_function(scope, obj, "fstat", fstat);
+    set_function(scope, obj, "stat", stat);
+    set_function(scope, obj, "read_dir", read_dir);
+    set_function(scope, obj, "mkdir", mkdir);
+    set_function(scope, obj, "unlink", unlink);
+    set_function(scope, obj, "rmdir", rmdir);
+    set_function(scope, obj, "write_stdio", write_stdio);
+    set_function(scope, obj, "read_stdin", read_stdin);
+    set_function(scope, obj, "clock_time_get", clock_time_get);
+    set_function(scope, obj, "random_bytes", random_bytes);
+    set_function(scope, obj, "encode_utf8", encode_utf8);
+    set_function(scope, obj, "decode_utf8", decode_utf8);
+    obj
+}
+
+fn set_function<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    obj: v8::Local<'s, v8::Object>,
+    name: &str,
+    f: impl v8::MapFnTo<v8::FunctionCallback>,
+) {
+    let ident = v8::String::new(scope, name).unwrap();
+    let value = v8::Function::builder(f).build(scope).unwrap();
+    obj.set(scope, ident.into(), value.into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_preopen() {
+        assert_eq!(
+            parse_preopen("data"),
+            ("data".to_string(), PathBuf::from("data"))
+        );
+        assert_eq!(
+            parse_preopen("/tmp/out::/out"),
+            ("/out".to_string(), PathBuf::from("/tmp/out"))
+        );
+    }
+
+    #[test]
+    fn test_is_confined() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        let roots = [root.as_path()];
+
+        assert!(is_confined(&root.join("sub/file"), true, roots));
+        assert!(is_confined(&root.join("new"), true, roots));
+        assert!(is_confined(&root.join("sub/../file"), true, roots));
+        assert!(!is_confined(&root.join("../file"), true, roots));
+        assert!(!is_confined(&root.join("sub/../../file"), false, roots));
+        assert!(!is_confined(&root.join(".."), false, roots));
+        assert!(!is_confined(&dir.path().join("root2"), true, roots));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_confined_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(dir.path().join("secret"), "").unwrap();
+        symlink(dir.path(), root.join("escape")).unwrap();
+        symlink(dir.path().join("missing"), root.join("dangling")).unwrap();
+        symlink("inner", root.join("relative")).unwrap();
+        let roots = [root.as_path()];
+
+        assert!(!is_confined(&root.join("escape/secret"), true, roots));
+        assert!(!is_confined(
+            &root.join("escape/root/../secret"),
+            true,
+            roots
+        ));
+        // Following a dangling symlink would create a file outside the root
+        assert!(!is_confined(&root.join("dangling"), true, roots));
+        // The link itself is inside the root, so it can be removed
+        assert!(is_confined(&root.join("escape"), false, roots));
+        assert!(is_confined(&root.join("relative"), true, roots));
+    }
+}
@@ -0,0 +1,352 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Just enough of WebSocket (RFC 6455) to talk to a DevTools frontend.
+
+use std::io::{self, Read, Write};
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// The largest message accepted from a client. DevTools frontends only send
+/// small commands, so anything larger is a broken or hostile peer.
+const MAX_MESSAGE_SIZE: u64 = 16 << 20;
+
+/// The value of `Sec-WebSocket-Accept` for the given `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{ACCEPT_GUID}", key.trim()).as_bytes()))
+}
+
+/// Read the next text message, answering pings on the way. Returns `None`
+/// when the connection is closed.
+pub fn read_message(stream: &mut (impl Read + Write)) -> io::Result<Option<String>> {
+    let mut message = vec![];
+    loop {
+        let mut header = [0u8; 2];
+        match stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0F;
+        let masked = header[1] & 0x80 != 0;
+        let len = match header[1] & 0x7F {
+            126 => {
+                let mut buf = [0u8; 2];
+                stream.read_exact(&mut buf)?;
+                u16::from_be_bytes(buf) as u64
+            }
+            127 => {
+                let mut buf = [0u8; 8];
+                stream.read_exact(&mut buf)?;
+                u64::from_be_bytes(buf)
+            }
+            n => n as u64,
+        };
+        if len > MAX_MESSAGE_SIZE - message.len() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("WebSocket message exceeds {MAX_MESSAGE_SIZE} bytes"),
+            ));
+        }
+        let mut mask = [0u8; 4];
+        if masked {
+            stream.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0u8; len as usize];
+        stream.read_exact(&mut payload)?;
+        if masked {
+            for (i, b) in payload.iter_mut().enumerate() {
+                *b ^= mask[i % 4];
+            }
+        }
+
+        match opcode {
+            OPCODE_TEXT | OPCODE_BINARY | OPCODE_CONTINUATION => {
+                message.extend_from_slice(&payload);
+                if fin {
+                    return String::from_utf8(message)
+                        .map(Some)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+            }
+            OPCODE_PING => write_frame(stream, OPCODE_PONG, &payload)?,
+            OPCODE_PONG => {}
+            OPCODE_CLOSE => {
+                let _ = write_frame(stream, OPCODE_CLOSE, &[]);
+                return Ok(None);
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown WebSocket opcode {opcode}"),
+                ));
+            }
+        }
+    }
+}
+
+/// Send a text message. Messages sent by a server are never masked.
+pub fn write_message(stream: &mut impl Write, message: &str) -> io::Result<()> {
+    write_frame(stream, OPCODE_TEXT, message.as_bytes())
+}
+
+fn write_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        n if n < 126 => frame.push(n as u8),
+        n if n <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        n => {
+            frame.push(127);
+            frame.extend_from_slice(&(n as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in padded.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0u8; 20];
+    for (i, v) in h.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&v.to_be_bytes());
+    }
+    out
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream reading from a fixed input and recording what is written.
+    struct MockStream {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(input: Vec<u8>) -> Self {
+            MockStream {
+                input: io::Cursor::new(input),
+                output: vec![],
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn masked_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![first, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn test_sha1() {
+        // FIPS 180-2 test vectors, plus the lengths around the padding
+        // boundary
+        let cases = [
+            (b"".to_vec(), "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+            (b"abc".to_vec(), "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq".to_vec(),
+                "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+            ),
+            (vec![b'a'; 55], "c1c8bbdc22796e28c0e15163d20899b65621d65a"),
+            (vec![b'a'; 56], "c2db330f6083854c99d4b5bfb6e8f29f201be699"),
+            (vec![b'a'; 64], "0098ba824b5c16427bd7a1122a5a442a25ec644d"),
+            (
+                vec![b'a'; 1_000_000],
+                "34aa973cd4c4daa4f61eeb2bdbad27316534016f",
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(hex(&sha1(&input)), expected, "{} bytes", input.len());
+        }
+    }
+
+    #[test]
+    fn test_base64() {
+        // RFC 4648 test vectors
+        let cases = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(base64(input.as_bytes()), expected);
+        }
+        assert_eq!(base64(&[0xFB, 0xFF, 0xBF]), "+/+/");
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // The example from RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ==\r\n"),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_read_message() {
+        let mut input = masked_frame(0x80 | OPCODE_TEXT, b"hello");
+        // A fragmented message with a ping in between
+        input.extend(masked_frame(OPCODE_TEXT, b"frag"));
+        input.extend(masked_frame(0x80 | OPCODE_PING, b"p"));
+        input.extend(masked_frame(0x80 | OPCODE_CONTINUATION, b"mented"));
+        input.extend(masked_frame(0x80 | OPCODE_CLOSE, b""));
+        let mut stream = MockStream::new(input);
+
+        assert_eq!(read_message(&mut stream).unwrap().as_deref(), Some("hello"));
+        assert_eq!(
+            read_message(&mut stream).unwrap().as_deref(),
+            Some("fragmented")
+        );
+        assert_eq!(stream.output, [0x80 | OPCODE_PONG, 1, b'p']);
+        assert_eq!(read_message(&mut stream).unwrap(), None);
+        assert_eq!(read_message(&mut stream).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_message_too_large() {
+        // The length alone must be rejected, before reading the payload
+        let mut input = vec![0x80 | OPCODE_TEXT, 127];
+        input.extend_from_slice(&u64::MAX.to_be_bytes());
+        let err = read_message(&mut MockStream::new(input)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut input = vec![OPCODE_TEXT, 127];
+        input.extend_from_slice(&MAX_MESSAGE_SIZE.to_be_bytes());
+        input.resize(input.len() + MAX_MESSAGE_SIZE as usize, b'a');
+        input.extend([0x80 | OPCODE_CONTINUATION, 1, b'a']);
+        let err = read_message(&mut MockStream::new(input)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_write_message() {
+        let mut out = vec![];
+        write_message(&mut out, "hi").unwrap();
+        assert_eq!(out, [0x80 | OPCODE_TEXT, 2, b'h', b'i']);
+
+        let mut out = vec![];
+        write_message(&mut out, &"a".repeat(200)).unwrap();
+        assert_eq!(out[..4], [0x80 | OPCODE_TEXT, 126, 0, 200]);
+        assert_eq!(out.len(), 204);
+    }
+}
@@ -0,0 +1,168 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! The `--worker` mode: run many programs in one process, to save the
+//! start-up of V8 when running test executables one after another.
+//!
+//! # Protocol
+//!
+//! Each line on stdin is a JSON request:
+//!
+//! ```json
+//! {"path": "a.wasm", "args": [], "test_args": {"package": "a", "file_and_index": []}}
+//! ```
+//!
+//! `args` and `test_args` may be omitted. The program runs in a fresh
+//! isolate, and prints to stdout and stderr as usual. When it finishes,
+//! stdout is flushed and [`END_MARKER`] is written, followed by a JSON line
+//! with the result, e.g. `{"exit_code":0}`. The worker exits once stdin is
+//! closed.
+//!
+//! Programs calling `exit` only end their own run. A run exceeding
+//! `--max-wall-time` ends with exit code 4, but exceeding `--max-heap` still
+//! exits the worker, which the client has to restart.
+//!
+//! Each run starts from the same environment variables, working directory
+//! and open files as the first one, whatever the previous runs changed.
+//! Programs get an empty stdin, since it carries the requests.
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Context;
+
+use crate::TestArgs;
+
+/// Written to stdout after the output of each run. It can't appear in UTF-8
+/// output.
+pub const END_MARKER: [u8; 4] = [0xFF, 0xFE, 0xFD, 0xFC];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static EXIT_CODE: Cell<Option<i32>> = const { Cell::new(None) };
+}
+
+#[derive(serde::Deserialize)]
+pub struct Request {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub test_args: Option<TestArgs>,
+}
+
+#[derive(serde::Serialize)]
+struct Response {
+    exit_code: i32,
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// End the current run with `code`, leaving the process alive.
+pub fn request_exit(scope: &mut v8::HandleScope, code: i32) {
+    EXIT_CODE.set(Some(code));
+    scope.terminate_execution();
+}
+
+/// The code the current run asked to exit with, if any.
+pub fn take_exit_code() -> Option<i32> {
+    EXIT_CODE.take()
+}
+
+/// The stdin of the program being run.
+pub fn program_stdin() -> Box<dyn Read> {
+    if is_enabled() {
+        Box::new(io::empty())
+    } else {
+        Box::new(io::stdin().lock())
+    }
+}
+
+/// The process-wide state a program can change.
+struct Snapshot {
+    env: HashMap<OsString, OsString>,
+    cwd: Option<PathBuf>,
+}
+
+impl Snapshot {
+    fn take() -> Self {
+        Snapshot {
+            env: std::env::vars_os().collect(),
+            cwd: std::env::current_dir().ok(),
+        }
+    }
+
+    fn restore(&self) {
+        for (key, _) in std::env::vars_os() {
+            if !self.env.contains_key(&key) {
+                // SAFETY: the worker runs one program at a time, on this thread
+                unsafe { std::env::remove_var(&key) };
+            }
+        }
+        for (key, value) in &self.env {
+            if std::env::var_os(key).as_ref() != Some(value) {
+                // SAFETY: as above
+                unsafe { std::env::set_var(key, value) };
+            }
+        }
+        if let Some(cwd) = &self.cwd {
+            let _ = std::env::set_current_dir(cwd);
+        }
+        crate::wasi::close_all();
+        crate::fs_api_temp::reset();
+    }
+}
+
+/// Serve requests from stdin with `run`, which returns the exit code of a
+/// run.
+pub fn serve(mut run: impl FnMut(Request) -> anyhow::Result<i32>) -> anyhow::Result<()> {
+    ENABLED.store(true, Ordering::Relaxed);
+    let snapshot = Snapshot::take();
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request: Request = serde_json_lenient::from_str(&line)
+            .with_context(|| format!("invalid worker request `{line}`"))?;
+        let exit_code = match run(request) {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("error: {e:#}");
+                1
+            }
+        };
+        snapshot.restore();
+
+        let mut stdout = io::stdout().lock();
+        stdout.flush()?;
+        stdout.write_all(&END_MARKER)?;
+        serde_json_lenient::to_writer(&mut stdout, &Response { exit_code })?;
+        stdout.write_all(b"\n")?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
@@ -230,3 +230,581 @@ fn test_moon_run_with_read_bytes_from_stdin() {
     let s = std::str::from_utf8(&out).unwrap().to_string();
     assert!(s.trim() == "0");
 }
+
+/// A minimal DevTools protocol client over a WebSocket connection.
+struct DevToolsClient {
+    stream: std::net::TcpStream,
+}
+
+impl DevToolsClient {
+    fn connect(addr: &str, id: &str) -> Self {
+        use std::io::{BufRead, BufReader, Write};
+
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET /{id} HTTP/1.1\r\nHost: {addr}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        )
+        .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut status = String::new();
+        reader.read_line(&mut status).unwrap();
+        assert!(status.contains("101"), "{status}");
+        let mut accept = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if line
+                .to_ascii_lowercase()
+                .starts_with("sec-websocket-accept")
+            {
+                accept = line;
+            }
+        }
+        assert!(accept.contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="), "{accept}");
+        Self { stream }
+    }
+
+    fn send(&mut self, message: &str) {
+        use std::io::Write;
+
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let payload = message.as_bytes();
+        let mut frame = vec![0x81];
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        self.stream.write_all(&frame).unwrap();
+    }
+
+    fn recv(&mut self) -> String {
+        use std::io::Read;
+
+        let mut header = [0u8; 2];
+        self.stream.read_exact(&mut header).unwrap();
+        let len = match header[1] & 0x7F {
+            126 => {
+                let mut buf = [0u8; 2];
+                self.stream.read_exact(&mut buf).unwrap();
+                u16::from_be_bytes(buf) as usize
+            }
+            127 => {
+                let mut buf = [0u8; 8];
+                self.stream.read_exact(&mut buf).unwrap();
+                u64::from_be_bytes(buf) as usize
+            }
+            n => n as usize,
+        };
+        let mut payload = vec![0u8; len];
+        self.stream.read_exact(&mut payload).unwrap();
+        String::from_utf8(payload).unwrap()
+    }
+
+    fn recv_until(&mut self, needle: &str) -> String {
+        loop {
+            let message = self.recv();
+            if message.contains(needle) {
+                return message;
+            }
+        }
+    }
+}
+
+/// Send a GET request to the inspector server and return the response.
+fn http_get(addr: &str, path: &str) -> String {
+    use std::io::{Read, Write};
+
+    let mut http = std::net::TcpStream::connect(addr).unwrap();
+    write!(http, "GET {path} HTTP/1.1\r\nHost: {addr}\r\n\r\n").unwrap();
+    let mut response = String::new();
+    http.read_to_string(&mut response).unwrap();
+    response
+}
+
+/// Skip the output before `Debugger listening on ws://<addr>/<id>` and
+/// return `<addr>/<id>`. `stderr` must stay open until the child exits, as
+/// it keeps writing to it.
+fn read_debugger_url(stderr: &mut impl std::io::BufRead) -> String {
+    loop {
+        let mut line = String::new();
+        assert!(stderr.read_line(&mut line).unwrap() > 0, "no debugger");
+        if let Some(url) = line.trim().strip_prefix("Debugger listening on ws://") {
+            return url.to_string();
+        }
+    }
+}
+
+#[test]
+fn test_moonrun_inspect_brk() {
+    use std::io::BufReader;
+
+    let dir = TestDir::new("test_cli_args.in");
+
+    snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moon"))
+        .current_dir(&dir)
+        .args(["build", "--debug"])
+        .assert()
+        .success();
+
+    let wasm_file = dir.join("target/wasm-gc/debug/build/main/main.wasm");
+
+    let mut child = std::process::Command::new(snapbox::cmd::cargo_bin("moonrun"))
+        .arg("--inspect-brk=127.0.0.1:0")
+        .arg(&wasm_file)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let url = read_debugger_url(&mut stderr);
+    let (addr, id) = url.split_once('/').unwrap();
+
+    // Target discovery
+    let response = http_get(addr, "/json/list");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains(&format!(r#""webSocketDebuggerUrl":"ws://{url}""#)));
+
+    let mut client = DevToolsClient::connect(addr, id);
+    client.send(r#"{"id":1,"method":"Runtime.enable"}"#);
+    client.recv_until(r#""id":1"#);
+    client.send(r#"{"id":2,"method":"Debugger.enable"}"#);
+    client.recv_until(r#""id":2"#);
+    client.send(r#"{"id":3,"method":"Runtime.runIfWaitingForDebugger"}"#);
+
+    // The module is reported with the source map next to it, which maps it
+    // back to the MoonBit sources
+    let parsed = client.recv_until(r#""scriptLanguage":"WebAssembly""#);
+    let parsed: serde_json_lenient::Value = serde_json_lenient::from_str(&parsed).unwrap();
+    let map_url = parsed["params"]["sourceMapURL"].as_str().unwrap();
+    let map_path = map_url
+        .strip_prefix(&format!("http://{addr}"))
+        .unwrap_or_else(|| panic!("unexpected source map URL: {map_url}"));
+    assert!(map_path.ends_with("main.wasm.map"), "{map_path}");
+    let response = http_get(addr, map_path);
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    let map: serde_json_lenient::Value = serde_json_lenient::from_str(body).unwrap();
+    let map_dir = Path::new(map_path).parent().unwrap();
+    let source = map["sources"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|s| s.as_str())
+        .find(|s| s.ends_with("main/main.mbt"))
+        .unwrap_or_else(|| panic!("main.mbt is not in the source map: {map}"));
+    let source = source.strip_prefix("file://").unwrap_or(source);
+    let source = if Path::new(source).is_absolute() {
+        source.replace('\\', "/")
+    } else {
+        map_dir.join(source).to_string_lossy().into_owned()
+    };
+    let response = http_get(addr, &format!("/{}", source.trim_start_matches('/')));
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert!(response.contains("fn get_args() -> Array[String]"));
+
+    // The program stops before it starts, once the module is compiled
+    let paused = client.recv_until(r#""method":"Debugger.paused""#);
+    assert!(paused.contains(r#""reason":"other""#), "{paused}");
+    client.send(r#"{"id":4,"method":"Debugger.resume"}"#);
+    client.recv_until(r#""id":4"#);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let s = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(s.contains(".wasm"));
+}
+
+#[test]
+fn test_moon_test_debugger() {
+    use std::io::BufReader;
+
+    let dir = TestDir::new("test_cli_args.in");
+    std::fs::write(
+        dir.join("main/main_wbtest.mbt"),
+        "test \"debugged\" {\n  println(get_args().length() > 0)\n}\n",
+    )
+    .unwrap();
+
+    let mut child = std::process::Command::new(snapbox::cmd::cargo_bin("moon"))
+        .current_dir(&dir)
+        .args(["test", "--target", "wasm-gc", "--debugger"])
+        .args([
+            "-p",
+            "username/hello/main",
+            "-f",
+            "main_wbtest.mbt",
+            "-i",
+            "0",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // `moon test` passes `--inspect-brk` to moonrun, which waits for us
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let url = read_debugger_url(&mut stderr);
+    let (addr, id) = url.split_once('/').unwrap();
+    let mut client = DevToolsClient::connect(addr, id);
+    client.send(r#"{"id":1,"method":"Debugger.enable"}"#);
+    client.recv_until(r#""id":1"#);
+    client.send(r#"{"id":2,"method":"Runtime.runIfWaitingForDebugger"}"#);
+    let parsed = client.recv_until(r#""scriptLanguage":"WebAssembly""#);
+    assert!(parsed.contains(r#"wasm.map""#), "{parsed}");
+    client.recv_until(r#""method":"Debugger.paused""#);
+    client.send(r#"{"id":3,"method":"Debugger.resume"}"#);
+    client.recv_until(r#""id":3"#);
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let s = std::str::from_utf8(&output.stdout).unwrap();
+    assert!(s.contains("Total tests: 1, passed: 1, failed: 0."), "{s}");
+}
+
+#[test]
+fn test_moonrun_cpu_prof() {
+    let dir = TestDir::new("test_cpu_prof.in");
+
+    snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moon"))
+        .current_dir(&dir)
+        .args(["build", "--debug"])
+        .assert()
+        .success();
+
+    let wasm_file = dir.join("target/wasm-gc/debug/build/main/main.wasm");
+    let profile = dir.join("main.cpuprofile");
+    let folded = dir.join("main.folded");
+
+    snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moonrun"))
+        .arg("--cpu-prof")
+        .arg(&profile)
+        .arg("--cpu-prof-folded")
+        .arg(&folded)
+        .arg(&wasm_file)
+        .assert()
+        .success()
+        .stdout_eq("2178309\n");
+
+    let profile: serde_json_lenient::Value =
+        serde_json_lenient::from_str(&std::fs::read_to_string(&profile).unwrap()).unwrap();
+    assert!(profile["startTime"].is_number());
+    assert!(profile["endTime"].is_number());
+    // The hot function is mapped back to its MoonBit source through the
+    // source map of the debug build
+    let nodes = profile["nodes"].as_array().unwrap();
+    let fib = nodes
+        .iter()
+        .map(|n| &n["callFrame"])
+        .find(|f| {
+            f["functionName"]
+                .as_str()
+                .is_some_and(|n| n.ends_with("fib"))
+        })
+        .unwrap_or_else(|| panic!("no frame of `fib` in {nodes:?}"));
+    assert!(fib["url"].as_str().unwrap().ends_with("main.mbt"), "{fib}");
+
+    let folded = std::fs::read_to_string(&folded).unwrap();
+    assert!(folded.contains("fib"), "{folded}");
+    for line in folded.lines() {
+        let (_, count) = line.rsplit_once(' ').unwrap();
+        assert!(count.parse::<u64>().unwrap() > 0);
+    }
+}
+
+#[test]
+fn test_moonrun_js_mode() {
+    let dir = TestDir::new("test_js_mode.in");
+
+    let out = snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moonrun"))
+        .arg(dir.join("main.cjs"))
+        .arg("moonrun")
+        .assert()
+        .code(3)
+        .get_output()
+        .stdout
+        .to_owned();
+    check(
+        std::str::from_utf8(&out).unwrap(),
+        expect![[r#"
+            hello, moonrun
+            written true
+            undefined undefined
+            microtask
+            timeout
+        "#]],
+    );
+}
+
+#[test]
+fn test_moonrun_js_mode_sandbox() {
+    let dir = TestDir::new("test_js_mode.in");
+
+    // The program itself is loaded, but not the modules it requires
+    let out = snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moonrun"))
+        .arg("--sandbox")
+        .arg(dir.join("main.cjs"))
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .to_owned();
+    let s = std::str::from_utf8(&out).unwrap();
+    assert!(s.contains("Permission denied: read access to"), "{s}");
+    assert!(s.contains("lib"), "{s}");
+}
+
+/// Run `moonrun --worker` with `requests`, returning the output and exit code
+/// of each run.
+fn run_worker(requests: &[serde_json_lenient::Value]) -> Vec<(String, i64)> {
+    let requests = requests
+        .iter()
+        .map(|r| format!("{r}\n"))
+        .collect::<String>();
+
+    let out = snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moonrun"))
+        .arg("--worker")
+        .stdin(requests)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .to_owned();
+
+    const END_MARKER: &[u8] = &[0xFF, 0xFE, 0xFD, 0xFC];
+    let mut runs = vec![];
+    let mut rest = &out[..];
+    while let Some(pos) = rest.windows(4).position(|w| w == END_MARKER) {
+        let output = std::str::from_utf8(&rest[..pos]).unwrap();
+        let after = &rest[pos + 4..];
+        let end = after.iter().position(|&b| b == b'\n').unwrap();
+        let result: serde_json_lenient::Value =
+            serde_json_lenient::from_slice(&after[..end]).unwrap();
+        runs.push((output.to_string(), result["exit_code"].as_i64().unwrap()));
+        rest = &after[end + 1..];
+    }
+    runs
+}
+
+#[test]
+fn test_moonrun_worker() {
+    let args_dir = TestDir::new("test_cli_args.in");
+    let trace_dir = TestDir::new("test_stack_trace.in");
+    for dir in [&args_dir, &trace_dir] {
+        snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moon"))
+            .current_dir(dir)
+            .arg("build")
+            .assert()
+            .success();
+    }
+    let args_wasm = args_dir.join("target/wasm-gc/release/build/main/main.wasm");
+    let trace_wasm = trace_dir.join("target/wasm-gc/release/build/main/main.wasm");
+
+    let runs = run_worker(&[
+        serde_json_lenient::json!({ "path": args_wasm, "args": ["first"] }),
+        serde_json_lenient::json!({ "path": trace_wasm }),
+        serde_json_lenient::json!({ "path": args_wasm, "args": ["second"] }),
+    ]);
+
+    assert_eq!(runs.len(), 3);
+    assert!(runs[0].0.contains(r#".wasm", "first""#));
+    assert_eq!(runs[0].1, 0);
+    assert_eq!(runs[1].1, 1);
+    assert!(runs[2].0.contains(r#".wasm", "second""#));
+    assert!(!runs[2].0.contains("first"));
+    assert_eq!(runs[2].1, 0);
+}
+
+#[test]
+fn test_moonrun_worker_isolation() {
+    let dir = TestDir::new("test_worker_env.in");
+    snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moon"))
+        .current_dir(&dir)
+        .arg("build")
+        .assert()
+        .success();
+    let wasm = dir.join("target/wasm-gc/release/build/main/main.wasm");
+
+    let runs = run_worker(&[
+        serde_json_lenient::json!({ "path": wasm, "args": ["set"] }),
+        serde_json_lenient::json!({ "path": wasm }),
+        // Reading stdin must not consume the requests that follow
+        serde_json_lenient::json!({ "path": wasm, "args": ["stdin"] }),
+        serde_json_lenient::json!({ "path": wasm }),
+    ]);
+
+    // Programs see the environment as it was when they started, so only the
+    // next run could see the variable set by the first one
+    let outputs: Vec<_> = runs.iter().map(|(output, _)| output.as_str()).collect();
+    assert_eq!(outputs, ["None\n", "None\n", "-1\nNone\n", "None\n"]);
+    assert!(runs.iter().all(|(_, code)| *code == 0));
+}
+
+#[test]
+fn test_moonrun_set_env_var_denied() {
+    let dir = TestDir::new("test_worker_env.in");
+    snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moon"))
+        .current_dir(&dir)
+        .arg("build")
+        .assert()
+        .success();
+    let wasm = dir.join("target/wasm-gc/release/build/main/main.wasm");
+
+    // A denied write aborts the program instead of being silently dropped
+    let out = snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moonrun"))
+        .arg("--sandbox")
+        .arg(&wasm)
+        .args(["--", "set"])
+        .assert()
+        .failure()
+        .get_output()
+        .to_owned();
+    let stderr = std::str::from_utf8(&out.stderr).unwrap();
+    assert!(
+        stderr.contains("Permission denied: access to environment variable `MOONRUN_WORKER_TEST`"),
+        "{stderr}"
+    );
+    assert!(out.stdout.is_empty());
+}
+
+/// Answer one HTTP request on loopback with its method, target, `X-Test`
+/// header and body, in chunks.
+fn serve_one_http_request() -> (u16, std::thread::JoinHandle<()>) {
+    use std::io::{BufRead, Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let (mut content_length, mut x_test) = (0, String::new());
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(": ").unwrap();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse().unwrap(),
+                "x-test" => x_test = value.to_string(),
+                _ => {}
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let mut parts = request_line.split_whitespace();
+        let echo = format!(
+            "{} {} {x_test} {}",
+            parts.next().unwrap(),
+            parts.next().unwrap(),
+            String::from_utf8(body).unwrap()
+        );
+        let (first, second) = echo.split_at(echo.len() / 2);
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{first}\r\n{:x}\r\n{second}\r\n0\r\n\r\n",
+            first.len(),
+            second.len()
+        )
+        .unwrap();
+    });
+    (port, handle)
+}
+
+#[test]
+fn test_moonrun_net() {
+    let dir = TestDir::new("test_net.in");
+    let (port, server) = serve_one_http_request();
+
+    let out = snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moonrun"))
+        .arg("--allow-net=127.0.0.1")
+        .arg(dir.join("main.cjs"))
+        .arg(port.to_string())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .to_owned();
+    server.join().unwrap();
+    check(
+        std::str::from_utf8(&out).unwrap(),
+        expect![[r#"
+            received ping
+            status 200
+            POST /echo?x=1 yes body
+        "#]],
+    );
+
+    // Network access is denied by default
+    let out = snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moonrun"))
+        .arg(dir.join("main.cjs"))
+        .arg("0")
+        .assert()
+        .failure()
+        .get_output()
+        .stderr
+        .to_owned();
+    let s = std::str::from_utf8(&out).unwrap();
+    assert!(s.contains("Permission denied: network access to `127.0.0.1:0`"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_moonrun_wasi_preopen() {
+    let dir = TestDir::new("test_wasi.in");
+    let wasm = wat::parse_file(dir.join("main.wat")).unwrap();
+    std::fs::write(dir.join("main.wasm"), wasm).unwrap();
+    // A symlink inside the preopen must not give access to its parent
+    std::os::unix::fs::symlink(dir.as_ref(), dir.join("root/escape")).unwrap();
+
+    let out = snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moonrun"))
+        .arg(format!("--dir={}", dir.join("root").display()))
+        .arg(dir.join("main.wasm"))
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .to_owned();
+    check(
+        std::str::from_utf8(&out).unwrap(),
+        expect![[r#"
+            hello from a preopen
+            confined
+        "#]],
+    );
+}
+
+#[test]
+fn test_moonrun_wasi_write_only() {
+    let dir = TestDir::new("test_wasi.in");
+    let wasm = wat::parse_file(dir.join("write.wat")).unwrap();
+    std::fs::write(dir.join("write.wasm"), wasm).unwrap();
+    let root = dir.join("root");
+
+    // Opening a file for writing only needs write access
+    snapbox::cmd::Command::new(snapbox::cmd::cargo_bin("moonrun"))
+        .arg("--sandbox")
+        .arg(format!("--allow-write={}", root.display()))
+        .arg(format!("--dir={}", root.display()))
+        .arg(dir.join("write.wasm"))
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(root.join("out.txt")).unwrap(),
+        "written\n"
+    );
+}
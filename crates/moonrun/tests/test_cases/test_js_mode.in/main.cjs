@@ -0,0 +1,15 @@
+const fs = require("node:fs");
+const path = require("path");
+const { greet } = require("./lib");
+
+console.log(greet(process.argv[2]));
+const file = path.join(__dirname, "out.txt");
+fs.writeFileSync(file, "written");
+console.log(fs.readFileSync(file, "utf8"), fs.existsSync(file));
+// moonrun's own host APIs are not exposed to programs
+console.log(typeof __moonrun_host_api, typeof __moonrun_wasi);
+setTimeout(() => {
+  console.log("timeout");
+  process.exitCode = 3;
+}, 10);
+Promise.resolve().then(() => console.log("microtask"));
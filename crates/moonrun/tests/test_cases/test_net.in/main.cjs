@@ -0,0 +1,32 @@
+const net = __moonbit_net_unstable;
+const encode = (s) => Uint8Array.from(s, (c) => c.charCodeAt(0));
+const decode = (bytes) => String.fromCharCode(...bytes);
+
+function check(result) {
+  if (result < 0) {
+    throw new Error(net.get_error_message());
+  }
+  return result;
+}
+
+// A TCP connection over loopback
+const listener = check(net.tcp_listen("127.0.0.1", 0));
+const port = check(net.tcp_local_port(listener));
+const client = check(net.tcp_connect("127.0.0.1", port));
+const server = check(net.tcp_accept(listener));
+check(net.tcp_write(client, encode("ping")));
+check(net.tcp_shutdown(client));
+let received = "";
+while (check(net.tcp_read(server, 2)) > 0) {
+  received += decode(net.get_read_bytes());
+}
+console.log(`received ${received}`);
+for (const handle of [server, client, listener]) {
+  check(net.close(handle));
+}
+
+// An HTTP request to the server of the test
+const url = `http://127.0.0.1:${process.argv[2]}/echo?x=1`;
+const status = check(net.http_request("POST", url, "X-Test: yes\n", encode("body")));
+console.log(`status ${status}`);
+console.log(decode(net.get_response_body()));
@@ -0,0 +1,38 @@
+;; Prints `data.txt` from the first preopened directory, then tries to
+;; open `escape/secret` through a symlink that points out of it.
+(module
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read"
+    (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "data.txt")
+  (data (i32.const 200) "escape/secret")
+  (data (i32.const 300) "confined\n")
+  (data (i32.const 320) "escaped\n")
+
+  ;; Write `len` bytes at `ptr` to stdout
+  (func $print (param $ptr i32) (param $len i32)
+    (i32.store (i32.const 0) (local.get $ptr))
+    (i32.store (i32.const 4) (local.get $len))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+
+  (func (export "_start")
+    ;; fd 3 is the first preopen
+    (if (call $path_open (i32.const 3) (i32.const 1) (i32.const 100) (i32.const 8)
+          (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 16))
+      (then unreachable))
+    (i32.store (i32.const 0) (i32.const 1024))
+    (i32.store (i32.const 4) (i32.const 256))
+    (if (call $fd_read (i32.load (i32.const 16)) (i32.const 0) (i32.const 1) (i32.const 8))
+      (then unreachable))
+    (call $print (i32.const 1024) (i32.load (i32.const 8)))
+
+    (if (i32.eq
+          (call $path_open (i32.const 3) (i32.const 1) (i32.const 200) (i32.const 13)
+            (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 16))
+          (i32.const 76))
+      (then (call $print (i32.const 300) (i32.const 9)))
+      (else (call $print (i32.const 320) (i32.const 8))))))
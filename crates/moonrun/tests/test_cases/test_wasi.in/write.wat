@@ -0,0 +1,20 @@
+;; Creates `out.txt` in the first preopened directory, opened with only the
+;; `fd_write` right, and writes to it.
+(module
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 100) "out.txt")
+  (data (i32.const 200) "written\n")
+
+  (func (export "_start")
+    ;; O_CREAT | O_TRUNC
+    (if (call $path_open (i32.const 3) (i32.const 1) (i32.const 100) (i32.const 7)
+          (i32.const 9) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 16))
+      (then unreachable))
+    (i32.store (i32.const 0) (i32.const 200))
+    (i32.store (i32.const 4) (i32.const 8))
+    (if (call $fd_write (i32.load (i32.const 16)) (i32.const 0) (i32.const 1) (i32.const 8))
+      (then unreachable))))
@@ -25,12 +25,12 @@ use crate::common::TargetBackend;
 /// Represents the environment a build script receives
 #[derive(Serialize, Deserialize)]
 pub struct BuildScriptEnvironment {
-    // pub build: BuildInfo,
+    pub build: BuildInfo,
     pub env: HashMap<String, String>,
     pub paths: Paths,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct BuildInfo {
     // /// The profile we're building with, e.g. `debug`, `release`.
     // pub profile: String,
@@ -40,7 +40,7 @@ pub struct BuildInfo {
     pub target: TargetInfo,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TargetInfo {
     // this is mostly useless now unless we're using the native backends, but
     // this will buy us some wiggle room in the future when very cross-y cross
@@ -67,6 +67,85 @@ pub struct TargetInfo {
     pub triplet: String,
 }
 
+impl BuildInfo {
+    /// The build info for building `kind` on this machine, cross-compiling to
+    /// `triple` if given.
+    pub fn new(kind: TargetBackend, triple: Option<&str>) -> anyhow::Result<Self> {
+        let target = match (kind, triple) {
+            (_, Some(triple)) => TargetInfo::from_triple(kind, triple)?,
+            (TargetBackend::Native | TargetBackend::LLVM, None) => TargetInfo::host(kind),
+            (_, None) => TargetInfo::virtual_target(kind),
+        };
+        Ok(BuildInfo {
+            host: TargetInfo::host(kind),
+            target,
+        })
+    }
+}
+
+impl TargetInfo {
+    /// The machine moon is running on, named by the triple the C toolchains
+    /// use for it.
+    pub fn host(kind: TargetBackend) -> Self {
+        let arch = std::env::consts::ARCH;
+        let triple = match std::env::consts::OS {
+            "macos" => format!("{arch}-apple-darwin"),
+            "windows" if cfg!(target_env = "gnu") => format!("{arch}-pc-windows-gnu"),
+            "windows" => format!("{arch}-pc-windows-msvc"),
+            "linux" if cfg!(target_env = "musl") => format!("{arch}-unknown-linux-musl"),
+            "linux" => format!("{arch}-unknown-linux-gnu"),
+            os => format!("{arch}-unknown-{os}"),
+        };
+        Self::from_triple(kind, &triple).expect("the host triple should be valid")
+    }
+
+    /// The non-native backends, which run on a virtual machine.
+    fn virtual_target(kind: TargetBackend) -> Self {
+        let arch = match kind {
+            TargetBackend::Wasm => "wasm32",
+            TargetBackend::WasmGC => "wasmgc",
+            TargetBackend::Js => "js",
+            TargetBackend::Native => "c",
+            TargetBackend::LLVM => "llvm",
+        };
+        TargetInfo {
+            kind,
+            arch: arch.to_string(),
+            vendor: "unknown".to_string(),
+            os: "unknown".to_string(),
+            abi: None,
+            triplet: format!("{arch}-unknown-unknown"),
+        }
+    }
+
+    /// Parse a target triple like `aarch64-unknown-linux-gnu`. The vendor may
+    /// be omitted as in `aarch64-linux-gnu`.
+    pub fn from_triple(kind: TargetBackend, triple: &str) -> anyhow::Result<Self> {
+        let parts = triple.split('-').collect::<Vec<_>>();
+        let (arch, vendor, os, abi) = match parts.as_slice() {
+            [arch, os] => (*arch, "unknown", *os, None),
+            [arch, os, abi] if *os == "linux" => (*arch, "unknown", *os, Some(*abi)),
+            [arch, vendor, os] => (*arch, *vendor, *os, None),
+            [arch, vendor, os, abi] => (*arch, *vendor, *os, Some(*abi)),
+            _ => anyhow::bail!(
+                "invalid target triple `{triple}`, expected `<arch>-<vendor>-<os>[-<abi>]`"
+            ),
+        };
+        if parts.iter().any(|part| part.is_empty()) {
+            anyhow::bail!("invalid target triple `{triple}`");
+        }
+        let os = if os == "darwin" { "macos" } else { os };
+        Ok(TargetInfo {
+            kind,
+            arch: arch.to_string(),
+            vendor: vendor.to_string(),
+            os: os.to_string(),
+            abi: abi.map(str::to_string),
+            triplet: triple.to_string(),
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Paths {
     /// The directory containing the current module, i.e. the parent directory
@@ -125,3 +204,56 @@ pub struct LinkConfig {
     #[serde(default)]
     pub link_search_paths: Vec<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_triple() {
+        let info = TargetInfo::from_triple(TargetBackend::Native, "aarch64-apple-darwin").unwrap();
+        assert_eq!(
+            (info.arch.as_str(), info.vendor.as_str(), info.os.as_str()),
+            ("aarch64", "apple", "macos")
+        );
+        assert_eq!(info.abi, None);
+        assert_eq!(info.triplet, "aarch64-apple-darwin");
+
+        let info = TargetInfo::from_triple(TargetBackend::Native, "x86_64-linux-musl").unwrap();
+        assert_eq!(
+            (info.vendor.as_str(), info.os.as_str()),
+            ("unknown", "linux")
+        );
+        assert_eq!(info.abi.as_deref(), Some("musl"));
+
+        let info =
+            TargetInfo::from_triple(TargetBackend::Native, "x86_64-pc-windows-msvc").unwrap();
+        assert_eq!((info.vendor.as_str(), info.os.as_str()), ("pc", "windows"));
+        assert_eq!(info.abi.as_deref(), Some("msvc"));
+
+        assert!(TargetInfo::from_triple(TargetBackend::Native, "x86_64").is_err());
+        assert!(TargetInfo::from_triple(TargetBackend::Native, "x86_64--linux").is_err());
+    }
+
+    #[test]
+    fn test_host() {
+        let host = TargetInfo::host(TargetBackend::Native);
+        assert_eq!(host.arch, std::env::consts::ARCH);
+        assert_eq!(host.os, std::env::consts::OS);
+        let expected = if cfg!(target_os = "linux") && cfg!(target_env = "gnu") {
+            Some("unknown-linux-gnu")
+        } else if cfg!(target_os = "macos") {
+            Some("apple-darwin")
+        } else if cfg!(target_os = "windows") && cfg!(target_env = "msvc") {
+            Some("pc-windows-msvc")
+        } else {
+            None
+        };
+        if let Some(expected) = expected {
+            assert_eq!(
+                host.triplet,
+                format!("{}-{expected}", std::env::consts::ARCH)
+            );
+        }
+    }
+}
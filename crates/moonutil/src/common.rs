@@ -16,12 +16,16 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
+use crate::compiler_flags::{CC, Sanitizer};
 use crate::cond_expr::{CompileCondition, OptLevel};
 pub use crate::dirs::check_moon_mod_exists;
 use crate::module::{MoonMod, MoonModJSON};
 use crate::mooncakes::ModuleName;
-use crate::package::{MoonPkg, MoonPkgJSON, Package, VirtualPkg, convert_pkg_json_to_package};
+use crate::package::{
+    MoonPkg, MoonPkgJSON, Package, TestLimits, VirtualPkg, convert_pkg_json_to_package,
+};
 use crate::path::PathComponent;
+use crate::profile::Profile;
 use anyhow::{Context, bail};
 use clap::ValueEnum;
 use fs4::fs_std::FileExt;
@@ -55,6 +59,11 @@ pub const MOON_TEST_DELIMITER_END: &str = "----- END MOON TEST RESULT -----";
 pub const MOON_COVERAGE_DELIMITER_BEGIN: &str = "----- BEGIN MOONBIT COVERAGE -----";
 pub const MOON_COVERAGE_DELIMITER_END: &str = "----- END MOONBIT COVERAGE -----";
 
+/// Delimiters of the report `moonrun` prints to stdout when a program exceeds
+/// one of its resource limits.
+pub const MOONRUN_LIMIT_DELIMITER_BEGIN: &str = "----- BEGIN MOONRUN LIMIT EXCEEDED -----";
+pub const MOONRUN_LIMIT_DELIMITER_END: &str = "----- END MOONRUN LIMIT EXCEEDED -----";
+
 pub const MOON_LOCK: &str = ".moon-lock";
 
 pub const WATCH_MODE_DIR: &str = "watch";
@@ -154,6 +163,12 @@ pub enum MoonModJSONFormatErrorKind {
     Version(#[from] semver::Error),
     #[error("`preferred-backend` is not a valid backend")]
     PreferredBackend(anyhow::Error),
+    #[error("`runners` has an invalid backend")]
+    Runners(anyhow::Error),
+    #[error("`profiles` bad format: {0}")]
+    Profiles(anyhow::Error),
+    #[error("`features` bad format: {0}")]
+    Features(anyhow::Error),
 }
 
 pub fn read_module_from_json(path: &Path) -> Result<MoonMod, MoonModJSONFormatError> {
@@ -496,11 +511,27 @@ impl MoonbuildOpt {
     }
 }
 
+/// How `moon build --explain` prints why build steps are rerun
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default)]
+pub enum ExplainFormat {
+    /// One line of text for each step
+    #[default]
+    Human,
+    /// One JSON object per line for each step
+    Json,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct BuildOpt {
     pub install_path: Option<PathBuf>,
 
     pub filter_package: Option<String>,
+
+    /// Record the time taken by each build step
+    pub timings: bool,
+
+    /// Print why each build step is rerun
+    pub explain: Option<ExplainFormat>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -521,6 +552,21 @@ pub struct TestOpt {
     pub test_failure_json: bool,
     pub display_backend_hint: Option<()>, // use Option to avoid if else
     pub patch_file: Option<PathBuf>,
+    /// Extra arguments passed to `moonrun` when running tests
+    pub moonrun_args: Vec<String>,
+    /// Resource limits from the command line, taking precedence over the
+    /// `test-limits` of each package
+    pub test_limits: TestLimits,
+    /// Whether to record a CPU profile of each test executable
+    pub profile: ProfileFlags,
+    /// Sanitizers the native and llvm test executables are built with
+    pub sanitizers: Vec<Sanitizer>,
+    /// Command to run the native and llvm test executables with, e.g.
+    /// `valgrind`. Empty to run them directly
+    pub runner: Vec<String>,
+    /// The `runners` command of the module for the target backend, replacing
+    /// the default runtime of test executables
+    pub backend_runner: Option<String>,
 }
 
 impl TestOpt {
@@ -535,6 +581,146 @@ impl TestOpt {
     }
 }
 
+/// Permissions of the WASM programs run by `moonrun`. See `moonrun --help`.
+#[derive(Debug, Clone, Default, clap::Parser, Serialize, Deserialize)]
+pub struct SandboxFlags {
+    /// Deny the program file system and environment variable access unless
+    /// granted with `--allow-read`, `--allow-write` or `--allow-env` (wasm and
+    /// wasm-gc backends only)
+    #[clap(long)]
+    pub sandbox: bool,
+
+    /// Allow the sandboxed program to read the given comma-separated paths,
+    /// or any path if none is given. Implies `--sandbox`
+    #[clap(
+        long,
+        value_name = "PATHS",
+        value_delimiter = ',',
+        num_args = 0..,
+        require_equals = true
+    )]
+    pub allow_read: Option<Vec<PathBuf>>,
+
+    /// Allow the sandboxed program to write the given comma-separated paths,
+    /// or any path if none is given. Implies `--sandbox`
+    #[clap(
+        long,
+        value_name = "PATHS",
+        value_delimiter = ',',
+        num_args = 0..,
+        require_equals = true
+    )]
+    pub allow_write: Option<Vec<PathBuf>>,
+
+    /// Allow the sandboxed program to access the given comma-separated
+    /// environment variables, or all of them if none is given. Implies
+    /// `--sandbox`
+    #[clap(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        num_args = 0..,
+        require_equals = true
+    )]
+    pub allow_env: Option<Vec<String>>,
+
+    /// Allow the program network access to the given comma-separated hosts,
+    /// as `HOST` or `HOST:PORT`, or to any host if none is given. Denied by
+    /// default (wasm and wasm-gc backends only)
+    #[clap(
+        long,
+        value_name = "HOSTS",
+        value_delimiter = ',',
+        num_args = 0..,
+        require_equals = true
+    )]
+    pub allow_net: Option<Vec<String>>,
+
+    /// Make a host directory available to WASI programs, as `<HOST>` or
+    /// `<HOST>::<GUEST>`. Can be given multiple times (wasm and wasm-gc
+    /// backends only)
+    #[clap(long = "dir", value_name = "DIR")]
+    pub dirs: Vec<String>,
+}
+
+impl SandboxFlags {
+    /// Whether file system and environment variable access is restricted,
+    /// either with `--sandbox` or by granting some of it explicitly, the same
+    /// way as `moonrun` does.
+    pub fn is_sandboxed(&self) -> bool {
+        self.sandbox
+            || self.allow_read.is_some()
+            || self.allow_write.is_some()
+            || self.allow_env.is_some()
+    }
+
+    /// The arguments to pass to `moonrun` before the program path.
+    pub fn to_moonrun_args(&self) -> Vec<String> {
+        // `--allow-x` alone grants everything
+        fn flag<T: AsRef<OsStr>>(name: &str, items: &[T]) -> String {
+            if items.is_empty() {
+                return name.to_string();
+            }
+            let items: Vec<_> = items.iter().map(|i| i.as_ref().to_string_lossy()).collect();
+            format!("{name}={}", items.join(","))
+        }
+        let mut args = vec![];
+        if self.is_sandboxed() {
+            args.push("--sandbox".to_string());
+            if let Some(paths) = &self.allow_read {
+                args.push(flag("--allow-read", paths));
+            }
+            if let Some(paths) = &self.allow_write {
+                args.push(flag("--allow-write", paths));
+            }
+            if let Some(names) = &self.allow_env {
+                args.push(flag("--allow-env", names));
+            }
+        }
+        if let Some(hosts) = &self.allow_net {
+            args.push(flag("--allow-net", hosts));
+        }
+        args.extend(self.dirs.iter().map(|d| format!("--dir={d}")));
+        args
+    }
+}
+
+/// CPU profiling of the WASM programs run by `moonrun`. See `moonrun --help`.
+#[derive(Debug, Clone, Default, clap::Parser, Serialize, Deserialize)]
+pub struct ProfileFlags {
+    /// Record a CPU profile of each executable run and write it next to it
+    /// as `<name>.cpuprofile`, viewable in Chrome DevTools (wasm and wasm-gc
+    /// backends only)
+    #[clap(long)]
+    pub profile: bool,
+
+    /// Also write the profile as folded stacks (`<name>.folded`) for
+    /// flamegraph tools
+    #[clap(long, requires = "profile")]
+    pub profile_folded: bool,
+}
+
+impl ProfileFlags {
+    /// The arguments to pass to `moonrun` before `executable`.
+    pub fn to_moonrun_args(&self, executable: &Path) -> Vec<String> {
+        if !self.profile {
+            return vec![];
+        }
+        let mut args = vec![
+            "--cpu-prof".to_string(),
+            executable
+                .with_extension("cpuprofile")
+                .display()
+                .to_string(),
+        ];
+        if self.profile_folded {
+            args.push("--cpu-prof-folded".to_string());
+            args.push(executable.with_extension("folded").display().to_string());
+        }
+        args
+    }
+}
+
 #[derive(serde::Serialize, Clone)]
 pub struct TestArtifacts {
     pub artifacts_path: Vec<PathBuf>,
@@ -569,6 +755,15 @@ pub struct MooncOpt {
     pub nostd: bool,
     pub render: bool,
     pub single_file: bool,
+    /// The triple to cross-compile the native backend for, or `None` to build
+    /// for the host
+    pub target_triple: Option<String>,
+    /// The C compiler for `target_triple`, see [`CC::for_target`]
+    pub cross_cc: Option<CC>,
+    /// The named build profile, or `None` for plain debug/release builds
+    pub profile: Option<Profile>,
+    /// The features enabled for each module, keyed by module name
+    pub features: HashMap<String, HashSet<String>>,
 }
 
 impl Default for MooncOpt {
@@ -587,8 +782,17 @@ impl MooncOpt {
             nostd: false,
             render: true,
             single_file: false,
+            target_triple: None,
+            cross_cc: None,
+            profile: None,
+            features: HashMap::new(),
         }
     }
+
+    /// The C compiler for native code, unless a package selects its own
+    pub fn native_cc(&self) -> CC {
+        self.cross_cc.clone().unwrap_or_default()
+    }
 }
 
 pub const DEP_PATH: &str = ".mooncakes";
@@ -1030,12 +1234,20 @@ pub fn set_native_backend_link_flags(
                     let mut native_config = existing_native.cloned().unwrap_or_default();
 
                     let mut stub_lib = Vec::new();
+                    let mut stub_obj = Vec::new();
                     module
                         .get_filtered_packages_and_its_deps_by_pkgname(pkg.full_name().as_str())
                         .unwrap()
                         .iter()
                         .for_each(|(_, pkg)| {
-                            if pkg.stub_lib.is_some() {
+                            if let Some(stubs) = &pkg.stub_lib {
+                                stub_obj.extend(stubs.iter().map(|f| {
+                                    pkg.artifact
+                                        .with_file_name(f)
+                                        .with_extension(O_EXT)
+                                        .display()
+                                        .to_string()
+                                }));
                                 stub_lib.push(
                                     pkg.artifact
                                         .with_file_name(format!("lib{}.{}", pkg.last_name(), A_EXT))
@@ -1057,6 +1269,7 @@ pub fn set_native_backend_link_flags(
 
                     if !stub_lib.is_empty() {
                         native_config.stub_lib_deps = Some(stub_lib);
+                        native_config.stub_obj_deps = Some(stub_obj);
                     }
 
                     link_configs.insert(
@@ -1181,6 +1394,8 @@ pub fn gen_moonbitlang_abort_pkg(moonc_opt: &MooncOpt) -> Package {
         link_flags: None,
         link_libs: vec![],
         link_search_paths: vec![],
+        test_limits: None,
+        js_test_runtime: None,
         module_root: module_root.into(),
     }
 }
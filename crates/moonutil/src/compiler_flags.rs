@@ -29,7 +29,7 @@ use std::{
 const ENV_MOON_CC: &str = "MOON_CC";
 const ENV_MOON_AR: &str = "MOON_AR";
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum CCKind {
     Msvc,     // cl.exe
     SystemCC, // cc
@@ -38,7 +38,7 @@ pub enum CCKind {
     Tcc,      // tcc
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ARKind {
     MsvcLib, // lib.exe
     GnuAr,   // ar
@@ -46,13 +46,14 @@ pub enum ARKind {
     TccAr,   // tcc -ar
 }
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CC {
     pub cc_kind: CCKind,
     pub cc_path: String,
     pub ar_kind: ARKind,
     pub ar_path: String,
-    pub is_env_override: bool, // Whether the cc is set by env MOON_CC
+    pub is_env_override: bool,  // Whether the cc is set by env MOON_CC
+    pub target: Option<String>, // The triple to cross-compile for, if any
 }
 
 impl Default for CC {
@@ -97,6 +98,7 @@ impl CC {
             ar_kind,
             ar_path,
             is_env_override: false,
+            target: None,
         }
     }
 
@@ -200,7 +202,39 @@ impl CC {
     pub fn is_libmoonbitrun_o_available(&self) -> bool {
         // If users set MOON_CC, we believe they know what they are doing
         // And we conservatively disable libmoonbitrun.o
-        CAN_USE_MOONBITRUN && !self.is_msvc() && !self.is_env_override
+        // The prebuilt libmoonbitrun.o is only usable on the host
+        CAN_USE_MOONBITRUN && !self.is_msvc() && !self.is_env_override && self.target.is_none()
+    }
+
+    /// Find a C compiler producing code for `triple`, which is
+    /// - `MOON_CC` (and `MOON_AR`) if set,
+    /// - or `<triple>-gcc` if it is in `PATH`,
+    /// - or `clang`, passing `--target=<triple>` to it.
+    pub fn for_target(triple: &str) -> anyhow::Result<Self> {
+        let cc = if let Some(env_cc) = ENV_CC.as_ref() {
+            env_cc.clone()
+        } else if let Ok(gcc) = which::which(format!("{triple}-gcc")) {
+            CC::try_from_path(&gcc.display().to_string())?
+        } else if let Ok(clang) = which::which("clang") {
+            let mut cc = CC::try_from_cc_path_and_kind("ar", &clang, CCKind::Clang)?;
+            if let Ok(ar) = which::which("llvm-ar") {
+                // llvm-ar understands objects of every target clang emits
+                cc.ar_kind = ARKind::LlvmAr;
+                cc.ar_path = ar.display().to_string();
+            }
+            cc
+        } else {
+            anyhow::bail!(
+                "no C compiler found for target `{triple}`, install `{triple}-gcc` or `clang`, or set `{ENV_MOON_CC}`"
+            );
+        };
+        if cc.is_msvc() || cc.is_tcc() {
+            anyhow::bail!("{} cannot cross-compile to `{triple}`", cc.cc_path);
+        }
+        Ok(CC {
+            target: Some(triple.to_string()),
+            ..cc
+        })
     }
 }
 
@@ -278,6 +312,32 @@ pub enum OptLevel {
     None,
 }
 
+/// Runtime sanitizers that can be compiled into native executables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum Sanitizer {
+    Address,
+    Undefined,
+    Thread,
+}
+
+impl Sanitizer {
+    pub fn name(self) -> &'static str {
+        match self {
+            Sanitizer::Address => "address",
+            Sanitizer::Undefined => "undefined",
+            Sanitizer::Thread => "thread",
+        }
+    }
+}
+
+/// Check that the requested sanitizers can be used together
+pub fn validate_sanitizers(sanitizers: &[Sanitizer]) -> anyhow::Result<()> {
+    if sanitizers.contains(&Sanitizer::Address) && sanitizers.contains(&Sanitizer::Thread) {
+        anyhow::bail!("`--sanitize address` cannot be combined with `--sanitize thread`");
+    }
+    Ok(())
+}
+
 #[derive(Clone, PartialEq, Eq, Builder)]
 #[builder(setter(into))]
 pub struct CCConfig {
@@ -303,6 +363,13 @@ pub struct CCConfig {
     // with extra __declspec(dllimport)
     // This is needed to use the shared runtime
     pub define_use_shared_runtime_macro: bool,
+    #[builder(default)]
+    // Instrument the output with the given sanitizers
+    pub sanitizers: Vec<Sanitizer>,
+    #[builder(default)]
+    // Flags of the system libraries used by the sources, e.g. from
+    // `pkg-config --cflags`. Unlike user flags, they keep the default flags
+    pub system_flags: Vec<String>,
 }
 
 #[derive(Clone, PartialEq, Eq, Builder)]
@@ -315,6 +382,9 @@ pub struct LinkerConfig<P: AsRef<Path>> {
     #[builder(default = None)]
     // This is the parent directory to the shared runtime library
     pub link_shared_runtime: Option<P>,
+    #[builder(default)]
+    // Link the sanitizer runtimes, must match the compiled objects
+    pub sanitizers: Vec<Sanitizer>,
 }
 
 #[derive(Clone, PartialEq, Eq, Builder)]
@@ -325,7 +395,14 @@ pub struct ArchiverConfig {
 }
 
 /// Resolve the C compiler to use from global state
+///
+/// A compiler for a cross target (see [`CC::for_target`]) already accounts for
+/// the environment, and overrides the `cc` of packages, which is usually meant
+/// for the host.
 pub fn resolve_cc(cc: CC, user_cc: Option<CC>) -> CC {
+    if cc.target.is_some() {
+        return cc;
+    }
     ENV_CC.clone().unwrap_or_else(|| user_cc.unwrap_or(cc))
 }
 
@@ -592,10 +669,12 @@ where
     // as user cannot easily specify them in the configuration file
     let has_user_flags = !user_link_flags.is_empty();
 
+    add_target_flags(&cc, &mut buf);
     add_linker_output_flags(&cc, &mut buf, &config, dest);
     add_linker_library_paths(&cc, &mut buf, &config, lpath);
     add_linker_intermediate_dir_flags(&cc, &mut buf, dest_dir);
     add_linker_shared_lib_flags(&cc, &mut buf, &config);
+    add_sanitizer_flags(&cc, &mut buf, &config.sanitizers);
 
     // Linker compiler-specific flags
     add_linker_msvc_specific_flags(&cc, &mut buf, has_user_flags);
@@ -770,6 +849,51 @@ fn add_cc_shared_runtime_flags(cc: &CC, buf: &mut Vec<String>, config: &CCConfig
     }
 }
 
+// Shared by the compiler and the linker. A gcc cross compiler is named after
+// its target, while clang is told the target explicitly
+fn add_target_flags(cc: &CC, buf: &mut Vec<String>) {
+    if let Some(target) = &cc.target
+        && cc.cc_kind == CCKind::Clang
+    {
+        buf.push(format!("--target={target}"));
+    }
+}
+
+// Shared by the compiler and the linker, as the sanitizer runtimes
+// must be linked with the same flags the objects were compiled with
+fn add_sanitizer_flags(cc: &CC, buf: &mut Vec<String>, sanitizers: &[Sanitizer]) {
+    if sanitizers.is_empty() {
+        return;
+    }
+    if cc.is_full_featured_gcc_like() {
+        let names: Vec<_> = sanitizers.iter().map(|s| s.name()).collect();
+        buf.push(format!("-fsanitize={}", names.join(",")));
+        buf.push("-fno-omit-frame-pointer".to_string());
+        if sanitizers.contains(&Sanitizer::Undefined) {
+            // make UBSan reports fatal so that the failing test is reported
+            buf.push("-fno-sanitize-recover=undefined".to_string());
+        }
+    } else if cc.is_msvc() {
+        for sanitizer in sanitizers {
+            if *sanitizer == Sanitizer::Address {
+                buf.push("/fsanitize=address".to_string());
+            } else {
+                eprintln!(
+                    "{}: `{}` sanitizer is not supported by msvc, ignored",
+                    "Warning".yellow().bold(),
+                    sanitizer.name(),
+                );
+            }
+        }
+    } else {
+        eprintln!(
+            "{}: Sanitizers are not supported by {}, ignored",
+            "Warning".yellow().bold(),
+            cc.cc_path,
+        );
+    }
+}
+
 // CC compiler-specific handling for moonbitrun
 fn add_cc_moonbitrun_with_warnings(cc: &CC, buf: &mut Vec<String>, config: &CCConfig) {
     if config.output_ty != OutputType::Object
@@ -850,6 +974,7 @@ where
     // as user cannot easily specify them in the configuration file
     let has_user_flags = !user_cc_flags.is_empty();
 
+    add_target_flags(&cc, &mut buf);
     add_cc_output_flags(&cc, &mut buf, &config, dest);
     add_cc_include_and_lib_paths(&cc, &mut buf, &paths.include_path, &paths.lib_path);
     add_cc_intermediate_dir_flags(&cc, &mut buf, &config, dest_dir);
@@ -864,7 +989,10 @@ where
 
     add_cc_optimization_flags(&cc, &mut buf, &config, has_user_flags);
     add_cc_shared_runtime_flags(&cc, &mut buf, &config);
+    // always set this even if user_cc_flags is set
+    add_sanitizer_flags(&cc, &mut buf, &config.sanitizers);
     add_cc_moonbitrun_with_warnings(&cc, &mut buf, &config);
+    buf.extend(config.system_flags.iter().cloned());
 
     buf.extend(src.into_iter().map(|s| s.into()));
 
@@ -16,10 +16,12 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
+use std::collections::HashSet;
+
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
-use crate::common::TargetBackend;
+use crate::{build_script::TargetInfo, common::TargetBackend};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum OptLevel {
@@ -52,6 +54,36 @@ pub enum LogicOp {
 pub enum Atom {
     OptLevel(OptLevel),
     Target(TargetBackend),
+    /// `os(name)`, the operating system of the target
+    Os(String),
+    /// `arch(name)`, the architecture of the target
+    Arch(String),
+    /// `feature(name)`, a feature declared in `moon.mod.json`
+    Feature(String),
+}
+
+/// The target platform and enabled features that `os(..)`, `arch(..)` and
+/// `feature(..)` atoms are evaluated against.
+#[derive(Debug, Clone, Default)]
+pub struct CfgEnv {
+    /// The operating system of the target, e.g. `linux`. `unknown` for
+    /// non-native backends.
+    pub os: String,
+    /// The architecture of the target, e.g. `x86_64`, or `wasm32`, `wasmgc`
+    /// and `js` for non-native backends.
+    pub arch: String,
+    /// The features enabled for the module the package belongs to
+    pub features: HashSet<String>,
+}
+
+impl CfgEnv {
+    pub fn new(target: &TargetInfo, features: HashSet<String>) -> Self {
+        CfgEnv {
+            os: target.os.clone(),
+            arch: target.arch.clone(),
+            features,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -62,21 +94,34 @@ pub enum CondExpr {
 }
 
 impl CondExpr {
-    pub fn eval(&self, opt_level: OptLevel, target_backend: TargetBackend) -> bool {
+    pub fn eval(&self, opt_level: OptLevel, target_backend: TargetBackend, env: &CfgEnv) -> bool {
         match self {
             CondExpr::Atom(atom) => match atom {
                 Atom::OptLevel(level) => level == &opt_level,
                 Atom::Target(backend) => backend == &target_backend,
+                Atom::Os(os) => os == &env.os,
+                Atom::Arch(arch) => arch == &env.arch,
+                Atom::Feature(feature) => env.features.contains(feature),
             },
             CondExpr::Condition(op, exprs) => match op {
-                LogicOp::And => exprs.iter().all(|x| x.eval(opt_level, target_backend)),
-                LogicOp::Or => exprs.iter().any(|x| x.eval(opt_level, target_backend)),
-                LogicOp::Not => !exprs.iter().any(|x| x.eval(opt_level, target_backend)),
+                LogicOp::And => exprs.iter().all(|x| x.eval(opt_level, target_backend, env)),
+                LogicOp::Or => exprs.iter().any(|x| x.eval(opt_level, target_backend, env)),
+                LogicOp::Not => !exprs.iter().any(|x| x.eval(opt_level, target_backend, env)),
             },
         }
     }
 
-    pub fn to_compile_condition(&self) -> CompileCondition {
+    /// All atoms in this expression, in order of appearance.
+    pub fn atoms(&self) -> Vec<&Atom> {
+        match self {
+            CondExpr::Atom(atom) => vec![atom],
+            CondExpr::Condition(_, exprs) => exprs.iter().flat_map(|x| x.atoms()).collect(),
+        }
+    }
+
+    /// Evaluate the expression for every backend and optimization level. The
+    /// `os(..)`, `arch(..)` and `feature(..)` atoms are fixed by `env`.
+    pub fn to_compile_condition(&self, env: &CfgEnv) -> CompileCondition {
         use std::collections::HashSet;
 
         let mut backend_set = HashSet::new();
@@ -93,7 +138,7 @@ impl CondExpr {
             (TargetBackend::LLVM, OptLevel::Debug),
             (TargetBackend::LLVM, OptLevel::Release),
         ] {
-            if self.eval(o, t) {
+            if self.eval(o, t, env) {
                 optlevel_set.insert(o);
                 backend_set.insert(t);
             }
@@ -164,12 +209,13 @@ fn test_001() {
 
 #[test]
 fn test_eval_001() {
+    let env = CfgEnv::default();
     // [or js]
     let lhs = CondExpr::Condition(
         LogicOp::Or,
         vec![CondExpr::Atom(Atom::Target(TargetBackend::Js))],
     );
-    let result = lhs.eval(OptLevel::Release, TargetBackend::Js);
+    let result = lhs.eval(OptLevel::Release, TargetBackend::Js, &env);
     assert!(result);
 
     // [or release]
@@ -177,40 +223,42 @@ fn test_eval_001() {
         LogicOp::Or,
         vec![CondExpr::Atom(Atom::OptLevel(OptLevel::Release))],
     );
-    let result = rhs.eval(OptLevel::Release, TargetBackend::Js);
+    let result = rhs.eval(OptLevel::Release, TargetBackend::Js, &env);
     assert!(result);
 
     // [and, [or js], [or, release]]
     let e = CondExpr::Condition(LogicOp::And, vec![lhs.clone(), rhs.clone()]);
-    let result = e.eval(OptLevel::Release, TargetBackend::Js);
+    let result = e.eval(OptLevel::Release, TargetBackend::Js, &env);
     assert!(result);
 
     let e = CondExpr::Condition(LogicOp::And, vec![lhs.clone(), rhs.clone()]);
-    let result = e.eval(OptLevel::Debug, TargetBackend::Js);
+    let result = e.eval(OptLevel::Debug, TargetBackend::Js, &env);
     assert!(!result);
 
     let e = CondExpr::Condition(LogicOp::And, vec![lhs, rhs]);
-    let result = e.eval(OptLevel::Release, TargetBackend::WasmGC);
+    let result = e.eval(OptLevel::Release, TargetBackend::WasmGC, &env);
     assert!(!result);
 }
 
 #[test]
 fn test_eval_002() {
+    let env = CfgEnv::default();
     // [not js]
     let lhs = CondExpr::Condition(
         LogicOp::Not,
         vec![CondExpr::Atom(Atom::Target(TargetBackend::Js))],
     );
-    let result = lhs.eval(OptLevel::Release, TargetBackend::Js);
+    let result = lhs.eval(OptLevel::Release, TargetBackend::Js, &env);
     assert!(!result);
-    let result = lhs.eval(OptLevel::Release, TargetBackend::Wasm);
+    let result = lhs.eval(OptLevel::Release, TargetBackend::Wasm, &env);
     assert!(result);
-    let result = lhs.eval(OptLevel::Release, TargetBackend::WasmGC);
+    let result = lhs.eval(OptLevel::Release, TargetBackend::WasmGC, &env);
     assert!(result);
 }
 
 #[test]
 fn test_eval_003() {
+    let env = CfgEnv::default();
     // [not wasm wasm-gc]
     let e = CondExpr::Condition(
         LogicOp::Not,
@@ -219,16 +267,54 @@ fn test_eval_003() {
             CondExpr::Atom(Atom::Target(TargetBackend::WasmGC)),
         ],
     );
-    let result = e.eval(OptLevel::Release, TargetBackend::Wasm);
+    let result = e.eval(OptLevel::Release, TargetBackend::Wasm, &env);
     assert!(!result);
-    let result = e.eval(OptLevel::Release, TargetBackend::WasmGC);
+    let result = e.eval(OptLevel::Release, TargetBackend::WasmGC, &env);
     assert!(!result);
-    let result = e.eval(OptLevel::Release, TargetBackend::Js);
+    let result = e.eval(OptLevel::Release, TargetBackend::Js, &env);
     assert!(result);
-    let result = e.eval(OptLevel::Release, TargetBackend::Js);
+    let result = e.eval(OptLevel::Release, TargetBackend::Js, &env);
     assert!(result);
 }
 
+#[test]
+fn test_eval_platform_and_feature() {
+    let env = CfgEnv {
+        os: "linux".to_string(),
+        arch: "x86_64".to_string(),
+        features: HashSet::from(["simd".to_string()]),
+    };
+    // [and, native, os(linux), [not feature(simd)]]
+    let e = parse_cond_expr(&StringOrArray::Array(vec![
+        StringOrArray::String("and".to_string()),
+        StringOrArray::String("native".to_string()),
+        StringOrArray::String("os(linux)".to_string()),
+        StringOrArray::Array(vec![
+            StringOrArray::String("not".to_string()),
+            StringOrArray::String("feature(simd)".to_string()),
+        ]),
+    ]))
+    .unwrap();
+    assert!(!e.eval(OptLevel::Release, TargetBackend::Native, &env));
+    let no_simd = CfgEnv {
+        features: HashSet::new(),
+        ..env.clone()
+    };
+    assert!(e.eval(OptLevel::Release, TargetBackend::Native, &no_simd));
+    assert!(!e.eval(OptLevel::Release, TargetBackend::Js, &no_simd));
+
+    let arch = parse_cond_target("arch(x86_64)").unwrap();
+    assert!(arch.eval(OptLevel::Debug, TargetBackend::Native, &env));
+    assert!(matches!(
+        StringOrArray::from(arch),
+        StringOrArray::String(s) if s == "arch(x86_64)"
+    ));
+
+    assert!(parse_cond_target("os()").is_err());
+    assert!(parse_cond_target("os(linux").is_err());
+    assert!(parse_cond_target("vendor(apple)").is_err());
+}
+
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum ParseLogicOpError {
     #[error("empty string")]
@@ -253,6 +339,8 @@ pub enum ParseTargetError {
     EmptyString,
     #[error("unknown target: {0}")]
     UnknownTarget(String),
+    #[error("invalid argument in `{0}`")]
+    InvalidArgument(String),
 }
 
 pub fn parse_cond_target(expr: &str) -> Result<CondExpr, ParseTargetError> {
@@ -267,10 +355,31 @@ pub fn parse_cond_target(expr: &str) -> Result<CondExpr, ParseTargetError> {
         "js" => Ok(CondExpr::Atom(Atom::Target(TargetBackend::Js))),
         "native" => Ok(CondExpr::Atom(Atom::Target(TargetBackend::Native))),
         "llvm" => Ok(CondExpr::Atom(Atom::Target(TargetBackend::LLVM))),
-        _ => Err(ParseTargetError::UnknownTarget(expr.to_string())),
+        _ => parse_cond_call(expr),
     }
 }
 
+/// Parse the `os(..)`, `arch(..)` and `feature(..)` atoms.
+fn parse_cond_call(expr: &str) -> Result<CondExpr, ParseTargetError> {
+    let unknown = || ParseTargetError::UnknownTarget(expr.to_string());
+    let (func, rest) = expr.split_once('(').ok_or_else(unknown)?;
+    let arg = rest.strip_suffix(')').ok_or_else(unknown)?;
+    if arg.is_empty()
+        || !arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(ParseTargetError::InvalidArgument(expr.to_string()));
+    }
+    let atom = match func {
+        "os" => Atom::Os(arg.to_string()),
+        "arch" => Atom::Arch(arg.to_string()),
+        "feature" => Atom::Feature(arg.to_string()),
+        _ => return Err(unknown()),
+    };
+    Ok(CondExpr::Atom(atom))
+}
+
 #[derive(Debug, thiserror::Error)]
 #[error("failed to parse conditional expression")]
 pub struct ParseCondExprError {
@@ -353,6 +462,9 @@ impl From<CondExpr> for StringOrArray {
                     TargetBackend::Native => StringOrArray::String("native".to_string()),
                     TargetBackend::LLVM => StringOrArray::String("llvm".to_string()),
                 },
+                Atom::Os(os) => StringOrArray::String(format!("os({os})")),
+                Atom::Arch(arch) => StringOrArray::String(format!("arch({arch})")),
+                Atom::Feature(feature) => StringOrArray::String(format!("feature({feature})")),
             },
             CondExpr::Condition(op, exprs) => {
                 let mut arr: Vec<StringOrArray> = Vec::with_capacity(exprs.len() + 1);
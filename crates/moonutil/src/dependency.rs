@@ -41,6 +41,13 @@ pub struct SourceDependencyInfo {
     /// Git branch to use.
     #[serde(skip_serializing_if = "Option::is_none", rename = "branch")]
     pub git_branch: Option<String>,
+    /// Only resolve this dependency when a feature of this module enables it
+    /// with `dep:<name>`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub optional: bool,
+    /// Features of the dependency to enable.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<String>,
 }
 
 fn version_is_default(version: &VersionReq) -> bool {
@@ -54,6 +61,8 @@ impl std::fmt::Debug for SourceDependencyInfo {
         } else {
             f.debug_struct("SourceDependencyInfo")
                 .field("version", &format_args!("{}", self.version))
+                .field("optional", &self.optional)
+                .field("features", &self.features)
                 .finish()
         }
     }
@@ -73,7 +82,11 @@ pub enum SourceDependencyInfoJson {
 impl SourceDependencyInfo {
     /// Check if the requirement is simple. That is, it only contains a version requirement
     fn is_simple(&self) -> bool {
-        self.path.is_none() && self.git.is_none() && self.git_branch.is_none()
+        self.path.is_none()
+            && self.git.is_none()
+            && self.git_branch.is_none()
+            && !self.optional
+            && self.features.is_empty()
     }
 
     #[allow(clippy::needless_update)] // More fields will be added later
@@ -205,6 +218,8 @@ impl From<BinaryDependencyInfo> for SourceDependencyInfo {
             path: dep.path,
             git: dep.git,
             git_branch: dep.git_branch,
+            optional: false,
+            features: vec![],
         }
     }
 }
@@ -126,8 +126,14 @@ pub fn mk_arch_mode_dir(
     moonc_opt: &MooncOpt,
     mode: RunMode,
 ) -> anyhow::Result<PathBuf> {
-    let arch_dir = target_dir.join(moonc_opt.link_opt.target_backend.to_dir_name());
-    let arch_mode_dir = if moonc_opt.build_opt.debug_flag {
+    let mut arch_dir = target_dir.join(moonc_opt.link_opt.target_backend.to_dir_name());
+    if let Some(triple) = &moonc_opt.target_triple {
+        // cross builds must not reuse objects built for the host
+        arch_dir.push(triple);
+    }
+    let arch_mode_dir = if let Some(profile) = &moonc_opt.profile {
+        arch_dir.join(&profile.name)
+    } else if moonc_opt.build_opt.debug_flag {
         arch_dir.join("debug")
     } else {
         arch_dir.join("release")
@@ -130,6 +130,7 @@ features! {
     (unstable, rr_export_package_graph, "Export the package dependency graph (only with Rupes Recta)"),
     (unstable, rr_export_build_plan, "Export the build plan graph (only with Rupes Recta)"),
     (unstable, rr_n2_explain, "Ask n2 to explain rerun reasons (only with Rupes Recta)"),
+    (unstable, build_server, "Forward build, check and test requests to a running `moon server`"),
 }
 
 impl FromStr for FeatureGate {
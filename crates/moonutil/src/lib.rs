@@ -31,12 +31,16 @@ pub mod features;
 pub mod fuzzy_match;
 pub mod git;
 pub mod graph;
+pub mod limits;
 pub mod module;
+pub mod module_features;
 pub mod moon_dir;
 pub mod mooncake_bin;
 pub mod mooncakes;
 pub mod package;
 pub mod path;
+pub mod profile;
 pub mod render;
 pub mod scan;
+pub mod system_deps;
 pub mod version;
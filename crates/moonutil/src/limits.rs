@@ -0,0 +1,155 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Parsing of the resource limits enforced by `moonrun`, shared by its
+//! command line, `moon test` and `test-limits` in moon.pkg.json.
+
+use std::time::Duration;
+
+/// Parse a size such as `1048576`, `512K`, `64MiB` or `2G`. Units are
+/// binary.
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: usize = number.parse().map_err(|_| format!("invalid size `{s}`"))?;
+    let multiplier: usize = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return Err(format!("invalid size unit in `{s}`, expected K, M or G")),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size `{s}` is too large"))
+}
+
+/// Parse a duration such as `30`, `30s`, `500ms`, `2m` or `1h`. A number
+/// without unit is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{s}`"))?;
+    let secs = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => {
+            return Err(format!(
+                "invalid duration unit in `{s}`, expected ms, s, m or h"
+            ));
+        }
+    };
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid duration `{s}`"))
+}
+
+/// Format a size in the largest unit that represents it exactly.
+pub fn format_size(size: usize) -> String {
+    match size {
+        s if s >= 1 << 30 && s % (1 << 30) == 0 => format!("{}G", s >> 30),
+        s if s >= 1 << 20 && s % (1 << 20) == 0 => format!("{}M", s >> 20),
+        s if s >= 1 << 10 && s % (1 << 10) == 0 => format!("{}K", s >> 10),
+        s => format!("{s} bytes"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1048576"), Ok(1048576));
+        assert_eq!(parse_size("100b"), Ok(100));
+        assert_eq!(parse_size("512K"), Ok(512 << 10));
+        assert_eq!(parse_size("64MiB"), Ok(64 << 20));
+        assert_eq!(parse_size("64mb"), Ok(64 << 20));
+        assert_eq!(parse_size(" 2G "), Ok(2 << 30));
+        assert_eq!(parse_size(""), Err("invalid size ``".to_string()));
+        assert_eq!(parse_size("M"), Err("invalid size `M`".to_string()));
+        assert_eq!(parse_size("-1M"), Err("invalid size `-1M`".to_string()));
+        assert_eq!(
+            parse_size("1.5G"),
+            Err("invalid size unit in `1.5G`, expected K, M or G".to_string())
+        );
+        assert_eq!(
+            parse_size("12T"),
+            Err("invalid size unit in `12T`, expected K, M or G".to_string())
+        );
+        assert_eq!(
+            parse_size("100000000000000000000000"),
+            Err("invalid size `100000000000000000000000`".to_string())
+        );
+        assert_eq!(
+            parse_size(&format!("{}G", usize::MAX)),
+            Err(format!("size `{}G` is too large", usize::MAX))
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("s"), Err("invalid duration `s`".to_string()));
+        assert_eq!(
+            parse_duration("1.2.3s"),
+            Err("invalid duration `1.2.3s`".to_string())
+        );
+        assert_eq!(
+            parse_duration("5 min"),
+            Err("invalid duration unit in `5 min`, expected ms, s, m or h".to_string())
+        );
+        assert_eq!(
+            parse_duration("1e400"),
+            Err("invalid duration unit in `1e400`, expected ms, s, m or h".to_string())
+        );
+        assert_eq!(
+            parse_duration(&"9".repeat(30)),
+            Err(format!("invalid duration `{}`", "9".repeat(30)))
+        );
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 bytes");
+        assert_eq!(format_size(1000), "1000 bytes");
+        assert_eq!(format_size(1024), "1K");
+        assert_eq!(format_size(1536), "1536 bytes");
+        assert_eq!(format_size(3 << 20), "3M");
+        assert_eq!(format_size((1 << 30) + (1 << 20)), "1025M");
+        assert_eq!(format_size(2 << 30), "2G");
+    }
+
+    #[test]
+    fn test_format_size_roundtrip() {
+        for size in [1 << 10, 512 << 20, 4 << 30] {
+            assert_eq!(parse_size(&format_size(size)), Ok(size));
+        }
+    }
+}
@@ -22,8 +22,10 @@ use crate::common::{
 use crate::dependency::{
     BinaryDependencyInfo, BinaryDependencyInfoJson, SourceDependencyInfo, SourceDependencyInfoJson,
 };
+use crate::module_features::{Features, validate_features};
 use crate::package::{AliasJSON, Package, PackageJSON};
 use crate::path::ImportPath;
+use crate::profile::{BuildProfile, validate_profiles};
 use anyhow::bail;
 use indexmap::map::IndexMap;
 use petgraph::graph::DiGraph;
@@ -799,9 +801,22 @@ pub struct MoonMod {
     pub preferred_target: Option<TargetBackend>,
 
     pub scripts: Option<IndexMap<String, String>>,
+    pub runners: Option<IndexMap<TargetBackend, String>>,
+    pub profiles: Option<IndexMap<String, BuildProfile>>,
+    pub features: Option<Features>,
     pub __moonbit_unstable_prebuild: Option<String>,
 }
 
+impl MoonMod {
+    /// The custom runner command configured for the given backend, if any.
+    pub fn runner(&self, backend: TargetBackend) -> Option<&str> {
+        self.runners
+            .as_ref()
+            .and_then(|r| r.get(&backend))
+            .map(|s| s.as_str())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
 #[schemars(
@@ -891,6 +906,36 @@ pub struct MoonModJSON {
     #[schemars(with = "Option<std::collections::HashMap<String, String>>")]
     pub scripts: Option<IndexMap<String, String>>,
 
+    /// Custom commands used to run executables of each backend, keyed by
+    /// backend name.
+    ///
+    /// `moon run`, `moon test` and `moon bench` execute the built artifact
+    /// through this command instead of the default runtime. `$artifact` in
+    /// the command is replaced by the path of the artifact, or the path is
+    /// appended when `$artifact` is absent. Program arguments follow, and
+    /// tests additionally receive the test selection in the
+    /// `MOON_TEST_ARGS` environment variable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<std::collections::HashMap<String, String>>")]
+    pub runners: Option<IndexMap<String, String>>,
+
+    /// Named build profiles, selected with `--build-profile <name>`.
+    ///
+    /// Each profile inherits from the built-in `debug` or `release` profile
+    /// and is built into its own directory under the target directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<std::collections::HashMap<String, BuildProfile>>")]
+    pub profiles: Option<IndexMap<String, BuildProfile>>,
+
+    /// Features of the module, tested by `feature(<name>)` in the `targets`
+    /// of `moon.pkg.json` and enabled with `moon build --features <name>`.
+    ///
+    /// Each feature maps to the list of other features it enables. An entry
+    /// `dep:<name>` enables the optional dependency `<name>` instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<std::collections::HashMap<String, Vec<String>>>")]
+    pub features: Option<Features>,
+
     /// The preferred target backend of this module.
     ///
     /// Toolchains are recommended to use this target as the default target
@@ -938,6 +983,21 @@ impl TryFrom<MoonModJSON> for MoonMod {
             .map(|x| TargetBackend::str_to_backend(&x))
             .transpose()
             .map_err(MoonModJSONFormatErrorKind::PreferredBackend)?;
+        let runners = j
+            .runners
+            .map(|r| {
+                r.into_iter()
+                    .map(|(k, v)| Ok((TargetBackend::str_to_backend(&k)?, v)))
+                    .collect::<anyhow::Result<IndexMap<_, _>>>()
+            })
+            .transpose()
+            .map_err(MoonModJSONFormatErrorKind::Runners)?;
+        if let Some(profiles) = &j.profiles {
+            validate_profiles(profiles).map_err(MoonModJSONFormatErrorKind::Profiles)?;
+        }
+        if let Some(features) = &j.features {
+            validate_features(features, &deps).map_err(MoonModJSONFormatErrorKind::Features)?;
+        }
 
         Ok(MoonMod {
             name: j.name,
@@ -963,6 +1023,9 @@ impl TryFrom<MoonModJSON> for MoonMod {
             exclude: j.exclude,
 
             scripts: j.scripts,
+            runners,
+            profiles: j.profiles,
+            features: j.features,
             preferred_target,
 
             __moonbit_unstable_prebuild: j.__moonbit_unstable_prebuild,
@@ -997,6 +1060,13 @@ pub fn convert_module_to_mod_json(m: MoonMod) -> MoonModJSON {
         exclude: m.exclude,
 
         scripts: m.scripts,
+        runners: m.runners.map(|r| {
+            r.into_iter()
+                .map(|(k, v)| (k.to_flag().to_owned(), v))
+                .collect()
+        }),
+        profiles: m.profiles,
+        features: m.features,
 
         preferred_target: m.preferred_target.map(|x| x.to_flag().to_owned()),
 
@@ -0,0 +1,266 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+//! Features declared in `moon.mod.json`, tested by `feature(..)` in the
+//! `targets` field of `moon.pkg.json`. A feature may also enable optional
+//! dependencies of the module with `dep:<name>`.
+
+use std::collections::HashSet;
+
+use anyhow::bail;
+use indexmap::IndexMap;
+
+use crate::{
+    cond_expr::{Atom, CondExprs},
+    dependency::SourceDependencyInfo,
+    module::MoonMod,
+    package::{Import, MoonPkg},
+};
+
+/// Features of a module, mapping each feature to the features and optional
+/// dependencies it enables
+pub type Features = IndexMap<String, Vec<String>>;
+
+/// The prefix of entries in [`Features`] that enable an optional dependency
+pub const DEP_PREFIX: &str = "dep:";
+
+fn is_valid_feature_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Check that every feature in `moon.mod.json` has a valid name and only
+/// enables declared features and optional dependencies in `deps`.
+pub fn validate_features(
+    features: &Features,
+    deps: &IndexMap<String, SourceDependencyInfo>,
+) -> anyhow::Result<()> {
+    for (name, enables) in features {
+        if !is_valid_feature_name(name) {
+            bail!("`{name}` is not a valid feature name");
+        }
+        for enabled in enables {
+            if let Some(dep) = enabled.strip_prefix(DEP_PREFIX) {
+                if !deps.get(dep).is_some_and(|d| d.optional) {
+                    bail!("feature `{name}` enables `{dep}`, which is not an optional dependency");
+                }
+            } else if !features.contains_key(enabled) {
+                bail!("feature `{name}` enables `{enabled}`, which is not declared");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Enable the `requested` features of `module`, together with every feature
+/// they enable transitively.
+pub fn resolve_features<'a>(
+    module: &str,
+    features: Option<&Features>,
+    requested: impl IntoIterator<Item = &'a String>,
+) -> anyhow::Result<HashSet<String>> {
+    let mut enabled = HashSet::new();
+    let mut stack: Vec<&str> = requested.into_iter().map(String::as_str).collect();
+    while let Some(name) = stack.pop() {
+        let Some(enables) = features.and_then(|f| f.get(name)) else {
+            bail!("feature `{name}` is not declared in `moon.mod.json` of module `{module}`");
+        };
+        if enabled.insert(name.to_string()) {
+            stack.extend(
+                enables
+                    .iter()
+                    .filter(|x| !x.starts_with(DEP_PREFIX))
+                    .map(String::as_str),
+            );
+        }
+    }
+    Ok(enabled)
+}
+
+/// Whether the dependency `name` of `module` should be resolved when the
+/// `enabled` features are on. Non-optional dependencies are always resolved.
+pub fn dep_enabled(module: &MoonMod, name: &str, enabled: &HashSet<String>) -> bool {
+    if !module.deps.get(name).is_some_and(|d| d.optional) {
+        return true;
+    }
+    let Some(features) = &module.features else {
+        return false;
+    };
+    enabled.iter().any(|feature| {
+        features.get(feature).is_some_and(|enables| {
+            enables
+                .iter()
+                .any(|x| x.strip_prefix(DEP_PREFIX) == Some(name))
+        })
+    })
+}
+
+/// The optional dependencies of `module` left out by the `enabled` features.
+/// `None` means the features are selected at build time, where every
+/// optional dependency has been resolved.
+pub fn disabled_deps<'a>(module: &'a MoonMod, enabled: Option<&HashSet<String>>) -> Vec<&'a str> {
+    let Some(enabled) = enabled else {
+        return vec![];
+    };
+    module
+        .deps
+        .keys()
+        .filter(|name| !dep_enabled(module, name, enabled))
+        .map(String::as_str)
+        .collect()
+}
+
+/// The import of `pkg` that refers to a module in `disabled_deps`, if any,
+/// together with that module. Such a package cannot be built and is left out.
+pub fn import_of_disabled_dep<'a, 'b>(
+    pkg: &'a MoonPkg,
+    disabled_deps: &[&'b str],
+) -> Option<(&'a str, &'b str)> {
+    pkg.imports
+        .iter()
+        .chain(&pkg.wbtest_imports)
+        .chain(&pkg.test_imports)
+        .map(Import::get_path)
+        .find_map(|path| {
+            let dep = disabled_deps.iter().find(|dep| {
+                path.strip_prefix(**dep)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })?;
+            Some((path, *dep))
+        })
+}
+
+/// The message for leaving out the package `pkg` of `module`, which imports
+/// `import` of the disabled optional dependency `dep`. It names the features
+/// enabling the dependency.
+pub fn skipped_package_message(module: &MoonMod, pkg: &str, import: &str, dep: &str) -> String {
+    let features = module
+        .features
+        .iter()
+        .flatten()
+        .filter(|(_, enables)| {
+            enables
+                .iter()
+                .any(|x| x.strip_prefix(DEP_PREFIX) == Some(dep))
+        })
+        .map(|(name, _)| format!("`{name}`"))
+        .collect::<Vec<_>>();
+    let enable = match features.as_slice() {
+        [] => "no feature enables it".to_string(),
+        [feature] => format!(
+            "enable feature {feature} of module `{}` to build it",
+            module.name
+        ),
+        _ => format!(
+            "enable one of the features {} of module `{}` to build it",
+            features.join(", "),
+            module.name
+        ),
+    };
+    format!(
+        "Skipping package `{pkg}` because it imports `{import}` of optional dependency `{dep}`, \
+         which is not enabled; {enable}"
+    )
+}
+
+/// Check that every `feature(..)` in the `targets` of a package is declared
+/// by its module.
+pub fn check_target_features(
+    targets: &CondExprs,
+    features: Option<&Features>,
+) -> anyhow::Result<()> {
+    for (file, expr) in targets {
+        for atom in expr.atoms() {
+            if let Atom::Feature(name) = atom
+                && !features.is_some_and(|f| f.contains_key(name))
+            {
+                bail!(
+                    "`{file}` depends on feature `{name}`, which is not declared in `moon.mod.json`"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_resolve_features() {
+    let features: Features = serde_json_lenient::from_str(
+        r#"{ "full": ["simd", "threads"], "simd": [], "threads": ["simd"] }"#,
+    )
+    .unwrap();
+    let deps = IndexMap::new();
+    validate_features(&features, &deps).unwrap();
+
+    let enabled = resolve_features("m", Some(&features), &["threads".to_string()]).unwrap();
+    assert_eq!(
+        enabled,
+        HashSet::from(["threads".to_string(), "simd".to_string()])
+    );
+    assert!(resolve_features("m", Some(&features), &["gpu".to_string()]).is_err());
+    assert!(resolve_features("m", None, &["simd".to_string()]).is_err());
+    assert!(resolve_features("m", None, &[]).unwrap().is_empty());
+
+    let bad: Features = serde_json_lenient::from_str(r#"{ "full": ["gpu"] }"#).unwrap();
+    assert!(validate_features(&bad, &deps).is_err());
+}
+
+#[test]
+fn test_skipped_package_message() {
+    let module: MoonMod = serde_json_lenient::from_str::<crate::module::MoonModJSON>(
+        r#"{
+            "name": "just/codec",
+            "deps": {
+                "just/zlib": { "path": "../zlib", "optional": true },
+                "just/brotli": { "path": "../brotli", "optional": true },
+                "just/lz4": { "path": "../lz4", "optional": true }
+            },
+            "features": {
+                "zlib": ["dep:just/zlib"],
+                "brotli": ["dep:just/brotli"],
+                "all": ["zlib", "dep:just/brotli"]
+            }
+        }"#,
+    )
+    .unwrap()
+    .try_into()
+    .unwrap();
+
+    let disabled = disabled_deps(&module, Some(&HashSet::from(["zlib".to_string()])));
+    assert_eq!(disabled, ["just/brotli", "just/lz4"]);
+
+    let pkg = crate::package::convert_pkg_json_to_package(
+        serde_json_lenient::from_str(r#"{ "import": ["just/codec/lib", "just/brotli/lib"] }"#)
+            .unwrap(),
+    )
+    .unwrap();
+    let (import, dep) = import_of_disabled_dep(&pkg, &disabled).unwrap();
+    assert_eq!((import, dep), ("just/brotli/lib", "just/brotli"));
+
+    assert_eq!(
+        skipped_package_message(&module, "just/codec/compress", import, dep),
+        "Skipping package `just/codec/compress` because it imports `just/brotli/lib` of \
+         optional dependency `just/brotli`, which is not enabled; enable one of the features \
+         `brotli`, `all` of module `just/codec` to build it"
+    );
+    assert!(
+        skipped_package_message(&module, "just/codec/fast", "just/lz4", "just/lz4")
+            .ends_with("; no feature enables it")
+    );
+}
@@ -316,7 +316,10 @@ impl std::str::FromStr for ModuleSource {
 pub static DEFAULT_VERSION: Version = Version::new(0, 0, 0);
 
 pub mod result {
-    use std::{collections::HashMap, sync::Arc};
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::Arc,
+    };
 
     use petgraph::graphmap::DiGraphMap;
     use slotmap::SlotMap;
@@ -355,6 +358,10 @@ pub mod result {
         ///
         /// Edges should point from dependents (downstream) to dependencies (upstream).
         dep_graph: DiGraphMap<ModuleId, DependencyKey>,
+
+        /// The features enabled for each module by its dependents. Features of
+        /// the input modules are selected at build time instead.
+        features: HashMap<ModuleId, HashSet<String>>,
     }
 
     impl ResolvedEnv {
@@ -406,6 +413,11 @@ pub mod result {
                 .find_map(|(_s, t, k)| if k == dep { Some(t) } else { None })
         }
 
+        /// Get the features enabled for a module by its dependents
+        pub fn features(&self, id: ModuleId) -> Option<&HashSet<String>> {
+            self.features.get(&id)
+        }
+
         pub fn dep_count(&self, id: ModuleId) -> usize {
             self.dep_graph
                 .neighbors_directed(id, petgraph::Direction::Outgoing)
@@ -437,6 +449,7 @@ pub mod result {
                 mapping: SlotMap::with_key(),
                 dep_graph: DiGraphMap::new(),
                 rev_map: HashMap::new(),
+                features: HashMap::new(),
             }
         }
 
@@ -475,6 +488,10 @@ pub mod result {
         pub fn add_dependency(&mut self, from: ModuleId, to: ModuleId, key: &DependencyKey) {
             self.dep_graph.add_edge(from, to, key.to_owned());
         }
+
+        pub fn set_features(&mut self, id: ModuleId, features: HashSet<String>) {
+            self.features.insert(id, features);
+        }
     }
 
     impl Default for ResolvedEnv {
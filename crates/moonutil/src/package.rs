@@ -115,6 +115,9 @@ pub struct Package {
     pub link_libs: Vec<String>,
     /// Additional link search paths to pass to all dependents
     pub link_search_paths: Vec<String>,
+
+    pub test_limits: Option<TestLimits>,
+    pub js_test_runtime: Option<JsRuntime>,
 }
 
 impl Package {
@@ -331,6 +334,91 @@ pub struct MoonPkgJSON {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub overrides: Option<Vec<String>>,
+
+    /// Resource limits for running the tests of this package
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "test-limits")]
+    #[serde(rename(serialize = "test-limits"))]
+    #[schemars(rename = "test-limits")]
+    pub test_limits: Option<TestLimits>,
+
+    /// The runtime running the tests of this package on the JS backend,
+    /// `node` by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "js-test-runtime")]
+    #[serde(rename(serialize = "js-test-runtime"))]
+    #[schemars(rename = "js-test-runtime")]
+    pub js_test_runtime: Option<JsRuntime>,
+}
+
+/// A runtime for the output of the JS backend. `moonrun` needs no external
+/// dependencies but only provides the Node.js APIs MoonBit programs use
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JsRuntime {
+    Moonrun,
+    #[default]
+    Node,
+}
+
+/// Resource limits enforced by `moonrun` when running WebAssembly tests
+#[derive(Debug, Default, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct TestLimits {
+    /// Maximum memory use of a test executable, e.g. `512M`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "max-heap")]
+    #[serde(rename(serialize = "max-heap"))]
+    #[schemars(rename = "max-heap")]
+    pub max_heap: Option<String>,
+
+    /// Maximum wall time of a test executable, e.g. `30s`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "max-wall-time")]
+    #[serde(rename(serialize = "max-wall-time"))]
+    #[schemars(rename = "max-wall-time")]
+    pub max_wall_time: Option<String>,
+}
+
+impl TestLimits {
+    /// Take the limits not set in `self` from `fallback`.
+    pub fn or(&self, fallback: Option<&TestLimits>) -> TestLimits {
+        TestLimits {
+            max_heap: self
+                .max_heap
+                .clone()
+                .or_else(|| fallback.and_then(|f| f.max_heap.clone())),
+            max_wall_time: self
+                .max_wall_time
+                .clone()
+                .or_else(|| fallback.and_then(|f| f.max_wall_time.clone())),
+        }
+    }
+
+    /// Check that the limits are a valid size and duration, so that mistakes
+    /// are reported up front rather than by every test run.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(max_heap) = &self.max_heap {
+            crate::limits::parse_size(max_heap)
+                .map_err(|e| anyhow::anyhow!("invalid `max-heap` in `test-limits`: {e}"))?;
+        }
+        if let Some(max_wall_time) = &self.max_wall_time {
+            crate::limits::parse_duration(max_wall_time)
+                .map_err(|e| anyhow::anyhow!("invalid `max-wall-time` in `test-limits`: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// The arguments to pass to `moonrun` before the program path.
+    pub fn to_moonrun_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(max_heap) = &self.max_heap {
+            args.push(format!("--max-heap={max_heap}"));
+        }
+        if let Some(max_wall_time) = &self.max_wall_time {
+            args.push(format!("--max-wall-time={max_wall_time}"));
+        }
+        args
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
@@ -494,6 +582,18 @@ impl LinkDepItem {
         }
     }
 
+    pub fn native_system_deps_cflags(&self, b: TargetBackend) -> &[String] {
+        match b {
+            Native => self
+                .link
+                .as_ref()
+                .and_then(|l| l.native.as_ref())
+                .and_then(|n| n.system_deps_cflags.as_deref())
+                .unwrap_or_default(),
+            _ => &[],
+        }
+    }
+
     pub fn native_stub_cc_link_flags(&self, b: TargetBackend) -> Option<&str> {
         match b {
             Native => self.link.as_ref()?.native.as_ref()?.stub_cc_link_flags.as_deref(),
@@ -504,6 +604,17 @@ impl LinkDepItem {
     pub fn native_stub_deps(&self) -> Option<&[String]> {
         self.link.as_ref()?.native.as_ref()?.stub_lib_deps.as_deref()
     }
+
+    pub fn native_stub_obj_deps(&self) -> Option<&[String]> {
+        self.link.as_ref()?.native.as_ref()?.stub_obj_deps.as_deref()
+    }
+
+    pub fn native_link_kind(&self, b: TargetBackend) -> NativeLinkKind {
+        match b {
+            Native => self.link.as_ref().and_then(|l| l.native.as_ref()?.kind).unwrap_or_default(),
+            _ => NativeLinkKind::Executable,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
@@ -548,6 +659,11 @@ pub struct NativeLinkConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub exports: Option<Vec<String>>,
 
+    /// The kind of native artifact to build from this package. Libraries come
+    /// with a C header declaring the functions in `exports`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<NativeLinkKind>,
+
     /// Custom C compiler for main MoonBit-generated C code
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cc: Option<String>,
@@ -572,12 +688,48 @@ pub struct NativeLinkConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stub_cc_link_flags: Option<String>,
 
+    /// System libraries found with `pkg-config`, mapped to a version requirement
+    /// such as `">=1.2"`. Their cflags are used for the C stubs and their libs
+    /// for the final link
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_deps: Option<IndexMap<String, String>>,
+
+    /// Compiler flags of the resolved `system_deps` for C stub compilation
+    ///
+    /// (should not be present in the `pkg.json`, generated and populated later)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub system_deps_cflags: Option<Vec<String>>,
+
     /// Compiled stub object files as dependencies for the executable
     ///
     /// (should not be present in the `pkg.json`, generated and populated later)
     #[serde(skip_serializing_if = "Option::is_none")]
     #[schemars(skip)]
     pub stub_lib_deps: Option<Vec<String>>,
+
+    /// Compiled stub object files to bundle into a static library
+    ///
+    /// (should not be present in the `pkg.json`, generated and populated later)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub stub_obj_deps: Option<Vec<String>>,
+}
+
+/// The kind of native artifact built from a linked package
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum NativeLinkKind {
+    #[default]
+    Executable,
+    Shared,
+    Static,
+}
+
+impl NativeLinkKind {
+    pub fn is_library(self) -> bool {
+        !matches!(self, NativeLinkKind::Executable)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
@@ -666,6 +818,7 @@ impl Link {
                     || n.cc_flags.is_some()
                     || n.cc_link_flags.is_some()
                     || n.exports.is_some()
+                    || n.kind.is_some()
             }),
         }
     }
@@ -745,6 +898,9 @@ pub struct MoonPkg {
     pub virtual_pkg: Option<VirtualPkg>,
     pub implement: Option<String>,
     pub overrides: Option<Vec<String>>,
+
+    pub test_limits: Option<TestLimits>,
+    pub js_test_runtime: Option<JsRuntime>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -868,6 +1024,10 @@ pub fn convert_pkg_json_to_package(j: MoonPkgJSON) -> anyhow::Result<MoonPkg> {
         supported_backends.extend(TargetBackend::all());
     };
 
+    if let Some(test_limits) = &j.test_limits {
+        test_limits.validate()?;
+    }
+
     let result = MoonPkg {
         name: None,
         is_main,
@@ -892,6 +1052,8 @@ pub fn convert_pkg_json_to_package(j: MoonPkgJSON) -> anyhow::Result<MoonPkg> {
         virtual_pkg: j.virtual_pkg,
         implement: j.implement,
         overrides: j.overrides,
+        test_limits: j.test_limits,
+        js_test_runtime: j.js_test_runtime,
     };
     Ok(result)
 }
@@ -0,0 +1,151 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Named build profiles declared in `moon.mod.json`.
+
+use anyhow::bail;
+use indexmap::IndexMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{compiler_flags, cond_expr::OptLevel};
+
+/// The built-in profile a named profile inherits from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BaseProfile {
+    Debug,
+    Release,
+}
+
+impl BaseProfile {
+    pub fn opt_level(self) -> OptLevel {
+        match self {
+            BaseProfile::Debug => OptLevel::Debug,
+            BaseProfile::Release => OptLevel::Release,
+        }
+    }
+}
+
+/// The optimization level of the C compiler for native backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CcOptLevel {
+    None,
+    Debug,
+    Size,
+    Speed,
+}
+
+impl From<CcOptLevel> for compiler_flags::OptLevel {
+    fn from(level: CcOptLevel) -> Self {
+        match level {
+            CcOptLevel::None => compiler_flags::OptLevel::None,
+            CcOptLevel::Debug => compiler_flags::OptLevel::Debug,
+            CcOptLevel::Size => compiler_flags::OptLevel::Size,
+            CcOptLevel::Speed => compiler_flags::OptLevel::Speed,
+        }
+    }
+}
+
+/// A named build profile, selected with `--build-profile <name>`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildProfile {
+    /// The built-in profile this profile starts from, `debug` or `release`
+    pub inherits: BaseProfile,
+
+    /// Extra flags passed to moonc when compiling packages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compile_flags: Option<Vec<String>>,
+
+    /// Extra flags passed to `moonc link-core` when linking, not to the C linker
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_flags: Option<Vec<String>>,
+
+    /// Optimization level of the C compiler for native backends
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cc_opt_level: Option<CcOptLevel>,
+
+    /// Whether the C compiler emits debug information for native backends
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_info: Option<bool>,
+
+    /// Whether to strip debug information from MoonBit output. Defaults to
+    /// `true` for release-based profiles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strip: Option<bool>,
+}
+
+impl BuildProfile {
+    fn builtin(base: BaseProfile) -> Self {
+        BuildProfile {
+            inherits: base,
+            compile_flags: None,
+            link_flags: None,
+            cc_opt_level: None,
+            debug_info: None,
+            strip: None,
+        }
+    }
+
+    pub fn strip(&self) -> bool {
+        self.strip.unwrap_or(self.inherits == BaseProfile::Release)
+    }
+}
+
+/// The build profile selected for a build
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// The name of the profile, also the name of its target subdirectory
+    pub name: String,
+    pub config: BuildProfile,
+}
+
+/// Check that no profile in `moon.mod.json` shadows a built-in one.
+pub fn validate_profiles(profiles: &IndexMap<String, BuildProfile>) -> anyhow::Result<()> {
+    for name in profiles.keys() {
+        if name == "debug" || name == "release" {
+            bail!("profile `{name}` is built-in and cannot be redefined");
+        }
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            bail!("`{name}` is not a valid profile name");
+        }
+    }
+    Ok(())
+}
+
+/// Find the profile named `name`, which is either `debug`, `release`, or one
+/// of `profiles` from `moon.mod.json`.
+pub fn resolve_profile(
+    profiles: Option<&IndexMap<String, BuildProfile>>,
+    name: &str,
+) -> anyhow::Result<Profile> {
+    let config = match name {
+        "debug" => BuildProfile::builtin(BaseProfile::Debug),
+        "release" => BuildProfile::builtin(BaseProfile::Release),
+        _ => match profiles.and_then(|p| p.get(name)) {
+            Some(profile) => profile.clone(),
+            None => bail!("profile `{name}` is not defined in `moon.mod.json`"),
+        },
+    };
+    Ok(Profile {
+        name: name.to_string(),
+        config,
+    })
+}
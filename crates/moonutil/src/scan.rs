@@ -16,8 +16,12 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
-use crate::cond_expr::{self, CompileCondition, CondExpr};
+use crate::build_script::BuildInfo;
+use crate::cond_expr::{self, CfgEnv, CompileCondition, CondExpr};
 use crate::module::{ModuleDB, MoonMod};
+use crate::module_features::{
+    check_target_features, disabled_deps, import_of_disabled_dep, skipped_package_message,
+};
 use crate::moon_dir::MOON_DIRS;
 use crate::mooncakes::DirSyncResult;
 use crate::mooncakes::result::ResolvedEnv;
@@ -194,6 +198,14 @@ fn scan_module_packages(
         }
     };
 
+    // Packages importing optional dependencies that no feature enabled are
+    // left out, since those dependencies were not resolved
+    let disabled_deps = if is_third_party {
+        disabled_deps(&mod_desc, moonc_opt.features.get(&mod_desc.name))
+    } else {
+        vec![]
+    };
+
     // scan local packages
     let mut walker = WalkDir::new(&module_source_dir)
         .into_iter()
@@ -226,6 +238,23 @@ fn scan_module_packages(
             // This is a module located within the current module. Don't recurse into it any more.
             walker.skip_current_dir();
         } else if has_moon_pkg {
+            if !disabled_deps.is_empty() {
+                let pkg = crate::common::read_package_desc_file_in_dir(path)?;
+                if let Some((import, dep)) = import_of_disabled_dep(&pkg, &disabled_deps) {
+                    let rel = PathComponent::from_path(path.strip_prefix(&module_source_dir)?)?;
+                    let pkg_name = if rel.full_name().is_empty() {
+                        mod_desc.name.clone()
+                    } else {
+                        format!("{}/{}", mod_desc.name, rel.full_name())
+                    };
+                    log::warn!(
+                        "{}",
+                        skipped_package_message(&mod_desc, &pkg_name, import, dep)
+                    );
+                    continue;
+                }
+            }
+
             // Go on scanning the package
             let cur_pkg = scan_one_package(
                 env,
@@ -442,6 +471,24 @@ fn scan_one_package(
 
     let artifact: PathBuf = target_dir.into();
 
+    if let Some(targets) = &pkg.targets {
+        check_target_features(targets, mod_desc.features.as_ref()).with_context(|| {
+            format!(
+                "invalid `targets` in \"{}\"",
+                pkg_path.join(MOON_PKG_JSON).display()
+            )
+        })?;
+    }
+    let cfg_env = {
+        let target = BuildInfo::new(
+            moonc_opt.build_opt.target_backend,
+            moonc_opt.target_triple.as_deref(),
+        )?
+        .target;
+        let features = moonc_opt.features.get(&mod_desc.name).cloned();
+        CfgEnv::new(&target, features.unwrap_or_default())
+    };
+
     let cond_targets = {
         let mut x = pkg.targets.unwrap_or(IndexMap::new());
 
@@ -480,7 +527,7 @@ fn scan_one_package(
                 cond_targets
                     .as_ref()
                     .and_then(|it| it.get(p.file_name().unwrap().to_str().unwrap()))
-                    .map(|f| f.to_compile_condition())
+                    .map(|f| f.to_compile_condition(&cfg_env))
                     .unwrap_or_default(),
             )
         }))
@@ -610,6 +657,8 @@ fn scan_one_package(
         overrides: pkg.overrides,
         link_libs: vec![],
         link_search_paths: vec![],
+        test_limits: pkg.test_limits,
+        js_test_runtime: pkg.js_test_runtime,
         link_flags: None,
     };
     if doc_mode {
@@ -774,13 +823,22 @@ pub fn scan(
             ..moonbuild_opt.clone()
         };
 
+        // Use the features enabled by the dependents of this module
+        let mut moonc_opt = moonc_opt.clone();
+        let info = resolved_modules.module_info(module_id);
+        if let Some(features) = resolved_modules.features(module_id) {
+            moonc_opt
+                .features
+                .insert(info.name.clone(), features.clone());
+        }
+
         scan_module_packages(
             &mut packages,
             &module_scan_paths,
             true,
             doc_mode,
             moonbuild_opt,
-            moonc_opt,
+            &moonc_opt,
         )?;
     }
 
@@ -0,0 +1,273 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Discovery of native system libraries declared in `native.system-deps`.
+
+use std::process::Command;
+
+use anyhow::{Context, bail};
+
+/// Environment variable overriding the `pkg-config` executable to use
+pub const PKG_CONFIG_ENV: &str = "PKG_CONFIG";
+
+/// Environment variables pointing `pkg-config` at the libraries of a target
+/// rather than those of the host. They can be given for one target only,
+/// e.g. as `PKG_CONFIG_SYSROOT_DIR_aarch64_unknown_linux_gnu`.
+const PKG_CONFIG_TARGET_ENVS: &[&str] = &[
+    "PKG_CONFIG_SYSROOT_DIR",
+    "PKG_CONFIG_LIBDIR",
+    "PKG_CONFIG_PATH",
+];
+
+/// Flags needed to build against a system library, as reported by `pkg-config`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SystemDepFlags {
+    /// Flags for compiling C sources that include the library's headers
+    pub cflags: Vec<String>,
+    /// Flags for linking the library into the final artifact
+    pub libs: Vec<String>,
+}
+
+impl SystemDepFlags {
+    pub fn extend(&mut self, other: SystemDepFlags) {
+        self.cflags.extend(other.cflags);
+        self.libs.extend(other.libs);
+    }
+}
+
+/// Turn a `system-deps` entry into a `pkg-config` module spec, e.g.
+/// `("zlib", ">=1.2")` into `zlib >= 1.2`. A bare version means "at least",
+/// and an empty version or `*` accepts any version.
+pub fn pkg_config_spec(name: &str, version: &str) -> anyhow::Result<String> {
+    let version = version.trim();
+    if version.is_empty() || version == "*" {
+        return Ok(name.to_string());
+    }
+    let split = version
+        .find(|c: char| !matches!(c, '<' | '>' | '=' | '!'))
+        .unwrap_or(version.len());
+    let (op, ver) = version.split_at(split);
+    let op = match op {
+        "" => ">=",
+        ">=" | "<=" | ">" | "<" | "!=" => op,
+        "=" | "==" => "=",
+        _ => bail!("invalid version requirement `{version}` for system dependency `{name}`"),
+    };
+    let ver = ver.trim();
+    if !ver.starts_with(|c: char| c.is_ascii_alphanumeric()) || ver.contains(char::is_whitespace) {
+        bail!("invalid version requirement `{version}` for system dependency `{name}`");
+    }
+    Ok(format!("{name} {op} {ver}"))
+}
+
+/// The `pkg-config` to run and the environment to run it with
+#[derive(Debug, PartialEq, Eq)]
+struct PkgConfig {
+    program: String,
+    envs: Vec<(&'static str, String)>,
+}
+
+/// Find the `pkg-config` for `target`, or for the host if `None`, reading
+/// the environment with `var`.
+///
+/// The host `pkg-config` reports the libraries of the host, so when
+/// cross-compiling it must be told where the libraries of the target are
+/// with `PKG_CONFIG_SYSROOT_DIR` or `PKG_CONFIG_LIBDIR`, or the target must
+/// get its own `PKG_CONFIG` or `PKG_CONFIG_PATH`. Like the variables of
+/// `PKG_CONFIG_TARGET_ENVS`, these can be given for one target by suffixing
+/// them with the triple. Returns `None` if none of them is set.
+fn pkg_config_for(target: Option<&str>, var: impl Fn(&str) -> Option<String>) -> Option<PkgConfig> {
+    let Some(target) = target else {
+        return Some(PkgConfig {
+            program: var(PKG_CONFIG_ENV).unwrap_or_else(|| "pkg-config".into()),
+            envs: vec![],
+        });
+    };
+    let for_target = |name: &str| {
+        var(&format!("{name}_{}", target.replace('-', "_")))
+            .or_else(|| var(&format!("{name}_{target}")))
+    };
+
+    let program = for_target(PKG_CONFIG_ENV);
+    let envs = PKG_CONFIG_TARGET_ENVS
+        .iter()
+        .filter_map(|&name| Some((name, for_target(name).or_else(|| var(name))?)))
+        .collect::<Vec<_>>();
+    let configured = program.is_some()
+        || for_target("PKG_CONFIG_PATH").is_some()
+        || envs
+            .iter()
+            .any(|(name, _)| *name == "PKG_CONFIG_SYSROOT_DIR" || *name == "PKG_CONFIG_LIBDIR");
+    configured.then(|| PkgConfig {
+        program: program
+            .or_else(|| var(PKG_CONFIG_ENV))
+            .unwrap_or_else(|| "pkg-config".into()),
+        envs,
+    })
+}
+
+/// Query `pkg-config` for a system library of `pkg` and the flags needed to
+/// use it, when building for `target` or for the host if `None`. Fails with
+/// the reason reported by `pkg-config` when the library is missing or its
+/// version does not satisfy `version`.
+pub fn probe_system_dep(
+    pkg: &str,
+    name: &str,
+    version: &str,
+    target: Option<&str>,
+) -> anyhow::Result<SystemDepFlags> {
+    let spec = pkg_config_spec(name, version)?;
+    let Some(PkgConfig {
+        program: pkg_config,
+        envs,
+    }) = pkg_config_for(target, |name| std::env::var(name).ok())
+    else {
+        bail!(
+            "cannot find system library `{spec}` required by package `{pkg}` for target `{}`: \
+             set `PKG_CONFIG_SYSROOT_DIR` or `PKG_CONFIG_LIBDIR` to point pkg-config at the \
+             libraries of the target",
+            target.unwrap_or_default()
+        );
+    };
+
+    let exists = Command::new(&pkg_config)
+        .envs(envs.iter().cloned())
+        .args(["--exists", "--print-errors", &spec])
+        .output()
+        .with_context(|| {
+            format!(
+                "failed to run `{pkg_config}` to find system library `{name}` required by package `{pkg}`; \
+                 is pkg-config installed?"
+            )
+        })?;
+    if !exists.status.success() {
+        let reason = String::from_utf8_lossy(&exists.stderr);
+        bail!(
+            "system library `{spec}` required by package `{pkg}` was not found{}{}",
+            if reason.trim().is_empty() { "" } else { ":\n" },
+            reason.trim_end()
+        );
+    }
+
+    let query = |flag: &str| -> anyhow::Result<Vec<String>> {
+        let out = Command::new(&pkg_config)
+            .envs(envs.iter().cloned())
+            .args([flag, name])
+            .output()
+            .with_context(|| format!("failed to run `{pkg_config} {flag} {name}`"))?;
+        if !out.status.success() {
+            bail!(
+                "`{pkg_config} {flag} {name}` failed: {}",
+                String::from_utf8_lossy(&out.stderr).trim_end()
+            );
+        }
+        Ok(String::from_utf8_lossy(&out.stdout)
+            .split_whitespace()
+            .map(str::to_string)
+            .collect())
+    };
+
+    Ok(SystemDepFlags {
+        cflags: query("--cflags")?,
+        libs: query("--libs")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_pkg_config_spec() {
+        assert_eq!(pkg_config_spec("zlib", "").unwrap(), "zlib");
+        assert_eq!(pkg_config_spec("zlib", "*").unwrap(), "zlib");
+        assert_eq!(pkg_config_spec("zlib", "1.2").unwrap(), "zlib >= 1.2");
+        assert_eq!(pkg_config_spec("zlib", ">=1.2").unwrap(), "zlib >= 1.2");
+        assert_eq!(pkg_config_spec("zlib", "< 2").unwrap(), "zlib < 2");
+        assert_eq!(pkg_config_spec("zlib", "==1.3").unwrap(), "zlib = 1.3");
+        assert!(pkg_config_spec("zlib", "~1.2").is_err());
+        assert!(pkg_config_spec("zlib", ">=").is_err());
+    }
+
+    #[test]
+    fn test_pkg_config_for() {
+        let triple = "aarch64-unknown-linux-gnu";
+        let pkg_config_for = |target, vars: &[(&str, &str)]| {
+            let vars = vars.iter().copied().collect::<HashMap<_, _>>();
+            pkg_config_for(target, |name| vars.get(name).map(|v| v.to_string()))
+        };
+        let pkg_config = |program: &str, envs: &[(&'static str, &str)]| PkgConfig {
+            program: program.to_string(),
+            envs: envs.iter().map(|&(k, v)| (k, v.to_string())).collect(),
+        };
+
+        // The host ignores the variables, `pkg-config` reads them itself
+        assert_eq!(
+            pkg_config_for(None, &[("PKG_CONFIG_PATH", "/host")]),
+            Some(pkg_config("pkg-config", &[]))
+        );
+        assert_eq!(
+            pkg_config_for(None, &[("PKG_CONFIG", "/bin/fake")]),
+            Some(pkg_config("/bin/fake", &[]))
+        );
+
+        // A target needs to be told where its libraries are
+        assert_eq!(pkg_config_for(Some(triple), &[]), None);
+        assert_eq!(
+            pkg_config_for(
+                Some(triple),
+                &[("PKG_CONFIG", "/bin/fake"), ("PKG_CONFIG_PATH", "/host")]
+            ),
+            None
+        );
+        assert_eq!(
+            pkg_config_for(Some(triple), &[("PKG_CONFIG_SYSROOT_DIR", "/sysroot")]),
+            Some(pkg_config(
+                "pkg-config",
+                &[("PKG_CONFIG_SYSROOT_DIR", "/sysroot")]
+            ))
+        );
+        assert_eq!(
+            pkg_config_for(
+                Some(triple),
+                &[
+                    ("PKG_CONFIG_PATH", "/host"),
+                    ("PKG_CONFIG_PATH_aarch64_unknown_linux_gnu", "/target"),
+                    ("PKG_CONFIG_LIBDIR_x86_64_unknown_linux_gnu", "/other"),
+                ]
+            ),
+            Some(pkg_config("pkg-config", &[("PKG_CONFIG_PATH", "/target")]))
+        );
+        assert_eq!(
+            pkg_config_for(
+                Some(triple),
+                &[
+                    ("PKG_CONFIG", "/bin/fake"),
+                    ("PKG_CONFIG_aarch64-unknown-linux-gnu", "/bin/cross"),
+                    ("PKG_CONFIG_LIBDIR", "/sysroot/lib"),
+                ]
+            ),
+            Some(pkg_config(
+                "/bin/cross",
+                &[("PKG_CONFIG_LIBDIR", "/sysroot/lib")]
+            ))
+        );
+    }
+}